@@ -41,6 +41,7 @@ mod uid;
 mod version;
 
 pub(crate) use connection::*;
+pub use connection::{discover_local, discover_uid, DiscoveredCamera};
 pub use credentials::*;
 pub use errors::Error;
 pub use ledstate::LightState;
@@ -101,6 +102,8 @@ pub struct BcCameraOpt {
     pub credentials: Credentials,
     /// Toggle debug print of underlying data
     pub debug: bool,
+    /// Local address to connect from, any when `None`
+    pub source_addr: Option<IpAddr>,
 }
 
 /// Used to choose the print format of various status messages like battery levels
@@ -138,7 +141,7 @@ impl BcCamera {
     /// Try to connect to the camera via appropaite methods and return
     /// the location that should be used
     async fn find_camera(options: &BcCameraOpt) -> Result<CameraLocation> {
-        let discovery = Discovery::new().await?;
+        let discovery = Discovery::new(options.source_addr).await?;
         if let ConnectionProtocol::Tcp | ConnectionProtocol::TcpUdp = options.protocol {
             let mut sockets = vec![];
             match options.port {
@@ -216,7 +219,7 @@ impl BcCamera {
                     }
                 }, if allow_local => Ok(v),
                 Ok(v) = async {
-                    let mut discovery = Discovery::new().await?;
+                    let mut discovery = Discovery::new(options.source_addr).await?;
                     let reg_result;
                     // Registration is looped as it seems that reolink
                     // only updates the registration lazily when someone attempts
@@ -240,7 +243,7 @@ impl BcCamera {
                         retry += 1;
                         tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
                         // New discovery to get new client IDs
-                        discovery = Discovery::new().await?;
+                        discovery = Discovery::new(options.source_addr).await?;
                     };
                     tokio::select! {
                         Ok(v) = async {
@@ -330,9 +333,15 @@ impl BcCamera {
         let (sink, source): (BcConnSink, BcConnSource) = {
             match BcCamera::find_camera(options).await? {
                 CameraLocation::Tcp(addr) => {
-                    let (x, r) = TcpSource::new(addr, &username, passwd.as_ref(), options.debug)
-                        .await?
-                        .split();
+                    let (x, r) = TcpSource::new(
+                        addr,
+                        options.source_addr,
+                        &username,
+                        passwd.as_ref(),
+                        options.debug,
+                    )
+                    .await?
+                    .split();
                     (Box::new(x), Box::new(r))
                 }
                 CameraLocation::Udp(discovery) => {
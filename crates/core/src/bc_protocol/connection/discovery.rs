@@ -4,7 +4,7 @@
 //!
 use super::DiscoveryResult;
 use crate::bc::model::*;
-use crate::bc_protocol::{md5_string, Md5Trunc, TcpSource};
+use crate::bc_protocol::{md5_string, DiscoveryMethods, Md5Trunc, TcpSource};
 use crate::bcudp::codex::BcUdpCodex;
 use crate::bcudp::model::*;
 use crate::bcudp::xml::*;
@@ -18,7 +18,7 @@ use log::*;
 use rand::{seq::SliceRandom, thread_rng, Rng};
 use std::collections::{btree_map::Entry, BTreeMap, HashSet};
 use std::convert::TryInto;
-use std::net::{Ipv4Addr, SocketAddr, ToSocketAddrs};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, ToSocketAddrs};
 use std::sync::Arc;
 use tokio::time::MissedTickBehavior;
 use tokio::{
@@ -117,8 +117,8 @@ fn valid_addr(ip_port: &IpPort) -> bool {
 }
 
 impl Discoverer {
-    async fn new() -> Result<Discoverer> {
-        let socket = Arc::new(connect().await?);
+    async fn new(source_addr: Option<IpAddr>) -> Result<Discoverer> {
+        let socket = Arc::new(connect(source_addr).await?);
         let local_addr = socket.local_addr()?;
         let inner: ArcFramedSocket = UdpFramed::new(socket.clone(), BcUdpCodex::new());
         let cancel = CancellationToken::new();
@@ -1077,13 +1077,17 @@ impl Drop for Discoverer {
 pub(crate) struct Discovery {
     discoverer: Discoverer,
     client_id: i32,
+    source_addr: Option<IpAddr>,
 }
 
 impl Discovery {
-    pub(crate) async fn new() -> Result<Self> {
+    /// The connections made by the discovery are from `source_addr` when it is
+    /// given otherwise from any local address
+    pub(crate) async fn new(source_addr: Option<IpAddr>) -> Result<Self> {
         Ok(Self {
-            discoverer: Discoverer::new().await?,
+            discoverer: Discoverer::new(source_addr).await?,
             client_id: generate_cid(),
+            source_addr,
         })
     }
 
@@ -1128,8 +1132,11 @@ impl Discovery {
     pub(crate) async fn check_tcp(&self, addr: SocketAddr, channel_id: u8) -> Result<()> {
         let username = "admin";
         let password = Some("123456");
-        let mut tcp_source =
-            timeout(*TCP_WAIT, TcpSource::new(addr, username, password, false)).await??;
+        let mut tcp_source = timeout(
+            *TCP_WAIT,
+            TcpSource::new(addr, self.source_addr, username, password, false),
+        )
+        .await??;
 
         let md5_username = md5_string(username, Md5Trunc::ZeroLast);
         let md5_password = password
@@ -1279,6 +1286,153 @@ impl Discovery {
     }
 }
 
+/// A camera that answered [`discover_local`] or [`discover_uid`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiscoveredCamera {
+    /// The UID of the camera, `None` when it could not be read from the reply
+    pub uid: Option<String>,
+    /// The address that the camera answered from or is registered at
+    pub addr: SocketAddr,
+    /// How it was found, either `Local`, `Remote` for the address that the camera
+    /// registered with the reolink servers or `Map` for its public address
+    pub method: DiscoveryMethods,
+}
+
+/// Broadcasts a C2D_S on all local networks and lists the cameras that reply
+/// within `wait`
+///
+/// The reply is binary and not fully understood. The address is always known
+/// but the UID is only filled in when something that looks like one is in it
+pub async fn discover_local(wait: Duration) -> Result<Vec<DiscoveredCamera>> {
+    // Cameras reply on port 3000 whatever we ask so try to be there
+    let socket = match UdpSocket::bind(SocketAddr::from(([0, 0, 0, 0], 3000))).await {
+        Ok(socket) => {
+            socket.set_broadcast(true)?;
+            socket
+        }
+        Err(e) => {
+            debug!(
+                "Cannot listen on port 3000 ({:?}), replies may be missed",
+                e
+            );
+            connect(None).await?
+        }
+    };
+    let port = socket.local_addr()?.port();
+    let packet = BcUdp::Discovery(UdpDiscovery {
+        tid: generate_tid(),
+        payload: UdpXml {
+            c2d_s: Some(C2dS {
+                to: PortList { port: port as u32 },
+            }),
+            ..Default::default()
+        },
+    })
+    .serialize(vec![])?;
+    let dests = get_broadcasts(&[2015])?;
+
+    let mut found: BTreeMap<std::net::IpAddr, DiscoveredCamera> = Default::default();
+    let mut buf = vec![0u8; 4096];
+    let mut resend = interval(*RESEND_WAIT);
+    resend.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    let mut sent = 0;
+    let deadline = tokio::time::sleep(wait);
+    tokio::pin!(deadline);
+    loop {
+        tokio::select! {
+            _ = &mut deadline => break,
+            _ = resend.tick(), if sent < 5 => {
+                sent += 1;
+                for dest in dests.iter() {
+                    if let Err(e) = socket.send_to(&packet, dest).await {
+                        trace!("Could not broadcast to {:?}: {:?}", dest, e);
+                    }
+                }
+            }
+            v = socket.recv_from(&mut buf) => {
+                let (len, addr) = v?;
+                let uid = find_uid(&buf[..len]);
+                trace!("Discovery reply from {:?} with uid {:?}", addr, uid);
+                let camera = found.entry(addr.ip()).or_insert(DiscoveredCamera {
+                    uid: None,
+                    addr,
+                    method: DiscoveryMethods::Local,
+                });
+                if camera.uid.is_none() {
+                    camera.uid = uid;
+                }
+            }
+        }
+    }
+    Ok(found.into_values().collect())
+}
+
+/// Looks for the camera with `uid` by broadcasting on the local networks and,
+/// with `remote`, by asking the reolink p2p servers where it registered
+///
+/// Each lookup is given `wait`. Only the addresses are reported, the
+/// connections that were made to find them are dropped
+pub async fn discover_uid(
+    uid: &str,
+    remote: bool,
+    wait: Duration,
+) -> Result<Vec<DiscoveredCamera>> {
+    let discovery = Discovery::new(None).await?;
+    let found = |addr, method| DiscoveredCamera {
+        uid: Some(uid.to_string()),
+        addr,
+        method,
+    };
+    let local = async {
+        match timeout(wait, discovery.local(uid, None)).await {
+            Ok(Ok(result)) => vec![found(result.addr, DiscoveryMethods::Local)],
+            Ok(Err(e)) => {
+                debug!("Local discovery of {} failed: {:?}", uid, e);
+                vec![]
+            }
+            Err(_) => vec![],
+        }
+    };
+    let registered = async {
+        if !remote {
+            return vec![];
+        }
+        match timeout(wait, discovery.get_registration(uid)).await {
+            Ok(Ok(reg)) => reg
+                .dev
+                .map(|addr| found(addr, DiscoveryMethods::Remote))
+                .into_iter()
+                .chain(reg.dmap.map(|addr| found(addr, DiscoveryMethods::Map)))
+                .collect(),
+            Ok(Err(e)) => {
+                debug!("Remote discovery of {} failed: {:?}", uid, e);
+                vec![]
+            }
+            Err(_) => vec![],
+        }
+    };
+    let (mut local, registered) = tokio::join!(local, registered);
+    local.extend(registered);
+    Ok(local)
+}
+
+/// Finds a UID in the binary reply of a C2D_S
+///
+/// UIDs are 16 upper case letters and digits
+fn find_uid(reply: &[u8]) -> Option<String> {
+    reply
+        .split(|b| !b.is_ascii_alphanumeric())
+        .find(|word| {
+            word.len() == 16
+                && word
+                    .iter()
+                    .all(|b| b.is_ascii_digit() || b.is_ascii_uppercase())
+                && word.iter().any(|b| b.is_ascii_digit())
+                && word.iter().any(|b| b.is_ascii_uppercase())
+        })
+        .map(|word| String::from_utf8_lossy(word).to_string())
+}
+
 fn get_local_ip() -> Result<std::net::IpAddr> {
     get_if_addrs::get_if_addrs()?
         .iter()
@@ -1322,23 +1476,47 @@ fn generate_cid() -> i32 {
     rng.gen()
 }
 
-async fn connect() -> Result<UdpSocket> {
+async fn connect(source_addr: Option<IpAddr>) -> Result<UdpSocket> {
     let mut ports: Vec<u16> = (53500..54000).collect();
     {
         let mut rng = thread_rng();
         ports.shuffle(&mut rng);
     }
 
+    let ip = source_addr.unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED));
     let addrs: Vec<_> = ports
         .iter()
-        .map(|&port| SocketAddr::from(([0, 0, 0, 0], port)))
+        .map(|&port| SocketAddr::new(ip, port))
         .collect();
-    let socket = UdpSocket::bind(&addrs[..]).await?;
+    let socket = UdpSocket::bind(&addrs[..])
+        .await
+        .map_err(|e| match source_addr {
+            Some(addr) => Error::SourceAddr {
+                addr,
+                error: Arc::new(e),
+            },
+            None => e.into(),
+        })?;
     socket.set_broadcast(true)?;
 
     Ok(socket)
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_uid() {
+        assert_eq!(
+            find_uid(b"\x01\x00IPC\x0095270000YGAKNWKJ\x00192.168.1.10\x00"),
+            Some("95270000YGAKNWKJ".to_string())
+        );
+        assert_eq!(find_uid(b"\x00Front Door\x00ABCDEFGHIJKLMNOP\x00"), None);
+        assert_eq!(find_uid(b"95270000ygaknwkj"), None);
+    }
+}
+
 /*
     # Discovery Methods
 
@@ -14,6 +14,7 @@ mod discovery;
 mod tcpsource;
 mod udpsource;
 
+pub use self::discovery::{discover_local, discover_uid, DiscoveredCamera};
 pub(crate) use self::{
     bcconn::BcConnection, bcconn::*, bcsub::BcSubscription, discovery::Discovery,
     tcpsource::TcpSource, udpsource::UdpSource,
@@ -1,9 +1,9 @@
 use crate::bc::model::*;
-use crate::Result;
 use crate::{bc::codex::BcCodex, Credentials};
+use crate::{Error, Result};
 use delegate::delegate;
 use futures::{sink::Sink, stream::Stream};
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
 use std::pin::Pin;
 use std::task::{Context, Poll};
 use tokio::net::{TcpSocket, TcpStream};
@@ -16,11 +16,12 @@ pub(crate) struct TcpSource {
 impl TcpSource {
     pub(crate) async fn new<T: Into<String>, U: Into<String>>(
         addr: SocketAddr,
+        source_addr: Option<IpAddr>,
         username: T,
         password: Option<U>,
         debug: bool,
     ) -> Result<TcpSource> {
-        let stream = connect_to(addr).await?;
+        let stream = connect_to(addr, source_addr).await?;
 
         let codex = if debug {
             BcCodex::new_with_debug(Credentials::new(username, password))
@@ -63,11 +64,21 @@ impl Sink<Bc> for TcpSource {
 }
 
 /// Helper to create a TcpStream with a connect timeout
-async fn connect_to(addr: SocketAddr) -> Result<TcpStream> {
+///
+/// When `source_addr` is given the connection is made from that local address
+async fn connect_to(addr: SocketAddr, source_addr: Option<IpAddr>) -> Result<TcpStream> {
     let socket = match addr {
         SocketAddr::V4(_) => TcpSocket::new_v4()?,
         SocketAddr::V6(_) => TcpSocket::new_v6()?,
     };
+    if let Some(source_addr) = source_addr {
+        socket
+            .bind(SocketAddr::new(source_addr, 0))
+            .map_err(|e| Error::SourceAddr {
+                addr: source_addr,
+                error: std::sync::Arc::new(e),
+            })?;
+    }
 
     Ok(socket.connect(addr).await?)
 }
@@ -87,6 +87,10 @@ pub enum Error {
     #[error(display = "Talk data is not ADPCM")]
     UnknownTalkEncoding,
 
+    /// Raised when the camera is still talking to another client after asking it to stop
+    #[error(display = "Camera is busy with another talk session")]
+    TalkBusy,
+
     /// Raised when dicovery times out waiting for a reply
     #[error(display = "Timed out while waiting for camera reply")]
     DiscoveryTimeout,
@@ -129,6 +133,15 @@ pub enum Error {
     #[error(display = "Connection from unknown source: {:?}", _0)]
     UnknownSource(std::net::SocketAddr),
 
+    /// Raised when the connection cannot be made from the requested local address
+    #[error(display = "Cannot use {} as the source address: {}", addr, error)]
+    SourceAddr {
+        /// The local address that was requested
+        addr: std::net::IpAddr,
+        /// Why it could not be bound
+        error: std::sync::Arc<std::io::Error>,
+    },
+
     /// Raised when the IP/Hostname cannot be understood
     #[error(display = "Could not parse as IP")]
     AddrParseError(#[error(source)] std::net::AddrParseError),
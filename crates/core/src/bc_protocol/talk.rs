@@ -166,6 +166,13 @@ impl BcCamera {
             // Retry
             sub.send(msg).await?;
             msg = sub.recv().await?;
+            if let BcMeta {
+                response_code: 422, ..
+            } = msg.meta
+            {
+                // Still talking to someone else
+                return Err(Error::TalkBusy);
+            }
         }
 
         if let BcMeta {
@@ -307,6 +314,13 @@ impl BcCamera {
             // Retry
             sub.send(msg).await?;
             msg = sub.recv().await?;
+            if let BcMeta {
+                response_code: 422, ..
+            } = msg.meta
+            {
+                // Still talking to someone else
+                return Err(Error::TalkBusy);
+            }
         }
 
         if let BcMeta {
@@ -92,6 +92,7 @@ impl AddressOrUid {
                 password: camera_config.password.clone(),
             },
             debug: false,
+            source_addr: None,
         };
 
         trace!("Camera Info: {:?}", options);
@@ -3,6 +3,14 @@ use clap::Parser;
 /// The battery command will dump the battery status to XML
 #[derive(Parser, Debug)]
 pub struct Opt {
-    /// The name of the camera. Must be a name in the config
-    pub camera: String,
+    /// The name of the camera. Must be a name in the config or a glob like
+    /// `Front*` for all the cameras it matches
+    #[arg(required_unless_present = "all")]
+    pub camera: Option<String>,
+    /// Get the battery status of every enabled camera in the config
+    #[arg(long, conflicts_with = "camera")]
+    pub all: bool,
+    /// Print the battery level and charging status as JSON instead of XML
+    #[arg(long)]
+    pub json: bool,
 }
@@ -2,19 +2,28 @@
 /// # Neolink Battery
 ///
 /// This module handles the printing of the Battery status
-/// in xml format
+/// in xml format, or as json with `--json`
+///
+/// Cameras without a battery are an error so scripts can tell them apart
+///
+/// With a glob or `--all` the cameras are asked at the same time and a
+/// summary is printed, it only fails if every camera failed
 ///
 /// # Usage
 ///
 /// ```bash
 /// neolink battery --config=config.toml CameraName
+/// neolink battery --config=config.toml --json CameraName
+/// neolink battery --config=config.toml --json --all
 /// ```
 ///
-use anyhow::{Context, Result};
+use anyhow::{anyhow, Context, Result};
+use neolink_core::bc::xml::BatteryInfo;
+use serde::Serialize;
 
 mod cmdline;
 
-use crate::common::NeoReactor;
+use crate::{common::NeoReactor, utils::CameraSelection};
 
 pub(crate) use cmdline::Opt;
 
@@ -22,25 +31,76 @@ pub(crate) use cmdline::Opt;
 ///
 /// Opt is the command line options
 pub(crate) async fn main(opt: Opt, reactor: NeoReactor) -> Result<()> {
-    let camera = reactor.get(&opt.camera).await?;
+    let config = reactor.config().await?.borrow().clone();
+    let selection = CameraSelection::new(&config, opt.camera.as_deref(), opt.all)?;
+    // The xml does not say which camera it is from
+    let label = matches!(selection, CameraSelection::Many(_));
+    let (opt, reactor) = (&opt, &reactor);
+    selection
+        .run(|name| battery(opt, reactor, name, label))
+        .await
+}
+
+async fn battery(opt: &Opt, reactor: &NeoReactor, name: String, label: bool) -> Result<()> {
+    let camera = reactor.get(&name).await?;
     log::debug!("Battery: Instance aquired");
 
     let state = camera
         .run_task(|cam| {
             Box::pin(async move {
-                cam.battery_info()
-                    .await
-                    .context("Unable to get camera Battery state")
+                match cam.battery_info().await {
+                    Err(neolink_core::Error::UnintelligibleReply { .. }) => {
+                        Err(anyhow!("The camera has no battery"))
+                    }
+                    v => v.context("Unable to get camera Battery state"),
+                }
             })
         })
-        .await?;
+        .await
+        .with_context(|| format!("{}: No battery status", name))?;
+
+    if opt.json {
+        println!(
+            "{}",
+            serde_json::to_string(&BatteryStatus::new(&name, &state))?
+        );
+        return Ok(());
+    }
 
     let ser = String::from_utf8(
         yaserde::ser::serialize_with_writer(&state, vec![], &Default::default())
             .expect("Should Ser the struct"),
     )
     .expect("Should be UTF8");
-    println!("{}", ser);
+    if label {
+        println!("<!-- {} -->\n{}", name, ser);
+    } else {
+        println!("{}", ser);
+    }
 
     Ok(())
 }
+
+/// The battery status in the json output
+#[derive(Serialize, Debug, PartialEq)]
+struct BatteryStatus<'a> {
+    camera: &'a str,
+    battery_percent: u32,
+    charging: bool,
+    charge_status: &'a str,
+    adapter_status: &'a str,
+    low_power: bool,
+}
+
+impl<'a> BatteryStatus<'a> {
+    fn new(camera: &'a str, info: &'a BatteryInfo) -> Self {
+        Self {
+            camera,
+            battery_percent: info.battery_percent,
+            charging: info.charge_status == "charging",
+            charge_status: &info.charge_status,
+            adapter_status: &info.adapter_status,
+            low_power: info.low_power != 0,
+        }
+    }
+}
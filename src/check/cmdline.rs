@@ -0,0 +1,11 @@
+use clap::Parser;
+
+/// The check command connects to every camera and pulls one frame without serving anything
+#[derive(Parser, Debug)]
+pub struct Opt {
+    /// Only check these cameras. Defaults to all the enabled cameras in the config
+    pub cameras: Vec<String>,
+    /// Seconds each camera has to connect, login and send a keyframe
+    #[arg(long, default_value_t = 30)]
+    pub timeout: u64,
+}
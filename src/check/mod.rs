@@ -0,0 +1,133 @@
+///
+/// # Neolink Check
+///
+/// This module handles the check subcommand
+///
+/// The subcommand tests a config before it is deployed. Every camera is
+/// connected, logged in and asked for a single keyframe at the same time.
+/// A table of the results is printed and neolink exits without serving
+/// anything. The exit code is non zero if any camera failed
+///
+/// # Usage
+///
+/// ```bash
+/// neolink check --config=config.toml
+/// # Check only some cameras and give each one 10s
+/// neolink check --config=config.toml --timeout=10 CameraName OtherCamera
+/// ```
+///
+use anyhow::{anyhow, Context, Result};
+use futures::stream::{FuturesOrdered, StreamExt};
+use neolink_core::{bc_protocol::StreamKind, bcmedia::model::BcMedia};
+use tokio::time::{timeout, Duration, Instant};
+
+mod cmdline;
+
+use crate::{
+    config::{CameraConfig, Config},
+    utils::connect_and_login,
+};
+pub(crate) use cmdline::Opt;
+
+/// Entry point for the check subcommand
+///
+/// Opt is the command line options
+pub(crate) async fn main(opt: Opt, config: Config) -> Result<()> {
+    let cameras = if opt.cameras.is_empty() {
+        config
+            .cameras
+            .iter()
+            .filter(|cam| cam.enabled)
+            .cloned()
+            .collect::<Vec<_>>()
+    } else {
+        opt.cameras
+            .iter()
+            .map(|name| {
+                config
+                    .cameras
+                    .iter()
+                    .find(|cam| &cam.name == name)
+                    .cloned()
+                    .ok_or(anyhow!("Camera `{name}` not found in config"))
+            })
+            .collect::<Result<Vec<_>>>()?
+    };
+    let limit = Duration::from_secs(opt.timeout);
+
+    let results = cameras
+        .iter()
+        .map(|camera_config| async move {
+            let start = Instant::now();
+            let result = timeout(limit, check_camera(camera_config))
+                .await
+                .unwrap_or_else(|_| Err(anyhow!("Timed out after {}s", limit.as_secs())));
+            (camera_config.name.as_str(), start.elapsed(), result)
+        })
+        .collect::<FuturesOrdered<_>>()
+        .collect::<Vec<_>>()
+        .await;
+
+    let width = results
+        .iter()
+        .map(|(name, _, _)| name.len())
+        .chain(std::iter::once("Camera".len()))
+        .max()
+        .unwrap_or_default();
+    println!(
+        "{:<width$}  {:<6}  {:>7}  Details",
+        "Camera", "Result", "Time"
+    );
+    for (name, elapsed, result) in results.iter() {
+        let (status, details) = match result {
+            Ok(stream) => ("PASS", format!("Received a keyframe on {stream:?}")),
+            Err(e) => ("FAIL", format!("{e:#}")),
+        };
+        println!(
+            "{:<width$}  {:<6}  {:>6.1}s  {}",
+            name,
+            status,
+            elapsed.as_secs_f64(),
+            details
+        );
+    }
+
+    let failed = results.iter().filter(|(_, _, r)| r.is_err()).count();
+    if failed > 0 {
+        Err(anyhow!(
+            "{failed} of {} cameras failed the check",
+            results.len()
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+/// Connects, logs in and waits for a keyframe of the first configured stream
+async fn check_camera(config: &CameraConfig) -> Result<StreamKind> {
+    let camera = connect_and_login(config).await?;
+    let stream = config
+        .stream_kinds()
+        .first()
+        .copied()
+        .unwrap_or(StreamKind::Main);
+
+    let result = async {
+        let mut stream_data = camera
+            .start_video(stream, 0, config.strict)
+            .await
+            .with_context(|| format!("Failed to start the {stream:?} stream"))?;
+        loop {
+            if let BcMedia::Iframe(_) = stream_data.get_data().await?? {
+                break;
+            }
+        }
+        let _ = stream_data.shutdown().await;
+        Ok(stream)
+    }
+    .await;
+
+    let _ = camera.logout().await;
+    let _ = camera.shutdown().await;
+    result
+}
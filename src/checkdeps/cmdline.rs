@@ -0,0 +1,9 @@
+use clap::Parser;
+
+/// The check-deps command reports which gstreamer plugins neolink can find
+#[derive(Parser, Debug)]
+pub struct Opt {
+    /// List every element, not only the missing ones
+    #[arg(long)]
+    pub all: bool,
+}
@@ -0,0 +1,105 @@
+///
+/// # Neolink Check Deps
+///
+/// This module handles the check-deps subcommand
+///
+/// The subcommand looks up the gstreamer elements that neolink needs and
+/// prints the missing ones with the plugin and package that provides them.
+/// With a config only the features it uses are looked at, without one every
+/// feature is. The exit code is non zero if an element that the streams need
+/// is missing
+///
+/// # Usage
+///
+/// ```bash
+/// neolink check-deps
+/// # Only the features of this config, listing the found elements too
+/// neolink check-deps --config=config.toml --all
+/// ```
+///
+use anyhow::{anyhow, Context, Result};
+use gstreamer::ElementFactory;
+
+mod cmdline;
+
+use crate::{
+    config::Config,
+    exit::ExitError,
+    rtsp::deps::{all_features, features, plugin_of, Fallback},
+};
+pub(crate) use cmdline::Opt;
+
+/// Entry point for the check-deps subcommand
+///
+/// Opt is the command line options
+pub(crate) fn main(opt: &Opt, config: Option<&Config>) -> Result<()> {
+    gstreamer::init().context("Gstreamer failed to initialise")?;
+    let features = match config {
+        Some(config) => features(config),
+        None => all_features(),
+    };
+    let available = |element: &str| ElementFactory::find(element).is_some();
+
+    let mut rows = vec![];
+    let mut required = 0;
+    for feature in features.iter() {
+        let feature_name = match feature.camera.as_deref() {
+            Some(camera) => format!("{camera}: {}", feature.name),
+            None => feature.name.to_string(),
+        };
+        let missing = feature.missing(available);
+        for element in feature.elements.iter() {
+            let status = if !missing.contains(element) {
+                "found"
+            } else if feature.fallback == Fallback::Required {
+                required += 1;
+                "MISSING"
+            } else {
+                "missing"
+            };
+            if opt.all || status != "found" {
+                rows.push((feature_name.clone(), *element, status));
+            }
+        }
+    }
+
+    if rows.is_empty() {
+        println!("All the gstreamer elements that neolink needs are installed");
+        return Ok(());
+    }
+    let feature_width = rows
+        .iter()
+        .map(|(feature, _, _)| feature.len())
+        .chain(std::iter::once("Feature".len()))
+        .max()
+        .unwrap_or_default();
+    let element_width = rows
+        .iter()
+        .map(|(_, element, _)| element.len())
+        .chain(std::iter::once("Element".len()))
+        .max()
+        .unwrap_or_default();
+    println!(
+        "{:<feature_width$}  {:<element_width$}  {:<7}  Plugin",
+        "Feature", "Element", "Status"
+    );
+    for (feature, element, status) in rows.iter() {
+        println!(
+            "{:<feature_width$}  {:<element_width$}  {:<7}  {}",
+            feature,
+            element,
+            status,
+            plugin_of(element)
+        );
+    }
+
+    if required > 0 {
+        Err(anyhow!(
+            "{required} elements that the streams need are missing"
+        ))
+        .context(ExitError::Plugins)
+    } else {
+        println!("Features with a missing element are turned off when neolink starts");
+        Ok(())
+    }
+}
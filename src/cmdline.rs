@@ -9,8 +9,19 @@ use std::str::FromStr;
 #[derive(Parser, Debug)]
 #[command(name = "neolink", arg_required_else_help = true, version = crate_version!(), author = crate_authors!("\n"))]
 pub struct Opt {
+    /// The config file, a directory of `*.toml` config files, `-` for stdin or
+    /// an http(s) url to fetch it from
     #[arg(short, long, global = true, value_parser = PathBuf::from_str)]
     pub config: Option<PathBuf>,
+    /// Write the logs as human readable text or as json lines
+    #[arg(
+        long,
+        global = true,
+        value_enum,
+        env = "NEOLINK_LOG_FORMAT",
+        default_value = "human"
+    )]
+    pub log_format: super::logging::LogFormat,
     #[structopt(subcommand)]
     pub cmd: Option<Command>,
 }
@@ -19,12 +30,20 @@ pub struct Opt {
 pub enum Command {
     Rtsp(super::rtsp::Opt),
     StatusLight(super::statusled::Opt),
+    #[command(alias = "floodlight")]
+    Light(super::light::Opt),
     Reboot(super::reboot::Opt),
     Pir(super::pir::Opt),
     Ptz(super::ptz::Opt),
     Talk(super::talk::Opt),
     Mqtt(super::mqtt::Opt),
     MqttRtsp(super::mqtt::Opt),
+    #[command(alias = "snapshot")]
     Image(super::image::Opt),
     Battery(super::battery::Opt),
+    Check(super::check::Opt),
+    CheckDeps(super::checkdeps::Opt),
+    Validate(super::validate::Opt),
+    Config(super::configdump::Opt),
+    Discover(super::discover::Opt),
 }
@@ -1,4 +1,9 @@
-use std::sync::{Arc, Weak};
+use anyhow::anyhow;
+use rand::{rngs::SmallRng, Rng, SeedableRng};
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Weak},
+};
 use tokio::{
     sync::watch::{Receiver as WatchReceiver, Sender as WatchSender},
     time::{interval, sleep, timeout, Duration, Instant},
@@ -8,6 +13,15 @@ use tokio_util::sync::CancellationToken;
 use crate::{config::CameraConfig, utils::connect_and_login, AnyResult};
 use neolink_core::bc_protocol::BcCamera;
 
+/// Retryable connection failures of a camera
+#[derive(Debug, Default, Clone, Eq, PartialEq)]
+pub(crate) struct ConnectionFailures {
+    pub(crate) count: u64,
+    pub(crate) last_error: Option<String>,
+    /// Set when the camera has given up and will not connect again
+    pub(crate) fatal: bool,
+}
+
 #[derive(Eq, PartialEq, Copy, Clone)]
 pub(crate) enum NeoCamThreadState {
     Connected,
@@ -19,6 +33,11 @@ pub(crate) struct NeoCamThread {
     config: WatchReceiver<CameraConfig>,
     cancel: CancellationToken,
     camera_watch: WatchSender<Weak<BcCamera>>,
+    failures: WatchSender<ConnectionFailures>,
+    reconnect: WatchReceiver<u64>,
+    restart: WatchReceiver<u64>,
+    /// Whether the last run of the camera got as far as logging in
+    logged_in: bool,
 }
 
 impl NeoCamThread {
@@ -26,6 +45,9 @@ impl NeoCamThread {
         watch_state_rx: WatchReceiver<NeoCamThreadState>,
         watch_config_rx: WatchReceiver<CameraConfig>,
         camera_watch_tx: WatchSender<Weak<BcCamera>>,
+        failures_tx: WatchSender<ConnectionFailures>,
+        reconnect_rx: WatchReceiver<u64>,
+        restart_rx: WatchReceiver<u64>,
         cancel: CancellationToken,
     ) -> Self {
         Self {
@@ -33,6 +55,10 @@ impl NeoCamThread {
             config: watch_config_rx,
             cancel,
             camera_watch: camera_watch_tx,
+            failures: failures_tx,
+            reconnect: reconnect_rx,
+            restart: restart_rx,
+            logged_in: false,
         }
     }
     async fn run_camera(&mut self, config: &CameraConfig) -> AnyResult<()> {
@@ -41,9 +67,11 @@ impl NeoCamThread {
 
         sleep(Duration::from_secs(2)).await; // Delay a little since some calls will error if camera is waking up
         update_camera_time(&camera, &name, config.update_time).await?;
+        check_channel(&camera, &name, config.channel_id).await;
         sleep(Duration::from_secs(2)).await; // Delay a little since some calls will error if camera is waking up
 
         self.camera_watch.send_replace(Arc::downgrade(&camera));
+        self.logged_in = true;
 
         let cancel_check = self.cancel.clone();
         // Now we wait for a disconnect
@@ -57,36 +85,7 @@ impl NeoCamThread {
                 v?;
                 Ok(())
             },
-            v = async {
-                let mut interval = interval(Duration::from_secs(5));
-                let mut missed_pings = 0;
-                loop {
-                    interval.tick().await;
-                    match timeout(Duration::from_secs(5), camera.get_linktype()).await {
-                        Ok(Ok(_)) => {
-                            missed_pings = 0;
-                            continue
-                        },
-                        Ok(Err(neolink_core::Error::UnintelligibleReply { .. })) => {
-                            // Camera does not support pings just wait forever
-                            futures::future::pending().await
-                        },
-                        Ok(Err(e)) => {
-                            break Err(e.into());
-                        },
-                        Err(_) => {
-                            // Timeout
-                            if missed_pings > 5 {
-                                missed_pings += 1;
-                                continue;
-                            } else {
-                                log::debug!("Timed out waiting for camera ping reply. Assuming unsupported");
-                                futures::future::pending().await
-                            }
-                        }
-                    }
-                }
-            } => v,
+            v = keepalive(&camera, &name, config.keepalive_secs) => v,
         }?;
 
         let _ = camera.logout().await;
@@ -100,10 +99,14 @@ impl NeoCamThread {
     // A watch sender is used to send the new camera
     // whenever it changes
     pub(crate) async fn run(&mut self) -> AnyResult<()> {
-        const MAX_BACKOFF: Duration = Duration::from_secs(5);
-        const MIN_BACKOFF: Duration = Duration::from_millis(50);
-
-        let mut backoff = MIN_BACKOFF;
+        let (min_backoff, max_backoff) = self.config.borrow().retry_bounds();
+        let mut backoff = Backoff::new(min_backoff, max_backoff);
+        // Rejected logins in a row, too many can lock the account on the camera
+        let mut login_failures = 0;
+        // Failed reconnects in a row, limited by max_retries
+        let mut retries = 0;
+        // Recent failures, limited by max_failures_in_window
+        let mut recent_failures = FailureWindow::default();
 
         loop {
             self.state
@@ -115,26 +118,46 @@ impl NeoCamThread {
             let config = config_rec.borrow_and_update().clone();
             let now = Instant::now();
             let name = config.name.clone();
+            let (min_backoff, max_backoff) = config.retry_bounds();
+            backoff.set_bounds(min_backoff, max_backoff);
 
             let mut state = self.state.clone();
+            // Only requests made after this point should drop the connection
+            self.reconnect.borrow_and_update();
+            let mut reconnect = self.reconnect.clone();
+            self.restart.borrow_and_update();
+            let mut restart = self.restart.clone();
+            self.logged_in = false;
 
             let res = tokio::select! {
-                Ok(_) = config_rec.changed() => {
+                Ok(_) = config_rec.wait_for(|new_conf| new_conf.connection_changed(&config)) => {
                     None
                 }
                 Ok(_) = state.wait_for(|state| matches!(state, NeoCamThreadState::Disconnected)) => {
                     None
                 }
+                Ok(_) = reconnect.changed() => {
+                    Some(Err(anyhow!("Reconnect was requested")))
+                }
+                Ok(_) = restart.changed() => {
+                    // Not a failure so connect again without a delay
+                    log::info!("{name}: Restarting on request");
+                    None
+                }
                 v = self.run_camera(&config) => {
                     Some(v)
                 }
             };
             self.camera_watch.send_replace(Weak::new());
+            if self.logged_in {
+                retries = 0;
+            }
 
             if res.is_none() {
                 // If None go back and reload NOW
                 //
-                // This occurs if there was a config change
+                // This occurs if how the camera connects was changed or a
+                // restart was requested
                 continue;
             }
 
@@ -143,10 +166,7 @@ impl NeoCamThread {
 
             if now.elapsed() > Duration::from_secs(60) {
                 // Command ran long enough to be considered a success
-                backoff = MIN_BACKOFF;
-            }
-            if backoff > MAX_BACKOFF {
-                backoff = MAX_BACKOFF;
+                backoff.reset();
             }
 
             match result {
@@ -158,22 +178,110 @@ impl NeoCamThread {
                 }
                 Err(e) => {
                     // An error
+                    let kind = FailureKind::of(&e);
+                    let failures_in_window = if kind == FailureKind::Retry {
+                        recent_failures.record(
+                            Instant::now(),
+                            Duration::from_secs(config.failure_window_secs),
+                        )
+                    } else {
+                        0
+                    };
                     // Check if it is non-retry
-                    let e_inner = e.downcast_ref::<neolink_core::Error>();
-                    match e_inner {
-                        Some(neolink_core::Error::CameraLoginFail) => {
+                    match kind {
+                        FailureKind::Login if login_failures + 1 < config.max_login_attempts => {
+                            login_failures += 1;
+                            self.failures.send_modify(|failures| {
+                                failures.count += 1;
+                                failures.last_error = Some(format!("{e:#}"));
+                            });
+                            log::warn!(
+                                "{name}: Login credentials were not accepted ({login_failures}/{})",
+                                config.max_login_attempts
+                            );
+                            let delay = backoff.next_delay();
+                            log::info!("{name}: Attempt login again in {:?}", delay);
+                            sleep(delay).await;
+                        }
+                        FailureKind::Login if config.wait_for_credentials => {
+                            log::error!("{name}: Login credentials were not accepted, waiting for them to be changed");
+                            self.failures.send_modify(|failures| {
+                                failures.count += 1;
+                                failures.last_error = Some(format!("{e:#}"));
+                            });
+                            let mut config_rec = self.config.clone();
+                            tokio::select! {
+                                _ = self.cancel.cancelled() => return Err(e),
+                                v = config_rec.wait_for(|new_conf| {
+                                    new_conf.username != config.username
+                                        || new_conf.password != config.password
+                                }) => {
+                                    v?;
+                                }
+                            }
+                            log::info!("{name}: Login credentials changed, logging in again");
+                            login_failures = 0;
+                            backoff.reset();
+                        }
+                        FailureKind::Login => {
                             // Fatal
                             log::error!("{name}: Login credentials were not accepted");
+                            self.failures.send_modify(|failures| {
+                                failures.fatal = true;
+                                failures.last_error = Some(format!("{e:#}"));
+                            });
                             log::debug!("NeoCamThread::run Login Cancel");
                             self.cancel.cancel();
                             return Err(e);
                         }
-                        _ => {
+                        FailureKind::Retry
+                            if config.max_failures_in_window > 0
+                                && failures_in_window > config.max_failures_in_window as usize =>
+                        {
+                            log::error!(
+                                "{name}: Giving up on the camera after more than {} failures within {}s: {:?}",
+                                config.max_failures_in_window,
+                                config.failure_window_secs,
+                                e
+                            );
+                            self.failures.send_modify(|failures| {
+                                failures.count += 1;
+                                failures.fatal = true;
+                                failures.last_error = Some(format!("{e:#}"));
+                            });
+                            log::debug!("NeoCamThread::run Failure Budget Cancel");
+                            self.cancel.cancel();
+                            return Err(e);
+                        }
+                        FailureKind::Retry
+                            if config.max_retries > 0 && retries >= config.max_retries =>
+                        {
+                            log::error!(
+                                "{name}: Giving up on the camera after {} failed reconnects: {:?}",
+                                config.max_retries,
+                                e
+                            );
+                            self.failures.send_modify(|failures| {
+                                failures.count += 1;
+                                failures.fatal = true;
+                                failures.last_error = Some(format!("{e:#}"));
+                            });
+                            log::debug!("NeoCamThread::run Retries Cancel");
+                            self.cancel.cancel();
+                            return Err(e);
+                        }
+                        FailureKind::Retry => {
                             // Non fatal
+                            login_failures = 0;
+                            retries += 1;
+                            self.failures.send_modify(|failures| {
+                                failures.count += 1;
+                                failures.last_error = Some(format!("{e:#}"));
+                            });
                             log::warn!("{name}: Connection Lost: {:?}", e);
-                            log::info!("{name}: Attempt reconnect in {:?}", backoff);
-                            sleep(backoff).await;
-                            backoff *= 2;
+                            let delay = backoff.next_delay();
+                            log::info!("{name}: Attempt reconnect in {:?}", delay);
+                            sleep(delay).await;
                         }
                     }
                 }
@@ -182,6 +290,103 @@ impl NeoCamThread {
     }
 }
 
+/// How the camera thread reacts to an error of the camera
+#[derive(Debug, Eq, PartialEq)]
+enum FailureKind {
+    /// The credentials were rejected, retrying may lock the account
+    Login,
+    /// Anything else such as a timeout or a lost connection
+    Retry,
+}
+
+impl FailureKind {
+    fn of(e: &anyhow::Error) -> Self {
+        match e.downcast_ref::<neolink_core::Error>() {
+            Some(neolink_core::Error::CameraLoginFail) => FailureKind::Login,
+            // Errors that did not come from the camera, e.g. our own timeouts, are retried
+            _ => FailureKind::Retry,
+        }
+    }
+}
+
+/// Doubling delay between reconnects, kept within the configured bounds
+///
+/// Each delay is jittered so that cameras on the same device do not
+/// all reconnect at the same moment
+pub(crate) struct Backoff {
+    min: Duration,
+    max: Duration,
+    current: Duration,
+    rng: SmallRng,
+}
+
+impl Backoff {
+    pub(crate) fn new(min: Duration, max: Duration) -> Self {
+        Self::with_rng(min, max, SmallRng::from_entropy())
+    }
+
+    fn with_rng(min: Duration, max: Duration, rng: SmallRng) -> Self {
+        Self {
+            min,
+            max,
+            current: min,
+            rng,
+        }
+    }
+
+    fn set_bounds(&mut self, min: Duration, max: Duration) {
+        self.min = min;
+        self.max = max;
+        self.current = self.current.clamp(min, max);
+    }
+
+    pub(crate) fn reset(&mut self) {
+        self.current = self.min;
+    }
+
+    /// Starts from the shortest delay again if the last attempt ran for
+    /// longer than the longest delay, it worked for a while then
+    pub(crate) fn reset_after(&mut self, ran: Duration) {
+        if ran > self.max {
+            self.reset();
+        }
+    }
+
+    /// The delay to wait now, the following delay will be doubled
+    pub(crate) fn next_delay(&mut self) -> Duration {
+        let delay = self
+            .current
+            .mul_f64(self.rng.gen_range(0.5..1.5))
+            .min(self.max);
+        self.current = (self.current * 2).clamp(self.min, self.max);
+        delay
+    }
+}
+
+/// The failures of a camera within a rolling window of time
+///
+/// An occasional blip ages out of the window while a camera that keeps
+/// failing piles up failures in it
+#[derive(Default)]
+struct FailureWindow {
+    failures: VecDeque<Instant>,
+}
+
+impl FailureWindow {
+    /// Adds a failure at `now` and returns the failures within `window` of it
+    fn record(&mut self, now: Instant, window: Duration) -> usize {
+        self.failures.push_back(now);
+        while self
+            .failures
+            .front()
+            .is_some_and(|failure| now.duration_since(*failure) > window)
+        {
+            self.failures.pop_front();
+        }
+        self.failures.len()
+    }
+}
+
 impl Drop for NeoCamThread {
     fn drop(&mut self) {
         log::debug!("Cancel:: NeoCamThread::drop");
@@ -189,8 +394,76 @@ impl Drop for NeoCamThread {
     }
 }
 
+/// Pings the camera every `period` seconds and errors once it stops answering
+///
+/// A timeout only counts once the camera has answered a ping, cameras
+/// that never answer are assumed not to support them
+async fn keepalive(camera: &BcCamera, name: &str, period: u64) -> AnyResult<()> {
+    if period == 0 {
+        return futures::future::pending().await;
+    }
+    let period = Duration::from_secs(period);
+    let mut interval = interval(period);
+    let mut answered = false;
+    loop {
+        interval.tick().await;
+        match timeout(period, camera.get_linktype()).await {
+            Ok(Ok(_)) => {
+                answered = true;
+            }
+            Ok(Err(neolink_core::Error::UnintelligibleReply { .. })) => {
+                log::debug!("{name}: Camera does not support pings");
+                return futures::future::pending().await;
+            }
+            Ok(Err(e)) => {
+                return Err(anyhow::Error::from(e).context("Keepalive ping failed"));
+            }
+            Err(_) if answered => {
+                return Err(anyhow!(
+                    "Camera did not answer the keepalive ping within {}s",
+                    period.as_secs()
+                ));
+            }
+            Err(_) => {
+                log::debug!(
+                    "{name}: Timed out waiting for camera ping reply. Assuming unsupported"
+                );
+                return futures::future::pending().await;
+            }
+        }
+    }
+}
+
+/// Warns when the camera does not have the channel of the config
+async fn check_channel(camera: &BcCamera, name: &str, channel_id: u8) {
+    match camera.get_support().await {
+        Ok(support) => match support.channel_num {
+            Some(channels) if channels > 0 && channel_id as u32 >= channels => log::warn!(
+                "{}: Channel {} was requested but the camera only has channels 0 to {}",
+                name,
+                channel_id,
+                channels - 1
+            ),
+            _ => {}
+        },
+        Err(e) => log::debug!("{}: Could not check the number of channels: {:?}", name, e),
+    }
+}
+
 async fn update_camera_time(camera: &BcCamera, name: &str, update_time: bool) -> AnyResult<()> {
-    let cam_time = camera.get_time().await?;
+    let cam_time = match camera.get_time().await {
+        Err(neolink_core::Error::MissingAbility { .. })
+        | Err(neolink_core::Error::CameraServiceUnavaliable(_)) => {
+            if update_time {
+                log::warn!(
+                    "{}: Camera does not support getting or setting its time, not syncing it",
+                    name
+                );
+            }
+            return Ok(());
+        }
+        cam_time => cam_time?,
+    };
     let mut update = false;
     if let Some(time) = cam_time {
         log::info!("{}: Camera time is already set: {}", name, time);
@@ -205,6 +478,15 @@ async fn update_camera_time(camera: &BcCamera, name: &str, update_time: bool) ->
         use std::time::SystemTime;
         let new_time = SystemTime::now();
 
+        if let Some(time) = cam_time {
+            let host_secs = new_time.duration_since(SystemTime::UNIX_EPOCH)?.as_secs() as i64;
+            let drift = time.unix_timestamp() - host_secs;
+            log::info!(
+                "{}: Camera clock is {}, correcting it",
+                name,
+                describe_drift(drift)
+            );
+        }
         log::info!("{}: Setting time to {:?}", name, new_time);
         match camera.set_time(new_time.into()).await {
             Ok(_) => {
@@ -213,6 +495,12 @@ async fn update_camera_time(camera: &BcCamera, name: &str, update_time: bool) ->
                     log::info!("{}: Camera time is now set: {}", name, time);
                 }
             }
+            Err(neolink_core::Error::MissingAbility { .. }) => {
+                log::warn!(
+                    "{}: Camera does not support setting its time, not syncing it",
+                    name
+                );
+            }
             Err(e) => {
                 log::error!(
                     "{}: Camera did not accept new time (is user an admin?): Error: {:?}",
@@ -224,3 +512,104 @@ async fn update_camera_time(camera: &BcCamera, name: &str, update_time: bool) ->
     }
     Ok(())
 }
+
+/// How far the camera clock is from the host, `drift` is camera minus host in seconds
+fn describe_drift(drift: i64) -> String {
+    let secs = drift.unsigned_abs();
+    let amount = match secs {
+        0 => return "in sync".to_string(),
+        1..=59 => format!("{}s", secs),
+        60..=3599 => format!("{}m {}s", secs / 60, secs % 60),
+        _ => format!("{}h {}m", secs / 3600, secs / 60 % 60),
+    };
+    if drift > 0 {
+        format!("{} ahead", amount)
+    } else {
+        format!("{} behind", amount)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_describe_drift() {
+        assert_eq!(describe_drift(0), "in sync");
+        assert_eq!(describe_drift(42), "42s ahead");
+        assert_eq!(describe_drift(-125), "2m 5s behind");
+        assert_eq!(describe_drift(7260), "2h 1m ahead");
+    }
+
+    #[test]
+    fn test_failure_kind() {
+        let login = anyhow::Error::from(neolink_core::Error::CameraLoginFail)
+            .context("Failed to login to camera Garage at Address: 192.168.1.10");
+        assert_eq!(FailureKind::of(&login), FailureKind::Login);
+
+        let timeout = anyhow!("Timed out after 15s while logging in");
+        assert_eq!(FailureKind::of(&timeout), FailureKind::Retry);
+
+        let dropped = anyhow::Error::from(neolink_core::Error::DroppedConnection);
+        assert_eq!(FailureKind::of(&dropped), FailureKind::Retry);
+
+        let keepalive = anyhow!("Camera did not answer the keepalive ping within 5s");
+        assert_eq!(FailureKind::of(&keepalive), FailureKind::Retry);
+    }
+
+    #[test]
+    fn test_failure_window() {
+        let window = Duration::from_secs(60);
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        let mut failures = FailureWindow::default();
+        assert_eq!(failures.record(at(0), window), 1);
+        assert_eq!(failures.record(at(10), window), 2);
+        assert_eq!(failures.record(at(60), window), 3);
+        // The first two have aged out
+        assert_eq!(failures.record(at(71), window), 2);
+        // An occasional blip stays at one
+        assert_eq!(failures.record(at(200), window), 1);
+        assert_eq!(failures.record(at(400), window), 1);
+    }
+
+    #[test]
+    fn test_backoff_bounds() {
+        let min = Duration::from_millis(300);
+        let max = Duration::from_secs(2);
+        let mut backoff = Backoff::with_rng(min, max, SmallRng::seed_from_u64(0));
+        let mut expected = vec![
+            Duration::from_millis(300),
+            Duration::from_millis(600),
+            Duration::from_millis(1200),
+            Duration::from_secs(2),
+            Duration::from_secs(2),
+            Duration::from_secs(2),
+        ];
+        for expected in expected.drain(..) {
+            assert_eq!(backoff.current, expected);
+            let delay = backoff.next_delay();
+            assert!(delay >= expected / 2);
+            assert!(delay <= max);
+        }
+
+        backoff.reset();
+        assert_eq!(backoff.current, min);
+
+        backoff.next_delay();
+        // A short run keeps doubling, one that outlasted the longest delay starts over
+        backoff.reset_after(Duration::from_secs(1));
+        assert_eq!(backoff.current, min * 2);
+        backoff.reset_after(Duration::from_secs(3));
+        assert_eq!(backoff.current, min);
+    }
+
+    #[test]
+    fn test_backoff_jitter() {
+        let min = Duration::from_millis(50);
+        let max = Duration::from_secs(5);
+        let mut first = Backoff::with_rng(min, max, SmallRng::seed_from_u64(1));
+        let mut second = Backoff::with_rng(min, max, SmallRng::seed_from_u64(2));
+        assert_ne!(first.next_delay(), second.next_delay());
+    }
+}
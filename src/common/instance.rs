@@ -6,7 +6,10 @@
 //! whenever the camera is lost/updated
 use anyhow::{anyhow, Context};
 use futures::TryFutureExt;
-use std::sync::{Arc, Weak};
+use std::{
+    collections::HashSet,
+    sync::{Arc, Weak},
+};
 use tokio::{
     sync::{
         mpsc::Sender as MpscSender, oneshot::channel as oneshot, watch::channel as watch,
@@ -16,7 +19,10 @@ use tokio::{
 };
 use tokio_util::sync::CancellationToken;
 
-use super::{MdState, NeoCamCommand, NeoCamThreadState, Permit, PushNoti, StreamInstance};
+use super::{
+    CameraVersion, ConnectionFailures, ForcePause, MdState, NeoCamCommand, NeoCamThreadState,
+    Permit, PushNoti, StreamInstance,
+};
 use crate::{config::CameraConfig, AnyResult, Result};
 use neolink_core::bc_protocol::{BcCamera, StreamKind};
 
@@ -261,6 +267,15 @@ impl NeoInstance {
         Ok(instance_rx.await?)
     }
 
+    /// The streams that are currently being pulled from the camera
+    pub(crate) async fn active_streams(&self) -> Result<WatchReceiver<HashSet<StreamKind>>> {
+        let (instance_tx, instance_rx) = oneshot();
+        self.camera_control
+            .send(NeoCamCommand::ActiveStreams(instance_tx))
+            .await?;
+        Ok(instance_rx.await?)
+    }
+
     pub(crate) async fn push_notifications(&self) -> Result<WatchReceiver<Option<PushNoti>>> {
         let uid = self
             .run_task(|cam| Box::pin(async move { Ok(cam.uid().await?) }))
@@ -313,6 +328,43 @@ impl NeoInstance {
         Ok(instance_rx.await?)
     }
 
+    /// The number of times the connection to the camera was lost and retried
+    pub(crate) async fn connection_failures(&self) -> Result<WatchReceiver<ConnectionFailures>> {
+        let (instance_tx, instance_rx) = oneshot();
+        self.camera_control
+            .send(NeoCamCommand::Failures(instance_tx))
+            .await?;
+        Ok(instance_rx.await?)
+    }
+
+    /// The model and firmware of the camera as it reported them on the last connection
+    pub(crate) async fn camera_version(&self) -> Result<WatchReceiver<CameraVersion>> {
+        let (instance_tx, instance_rx) = oneshot();
+        self.camera_control
+            .send(NeoCamCommand::Version(instance_tx))
+            .await?;
+        Ok(instance_rx.await?)
+    }
+
+    /// The pause state forced over the control channel, `None` while it is automatic
+    pub(crate) async fn force_pause(&self) -> Result<WatchReceiver<Option<ForcePause>>> {
+        let (instance_tx, instance_rx) = oneshot();
+        self.camera_control
+            .send(NeoCamCommand::ForcePause(instance_tx))
+            .await?;
+        Ok(instance_rx.await?)
+    }
+
+    /// Pauses or resumes the streams whatever their `[pause]` says until it is
+    /// set back to `None`
+    pub(crate) async fn set_force_pause(&self, force: Option<ForcePause>) -> Result<()> {
+        let (instance_tx, instance_rx) = oneshot();
+        self.camera_control
+            .send(NeoCamCommand::SetForcePause(force, instance_tx))
+            .await?;
+        Ok(instance_rx.await?)
+    }
+
     pub(crate) fn camera(&self) -> WatchReceiver<Weak<BcCamera>> {
         self.camera_watch.clone()
     }
@@ -325,6 +377,49 @@ impl NeoInstance {
         Ok(instance_rx.await?)
     }
 
+    /// Drops the current connection to the camera and retries it
+    pub(crate) async fn reconnect(&self) -> Result<()> {
+        let (instance_tx, instance_rx) = oneshot();
+        self.camera_control
+            .send(NeoCamCommand::Reconnect(instance_tx))
+            .await?;
+        Ok(instance_rx.await?)
+    }
+
+    /// Drops the current connection to the camera and waits for it to connect again
+    ///
+    /// Unlike [`NeoInstance::reconnect`] this is not counted as a failure and
+    /// it connects again without a delay
+    pub(crate) async fn restart(&self, wait: Duration) -> Result<()> {
+        let mut camera_watch = self.camera_watch.clone();
+        let old = camera_watch.borrow_and_update().clone();
+        let failures = self.connection_failures().await?;
+        let (instance_tx, instance_rx) = oneshot();
+        self.camera_control
+            .send(NeoCamCommand::Restart(instance_tx))
+            .await?;
+        instance_rx.await?;
+
+        let connected = tokio::time::timeout(
+            wait,
+            camera_watch
+                .wait_for(|camera| !Weak::ptr_eq(camera, &old) && camera.upgrade().is_some()),
+        )
+        .await;
+        match connected {
+            Ok(v) => {
+                v?;
+                Ok(())
+            }
+            Err(_) => match failures.borrow().last_error.as_ref() {
+                Some(e) => Err(anyhow!(
+                    "Camera did not connect again within {wait:?}, last error: {e}"
+                )),
+                None => Err(anyhow!("Camera did not connect again within {wait:?}")),
+            },
+        }
+    }
+
     pub(crate) async fn disconnect(&self) -> Result<()> {
         let (instance_tx, instance_rx) = oneshot();
         self.camera_control
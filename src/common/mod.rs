@@ -2,6 +2,7 @@ mod camthread;
 mod instance;
 mod mdthread;
 mod neocam;
+mod paramsets;
 mod pushnoti;
 mod reactor;
 mod streamthread;
@@ -11,6 +12,7 @@ pub(crate) use camthread::*;
 pub(crate) use instance::*;
 pub(crate) use mdthread::*;
 pub(crate) use neocam::*;
+pub(crate) use paramsets::*;
 pub(crate) use pushnoti::*;
 pub(crate) use reactor::*;
 pub(crate) use streamthread::*;
@@ -6,7 +6,10 @@
 //!    Clonable interface to share amongst threadsanyhow::anyhow;
 use anyhow::Context;
 use futures::{stream::StreamExt, TryFutureExt};
-use std::sync::Weak;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Weak,
+};
 use tokio::{
     sync::{
         mpsc::{channel as mpsc, Sender as MpscSender},
@@ -20,11 +23,15 @@ use tokio_stream::wrappers::ReceiverStream;
 use tokio_util::sync::CancellationToken;
 
 use super::{
-    MdRequest, MdState, NeoCamMdThread, NeoCamStreamThread, NeoCamThread, NeoCamThreadState,
-    NeoInstance, Permit, PnRequest, PushNoti, StreamInstance, StreamRequest, UseCounter,
+    ConnectionFailures, MdRequest, MdState, NeoCamMdThread, NeoCamStreamThread, NeoCamThread,
+    NeoCamThreadState, NeoInstance, Permit, PnRequest, PushNoti, StreamInstance, StreamRequest,
+    UseCounter,
 };
 use crate::{config::CameraConfig, AnyResult, Result};
-use neolink_core::bc_protocol::{BcCamera, StreamKind};
+use neolink_core::{
+    bc::xml::VersionInfo,
+    bc_protocol::{BcCamera, StreamKind},
+};
 
 #[allow(dead_code)]
 pub(crate) enum NeoCamCommand {
@@ -34,14 +41,65 @@ pub(crate) enum NeoCamCommand {
     HighStream(OneshotSender<Option<StreamInstance>>),
     LowStream(OneshotSender<Option<StreamInstance>>),
     Streams(OneshotSender<Vec<StreamInstance>>),
+    ActiveStreams(OneshotSender<WatchReceiver<HashSet<StreamKind>>>),
     Motion(OneshotSender<WatchReceiver<MdState>>),
     Config(OneshotSender<WatchReceiver<CameraConfig>>),
     Disconnect(OneshotSender<()>),
     Connect(OneshotSender<()>),
+    Reconnect(OneshotSender<()>),
+    Restart(OneshotSender<()>),
     State(OneshotSender<NeoCamThreadState>),
     GetPermit(OneshotSender<Permit>),
     PushNoti(OneshotSender<WatchReceiver<Option<PushNoti>>>),
+    Failures(OneshotSender<WatchReceiver<ConnectionFailures>>),
+    ForcePause(OneshotSender<WatchReceiver<Option<ForcePause>>>),
+    SetForcePause(Option<ForcePause>, OneshotSender<()>),
+    Version(OneshotSender<WatchReceiver<CameraVersion>>),
+}
+
+/// A pause state forced over the control channel that overrides the `[pause]` of the camera
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub(crate) enum ForcePause {
+    Pause,
+    Resume,
+}
+
+/// What the camera says it is once logged in, `None` until then or if it does
+/// not say
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub(crate) struct CameraVersion {
+    pub(crate) model: Option<String>,
+    pub(crate) firmware: Option<String>,
+}
+
+impl CameraVersion {
+    fn new(info: &VersionInfo) -> Self {
+        let known = |value: &str| Some(value.to_string()).filter(|value| !value.is_empty());
+        Self {
+            model: info.model.as_deref().and_then(known),
+            firmware: known(&info.firmwareVersion),
+        }
+    }
+
+    /// The minimum firmware of the model if this firmware is older than it
+    fn below<'a>(&self, min_firmware: &'a HashMap<String, String>) -> Option<&'a str> {
+        let min = min_firmware.get(self.model.as_ref()?)?;
+        let firmware = firmware_numbers(self.firmware.as_ref()?);
+        let min_numbers = firmware_numbers(min);
+        (!firmware.is_empty() && !min_numbers.is_empty() && firmware < min_numbers)
+            .then_some(min.as_str())
+    }
+}
+
+/// The numbers of a firmware version in order, e.g. `v3.0.0.2356_23062000`
+/// is `[3, 0, 0, 2356, 23062000]`
+fn firmware_numbers(firmware: &str) -> Vec<u64> {
+    firmware
+        .split(|c: char| !c.is_ascii_digit())
+        .filter_map(|number| number.parse().ok())
+        .collect()
 }
+
 /// The underlying camera binding
 pub(crate) struct NeoCam {
     cancel: CancellationToken,
@@ -62,6 +120,11 @@ impl NeoCam {
         let (stream_request_tx, stream_request_rx) = mpsc(100);
         let (md_request_tx, md_request_rx) = mpsc(100);
         let (state_tx, state_rx) = watch(NeoCamThreadState::Connected);
+        let (failures_tx, failures_rx) = watch(ConnectionFailures::default());
+        let (reconnect_tx, reconnect_rx) = watch(0u64);
+        let (restart_tx, restart_rx) = watch(0u64);
+        let (force_pause_tx, force_pause_rx) = watch(None);
+        let (version_tx, version_rx) = watch(CameraVersion::default());
 
         let set = JoinSet::new();
         let users = UseCounter::new().await;
@@ -138,6 +201,13 @@ impl NeoCam {
                                     }
                                 ).await?;
                             },
+                            NeoCamCommand::ActiveStreams(sender) => {
+                                stream_request_tx.send(
+                                    StreamRequest::Active {
+                                        sender,
+                                    }
+                                ).await?;
+                            },
                             NeoCamCommand::Motion(sender) => {
                                 md_request_tx.send(
                                     MdRequest::Get {
@@ -155,6 +225,16 @@ impl NeoCam {
                                 }
                                 let _ = sender.send(());
                             }
+                            NeoCamCommand::Reconnect(sender) => {
+                                reconnect_tx.send_modify(|requests| *requests += 1);
+                                log::debug!("{}: Reconnect On Request", thread_watch_config_rx.borrow().name);
+                                let _ = sender.send(());
+                            }
+                            NeoCamCommand::Restart(sender) => {
+                                restart_tx.send_modify(|requests| *requests += 1);
+                                log::debug!("{}: Restart On Request", thread_watch_config_rx.borrow().name);
+                                let _ = sender.send(());
+                            }
                             NeoCamCommand::Disconnect(sender) => {
                                 if !matches!(*state_tx.borrow(), NeoCamThreadState::Disconnected) {
                                     state_tx.send_replace(NeoCamThreadState::Disconnected);
@@ -175,6 +255,20 @@ impl NeoCam {
                                     }
                                 ).await?;
                             },
+                            NeoCamCommand::Failures(sender) => {
+                                let _ = sender.send(failures_rx.clone());
+                            },
+                            NeoCamCommand::ForcePause(sender) => {
+                                let _ = sender.send(force_pause_rx.clone());
+                            },
+                            NeoCamCommand::SetForcePause(force, sender) => {
+                                force_pause_tx.send_replace(force);
+                                log::debug!("{}: Force pause {:?} On Request", thread_watch_config_rx.borrow().name, force);
+                                let _ = sender.send(());
+                            },
+                            NeoCamCommand::Version(sender) => {
+                                let _ = sender.send(version_rx.clone());
+                            },
                         }
                     }
                     log::debug!("Control thread Senders dropped");
@@ -203,6 +297,9 @@ impl NeoCam {
             state_rx,
             thread_watch_config_rx,
             camera_watch_tx,
+            failures_tx,
+            reconnect_rx,
+            restart_rx,
             me.cancel.clone(),
         )
         .await;
@@ -240,37 +337,66 @@ impl NeoCam {
             }
         });
 
-        // This thread just does a one time report on camera info
+        // This thread reports the camera info, the version is asked again on
+        // each connection since a firmware update reboots the camera
         let report_instance = instance.subscribe().await?;
         let report_cancel = me.cancel.clone();
-        let report_name = config.name.clone();
+        let report_config = watch_config_rx.clone();
+        let mut report_camera = me.camera_watch.clone();
         me.set.spawn(async move {
             tokio::select! {
                 _ = report_cancel.cancelled() => {
                     AnyResult::Ok(())
                 }
                 v = async {
-                    let version = report_instance.run_task(|cam| Box::pin(
-                        async move {
-                            Ok(cam.version().await?)
+                    let report_name = report_config.borrow().name.clone();
+                    let mut first = true;
+                    loop {
+                        report_camera.borrow_and_update();
+                        let version = match report_instance.run_task(|cam| Box::pin(
+                            async move {
+                                Ok(cam.version().await?)
+                            }
+                        )).await {
+                            Ok(version) => CameraVersion::new(&version),
+                            Err(e) => {
+                                log::debug!("{}: Could not get the version: {:?}", report_name, e);
+                                CameraVersion::default()
+                            }
+                        };
+                        if version != *version_tx.borrow() || first {
+                            log::info!("{}: Model {}", report_name, version.model.as_deref().unwrap_or("Undeclared"));
+                            log::info!("{}: Firmware Version {}", report_name, version.firmware.as_deref().unwrap_or("Unknown"));
+                            if let Some(min) = version.below(&report_config.borrow().min_firmware) {
+                                log::warn!(
+                                    "{}: Firmware {} is older than the {} set in min_firmware for the {}, consider updating the camera",
+                                    report_name,
+                                    version.firmware.as_deref().unwrap_or_default(),
+                                    min,
+                                    version.model.as_deref().unwrap_or_default(),
+                                );
+                            }
                         }
-                    )).await?;
-                    log::info!("{}: Model {}", report_name, version.model.unwrap_or("Undeclared".to_string()));
-                    log::info!("{}: Firmware Version {}", report_name, version.firmwareVersion);
+                        version_tx.send_replace(version);
 
-                    let stream_info = report_instance.run_task(|cam| Box::pin(
-                        async move {
-                            Ok(cam.get_stream_info().await?)
+                        if first {
+                            first = false;
+                            let stream_info = report_instance.run_task(|cam| Box::pin(
+                                async move {
+                                    Ok(cam.get_stream_info().await?)
+                                }
+                            )).await?;
+                            let mut supported_streams = vec![];
+                            for encode in stream_info.stream_infos.iter().flat_map(|stream_info| stream_info.encode_tables.clone()) {
+                                supported_streams.push(std::format!("    {}: {}x{}", encode.name, encode.resolution.width, encode.resolution.height));
+                            }
+                            log::debug!("{}: Listing Camera Supported Streams\n{}", report_name, supported_streams.join("\n"));
                         }
-                    )).await?;
-                    let mut supported_streams = vec![];
-                    for encode in stream_info.stream_infos.iter().flat_map(|stream_info| stream_info.encode_tables.clone()) {
-                        supported_streams.push(std::format!("    {}: {}x{}", encode.name, encode.resolution.width, encode.resolution.height));
-                    }
-                    log::debug!("{}: Listing Camera Supported Streams\n{}", report_name, supported_streams.join("\n"));
-
 
-                    Ok(())
+                        // Wait for the next connection
+                        report_camera.changed().await?;
+                        report_camera.wait_for(|camera| camera.upgrade().is_some()).await?;
+                    }
                 } => v
             }
         });
@@ -425,8 +551,16 @@ impl NeoCam {
         )
     }
 
+    /// Cameras whose config is unchanged are not notified so they keep running
     pub(crate) async fn update_config(&self, config: CameraConfig) -> Result<()> {
-        self.config_watch.send_replace(config);
+        self.config_watch.send_if_modified(|old| {
+            if *old != config {
+                *old = config;
+                true
+            } else {
+                false
+            }
+        });
         Ok(())
     }
 }
@@ -444,3 +578,37 @@ impl Drop for NeoCam {
         });
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_firmware_below() {
+        let min_firmware =
+            HashMap::from([("RLC-510A".to_string(), "v3.0.0.2356_23062000".to_string())]);
+        let version = |model: &str, firmware: &str| CameraVersion {
+            model: Some(model.to_string()),
+            firmware: Some(firmware.to_string()),
+        };
+        assert_eq!(
+            version("RLC-510A", "v3.0.0.136_20121102").below(&min_firmware),
+            Some("v3.0.0.2356_23062000")
+        );
+        assert_eq!(
+            version("RLC-510A", "v3.0.0.2356_23062000").below(&min_firmware),
+            None
+        );
+        assert_eq!(
+            version("RLC-510A", "v3.1.0.956_22041503").below(&min_firmware),
+            None
+        );
+        // Other models, firmware that cannot be read and cameras that do not say
+        assert_eq!(
+            version("E1 Zoom", "v3.0.0.136_20121102").below(&min_firmware),
+            None
+        );
+        assert_eq!(version("RLC-510A", "unknown").below(&min_firmware), None);
+        assert_eq!(CameraVersion::default().below(&min_firmware), None);
+    }
+}
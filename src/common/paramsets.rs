@@ -0,0 +1,142 @@
+//! Keeps the parameter sets of an H265 stream
+//!
+//! Reolink cameras only send the VPS, SPS and PPS with the first keyframe of
+//! a stream. A client that joins later starts at a keyframe without them and
+//! shows a green screen, so they are kept here to be sent again in front of
+//! that keyframe
+
+const START_CODE: [u8; 4] = [0, 0, 0, 1];
+
+const VPS: u8 = 32;
+const SPS: u8 = 33;
+const PPS: u8 = 34;
+
+/// The latest VPS, SPS and PPS seen in an H265 stream
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct ParameterSets {
+    vps: Option<Vec<u8>>,
+    sps: Option<Vec<u8>>,
+    pps: Option<Vec<u8>>,
+}
+
+impl ParameterSets {
+    /// Keeps the parameter sets of an annex b frame, true when any changed
+    pub(crate) fn update(&mut self, frame: &[u8]) -> bool {
+        let mut changed = false;
+        for nal in nal_units(frame) {
+            let slot = match nal_type(nal) {
+                Some(VPS) => &mut self.vps,
+                Some(SPS) => &mut self.sps,
+                Some(PPS) => &mut self.pps,
+                _ => continue,
+            };
+            if slot.as_deref() != Some(nal) {
+                *slot = Some(nal.to_vec());
+                changed = true;
+            }
+        }
+        changed
+    }
+
+    /// The frame with the parameter sets in front of it
+    ///
+    /// None when the frame already has all three or not all of them have been
+    /// seen yet
+    pub(crate) fn prepend_to(&self, frame: &[u8]) -> Option<Vec<u8>> {
+        let (Some(vps), Some(sps), Some(pps)) = (&self.vps, &self.sps, &self.pps) else {
+            return None;
+        };
+        let mut has = [false; 3];
+        for nal in nal_units(frame) {
+            match nal_type(nal) {
+                Some(VPS) => has[0] = true,
+                Some(SPS) => has[1] = true,
+                Some(PPS) => has[2] = true,
+                _ => {}
+            }
+        }
+        if has.iter().all(|has| *has) {
+            return None;
+        }
+        let mut out = Vec::with_capacity(
+            3 * START_CODE.len() + vps.len() + sps.len() + pps.len() + frame.len(),
+        );
+        for nal in [vps, sps, pps] {
+            out.extend_from_slice(&START_CODE);
+            out.extend_from_slice(nal);
+        }
+        out.extend_from_slice(frame);
+        Some(out)
+    }
+}
+
+fn nal_type(nal: &[u8]) -> Option<u8> {
+    nal.first().map(|header| (header >> 1) & 0x3f)
+}
+
+/// The nal units of an annex b frame without their start codes
+fn nal_units(frame: &[u8]) -> impl Iterator<Item = &[u8]> {
+    let mut starts = vec![];
+    let mut i = 0;
+    while i + 3 <= frame.len() {
+        if frame[i..i + 3] == [0, 0, 1] {
+            starts.push(i + 3);
+            i += 3;
+        } else {
+            i += 1;
+        }
+    }
+    let ends = starts
+        .iter()
+        .skip(1)
+        .map(|start| {
+            // Either the 3 or the 4 byte start code of the next unit
+            let end = start - 3;
+            if end > 0 && frame[end - 1] == 0 {
+                end - 1
+            } else {
+                end
+            }
+        })
+        .chain(std::iter::once(frame.len()))
+        .collect::<Vec<_>>();
+    starts
+        .into_iter()
+        .zip(ends)
+        .map(move |(start, end)| &frame[start..end])
+        .filter(|nal| !nal.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn nal(kind: u8, body: &[u8]) -> Vec<u8> {
+        let mut nal = START_CODE.to_vec();
+        nal.extend_from_slice(&[kind << 1, 1]);
+        nal.extend_from_slice(body);
+        nal
+    }
+
+    #[test]
+    fn test_parameter_sets() {
+        let idr = nal(19, &[9, 9, 9]);
+        let first = [nal(VPS, &[1]), nal(SPS, &[2]), nal(PPS, &[3]), idr.clone()].concat();
+
+        let mut sets = ParameterSets::default();
+        assert_eq!(sets.prepend_to(&idr), None);
+        assert!(sets.update(&first));
+        assert!(!sets.update(&first));
+        // The first keyframe already has them
+        assert_eq!(sets.prepend_to(&first), None);
+        // A later one is given them
+        assert_eq!(sets.prepend_to(&idr), Some(first.clone()));
+
+        // A new SPS replaces the old one
+        assert!(sets.update(&[&nal(SPS, &[4])[1..], &idr].concat()));
+        assert_eq!(
+            sets.prepend_to(&idr),
+            Some([nal(VPS, &[1]), nal(SPS, &[4]), nal(PPS, &[3]), idr].concat())
+        );
+    }
+}
@@ -5,7 +5,7 @@
 
 use futures::stream::{FuturesUnordered, StreamExt};
 use std::{
-    collections::{hash_map::Entry, HashMap, VecDeque},
+    collections::{hash_map::Entry, HashMap, HashSet, VecDeque},
     sync::Arc,
 };
 use tokio::{
@@ -22,7 +22,7 @@ use tokio::{
 };
 use tokio_util::sync::CancellationToken;
 
-use super::{NeoInstance, Permit, UseCounter};
+use super::{NeoInstance, ParameterSets, Permit, UseCounter};
 use crate::{AnyResult, Result};
 use neolink_core::{bc_protocol::StreamKind, bcmedia::model::*};
 
@@ -31,6 +31,7 @@ pub(crate) struct NeoCamStreamThread {
     stream_request_rx: MpscReceiver<StreamRequest>,
     cancel: CancellationToken,
     instance: NeoInstance,
+    active: Arc<WatchSender<HashSet<StreamKind>>>,
 }
 
 impl NeoCamStreamThread {
@@ -43,6 +44,7 @@ impl NeoCamStreamThread {
             stream_request_rx,
             cancel: CancellationToken::new(),
             instance,
+            active: Arc::new(watch(HashSet::new()).0),
         })
     }
     pub(crate) async fn run(&mut self) -> Result<()> {
@@ -82,6 +84,7 @@ impl NeoCamStreamThread {
                                         name,
                                         self.instance.subscribe().await?,
                                         strict,
+                                        self.active.clone(),
                                     ).await?;
                                     let data = vac.insert(data);
 
@@ -99,13 +102,13 @@ impl NeoCamStreamThread {
                                 StreamKind::Sub,
                             ];
                             let config = self.instance.config().await?.borrow().clone();
-                            let config_streams = config.stream.as_stream_kinds();
+                            let config_streams = config.stream_kinds();
                             for name in streams.drain(..) {
                                 if config_streams.contains(&name) {
                                     // Fill it in
                                     if let Entry::Vacant(vac) = self.streams.entry(name) {
                                         vac.insert(
-                                            StreamData::new(name, self.instance.subscribe().await?, config.strict)
+                                            StreamData::new(name, self.instance.subscribe().await?, config.strict, self.active.clone())
                                                 .await?,
                                         );
                                     }
@@ -130,13 +133,13 @@ impl NeoCamStreamThread {
                                 StreamKind::Main,
                             ];
                             let config = self.instance.config().await?.borrow().clone();
-                            let config_streams = config.stream.as_stream_kinds();
+                            let config_streams = config.stream_kinds();
                             for name in streams.drain(..) {
                                 if config_streams.contains(&name) {
                                     // Fill it in
                                     if let Entry::Vacant(vac) = self.streams.entry(name) {
                                         vac.insert(
-                                            StreamData::new(name, self.instance.subscribe().await?, config.strict)
+                                            StreamData::new(name, self.instance.subscribe().await?, config.strict, self.active.clone())
                                                 .await?,
                                         );
                                     }
@@ -156,11 +159,11 @@ impl NeoCamStreamThread {
                             sender
                         } => {
                             let config = self.instance.config().await?.borrow_and_update().clone();
-                            let streams = config.stream.as_stream_kinds();
+                            let streams = config.stream_kinds();
                             for stream in streams.iter().copied() {
                                 if let Entry::Vacant(vac) = self.streams.entry(stream) {
                                     vac.insert(
-                                        StreamData::new(stream, self.instance.subscribe().await?, config.strict)
+                                        StreamData::new(stream, self.instance.subscribe().await?, config.strict, self.active.clone())
                                             .await?,
                                     );
                                 }
@@ -175,6 +178,11 @@ impl NeoCamStreamThread {
                             ).collect::<FuturesUnordered<_>>().collect::<Vec<_>>().await;
                             let _ = sender.send(streams.drain(..).flatten().collect());
                         }
+                        StreamRequest::Active {
+                            sender
+                        } => {
+                            let _ = sender.send(self.active.subscribe());
+                        }
                     }
                 }
                 Ok(())
@@ -221,6 +229,10 @@ pub(crate) enum StreamRequest {
     All {
         sender: OneshotSender<Vec<StreamInstance>>,
     },
+    /// Get the streams that are currently being pulled from the camera
+    Active {
+        sender: OneshotSender<WatchReceiver<HashSet<StreamKind>>>,
+    },
 }
 
 /// The data of a running stream
@@ -229,7 +241,9 @@ pub(crate) struct StreamData {
     aud: BroadcastSender<StampedData>,
     vid_history: Arc<WatchSender<VecDeque<StampedData>>>,
     aud_history: Arc<WatchSender<VecDeque<StampedData>>>,
+    param_sets: Arc<WatchSender<ParameterSets>>,
     config: Arc<WatchSender<StreamConfig>>,
+    failures: Arc<WatchSender<u32>>,
     name: StreamKind,
     instance: NeoInstance,
     cancel: CancellationToken,
@@ -281,13 +295,16 @@ pub(crate) struct StampedData {
 }
 
 pub(crate) struct StreamInstance {
-    #[allow(dead_code)]
     pub(crate) name: StreamKind,
     pub(crate) vid: BroadcastReceiver<StampedData>,
     pub(crate) vid_history: WatchReceiver<VecDeque<StampedData>>,
     pub(crate) aud: BroadcastReceiver<StampedData>,
     pub(crate) aud_history: WatchReceiver<VecDeque<StampedData>>,
+    /// The latest parameter sets of an H265 stream
+    pub(crate) param_sets: WatchReceiver<ParameterSets>,
     pub(crate) config: WatchReceiver<StreamConfig>,
+    /// How many times in a row the stream failed before sending a keyframe
+    pub(crate) failures: WatchReceiver<u32>,
     in_use: Permit,
 }
 
@@ -299,7 +316,9 @@ impl StreamInstance {
             vid_history: data.vid_history.subscribe(),
             aud: data.aud.subscribe(),
             aud_history: data.aud_history.subscribe(),
+            param_sets: data.param_sets.subscribe(),
             config: data.config.subscribe(),
+            failures: data.failures.subscribe(),
             in_use: data.users.create_activated().await?,
         })
     }
@@ -316,8 +335,12 @@ impl StreamInstance {
 }
 
 impl StreamData {
-    async fn new(name: StreamKind, instance: NeoInstance, strict: bool) -> Result<Self> {
-        const BUFFER_DURATION: Duration = Duration::from_secs(15);
+    async fn new(
+        name: StreamKind,
+        instance: NeoInstance,
+        strict: bool,
+        active: Arc<WatchSender<HashSet<StreamKind>>>,
+    ) -> Result<Self> {
         // At 30fps for 15s with audio is is about 900 frames
         // Therefore we set this buffer to a rather large 2000
         let (vid, _) = broadcast::<StampedData>(2000);
@@ -326,6 +349,8 @@ impl StreamData {
         let vid_history = Arc::new(vid_history);
         let (aud_history, _) = watch::<VecDeque<StampedData>>(VecDeque::new());
         let aud_history = Arc::new(aud_history);
+        let (param_sets, _) = watch(ParameterSets::default());
+        let param_sets = Arc::new(param_sets);
         let (resolution, bitrate, fps, fps_table) = instance
             .run_passive_task(|cam| {
                 Box::pin(async move {
@@ -367,14 +392,17 @@ impl StreamData {
             bitrate,
             fps,
         });
+        let (failures_tx, _) = watch(0u32);
         let mut me = Self {
             name,
             cancel: CancellationToken::new(),
             config: Arc::new(config_tx),
+            failures: Arc::new(failures_tx),
             vid,
             vid_history,
             aud,
             aud_history,
+            param_sets,
             instance,
             handle: None,
             strict,
@@ -386,13 +414,16 @@ impl StreamData {
         let aud = me.aud.clone();
         let instance = me.instance.subscribe().await?;
         let name = me.name;
-        let cam_name = instance.config().await?.borrow().name.clone();
+        let cam_config = instance.config().await?;
+        let cam_name = cam_config.borrow().name.clone();
         let print_name = format!("{cam_name}::{name}");
         let strict = me.strict;
         let config = me.config.clone();
         let thread_inuse = me.users.create_deactivated().await?;
         let vid_history = me.vid_history.clone();
         let aud_history = me.aud_history.clone();
+        let param_sets = me.param_sets.clone();
+        let failures = me.failures.clone();
         let mut permit = instance.permit().await?;
         me.handle = Some(tokio::task::spawn(async move {
             let r = tokio::select! {
@@ -401,6 +432,7 @@ impl StreamData {
                 },
                 v = async {
                     loop {
+                        active.send_if_modified(|active| active.insert(name));
                         let (watchdog_tx, mut watchdog_rx) = mpsc(1);
                         let (watchdog_eat_tx, watchdog_eat_rx) = oneshot();
                         // Give the watchdog his own thread to play in
@@ -408,8 +440,12 @@ impl StreamData {
                         // This should stop one branch of the select from waking the other
                         // too often
                         let watchdog_print_name = print_name.clone();
+                        let stall_timeout = Duration::from_secs(cam_config.borrow().stall_timeout_secs);
+                        let buffer_duration = Duration::from_millis(cam_config.borrow().buffer_duration_ms);
+                        let keep_gop = cam_config.borrow().fast_start;
                         tokio::task::spawn(async move {
                             let mut check_timeout = timeout(Duration::from_secs(15), watchdog_rx.recv()).await; // Wait longer for the first feed
+                            let mut fed = false;
                             loop {
                                 match check_timeout {
                                     Err(_) => {
@@ -424,18 +460,20 @@ impl StreamData {
                                     }
                                     Ok(_) => {
                                         // log::debug!("{print_name}: Good Doggo");
-                                        check_timeout = timeout(Duration::from_secs(10), watchdog_rx.recv()).await;
+                                        fed = true;
+                                        check_timeout = timeout(stall_timeout, watchdog_rx.recv()).await;
                                     }
                                 }
                             }
                             // Watch dog is hungry send the kill to the stream thread
-                            let _ = watchdog_eat_tx.send(());
+                            let _ = watchdog_eat_tx.send(fed);
                         }) ;
 
                         tokio::select! {
                             v = thread_inuse.dropped_users() => {
                                 // Handles the stop and restart when no active users
                                 log::debug!("{print_name}: Streaming STOP");
+                                active.send_if_modified(|active| active.remove(&name));
                                 permit.deactivate().await?;
                                 v?;
                                 thread_inuse.aquired_users().await?; // Wait for new users of the stream
@@ -443,7 +481,13 @@ impl StreamData {
                                 log::debug!("{print_name}: Streaming START");
                                 AnyResult::Ok(())
                             },
-                            _ = watchdog_eat_rx => {
+                            fed = watchdog_eat_rx => {
+                                failures.send_modify(|count| *count += 1);
+                                if let Ok(true) = fed {
+                                    // The camera is still connected but has stopped sending frames
+                                    log::warn!("{print_name}: No frames for {:?}, reconnecting to the camera", stall_timeout);
+                                    instance.reconnect().await?;
+                                }
                                 sleep(Duration::from_secs(1)).await;
                                 AnyResult::Ok(())
                             },
@@ -453,6 +497,8 @@ impl StreamData {
                                     let stream_config = config.clone();
                                     let vid_history = vid_history.clone();
                                     let aud_history = aud_history.clone();
+                                    let param_sets = param_sets.clone();
+                                    let failures = failures.clone();
                                     let watchdog_tx = watchdog_tx.clone();
                                     let fps_table = fps_table.clone();
                                     let print_name = print_name.clone();
@@ -556,8 +602,11 @@ impl StreamData {
                                                 }
 
                                                 match data {
-                                                    BcMedia::Iframe(BcMediaIframe{data, microseconds, ..}) => {
+                                                    BcMedia::Iframe(BcMediaIframe{data, microseconds, video_type, ..}) => {
                                                         prev_ts = Duration::from_micros(microseconds as u64);
+                                                        if matches!(video_type, VideoType::H265) {
+                                                            param_sets.send_if_modified(|sets| sets.update(&data));
+                                                        }
                                                         // log::debug!("IFrame: {prev_ts:?}");
                                                         let d = StampedData{
                                                                 keyframe: true,
@@ -565,13 +614,9 @@ impl StreamData {
                                                                 ts: prev_ts
                                                         };
                                                         let _ = vid_tx.send(d.clone());
-                                                        vid_history.send_modify(|history| {
-                                                           let drop_time = d.ts.saturating_sub(BUFFER_DURATION);
-                                                           history.push_back(d);
-                                                           while history.front().is_some_and(|di| di.ts < drop_time) {
-                                                               history.pop_front();
-                                                           }
-                                                        });
+                                                        vid_history.send_modify(|history| push_history(history, d, buffer_duration, keep_gop));
+                                                        // The stream works again
+                                                        failures.send_if_modified(|count| std::mem::take(count) != 0);
                                                         recieved_iframe = true;
                                                         aud_keyframe = true;
                                                         log::trace!("Sent Vid Key Frame");
@@ -586,13 +631,7 @@ impl StreamData {
                                                             ts: prev_ts
                                                         };
                                                         let _ = vid_tx.send(d.clone());
-                                                        vid_history.send_modify(|history| {
-                                                           let drop_time = d.ts.saturating_sub(BUFFER_DURATION);
-                                                           history.push_back(d);
-                                                           while history.front().is_some_and(|di| di.ts < drop_time) {
-                                                               history.pop_front();
-                                                           }
-                                                        });
+                                                        vid_history.send_modify(|history| push_history(history, d, buffer_duration, keep_gop));
                                                         log::trace!("Sent Vid Frame");
                                                     }
                                                     BcMedia::Aac(BcMediaAac{data, ..}) | BcMedia::Adpcm(BcMediaAdpcm{data,..}) if recieved_iframe => {
@@ -604,13 +643,7 @@ impl StreamData {
                                                         };
                                                         aud_keyframe = false;
                                                         let _ = aud_tx.send(d.clone())?;
-                                                        aud_history.send_modify(|history| {
-                                                           let drop_time = d.ts.saturating_sub(BUFFER_DURATION);
-                                                           history.push_back(d);
-                                                           while history.front().is_some_and(|di| di.ts < drop_time) {
-                                                               history.pop_front();
-                                                           }
-                                                        });
+                                                        aud_history.send_modify(|history| push_history(history, d, buffer_duration, keep_gop));
                                                         log::trace!("Sent Aud Frame");
                                                     },
                                                     _ => {},
@@ -626,6 +659,7 @@ impl StreamData {
                                         break Ok(());
                                     },
                                     Ok(Err(e)) => {
+                                        failures.send_modify(|count| *count += 1);
                                         log::debug!("{print_name}: Video Stream Restarting Due to Error: {:?}", e);
                                         AnyResult::Ok(())
                                     },
@@ -639,6 +673,7 @@ impl StreamData {
                     }
                 } => v,
             };
+            active.send_if_modified(|active| active.remove(&name));
             log::debug!("{print_name}: Stream Thead Stopped: {:?}", r);
             r
         }));
@@ -656,6 +691,41 @@ impl StreamData {
     }
 }
 
+/// Adds a frame to the history and drops the frames that are older than `duration`
+///
+/// With `keep_gop` the latest keyframe and the frames after it are kept even
+/// when they are older, so that there is always a keyframe to start from.
+/// If the timestamps jump back by more than `duration`, as they do when the
+/// camera restarts its clock, the old frames can no longer be compared so they
+/// are all dropped
+fn push_history(
+    history: &mut VecDeque<StampedData>,
+    data: StampedData,
+    duration: Duration,
+    keep_gop: bool,
+) {
+    if history
+        .back()
+        .is_some_and(|last| last.ts > data.ts + duration)
+    {
+        history.clear();
+    }
+    let drop_time = data.ts.saturating_sub(duration);
+    history.push_back(data);
+    let mut droppable = if keep_gop {
+        history
+            .iter()
+            .rposition(|di| di.keyframe)
+            .unwrap_or(history.len())
+    } else {
+        history.len()
+    };
+    while droppable > 0 && history.front().is_some_and(|di| di.ts < drop_time) {
+        history.pop_front();
+        droppable -= 1;
+    }
+}
+
 impl Drop for StreamData {
     fn drop(&mut self) {
         log::trace!("Drop StreamData");
@@ -669,3 +739,60 @@ impl Drop for StreamData {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(ms: u64) -> StampedData {
+        StampedData {
+            keyframe: ms % 4000 == 0,
+            data: Arc::new(vec![]),
+            ts: Duration::from_millis(ms),
+        }
+    }
+
+    fn timestamps(history: &VecDeque<StampedData>) -> Vec<u64> {
+        history.iter().map(|d| d.ts.as_millis() as u64).collect()
+    }
+
+    #[test]
+    fn test_history_drops_old_frames() {
+        let duration = Duration::from_millis(2000);
+        let mut history = VecDeque::new();
+        for ms in (0..=5000).step_by(500) {
+            push_history(&mut history, frame(ms), duration, false);
+        }
+        assert_eq!(timestamps(&history), vec![3000, 3500, 4000, 4500, 5000]);
+    }
+
+    #[test]
+    fn test_history_clears_on_jump() {
+        let duration = Duration::from_millis(2000);
+        let mut history = VecDeque::new();
+        for ms in [10000, 10500, 11000] {
+            push_history(&mut history, frame(ms), duration, false);
+        }
+        // Small steps back are kept
+        push_history(&mut history, frame(10900), duration, false);
+        assert_eq!(history.len(), 4);
+        // The camera restarted its clock
+        push_history(&mut history, frame(100), duration, false);
+        assert_eq!(timestamps(&history), vec![100]);
+    }
+
+    #[test]
+    fn test_history_keeps_gop() {
+        let duration = Duration::from_millis(2000);
+        let mut history = VecDeque::new();
+        // A keyframe every 4s
+        for ms in (0..=7000).step_by(500) {
+            push_history(&mut history, frame(ms), duration, true);
+        }
+        assert_eq!(history.front().map(|d| d.ts.as_millis()), Some(4000));
+        assert_eq!(history.len(), 7);
+        // The older gop is dropped once there is a newer keyframe
+        push_history(&mut history, frame(8000), duration, true);
+        assert_eq!(timestamps(&history), vec![6000, 6500, 7000, 8000]);
+    }
+}
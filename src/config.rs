@@ -1,28 +1,59 @@
-use crate::mqtt::Discoveries;
+use crate::{mqtt::Discoveries, AnyResult};
+use anyhow::{anyhow, Context};
 use lazy_static::lazy_static;
 use neolink_core::bc_protocol::{DiscoveryMethods, PrintFormat, StreamKind};
 use regex::Regex;
 use serde::{Deserialize, Serialize};
 use std::clone::Clone;
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::io::Read;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::Duration;
 use validator::{Validate, ValidationError};
 use validator_derive::Validate;
 
 lazy_static! {
-    static ref RE_TLS_CLIENT_AUTH: Regex = Regex::new(r"^(none|request|require)$").unwrap();
-    static ref RE_PAUSE_MODE: Regex = Regex::new(r"^(black|still|test|none)$").unwrap();
+    static ref RE_PAUSE_MODE: Regex = Regex::new(r"^(black|still|test|loop|freeze|none)$").unwrap();
+    static ref RE_RTSP_PATH: Regex = Regex::new(r"^(/[A-Za-z0-9._~!$&'()*+,;=:@%-]+)+$").unwrap();
+    static ref RE_ENCODER_PRESET: Regex = Regex::new(
+        r"^(ultrafast|superfast|veryfast|faster|fast|medium|slow|slower|veryslow|placebo)$"
+    )
+    .unwrap();
+    static ref RE_LOG_LEVEL: Regex =
+        Regex::new(r"^(?i)(off|error|warn|info|debug|trace)$").unwrap();
+    static ref RE_PATH_SEGMENT: Regex = Regex::new(r"^[A-Za-z0-9._~-]+$").unwrap();
     static ref RE_MAXENC_SRC: Regex =
         Regex::new(r"^([nN]one|[Aa][Ee][Ss]|[Bb][Cc][Ee][Nn][Cc][Rr][Yy][Pp][Tt])$").unwrap();
 }
 
+/// In a config directory this file holds the global settings
+const MAIN_CONFIG_FILE: &str = "neolink.toml";
+/// The `--config` that reads the config from stdin
+const STDIN_CONFIG: &str = "-";
+/// How long fetching the config from a url may take
+const FETCH_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The settings of neolink and its cameras, as read from the config file
+///
+/// It can be built with serde, e.g. `toml::from_str`, from the same text as the
+/// config file
 #[derive(Debug, Deserialize, Serialize, Validate, Clone, PartialEq)]
-pub(crate) struct Config {
+#[validate(schema(function = "validate_config"))]
+pub struct Config {
     #[validate]
+    #[serde(default)]
     pub(crate) cameras: Vec<CameraConfig>,
 
     #[serde(rename = "bind", default = "default_bind_addr")]
     pub(crate) bind_addr: String,
 
+    /// Bind to the address of this network interface, e.g. `eth0`, instead of `bind`
+    #[serde(default)]
+    pub(crate) bind_interface: Option<String>,
+
     #[validate(range(min = 0, max = 65535, message = "Invalid port", code = "bind_port"))]
     #[serde(default = "default_bind_port")]
     pub(crate) bind_port: u16,
@@ -33,20 +64,661 @@ pub(crate) struct Config {
     #[serde(default = "default_certificate")]
     pub(crate) certificate: Option<String>,
 
+    /// The passphrase of a PKCS#12 (`.p12` or `.pfx`) certificate
+    #[serde(default)]
+    pub(crate) certificate_password: Option<String>,
+
+    /// Certificates served instead of `certificate` to rtsps clients that ask
+    /// for these hostnames, `*.example.com` covers its subdomains
+    #[serde(default)]
+    pub(crate) sni_certificates: BTreeMap<String, String>,
+
     #[serde(default = "Default::default")]
     pub(crate) mqtt: Option<MqttServerConfig>,
 
-    #[validate(regex(
-        path = "RE_TLS_CLIENT_AUTH",
-        message = "Incorrect tls auth",
-        code = "tls_client_auth"
-    ))]
-    #[serde(default = "default_tls_client_auth")]
-    pub(crate) tls_client_auth: String,
+    #[serde(default)]
+    pub(crate) tls_client_auth: TlsClientAuth,
 
     #[validate]
     #[serde(default)]
     pub(crate) users: Vec<UserConfig>,
+
+    #[serde(default, alias = "duplicates")]
+    pub(crate) duplicate_names: DuplicateNames,
+
+    /// Initial delay in ms before reconnecting to a camera, doubles on each failure
+    #[serde(default = "default_retry_initial_ms")]
+    pub(crate) retry_initial_ms: u64,
+
+    /// Maximum delay in seconds between reconnection attempts
+    #[serde(default = "default_retry_max_secs")]
+    pub(crate) retry_max_secs: u64,
+
+    /// Seconds to wait for rtsp clients to disconnect on shutdown
+    #[serde(default = "default_shutdown_grace")]
+    pub(crate) shutdown_grace: u64,
+
+    /// Seconds without a keepalive before an rtsp client's session is closed
+    #[validate(range(
+        min = 1,
+        message = "Invalid idle timeout",
+        code = "client_idle_timeout_secs"
+    ))]
+    #[serde(default = "default_client_idle_timeout_secs")]
+    pub(crate) client_idle_timeout_secs: u32,
+
+    /// The DSCP that the rtp sent over udp is marked with, unmarked if unset
+    #[validate(range(max = 63, message = "Invalid dscp, it is 0 to 63", code = "dscp"))]
+    #[serde(default)]
+    pub(crate) dscp: Option<u8>,
+
+    /// Threads of the tokio runtime, one per core if unset
+    #[validate(range(min = 1, message = "Invalid worker threads", code = "worker_threads"))]
+    #[serde(default)]
+    pub(crate) worker_threads: Option<usize>,
+
+    /// Most threads that tokio starts for blocking work such as gstreamer
+    #[validate(range(
+        min = 2,
+        message = "Invalid max blocking threads, the gstreamer main loop always takes one",
+        code = "max_blocking_threads"
+    ))]
+    #[serde(default)]
+    pub(crate) max_blocking_threads: Option<usize>,
+
+    /// Serve the cameras to NVRs over ONVIF, needs the `onvif` feature
+    #[serde(default)]
+    pub(crate) onvif: Option<OnvifConfig>,
+
+    /// How the status and metrics servers are served
+    #[serde(default)]
+    pub(crate) http: HttpConfig,
+
+    /// The oldest firmware of each camera model that is not warned about
+    #[serde(default)]
+    pub(crate) min_firmware: HashMap<String, String>,
+
+    /// Camera settings, e.g. `username`, that apply to every camera that lacks them
+    ///
+    /// They are merged into the cameras by [`Config::from_toml`]
+    #[serde(default, skip_serializing)]
+    pub(crate) camera_defaults: Option<toml::Table>,
+}
+
+/// The ONVIF discovery responder and media service
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+pub(crate) struct OnvifConfig {
+    /// The address NVRs reach neolink on, it is used in the service and stream urls
+    pub(crate) address: String,
+
+    /// The http port of the ONVIF service
+    #[serde(default = "default_onvif_port")]
+    pub(crate) port: u16,
+
+    /// Answer WS-Discovery probes so that NVRs find neolink on their own
+    #[serde(default = "default_true")]
+    pub(crate) discovery: bool,
+}
+
+/// The https and auth of the status and metrics servers
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq, Default)]
+pub(crate) struct HttpConfig {
+    /// Serve https instead of http
+    #[serde(default = "default_false")]
+    pub(crate) tls: bool,
+
+    /// The certificate of the https, the global `certificate` if unset
+    #[serde(default)]
+    pub(crate) certificate: Option<String>,
+
+    /// The passphrase of a PKCS#12 `certificate`
+    #[serde(default)]
+    pub(crate) certificate_password: Option<String>,
+
+    /// Every request needs the basic auth of one of the `[[users]]`
+    #[serde(default = "default_false")]
+    pub(crate) require_auth: bool,
+}
+
+/// What to do when two cameras would be served on the same rtsp path
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Eq, PartialEq, Default)]
+pub(crate) enum DuplicateNames {
+    /// Refuse to start
+    #[default]
+    #[serde(alias = "error")]
+    Error,
+    /// Rename the later camera by appending `-2`, `-3` etc.
+    #[serde(alias = "suffix")]
+    Suffix,
+}
+
+/// Whether rtsps clients must present a certificate signed by the server's CA
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Eq, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum TlsClientAuth {
+    #[default]
+    None,
+    #[serde(alias = "requested")]
+    Request,
+    #[serde(alias = "required")]
+    Require,
+}
+
+/// The lower transports that rtsp clients may set up
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Eq, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum RtspTransport {
+    #[default]
+    Any,
+    /// Rtp interleaved in the rtsp connection, which gets through NAT
+    Tcp,
+    Udp,
+}
+
+/// How much the rtsp stream buffers before and while serving clients
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Eq, PartialEq, Default)]
+pub(crate) enum Latency {
+    /// Buffer several seconds to smooth over network hiccups
+    #[default]
+    #[serde(alias = "normal")]
+    Normal,
+    /// Start from the latest keyframe and keep the buffers small
+    #[serde(alias = "low")]
+    Low,
+}
+
+/// The video codec that the rtsp clients are served
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Eq, PartialEq)]
+pub(crate) enum Transcode {
+    #[serde(alias = "h264", alias = "H.264")]
+    H264,
+    #[serde(alias = "h265", alias = "H.265", alias = "hevc")]
+    H265,
+}
+
+impl Config {
+    /// Reads the config from a file or from all the `*.toml` files of a directory
+    ///
+    /// For a directory the global settings are taken from `neolink.toml`, or the
+    /// first file by name if there is none, and the cameras of every file are merged.
+    /// Global settings in the other files are ignored
+    ///
+    /// A path of `-` reads the config from stdin and an `http://` or `https://`
+    /// url fetches it. Stdin is only read once, later loads such as on SIGHUP
+    /// get the same text, while a url is fetched again each time
+    pub(crate) fn load(path: &Path) -> AnyResult<Config> {
+        match path.to_str() {
+            Some(STDIN_CONFIG) => return Self::load_text(read_stdin()?, "stdin"),
+            Some(url) if url.starts_with("http://") || url.starts_with("https://") => {
+                return Self::load_text(&fetch(url)?, url);
+            }
+            _ => {}
+        }
+        let mut files = if path.is_dir() {
+            let mut files = fs::read_dir(path)
+                .with_context(|| format!("Failed to read {:?}", path))?
+                .map(|entry| entry.map(|entry| entry.path()))
+                .collect::<Result<Vec<_>, _>>()
+                .with_context(|| format!("Failed to read {:?}", path))?
+                .into_iter()
+                .filter(|file| file.is_file() && file.extension().is_some_and(|ext| ext == "toml"))
+                .collect::<Vec<_>>();
+            files.sort();
+            // Stable so the rest stay sorted by name
+            files.sort_by_key(|file| {
+                file.file_name()
+                    .is_some_and(|name| name != MAIN_CONFIG_FILE)
+            });
+            files
+        } else {
+            vec![path.to_path_buf()]
+        }
+        .into_iter();
+
+        let main_file = files
+            .next()
+            .ok_or_else(|| anyhow!("No *.toml config files in {:?}", path))?;
+        let mut config = Self::load_file(&main_file, None)?;
+        let mut camera_files: HashMap<String, PathBuf> = config
+            .cameras
+            .iter()
+            .map(|camera| (camera.name.clone(), main_file.clone()))
+            .collect();
+        for file in files {
+            for camera in Self::load_file(&file, config.camera_defaults.as_ref())?
+                .cameras
+                .drain(..)
+            {
+                if let Some(other_file) = camera_files.get(&camera.name) {
+                    return Err(anyhow!(
+                        "Camera `{}` is in both {:?} and {:?}",
+                        camera.name,
+                        other_file,
+                        file
+                    ));
+                }
+                camera_files.insert(camera.name.clone(), file.clone());
+                config.cameras.push(camera);
+            }
+        }
+
+        // A directory of files that all lack cameras is most likely the wrong one
+        if path.is_dir() && config.cameras.is_empty() {
+            return Err(anyhow!("No [[cameras]] found in {:?}", path));
+        }
+        Ok(config)
+    }
+
+    /// The config of text that did not come from a file
+    fn load_text(text: &str, source: &str) -> AnyResult<Config> {
+        Self::from_toml(text, None)
+            .with_context(|| format!("Failed to parse the config from {source}"))
+    }
+
+    fn load_file(file: &Path, camera_defaults: Option<&toml::Table>) -> AnyResult<Config> {
+        Self::from_toml(
+            &fs::read_to_string(file).with_context(|| format!("Failed to read {:?}", file))?,
+            camera_defaults,
+        )
+        .with_context(|| format!("Failed to parse the {:?} config file", file))
+    }
+
+    /// Parses a config and fills in the cameras from its `[camera_defaults]`
+    ///
+    /// Environment variables in the values are expanded first, see [`expand_env`]
+    ///
+    /// When the config has no `[camera_defaults]` of its own the given ones are
+    /// used, this is how the other files of a config directory get the defaults
+    /// of the main file. Values set on a camera always win, tables such as
+    /// `pause` are merged key by key
+    pub(crate) fn from_toml(
+        text: &str,
+        camera_defaults: Option<&toml::Table>,
+    ) -> AnyResult<Config> {
+        let mut table: toml::Table = text.parse()?;
+        let mut interpolated = false;
+        for (key, value) in table.iter_mut() {
+            interpolated |= interpolate_env(value, key)?;
+        }
+        let defaults = match table.get("camera_defaults") {
+            Some(toml::Value::Table(defaults)) => Some(defaults.clone()),
+            Some(_) => return Err(anyhow!("`camera_defaults` must be a table")),
+            None => camera_defaults.cloned(),
+        };
+        if defaults.is_none() && !interpolated {
+            // Parsed from the text so that errors show the line
+            return Ok(toml::from_str(text)?);
+        }
+        if let Some(defaults) = defaults {
+            if defaults.contains_key("name") {
+                return Err(anyhow!(
+                    "`camera_defaults` cannot have a `name`, each camera needs its own"
+                ));
+            }
+            if let Some(toml::Value::Array(cameras)) = table.get_mut("cameras") {
+                for camera in cameras.iter_mut() {
+                    if let toml::Value::Table(camera) = camera {
+                        merge_defaults(camera, &defaults, "");
+                    }
+                }
+            }
+        }
+        Ok(toml::Value::Table(table).try_into()?)
+    }
+
+    /// Checks the config and fills in the cameras as is done after loading it
+    pub(crate) fn prepare(mut self) -> AnyResult<Config> {
+        self.validate()?;
+        self.resolve_bind_interface()?;
+        self.inherit_globals()?;
+        self.expand_channels();
+        self.resolve_duplicate_names()
+            .context("Failed to resolve the cameras")?;
+        Ok(self)
+    }
+
+    /// Replaces `bind` with the current address of `bind_interface`
+    ///
+    /// An IPv4 address is preferred when the interface has both
+    pub(crate) fn resolve_bind_interface(&mut self) -> AnyResult<()> {
+        let Some(name) = self.bind_interface.as_ref() else {
+            return Ok(());
+        };
+        let interfaces =
+            get_if_addrs::get_if_addrs().context("Failed to list the network interfaces")?;
+        let addrs = interfaces
+            .iter()
+            .filter(|interface| &interface.name == name)
+            .map(|interface| interface.ip())
+            .collect::<Vec<_>>();
+        let addr = addrs
+            .iter()
+            .find(|addr| addr.is_ipv4())
+            .or_else(|| addrs.first())
+            .ok_or_else(|| {
+                let mut names = interfaces
+                    .iter()
+                    .map(|interface| interface.name.as_str())
+                    .collect::<Vec<_>>();
+                names.sort();
+                names.dedup();
+                anyhow!(
+                    "There is no network interface `{name}` with an address for bind_interface, the interfaces are: {}",
+                    names.join(", ")
+                )
+            })?;
+        log::debug!("Binding to {addr} of {name}");
+        self.bind_addr = addr.to_string();
+        Ok(())
+    }
+
+    /// Copies the global settings into the cameras that do not override them
+    pub(crate) fn inherit_globals(&mut self) -> AnyResult<()> {
+        for camera in self.cameras.iter_mut() {
+            let initial = *camera.retry_initial_ms.get_or_insert(self.retry_initial_ms);
+            let max = *camera.retry_max_secs.get_or_insert(self.retry_max_secs);
+            if !retry_bounds_valid(initial, max) {
+                return Err(anyhow!(
+                    "Camera `{}` has retry_initial_ms ({}ms) that is not less than retry_max_secs ({}s)",
+                    camera.name,
+                    initial,
+                    max
+                ));
+            }
+            for (model, firmware) in self.min_firmware.iter() {
+                camera
+                    .min_firmware
+                    .entry(model.clone())
+                    .or_insert_with(|| firmware.clone());
+            }
+        }
+        Ok(())
+    }
+
+    /// Splits each camera with `channels` into one camera per channel
+    ///
+    /// They are named `{name}-channel{n}` and served at `{path}/channel{n}`
+    pub(crate) fn expand_channels(&mut self) {
+        self.cameras = std::mem::take(&mut self.cameras)
+            .into_iter()
+            .flat_map(|camera| match camera.channels.as_ref() {
+                Some(channels) => channels
+                    .iter()
+                    .map(|&channel| {
+                        let mut split = camera.clone();
+                        split.name = format!("{}-channel{channel}", camera.name);
+                        split.rtsp_path =
+                            Some(format!("{}/channel{channel}", camera.rtsp_base_path()));
+                        split.channel_id = channel;
+                        split.channels = None;
+                        split
+                    })
+                    .collect::<Vec<_>>(),
+                None => vec![camera],
+            })
+            .collect();
+    }
+
+    /// The names of the cameras that are not disabled
+    pub(crate) fn enabled_names(&self) -> HashSet<String> {
+        self.cameras
+            .iter()
+            .filter(|camera| camera.enabled)
+            .map(|camera| camera.name.clone())
+            .collect()
+    }
+
+    /// Checks that no two enabled cameras resolve to the same rtsp path
+    ///
+    /// Depending on `duplicate_names` this will either error or rename
+    /// the later camera by adding a numeric suffix, e.g. `Garage-2`
+    pub(crate) fn resolve_duplicate_names(&mut self) -> AnyResult<()> {
+        let policy = self.duplicate_names;
+        let default_bind = (self.bind_addr.clone(), self.bind_port);
+        let mut used_paths: HashMap<(String, u16, String), String> = Default::default();
+        for camera in self.cameras.iter_mut().filter(|cam| cam.enabled) {
+            let (addr, port) = camera.rtsp_bind(&default_bind.0, default_bind.1);
+            let original_name = camera.name.clone();
+            let mut suffix = 1;
+            loop {
+                let collision = camera.all_rtsp_paths().into_iter().find_map(|path| {
+                    used_paths
+                        .get(&(addr.clone(), port, path.clone()))
+                        .map(|other_name| (path.clone(), other_name.clone()))
+                });
+                match (collision, policy) {
+                    (None, _) => break,
+                    (Some((path, other_name)), DuplicateNames::Error) => {
+                        return Err(anyhow!(
+                            "Camera `{}` would be served at `{}` which is already used by camera `{}`. Rename one of them or set `duplicate_names = \"suffix\"`",
+                            camera.name,
+                            path,
+                            other_name
+                        ));
+                    }
+                    (Some((path, other_name)), DuplicateNames::Suffix)
+                        if camera.rtsp_path.is_some() =>
+                    {
+                        return Err(anyhow!(
+                            "Camera `{}` has `rtsp_path = \"{}\"` so it would be served at `{}` which is already used by camera `{}`. Choose a different rtsp_path",
+                            camera.name,
+                            camera.rtsp_base_path(),
+                            path,
+                            other_name
+                        ));
+                    }
+                    (Some(_), DuplicateNames::Suffix) => {
+                        suffix += 1;
+                        camera.name = format!("{}-{}", original_name, suffix);
+                    }
+                }
+            }
+            if camera.name != original_name {
+                log::warn!(
+                    "{}: Rtsp path collides with another camera. Serving as `{}` instead",
+                    original_name,
+                    camera.name
+                );
+            }
+            for path in camera.all_rtsp_paths() {
+                used_paths.insert((addr.clone(), port, path), camera.name.clone());
+            }
+        }
+        Ok(())
+    }
+
+    /// The certificate and its passphrase that the status and metrics servers use for https
+    pub(crate) fn http_certificate(&self) -> Option<(&str, Option<&str>)> {
+        match self.http.certificate.as_deref() {
+            Some(cert) => Some((cert, self.http.certificate_password.as_deref())),
+            None => Some((
+                self.certificate.as_deref()?,
+                self.certificate_password.as_deref(),
+            )),
+        }
+    }
+}
+
+/// All of stdin, read the first time the config is loaded from it
+fn read_stdin() -> AnyResult<&'static str> {
+    static STDIN: OnceLock<String> = OnceLock::new();
+    if let Some(text) = STDIN.get() {
+        return Ok(text);
+    }
+    let mut text = String::new();
+    std::io::stdin()
+        .read_to_string(&mut text)
+        .context("Failed to read the config from stdin")?;
+    Ok(STDIN.get_or_init(|| text))
+}
+
+/// The body of a config url
+///
+/// The blocking client runs in a thread of its own as it cannot run inside the
+/// tokio runtime, which the config is also loaded from on SIGHUP
+fn fetch(url: &str) -> AnyResult<String> {
+    let thread_url = url.to_string();
+    std::thread::spawn(move || {
+        reqwest::blocking::Client::builder()
+            .timeout(FETCH_TIMEOUT)
+            .build()?
+            .get(thread_url)
+            .send()?
+            .error_for_status()?
+            .text()
+    })
+    .join()
+    .map_err(|_| anyhow!("Fetching the config panicked"))?
+    .with_context(|| format!("Failed to fetch the config from {url}"))
+}
+
+/// Expands the environment variables in all the strings of a config value
+///
+/// `path` is where the value is in the config, e.g. `cameras[0].password`,
+/// and is used to say which field needs a missing variable. Returns whether
+/// anything was expanded
+fn interpolate_env(value: &mut toml::Value, path: &str) -> AnyResult<bool> {
+    match value {
+        toml::Value::String(text) => match expand_env(text, |var| std::env::var(var).ok()) {
+            Ok(Some(expanded)) => {
+                *text = expanded;
+                Ok(true)
+            }
+            Ok(None) => Ok(false),
+            Err(var) => Err(anyhow!(
+                "The environment variable `{}` used by `{}` is not set",
+                var,
+                path
+            )),
+        },
+        toml::Value::Array(values) => {
+            let mut expanded = false;
+            for (index, value) in values.iter_mut().enumerate() {
+                expanded |= interpolate_env(value, &format!("{}[{}]", path, index))?;
+            }
+            Ok(expanded)
+        }
+        toml::Value::Table(table) => {
+            let mut expanded = false;
+            for (key, value) in table.iter_mut() {
+                expanded |= interpolate_env(value, &format!("{}.{}", path, key))?;
+            }
+            Ok(expanded)
+        }
+        _ => Ok(false),
+    }
+}
+
+/// Replaces `${VAR}` and `${VAR:-default}` in `text` with the value of `VAR`
+///
+/// Like in a shell the default is used when `VAR` is unset or empty. `$${`
+/// is a literal `${`. Returns `None` when there is nothing to expand and the
+/// name of the variable when one without a default is missing
+fn expand_env<F: Fn(&str) -> Option<String>>(
+    text: &str,
+    lookup: F,
+) -> Result<Option<String>, String> {
+    if !text.contains("${") {
+        return Ok(None);
+    }
+    let mut expanded = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find("${") {
+        if rest[..start].ends_with('$') {
+            expanded.push_str(&rest[..start - 1]);
+            expanded.push_str("${");
+            rest = &rest[start + 2..];
+            continue;
+        }
+        expanded.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let Some(end) = after.find('}') else {
+            // Not closed so not a variable
+            expanded.push_str(&rest[start..]);
+            rest = "";
+            break;
+        };
+        let (var, default) = match after[..end].split_once(":-") {
+            Some((var, default)) => (var, Some(default)),
+            None => (&after[..end], None),
+        };
+        let value = match (lookup(var), default) {
+            (Some(value), Some(default)) if value.is_empty() => default.to_string(),
+            (Some(value), _) => value,
+            (None, Some(default)) => default.to_string(),
+            (None, None) => return Err(var.to_string()),
+        };
+        expanded.push_str(&value);
+        rest = &after[end + 1..];
+    }
+    expanded.push_str(rest);
+    Ok(Some(expanded))
+}
+
+/// The other names that serde accepts for the keys of a camera as
+/// `(alias, key)`, with the keys of its tables under `table.key`
+///
+/// These must follow the `alias` of the fields of [`CameraConfig`] and the
+/// structs of its tables
+const CAMERA_ALIASES: &[(&str, &str)] = &[
+    ("streams", "stream"),
+    ("channel", "channel_id"),
+    ("print", "print_format"),
+    ("time", "update_time"),
+    ("sync_time", "update_time"),
+    ("size", "buffer_size"),
+    ("buffer", "buffer_size"),
+    ("enable", "enabled"),
+    ("verbose", "debug"),
+    ("splash", "use_splash"),
+    ("pattern", "splash_pattern"),
+    ("retries", "max_discovery_retries"),
+    ("push", "push_notifications"),
+    ("push_noti", "push_notifications"),
+    ("idle", "idle_disconnect"),
+    ("idle_disc", "idle_disconnect"),
+    ("pause.on_client", "pause.on_disconnect"),
+    ("pause.timeout", "pause.motion_timeout"),
+    ("overlay.enable", "overlay.enabled"),
+    ("record.enable", "record.enabled"),
+    ("hls.enable", "hls.enabled"),
+];
+
+/// The key of a camera that `key` in the table at `prefix` stands for
+fn canonical_key(prefix: &str, key: &str) -> String {
+    let path = if prefix.is_empty() {
+        key.to_string()
+    } else {
+        format!("{prefix}.{key}")
+    };
+    CAMERA_ALIASES
+        .iter()
+        .find(|(alias, _)| *alias == path)
+        .map(|(_, canonical)| canonical.to_string())
+        .unwrap_or(path)
+}
+
+/// Adds the keys of `defaults` that `table` lacks, merging the tables they both have
+///
+/// A key counts as present under any of its aliases, otherwise the camera
+/// would end up with both and fail with a duplicate field
+fn merge_defaults(table: &mut toml::Table, defaults: &toml::Table, prefix: &str) {
+    for (key, default) in defaults.iter() {
+        let canonical = canonical_key(prefix, key);
+        let present = table
+            .keys()
+            .find(|other| canonical_key(prefix, other) == canonical)
+            .cloned();
+        match (present.and_then(|key| table.get_mut(&key)), default) {
+            (None, default) => {
+                table.insert(key.clone(), default.clone());
+            }
+            (Some(toml::Value::Table(value)), toml::Value::Table(default)) => {
+                merge_defaults(value, default, &canonical);
+            }
+            (Some(_), _) => {}
+        }
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone, Validate, PartialEq, Eq)]
@@ -67,7 +739,7 @@ pub(crate) struct MqttServerConfig {
     pub(crate) client_auth: Option<(std::path::PathBuf, std::path::PathBuf)>,
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone, Copy, Eq, PartialEq)]
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Eq, PartialEq, Hash)]
 pub(crate) enum StreamConfig {
     #[serde(alias = "none")]
     None,
@@ -99,6 +771,23 @@ pub(crate) enum StreamConfig {
 }
 
 impl StreamConfig {
+    /// The setting that serves exactly these streams, if there is one
+    pub(crate) fn from_stream_kinds(streams: &HashSet<StreamKind>) -> Option<Self> {
+        match (
+            streams.contains(&StreamKind::Main),
+            streams.contains(&StreamKind::Sub),
+            streams.contains(&StreamKind::Extern),
+        ) {
+            (false, false, false) => Some(StreamConfig::None),
+            (true, false, false) => Some(StreamConfig::Main),
+            (false, true, false) => Some(StreamConfig::Sub),
+            (false, false, true) => Some(StreamConfig::Extern),
+            (true, true, false) => Some(StreamConfig::Both),
+            (true, true, true) => Some(StreamConfig::All),
+            _ => None,
+        }
+    }
+
     pub(crate) fn as_stream_kinds(&self) -> Vec<StreamKind> {
         match self {
             StreamConfig::All => {
@@ -123,9 +812,39 @@ impl StreamConfig {
     }
 }
 
+/// Accepts either a single stream setting or a list like `["main", "sub"]`
+fn deserialize_stream_config<'de, D>(deserializer: D) -> Result<StreamConfig, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(StreamConfig),
+        Many(Vec<StreamConfig>),
+    }
+
+    match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(stream) => Ok(stream),
+        OneOrMany::Many(streams) => {
+            let kinds = streams
+                .iter()
+                .flat_map(|stream| stream.as_stream_kinds())
+                .collect::<HashSet<_>>();
+            StreamConfig::from_stream_kinds(&kinds).ok_or_else(|| {
+                serde::de::Error::custom(format!(
+                    "Cannot serve just the streams {:?}, only main, sub, extern, main and sub, or all of them are supported",
+                    streams
+                ))
+            })
+        }
+    }
+}
+
+/// The settings of one camera, a `[[cameras]]` table of the config file
 #[derive(Debug, Deserialize, Serialize, Validate, Clone, PartialEq)]
 #[validate(schema(function = "validate_camera_config"))]
-pub(crate) struct CameraConfig {
+pub struct CameraConfig {
     pub(crate) name: String,
 
     #[serde(rename = "address")]
@@ -137,15 +856,28 @@ pub(crate) struct CameraConfig {
     pub(crate) username: String,
     pub(crate) password: Option<String>,
 
-    #[serde(default = "default_stream")]
+    #[serde(
+        default = "default_stream",
+        alias = "streams",
+        deserialize_with = "deserialize_stream_config"
+    )]
     pub(crate) stream: StreamConfig,
 
     pub(crate) permitted_users: Option<Vec<String>>,
 
+    /// Tokens that give access to the streams of this camera with `?token=`
+    #[validate]
+    #[serde(default)]
+    pub(crate) tokens: Vec<TokenConfig>,
+
     #[validate(range(min = 0, max = 31, message = "Invalid channel", code = "channel_id"))]
     #[serde(default = "default_channel_id", alias = "channel")]
     pub(crate) channel_id: u8,
 
+    /// Serve several channels of a multi lens camera, each as its own camera
+    #[serde(default)]
+    pub(crate) channels: Option<Vec<u8>>,
+
     #[validate]
     #[serde(default = "default_mqtt")]
     pub(crate) mqtt: MqttConfig,
@@ -154,9 +886,38 @@ pub(crate) struct CameraConfig {
     #[serde(default = "default_pause")]
     pub(crate) pause: PauseConfig,
 
+    #[serde(default)]
+    pub(crate) motion: MotionConfig,
+
+    /// Also push the highest quality stream to an external ingest
+    ///
+    /// Not called `push` since that is an alias of `push_notifications`
+    #[serde(default)]
+    pub(crate) restream: Option<PushConfig>,
+
+    /// Record the highest quality stream to disk
+    #[validate]
+    #[serde(default)]
+    pub(crate) record: Option<RecordConfig>,
+
+    /// Serve the streams as HLS on the status server for browsers
+    #[validate]
+    #[serde(default)]
+    pub(crate) hls: Option<HlsConfig>,
+
+    /// Burn the time into the video served to rtsp clients
+    #[validate]
+    #[serde(default)]
+    pub(crate) overlay: Option<OverlayConfig>,
+
     #[serde(default = "default_discovery")]
     pub(crate) discovery: DiscoveryMethods,
 
+    /// Local address that the connections to the camera are made from, for
+    /// hosts with more than one network
+    #[serde(default)]
+    pub(crate) source_addr: Option<IpAddr>,
+
     #[serde(default = "default_maxenc")]
     #[validate(regex(
         path = "RE_MAXENC_SRC",
@@ -172,7 +933,8 @@ pub(crate) struct CameraConfig {
     #[serde(default = "default_print", alias = "print")]
     pub(crate) print_format: PrintFormat,
 
-    #[serde(default = "default_update_time", alias = "time")]
+    /// Set the clock of the camera to the time of this host after each login
+    #[serde(default = "default_update_time", alias = "time", alias = "sync_time")]
     pub(crate) update_time: bool,
 
     #[validate(range(
@@ -184,9 +946,101 @@ pub(crate) struct CameraConfig {
     #[serde(default = "default_buffer_size", alias = "size", alias = "buffer")]
     pub(crate) buffer_size: usize,
 
+    /// Serve the substream on the main stream's paths while the main stream keeps failing
+    #[serde(default = "default_false")]
+    pub(crate) fallback_to_substream: bool,
+
+    /// How many times in a row the main stream must fail before falling back
+    #[validate(range(
+        min = 1,
+        message = "Invalid fallback after failures",
+        code = "fallback_after_failures"
+    ))]
+    #[serde(default = "default_fallback_after_failures")]
+    pub(crate) fallback_after_failures: u32,
+
+    /// A disabled camera is kept in the config but never connected or served
     #[serde(default = "default_true", alias = "enable")]
     pub(crate) enabled: bool,
 
+    /// Trades robustness against network hiccups for a lower latency
+    #[serde(default)]
+    pub(crate) latency: Latency,
+
+    /// The rtp jitterbuffer latency of the rtsp streams, set by `latency` if unset
+    #[validate(range(
+        max = 10000,
+        message = "Invalid jitterbuffer",
+        code = "jitterbuffer_ms"
+    ))]
+    #[serde(default)]
+    pub(crate) jitterbuffer_ms: Option<u32>,
+
+    /// Which transports the rtsp clients may use
+    #[serde(default)]
+    pub(crate) rtsp_transport: RtspTransport,
+
+    /// Re-encode the video to this codec when the camera sends another one
+    #[serde(default)]
+    pub(crate) transcode: Option<Transcode>,
+
+    /// Serve this camera under this path instead of one derived from its name
+    #[serde(default)]
+    #[validate(regex(
+        path = "RE_RTSP_PATH",
+        message = "Invalid rtsp path, it should look like `/front/door`",
+        code = "rtsp_path"
+    ))]
+    pub(crate) rtsp_path: Option<String>,
+
+    /// The extra path segment the substream is also served under
+    #[serde(default = "default_substream_suffix")]
+    #[validate(regex(
+        path = "RE_PATH_SEGMENT",
+        message = "Invalid substream suffix",
+        code = "substream_suffix"
+    ))]
+    pub(crate) substream_suffix: String,
+
+    /// An extra path segment the main stream is also served under
+    #[serde(default)]
+    #[validate(regex(
+        path = "RE_PATH_SEGMENT",
+        message = "Invalid mainstream alias",
+        code = "mainstream_alias"
+    ))]
+    pub(crate) mainstream_alias: Option<String>,
+
+    /// Serve the substream when `stream` includes it, when false its paths
+    /// are not registered and return 404
+    #[serde(default = "default_true")]
+    pub(crate) serve_substream: bool,
+
+    /// Also serve just the audio of the camera at `{rtsp_path}/audio`
+    #[serde(default = "default_false")]
+    pub(crate) serve_audio: bool,
+
+    /// Also serve just the keyframes of the camera at `{rtsp_path}/{keyframe_only_suffix}`
+    #[serde(default = "default_false")]
+    pub(crate) keyframe_only: bool,
+
+    /// The path segment of the keyframe only stream
+    #[serde(default = "default_keyframe_only_suffix")]
+    #[validate(regex(
+        path = "RE_PATH_SEGMENT",
+        message = "Invalid keyframe only suffix",
+        code = "keyframe_only_suffix"
+    ))]
+    pub(crate) keyframe_only_suffix: String,
+
+    /// Serve this camera on a different address than the global `bind`
+    #[serde(default, rename = "bind")]
+    pub(crate) bind_addr: Option<String>,
+
+    /// Serve this camera on a different port than the global `bind_port`
+    #[serde(default)]
+    pub(crate) bind_port: Option<u16>,
+
     #[serde(default = "default_false", alias = "verbose")]
     pub(crate) debug: bool,
 
@@ -196,11 +1050,11 @@ pub(crate) struct CameraConfig {
     #[serde(default = "default_splash", alias = "pattern")]
     pub(crate) splash_pattern: SplashPattern,
 
-    #[serde(
-        default = "default_max_discovery_retries",
-        alias = "retries",
-        alias = "max_retries"
-    )]
+    /// A `.jpg` or `.png` shown instead of the `splash_pattern` until the stream is ready
+    #[serde(default)]
+    pub(crate) not_ready_image: Option<String>,
+
+    #[serde(default = "default_max_discovery_retries", alias = "retries")]
     pub(crate) max_discovery_retries: usize,
 
     #[serde(default = "default_true", alias = "push", alias = "push_noti")]
@@ -208,6 +1062,333 @@ pub(crate) struct CameraConfig {
 
     #[serde(default = "default_false", alias = "idle", alias = "idle_disc")]
     pub(crate) idle_disconnect: bool,
+
+    /// How long to wait for the camera to be found and connected to
+    #[validate(range(
+        min = 1,
+        message = "Invalid connect timeout",
+        code = "connect_timeout_secs"
+    ))]
+    #[serde(default = "default_connect_timeout_secs")]
+    pub(crate) connect_timeout_secs: u64,
+
+    /// How long to wait for the camera to accept the login
+    #[validate(range(
+        min = 1,
+        message = "Invalid login timeout",
+        code = "login_timeout_secs"
+    ))]
+    #[serde(default = "default_login_timeout_secs")]
+    pub(crate) login_timeout_secs: u64,
+
+    /// Reconnect if a running stream sends no frames for this long
+    #[validate(range(
+        min = 1,
+        message = "Invalid stall timeout",
+        code = "stall_timeout_secs"
+    ))]
+    #[serde(default = "default_stall_timeout_secs")]
+    pub(crate) stall_timeout_secs: u64,
+
+    /// How many milliseconds of frames are kept for new clients
+    #[validate(range(
+        min = 1000,
+        max = 120000,
+        message = "Invalid buffer duration",
+        code = "buffer_duration_ms"
+    ))]
+    #[serde(default = "default_buffer_duration_ms")]
+    pub(crate) buffer_duration_ms: u64,
+
+    /// Keep the whole latest gop buffered and start new clients from its keyframe
+    #[serde(default = "default_false")]
+    pub(crate) fast_start: bool,
+
+    /// Send the VPS, SPS and PPS again in front of every keyframe of an H265 stream
+    #[serde(default = "default_false")]
+    pub(crate) repeat_parameter_sets: bool,
+
+    /// Jump a client to live once it is this many milliseconds behind the camera
+    #[validate(range(min = 1, message = "Invalid max drift", code = "max_drift_ms"))]
+    #[serde(default)]
+    pub(crate) max_drift_ms: Option<u64>,
+
+    /// Most kilobytes queued for each client before its oldest data is dropped
+    #[validate(range(min = 64, message = "Invalid client queue", code = "client_queue_kb"))]
+    #[serde(default)]
+    pub(crate) client_queue_kb: Option<u32>,
+
+    /// How many times in a row the credentials may be rejected before giving up on them
+    #[validate(range(
+        min = 1,
+        message = "Invalid max login attempts",
+        code = "max_login_attempts"
+    ))]
+    #[serde(default = "default_max_login_attempts")]
+    pub(crate) max_login_attempts: u32,
+
+    /// How many reconnects in a row may fail before giving up on the camera, 0 retries forever
+    #[serde(default)]
+    pub(crate) max_retries: u32,
+
+    /// How many failures within `failure_window_secs` are tolerated before
+    /// giving up on the camera, 0 never gives up
+    #[serde(default)]
+    pub(crate) max_failures_in_window: u32,
+
+    /// The rolling window of `max_failures_in_window`
+    #[validate(range(
+        min = 1,
+        message = "Invalid failure window",
+        code = "failure_window_secs"
+    ))]
+    #[serde(default = "default_failure_window_secs")]
+    pub(crate) failure_window_secs: u64,
+
+    /// Instead of stopping the camera when the credentials are rejected,
+    /// wait for them to be changed in the config and log in again
+    #[serde(default = "default_false")]
+    pub(crate) wait_for_credentials: bool,
+
+    /// Overrides the global `retry_initial_ms`
+    #[serde(default)]
+    pub(crate) retry_initial_ms: Option<u64>,
+
+    /// Overrides the global `retry_max_secs`
+    #[serde(default)]
+    pub(crate) retry_max_secs: Option<u64>,
+
+    /// The most rtsp clients served at once across all streams of the camera
+    #[validate(range(min = 1, message = "Invalid max clients", code = "max_clients"))]
+    #[serde(default)]
+    pub(crate) max_clients: Option<u32>,
+
+    /// Close main stream sessions that keep losing packets so their client can reconnect to the substream
+    #[serde(default = "default_false")]
+    pub(crate) adaptive: bool,
+
+    /// The percentage of lost packets that `adaptive` treats as struggling
+    #[validate(range(
+        min = 1,
+        max = 100,
+        message = "Invalid adaptive loss percent",
+        code = "adaptive_loss_percent"
+    ))]
+    #[serde(default = "default_adaptive_loss_percent")]
+    pub(crate) adaptive_loss_percent: u8,
+
+    /// Close rtsp sessions that have been playing for longer than this
+    #[validate(range(
+        min = 1,
+        message = "Invalid max session secs",
+        code = "max_session_secs"
+    ))]
+    #[serde(default)]
+    pub(crate) max_session_secs: Option<u64>,
+
+    /// Serve the latest keyframe as a jpeg and as mjpeg on the status server
+    #[serde(default = "default_false")]
+    pub(crate) http_snapshots: bool,
+
+    /// The log level of this camera's logs instead of that of `RUST_LOG`
+    #[validate(regex(
+        path = "RE_LOG_LEVEL",
+        message = "Incorrect log level",
+        code = "log_level"
+    ))]
+    #[serde(default)]
+    pub(crate) log_level: Option<String>,
+
+    /// Seconds between keepalive pings to the camera, 0 turns them off
+    #[serde(default = "default_keepalive_secs")]
+    pub(crate) keepalive_secs: u64,
+
+    /// The oldest firmware of each model that is not warned about, the models
+    /// that it lacks come from the global `[min_firmware]`
+    #[serde(default)]
+    pub(crate) min_firmware: HashMap<String, String>,
+}
+
+impl CameraConfig {
+    /// The address and port of the rtsp server this camera is served on
+    ///
+    /// Falls back to the global values when not overridden
+    pub(crate) fn rtsp_bind(&self, default_addr: &str, default_port: u16) -> (String, u16) {
+        (
+            self.bind_addr
+                .clone()
+                .unwrap_or_else(|| default_addr.to_string()),
+            self.bind_port.unwrap_or(default_port),
+        )
+    }
+
+    /// The initial and maximum delay between reconnection attempts
+    pub(crate) fn retry_bounds(&self) -> (Duration, Duration) {
+        (
+            Duration::from_millis(
+                self.retry_initial_ms
+                    .unwrap_or_else(default_retry_initial_ms),
+            ),
+            Duration::from_secs(self.retry_max_secs.unwrap_or_else(default_retry_max_secs)),
+        )
+    }
+
+    /// The mount point that all the streams of this camera are served under
+    ///
+    /// This is `rtsp_path` when set, otherwise the percent encoded name
+    pub(crate) fn rtsp_base_path(&self) -> String {
+        match self.rtsp_path.as_ref() {
+            Some(path) => path.clone(),
+            None => format!("/{}", percent_encode_segment(&self.name)),
+        }
+    }
+
+    /// The rtsp paths that the given stream of this camera is served on
+    ///
+    /// The highest quality active stream is also served on the bare base path
+    pub(crate) fn rtsp_paths(&self, stream: StreamKind) -> Vec<String> {
+        let base = self.rtsp_base_path();
+        let mut paths = match stream {
+            StreamKind::Main => vec![
+                format!("{base}/main"),
+                format!("{base}/Main"),
+                format!("{base}/mainStream"),
+                format!("{base}/MainStream"),
+                format!("{base}/Mainstream"),
+                format!("{base}/mainstream"),
+            ],
+            StreamKind::Sub => vec![
+                format!("{base}/sub"),
+                format!("{base}/Sub"),
+                format!("{base}/subStream"),
+                format!("{base}/SubStream"),
+                format!("{base}/Substream"),
+                format!("{base}/substream"),
+            ],
+            StreamKind::Extern => vec![
+                format!("{base}/extern"),
+                format!("{base}/Extern"),
+                format!("{base}/externStream"),
+                format!("{base}/ExternStream"),
+                format!("{base}/Externstream"),
+                format!("{base}/externstream"),
+            ],
+        };
+        let extra = match stream {
+            StreamKind::Main => self.mainstream_alias.as_ref(),
+            StreamKind::Sub => Some(&self.substream_suffix),
+            StreamKind::Extern => None,
+        };
+        if let Some(extra) = extra.map(|extra| format!("{base}/{extra}")) {
+            if !paths.contains(&extra) {
+                paths.push(extra);
+            }
+        }
+        if self.is_base_stream(stream) {
+            paths.push(base);
+        }
+        paths
+    }
+
+    /// The rtsp paths that just the audio of the given stream is served on
+    ///
+    /// Only the stream served on the bare base path gets an audio path
+    pub(crate) fn rtsp_audio_paths(&self, stream: StreamKind) -> Vec<String> {
+        if self.serve_audio && self.is_base_stream(stream) {
+            vec![format!("{}/audio", self.rtsp_base_path())]
+        } else {
+            vec![]
+        }
+    }
+
+    /// The rtsp paths that just the keyframes of the given stream are served on
+    ///
+    /// Like the audio only the stream served on the bare base path gets one
+    pub(crate) fn rtsp_keyframe_paths(&self, stream: StreamKind) -> Vec<String> {
+        if self.keyframe_only && self.is_base_stream(stream) {
+            vec![format!(
+                "{}/{}",
+                self.rtsp_base_path(),
+                self.keyframe_only_suffix
+            )]
+        } else {
+            vec![]
+        }
+    }
+
+    /// The rtsp role that the access tokens of this camera are given
+    ///
+    /// Roles must be valid gstreamer structure names which camera names need not be
+    pub(crate) fn token_role(&self) -> String {
+        format!("token-{:x}", md5::compute(&self.name))
+    }
+
+    /// The streams of `stream` that are served, without the substream unless
+    /// `serve_substream`
+    pub(crate) fn stream_kinds(&self) -> Vec<StreamKind> {
+        let mut streams = self.stream.as_stream_kinds();
+        if !self.serve_substream {
+            streams.retain(|stream| *stream != StreamKind::Sub);
+        }
+        streams
+    }
+
+    /// If this is the highest quality active stream
+    pub(crate) fn is_base_stream(&self, stream: StreamKind) -> bool {
+        let active_streams = self.stream_kinds();
+        match stream {
+            StreamKind::Main => true,
+            StreamKind::Sub => !active_streams.contains(&StreamKind::Main),
+            StreamKind::Extern => {
+                !active_streams.contains(&StreamKind::Main)
+                    && !active_streams.contains(&StreamKind::Sub)
+            }
+        }
+    }
+
+    /// The active stream of this camera that is served on the rtsp `path`
+    pub(crate) fn stream_of_path(&self, path: &str) -> Option<StreamKind> {
+        self.stream_kinds().into_iter().find(|stream| {
+            self.rtsp_paths(*stream)
+                .iter()
+                .chain(self.rtsp_audio_paths(*stream).iter())
+                .chain(self.rtsp_keyframe_paths(*stream).iter())
+                .any(|other| other == path)
+        })
+    }
+
+    /// All the rtsp paths of all the active streams of this camera
+    pub(crate) fn all_rtsp_paths(&self) -> Vec<String> {
+        self.stream_kinds()
+            .iter()
+            .flat_map(|stream| {
+                let mut paths = self.rtsp_paths(*stream);
+                paths.extend(self.rtsp_audio_paths(*stream));
+                paths.extend(self.rtsp_keyframe_paths(*stream));
+                paths
+            })
+            .collect()
+    }
+
+    /// Whether the camera must reconnect to apply `other`
+    ///
+    /// Everything else is picked up by the streams while they keep running
+    pub(crate) fn connection_changed(&self, other: &CameraConfig) -> bool {
+        self.camera_addr != other.camera_addr
+            || self.camera_uid != other.camera_uid
+            || self.username != other.username
+            || self.password != other.password
+            || self.channel_id != other.channel_id
+            || self.discovery != other.discovery
+            || self.source_addr != other.source_addr
+            || self.max_discovery_retries != other.max_discovery_retries
+            || self.max_encryption != other.max_encryption
+            || self.debug != other.debug
+            || self.connect_timeout_secs != other.connect_timeout_secs
+            || self.login_timeout_secs != other.login_timeout_secs
+            || self.update_time != other.update_time
+            || self.keepalive_secs != other.keepalive_secs
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize, Validate, Clone, PartialEq, Eq, Hash)]
@@ -218,6 +1399,32 @@ pub(crate) struct UserConfig {
 
     #[serde(alias = "password")]
     pub(crate) pass: String,
+
+    /// Limits the user to some of the streams of a camera, keyed by camera name
+    ///
+    /// Cameras that are not listed have all of their streams available
+    #[serde(default)]
+    pub(crate) streams: BTreeMap<String, StreamConfig>,
+
+    /// Limits the user to these cameras, empty or absent allows all cameras
+    #[serde(default, alias = "cameras")]
+    pub(crate) allowed_cameras: Option<Vec<String>>,
+}
+
+impl UserConfig {
+    /// Whether this user may watch the given stream of a camera
+    pub(crate) fn can_view(&self, camera: &str, stream: StreamKind) -> bool {
+        let allowed = match self.allowed_cameras.as_deref() {
+            None | Some([]) => true,
+            Some(cameras) => cameras.iter().any(|allowed| allowed == camera),
+        };
+        allowed
+            && self
+                .streams
+                .get(camera)
+                .map(|streams| streams.as_stream_kinds().contains(&stream))
+                .unwrap_or(true)
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone, Validate, PartialEq, Eq)]
@@ -269,71 +1476,392 @@ pub(crate) struct MqttConfig {
 pub(crate) struct MqttDiscoveryConfig {
     pub(crate) topic: String,
 
-    pub(crate) features: HashSet<Discoveries>,
+    pub(crate) features: HashSet<Discoveries>,
+}
+
+fn validate_mqtt_server(config: &MqttServerConfig) -> Result<(), ValidationError> {
+    if config.ca.is_some() && config.client_auth.is_some() {
+        Err(ValidationError::new(
+            "Cannot have both ca and client_auth set",
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+const fn default_true() -> bool {
+    true
+}
+
+const fn default_false() -> bool {
+    false
+}
+
+fn default_mqtt() -> MqttConfig {
+    MqttConfig {
+        enable_motion: true,
+        enable_light: true,
+        enable_battery: true,
+        battery_update: 2000,
+        enable_preview: true,
+        preview_update: 2000,
+        enable_floodlight: true,
+        floodlight_update: 2000,
+        discovery: Default::default(),
+    }
+}
+
+fn default_print() -> PrintFormat {
+    PrintFormat::None
+}
+
+fn default_discovery() -> DiscoveryMethods {
+    DiscoveryMethods::Relay
+}
+
+fn default_maxenc() -> String {
+    "Aes".to_string()
+}
+
+#[derive(Debug, Deserialize, Serialize, Validate, Clone, PartialEq)]
+pub(crate) struct PauseConfig {
+    #[serde(default = "default_on_motion")]
+    pub(crate) on_motion: bool,
+
+    #[serde(default = "default_on_disconnect", alias = "on_client")]
+    pub(crate) on_disconnect: bool,
+
+    #[serde(default = "default_motion_timeout", alias = "timeout")]
+    pub(crate) motion_timeout: f64,
+
+    /// With both `on_motion` and `on_disconnect`, whether streaming needs
+    /// motion and a client or just one of them
+    #[serde(default)]
+    pub(crate) require: PauseRequire,
+
+    #[serde(default = "default_pause_mode")]
+    #[validate(regex(
+        path = "RE_PAUSE_MODE",
+        message = "Incorrect pause mode",
+        code = "mode"
+    ))]
+    pub(crate) mode: String,
+
+    /// Clip that is replayed while paused when `mode = "loop"`
+    #[serde(default)]
+    pub(crate) loop_file: Option<String>,
+
+    /// Encoder for the `black`, `still` and `test` modes
+    #[serde(default)]
+    pub(crate) encoder: PauseEncoder,
+
+    /// Bitrate in kbit/s of the `black`, `still` and `test` modes
+    #[validate(range(min = 1, message = "Invalid pause bitrate", code = "bitrate"))]
+    #[serde(default = "default_pause_bitrate")]
+    pub(crate) bitrate: u32,
+
+    /// Speed preset of the software encoder
+    #[validate(regex(
+        path = "RE_ENCODER_PRESET",
+        message = "Incorrect encoder preset",
+        code = "preset"
+    ))]
+    #[serde(default = "default_pause_preset")]
+    pub(crate) preset: String,
+
+    /// Only stream within these windows of the day
+    #[validate]
+    #[serde(default)]
+    pub(crate) schedule: Option<ScheduleConfig>,
+
+    /// Hold a pause back until the next keyframe, for at most the `motion_timeout`
+    #[serde(default = "default_true")]
+    pub(crate) align_to_keyframe: bool,
+}
+
+impl PauseConfig {
+    /// Whether anything pauses the stream
+    pub(crate) fn pauses(&self) -> bool {
+        self.on_motion || self.on_disconnect || self.schedule.is_some()
+    }
+}
+
+/// The times of day that a camera streams, it is paused at all other times
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq, Validate)]
+pub(crate) struct ScheduleConfig {
+    /// Windows like `"07:00-19:00"`, one that ends before it starts runs past midnight
+    #[validate(custom = "validate_schedule_windows")]
+    pub(crate) windows: Vec<String>,
+
+    /// A zone of the tz database like `Europe/Dublin`, the local time if unset
+    #[validate(custom = "validate_timezone")]
+    #[serde(default)]
+    pub(crate) timezone: Option<String>,
+}
+
+impl ScheduleConfig {
+    /// Whether the minute of the day, in the wall clock time of the
+    /// timezone, is within one of the windows
+    pub(crate) fn contains(&self, minute: u16) -> bool {
+        self.windows
+            .iter()
+            .filter_map(|window| parse_window(window))
+            .any(|(start, end)| {
+                if start < end {
+                    (start..end).contains(&minute)
+                } else {
+                    minute >= start || minute < end
+                }
+            })
+    }
+}
+
+/// The start and end minute of the day of a window like `"22:30-06:00"`
+///
+/// The end may be `24:00`, the start and end cannot be the same
+fn parse_window(window: &str) -> Option<(u16, u16)> {
+    let minute = |time: &str| {
+        let (hours, minutes) = time.trim().split_once(':')?;
+        let (hours, minutes) = (hours.parse::<u16>().ok()?, minutes.parse::<u16>().ok()?);
+        (minutes < 60 && hours * 60 + minutes <= 24 * 60).then_some(hours * 60 + minutes)
+    };
+    let (start, end) = window.split_once('-')?;
+    let (start, end) = (minute(start)?, minute(end)?);
+    (start < 24 * 60 && start != end).then_some((start, end))
+}
+
+/// How `on_motion` and `on_disconnect` combine when both are set
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Eq, PartialEq, Default)]
+pub(crate) enum PauseRequire {
+    /// Stream only while there is motion and a client
+    #[default]
+    #[serde(alias = "all")]
+    All,
+    /// Stream while there is motion or a client
+    #[serde(alias = "any")]
+    Any,
+}
+
+/// Which encoder makes the pause stream
+///
+/// The hardware ones fall back to software when gstreamer lacks them
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Eq, PartialEq, Default)]
+pub(crate) enum PauseEncoder {
+    /// x264enc or x265enc
+    #[default]
+    #[serde(alias = "x264", alias = "software")]
+    X264,
+    /// Intel/AMD va-api
+    #[serde(alias = "vaapi")]
+    Vaapi,
+    /// Video4Linux2 e.g. on a raspberry pi
+    #[serde(alias = "v4l2")]
+    V4l2,
+}
+
+/// An external ingest that the camera is pushed to
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+pub(crate) struct PushConfig {
+    /// Where to push to e.g. `rtmp://a.rtmp.youtube.com/live2/KEY` or `srt://relay:9000`
+    pub(crate) url: String,
+
+    #[serde(default)]
+    pub(crate) protocol: PushProtocol,
+}
+
+/// How the camera is pushed
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Eq, PartialEq, Default)]
+pub(crate) enum PushProtocol {
+    /// Flv over RTMP, only H264 cameras are supported
+    #[default]
+    #[serde(alias = "rtmp")]
+    Rtmp,
+    /// Mpeg-ts over SRT
+    #[serde(alias = "srt")]
+    Srt,
+}
+
+/// An access token of a camera for clients that cannot use basic auth
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq, Hash, Validate)]
+pub(crate) struct TokenConfig {
+    #[validate(custom = "validate_token")]
+    pub(crate) token: String,
+
+    /// When the token stops working as `YYYY-MM-DD` or `YYYY-MM-DDTHH:MM:SSZ` in UTC
+    #[validate(custom = "validate_expires")]
+    #[serde(default)]
+    pub(crate) expires: Option<String>,
+}
+
+impl TokenConfig {
+    /// The expiry in seconds since the unix epoch
+    pub(crate) fn expires_at(&self) -> Option<u64> {
+        self.expires.as_deref().and_then(parse_utc)
+    }
 }
 
-fn validate_mqtt_server(config: &MqttServerConfig) -> Result<(), ValidationError> {
-    if config.ca.is_some() && config.client_auth.is_some() {
-        Err(ValidationError::new(
-            "Cannot have both ca and client_auth set",
-        ))
-    } else {
-        Ok(())
+/// Seconds since the unix epoch of a `YYYY-MM-DD` or `YYYY-MM-DDTHH:MM:SS[Z]` UTC time
+fn parse_utc(time: &str) -> Option<u64> {
+    let time = time.strip_suffix('Z').unwrap_or(time);
+    let (date, clock) = time.split_once('T').unwrap_or((time, "00:00:00"));
+    let number = |part: Option<&str>| part.and_then(|part| part.parse::<u64>().ok());
+    let mut date = date.split('-');
+    let (year, month, day) = (
+        number(date.next())?,
+        number(date.next())?,
+        number(date.next())?,
+    );
+    let mut clock = clock.split(':');
+    let (hour, minute, second) = (
+        number(clock.next())?,
+        number(clock.next())?,
+        number(clock.next())?,
+    );
+    if date.next().is_some()
+        || clock.next().is_some()
+        || year < 1970
+        || !(1..=12).contains(&month)
+        || !(1..=31).contains(&day)
+        || hour > 23
+        || minute > 59
+        || second > 59
+    {
+        return None;
     }
+    // Days from civil, from Howard Hinnant's date algorithms
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year / 400;
+    let yoe = year - era * 400;
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = (era * 146097 + doe).checked_sub(719468)?;
+    Some(days * 86400 + hour * 3600 + minute * 60 + second)
 }
 
-const fn default_true() -> bool {
-    true
+/// A clock drawn onto the video, which is then decoded and encoded again
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq, Validate)]
+pub(crate) struct OverlayConfig {
+    #[serde(default = "default_true", alias = "enable")]
+    pub(crate) enabled: bool,
+
+    /// A zone of the tz database like `Europe/Dublin`, the local time if unset
+    #[validate(custom = "validate_timezone")]
+    #[serde(default)]
+    pub(crate) timezone: Option<String>,
+
+    /// The strftime format of the time
+    #[serde(default = "default_overlay_format")]
+    pub(crate) format: String,
 }
 
-const fn default_false() -> bool {
-    false
+/// Recording of the stream to rotating files on disk
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq, Validate)]
+pub(crate) struct RecordConfig {
+    #[serde(default = "default_true", alias = "enable")]
+    pub(crate) enabled: bool,
+
+    /// The recordings of each camera go into a folder of the camera's name in here
+    pub(crate) directory: PathBuf,
+
+    /// A new file is started after this many minutes
+    #[validate(range(min = 1, message = "Invalid segment minutes", code = "segment_minutes"))]
+    #[serde(default = "default_segment_minutes")]
+    pub(crate) segment_minutes: u64,
+
+    /// Recordings older than this many days are deleted, 0 keeps them forever
+    #[serde(default = "default_retention_days")]
+    pub(crate) retention_days: u64,
+
+    #[serde(default)]
+    pub(crate) format: RecordFormat,
+
+    #[serde(default)]
+    pub(crate) mode: RecordMode,
+
+    /// In motion mode, how many seconds before the motion are also recorded
+    ///
+    /// Limited by the `buffer_duration_ms` of the camera
+    #[serde(default = "default_pre_seconds")]
+    pub(crate) pre_seconds: u64,
+
+    /// In motion mode, how many seconds to keep recording after the motion stops
+    #[serde(default = "default_post_seconds")]
+    pub(crate) post_seconds: u64,
 }
 
-fn default_mqtt() -> MqttConfig {
-    MqttConfig {
-        enable_motion: true,
-        enable_light: true,
-        enable_battery: true,
-        battery_update: 2000,
-        enable_preview: true,
-        preview_update: 2000,
-        enable_floodlight: true,
-        floodlight_update: 2000,
-        discovery: Default::default(),
-    }
+/// HLS playlists of the streams, written to a temporary directory
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq, Validate)]
+pub(crate) struct HlsConfig {
+    #[serde(default = "default_true", alias = "enable")]
+    pub(crate) enabled: bool,
+
+    /// The length of each segment, it is cut at the first keyframe after this
+    #[validate(range(min = 1, message = "Invalid segment seconds", code = "segment_secs"))]
+    #[serde(default = "default_hls_segment_secs")]
+    pub(crate) segment_secs: u32,
+
+    /// How many segments the playlist holds, older ones are deleted
+    #[validate(range(min = 2, message = "Invalid segments", code = "segments"))]
+    #[serde(default = "default_hls_segments")]
+    pub(crate) segments: u32,
 }
 
-fn default_print() -> PrintFormat {
-    PrintFormat::None
+/// When the stream is recorded
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Eq, PartialEq, Default)]
+pub(crate) enum RecordMode {
+    /// All the time, unless the stream is paused
+    #[default]
+    #[serde(alias = "continuous")]
+    Continuous,
+    /// Only around motion
+    #[serde(alias = "motion")]
+    Motion,
 }
 
-fn default_discovery() -> DiscoveryMethods {
-    DiscoveryMethods::Relay
+/// The container of the recordings
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Eq, PartialEq, Default)]
+pub(crate) enum RecordFormat {
+    #[default]
+    #[serde(alias = "mp4")]
+    Mp4,
+    /// Unlike mp4 the file can still be played if neolink stops without finishing it
+    #[serde(alias = "mkv")]
+    Mkv,
 }
 
-fn default_maxenc() -> String {
-    "Aes".to_string()
+impl RecordFormat {
+    pub(crate) fn extension(&self) -> &'static str {
+        match self {
+            RecordFormat::Mp4 => "mp4",
+            RecordFormat::Mkv => "mkv",
+        }
+    }
 }
 
-#[derive(Debug, Deserialize, Serialize, Validate, Clone, PartialEq)]
-pub(crate) struct PauseConfig {
-    #[serde(default = "default_on_motion")]
-    pub(crate) on_motion: bool,
+/// Hooks that are run when motion starts or stops
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq, Default)]
+pub(crate) struct MotionConfig {
+    /// Shell command run when motion starts
+    #[serde(default)]
+    pub(crate) on_start_command: Option<String>,
 
-    #[serde(default = "default_on_disconnect", alias = "on_client")]
-    pub(crate) on_disconnect: bool,
+    /// Shell command run when motion stops
+    #[serde(default)]
+    pub(crate) on_stop_command: Option<String>,
 
-    #[serde(default = "default_motion_timeout", alias = "timeout")]
-    pub(crate) motion_timeout: f64,
+    /// Url that is sent a json POST on each motion start and stop
+    #[serde(default)]
+    pub(crate) webhook_url: Option<String>,
+}
 
-    #[serde(default = "default_pause_mode")]
-    #[validate(regex(
-        path = "RE_PAUSE_MODE",
-        message = "Incorrect pause mode",
-        code = "mode"
-    ))]
-    pub(crate) mode: String,
+impl MotionConfig {
+    /// Whether anything is run on motion
+    pub(crate) fn has_hooks(&self) -> bool {
+        self.on_start_command.is_some()
+            || self.on_stop_command.is_some()
+            || self.webhook_url.is_some()
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone, Copy, Eq, PartialEq)]
@@ -443,10 +1971,6 @@ fn default_certificate() -> Option<String> {
     None
 }
 
-fn default_tls_client_auth() -> String {
-    "none".to_string()
-}
-
 fn default_tokio_console() -> bool {
     false
 }
@@ -475,6 +1999,14 @@ fn default_pause_mode() -> String {
     "none".to_string()
 }
 
+fn default_pause_bitrate() -> u32 {
+    512
+}
+
+fn default_pause_preset() -> String {
+    "ultrafast".to_string()
+}
+
 fn default_strict() -> bool {
     false
 }
@@ -484,7 +2016,14 @@ fn default_pause() -> PauseConfig {
         on_motion: default_on_motion(),
         on_disconnect: default_on_disconnect(),
         motion_timeout: default_motion_timeout(),
+        require: Default::default(),
         mode: default_pause_mode(),
+        loop_file: None,
+        encoder: Default::default(),
+        bitrate: default_pause_bitrate(),
+        preset: default_pause_preset(),
+        schedule: None,
+        align_to_keyframe: true,
     }
 }
 
@@ -500,10 +2039,112 @@ fn default_2000() -> u64 {
     2000
 }
 
+fn default_retry_initial_ms() -> u64 {
+    50
+}
+
+fn default_failure_window_secs() -> u64 {
+    60
+}
+
+fn default_retry_max_secs() -> u64 {
+    5
+}
+
+fn default_client_idle_timeout_secs() -> u32 {
+    5
+}
+
+fn default_shutdown_grace() -> u64 {
+    10
+}
+
+fn default_connect_timeout_secs() -> u64 {
+    60
+}
+
+fn default_login_timeout_secs() -> u64 {
+    15
+}
+
+fn default_stall_timeout_secs() -> u64 {
+    10
+}
+
+fn default_onvif_port() -> u16 {
+    8000
+}
+
+fn default_fallback_after_failures() -> u32 {
+    3
+}
+
+fn default_buffer_duration_ms() -> u64 {
+    15000
+}
+
+fn default_overlay_format() -> String {
+    "%Y-%m-%d %H:%M:%S".to_string()
+}
+
+fn default_segment_minutes() -> u64 {
+    10
+}
+
+fn default_hls_segment_secs() -> u32 {
+    2
+}
+
+fn default_hls_segments() -> u32 {
+    6
+}
+
+fn default_retention_days() -> u64 {
+    7
+}
+
+fn default_pre_seconds() -> u64 {
+    5
+}
+
+fn default_post_seconds() -> u64 {
+    10
+}
+
+fn default_adaptive_loss_percent() -> u8 {
+    10
+}
+
+fn default_keepalive_secs() -> u64 {
+    5
+}
+
+fn default_max_login_attempts() -> u32 {
+    1
+}
+
+fn default_substream_suffix() -> String {
+    "subStream".to_string()
+}
+
+fn default_keyframe_only_suffix() -> String {
+    "lowbw".to_string()
+}
+
 fn default_splash() -> SplashPattern {
     SplashPattern::Snow
 }
 
+/// The gstreamer element that decodes the image, by its extension
+pub(crate) fn image_decoder(path: &str) -> Option<&'static str> {
+    let ext = Path::new(path).extension()?.to_str()?.to_ascii_lowercase();
+    match ext.as_str() {
+        "jpg" | "jpeg" => Some("jpegdec"),
+        "png" => Some("pngdec"),
+        _ => None,
+    }
+}
+
 pub(crate) static RESERVED_NAMES: &[&str] = &["anyone", "anonymous"];
 fn validate_username(name: &str) -> Result<(), ValidationError> {
     if name.trim().is_empty() {
@@ -515,11 +2156,944 @@ fn validate_username(name: &str) -> Result<(), ValidationError> {
     Ok(())
 }
 
+fn validate_token(token: &str) -> Result<(), ValidationError> {
+    // It is sent in the url so it must not need escaping
+    if token.len() < 8
+        || !token
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_.~".contains(c))
+    {
+        return Err(ValidationError::new(
+            "Tokens need at least 8 letters, digits or -_.~",
+        ));
+    }
+    Ok(())
+}
+
+fn validate_expires(expires: &str) -> Result<(), ValidationError> {
+    if parse_utc(expires).is_none() {
+        return Err(ValidationError::new(
+            "Expected an expiry like 2025-01-31 or 2025-01-31T12:00:00Z",
+        ));
+    }
+    Ok(())
+}
+
+/// The zone must be in the tz database as gstreamer quietly falls back to UTC
+fn validate_timezone(timezone: &str) -> Result<(), ValidationError> {
+    if timezone == "UTC" {
+        return Ok(());
+    }
+    let zoneinfo = std::env::var_os("TZDIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("/usr/share/zoneinfo"));
+    let path = Path::new(timezone);
+    if timezone.is_empty()
+        || !path
+            .components()
+            .all(|part| matches!(part, std::path::Component::Normal(_)))
+        || !zoneinfo.join(path).is_file()
+    {
+        return Err(ValidationError::new(
+            "Unknown timezone, expected a zone of the tz database like Europe/Dublin",
+        ));
+    }
+    Ok(())
+}
+
+/// Whether the first delay between reconnects is shorter than the longest one
+fn retry_bounds_valid(initial_ms: u64, max_secs: u64) -> bool {
+    Duration::from_millis(initial_ms) < Duration::from_secs(max_secs)
+}
+
+fn validate_config(config: &Config) -> Result<(), ValidationError> {
+    if !retry_bounds_valid(config.retry_initial_ms, config.retry_max_secs) {
+        return Err(ValidationError::new(
+            "retry_initial_ms must be less than retry_max_secs",
+        ));
+    }
+    Ok(())
+}
+
+fn validate_schedule_windows(windows: &[String]) -> Result<(), ValidationError> {
+    if windows.is_empty() {
+        return Err(ValidationError::new(
+            "The schedule needs at least one window",
+        ));
+    }
+    if windows.iter().any(|window| parse_window(window).is_none()) {
+        return Err(ValidationError::new(
+            "Invalid schedule window, expected a start and end like \"07:00-19:00\"",
+        ));
+    }
+    Ok(())
+}
+
 fn validate_camera_config(camera_config: &CameraConfig) -> Result<(), ValidationError> {
+    if let Some(channels) = camera_config.channels.as_ref() {
+        if channels.is_empty() {
+            return Err(ValidationError::new(
+                "channels must list at least one channel",
+            ));
+        }
+        if channels.iter().any(|channel| *channel > 31) {
+            return Err(ValidationError::new("Invalid channel in channels"));
+        }
+        if channels.iter().collect::<HashSet<_>>().len() != channels.len() {
+            return Err(ValidationError::new("channels lists a channel twice"));
+        }
+    }
+    if let (Some(initial), Some(max)) =
+        (camera_config.retry_initial_ms, camera_config.retry_max_secs)
+    {
+        if !retry_bounds_valid(initial, max) {
+            return Err(ValidationError::new(
+                "retry_initial_ms must be less than retry_max_secs",
+            ));
+        }
+    }
+    if let Some(image) = camera_config.not_ready_image.as_deref() {
+        if image_decoder(image).is_none() {
+            return Err(ValidationError::new(
+                "not_ready_image must be a .jpg, .jpeg or .png",
+            ));
+        }
+    }
+    if !camera_config.serve_substream && camera_config.stream == StreamConfig::Sub {
+        return Err(ValidationError::new(
+            "serve_substream = false leaves stream = \"sub\" nothing to serve",
+        ));
+    }
+    if let Some(alias) = camera_config.mainstream_alias.as_ref() {
+        if [StreamKind::Sub, StreamKind::Extern].iter().any(|stream| {
+            camera_config.rtsp_paths(*stream).contains(&format!(
+                "{}/{}",
+                camera_config.rtsp_base_path(),
+                alias
+            ))
+        }) {
+            return Err(ValidationError::new(
+                "mainstream_alias must not be the path of another stream",
+            ));
+        }
+    }
+    if camera_config
+        .rtsp_paths(StreamKind::Main)
+        .iter()
+        .chain(camera_config.rtsp_paths(StreamKind::Extern).iter())
+        .any(|path| {
+            path == &format!(
+                "{}/{}",
+                camera_config.rtsp_base_path(),
+                camera_config.substream_suffix
+            )
+        })
+    {
+        return Err(ValidationError::new(
+            "substream_suffix must not be the path of another stream",
+        ));
+    }
+    if camera_config.keyframe_only {
+        let keyframe_path = format!(
+            "{}/{}",
+            camera_config.rtsp_base_path(),
+            camera_config.keyframe_only_suffix
+        );
+        if [StreamKind::Main, StreamKind::Sub, StreamKind::Extern]
+            .iter()
+            .any(|stream| {
+                camera_config.rtsp_paths(*stream).contains(&keyframe_path)
+                    || camera_config
+                        .rtsp_audio_paths(*stream)
+                        .contains(&keyframe_path)
+            })
+        {
+            return Err(ValidationError::new(
+                "keyframe_only_suffix must not be the path of another stream",
+            ));
+        }
+    }
     match (&camera_config.camera_addr, &camera_config.camera_uid) {
         (None, None) => Err(ValidationError::new(
             "Either camera address or uid must be given",
         )),
+        (Some(_), Some(_)) => Err(ValidationError::new(
+            "Only one of camera address or uid can be given",
+        )),
         _ => Ok(()),
     }
 }
+
+/// Percent encode everything but the unreserved characters of RFC 3986
+/// so that a camera name can be used as a single path segment
+fn percent_encode_segment(segment: &str) -> String {
+    let mut encoded = String::with_capacity(segment.len());
+    for byte in segment.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The camera Garage with the `extra` lines added to it
+    fn camera(extra: &str) -> CameraConfig {
+        toml::from_str(&format!(
+            "name = \"Garage\"\nusername = \"admin\"\naddress = \"192.168.1.10\"\n{extra}"
+        ))
+        .unwrap()
+    }
+
+    /// A config of just [`camera`]
+    fn camera_config(extra: &str) -> Config {
+        toml::from_str(&format!(
+            "[[cameras]]\nname = \"Garage\"\nusername = \"admin\"\naddress = \"192.168.1.10\"\n{extra}"
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_tls_client_auth() {
+        let config: Config = toml::from_str(r#"tls_client_auth = "required""#).unwrap();
+        assert_eq!(config.tls_client_auth, TlsClientAuth::Require);
+        let config: Config = toml::from_str("").unwrap();
+        assert_eq!(config.tls_client_auth, TlsClientAuth::None);
+
+        let e = toml::from_str::<Config>(r#"tls_client_auth = "requird""#).unwrap_err();
+        assert!(
+            e.to_string().contains(
+                "unknown variant `requird`, expected one of `none`, `request`, `require`"
+            ),
+            "{}",
+            e
+        );
+    }
+
+    #[test]
+    fn test_dscp() {
+        let config: Config = toml::from_str("dscp = 34").unwrap();
+        assert_eq!(config.dscp, Some(34));
+        assert!(config.validate().is_ok());
+        let config: Config = toml::from_str("").unwrap();
+        assert_eq!(config.dscp, None);
+
+        let config: Config = toml::from_str("dscp = 64").unwrap();
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_retry_bounds() {
+        let config: Config = toml::from_str("retry_initial_ms = 500\nretry_max_secs = 1").unwrap();
+        assert!(config.validate().is_ok());
+        let config: Config = toml::from_str("retry_initial_ms = 1000\nretry_max_secs = 1").unwrap();
+        assert!(config.validate().is_err());
+        // Too large to be turned into milliseconds
+        let config: Config = toml::from_str("retry_max_secs = 9223372036854775807").unwrap();
+        assert!(config.validate().is_ok());
+
+        let camera = camera("retry_initial_ms = 2000\nretry_max_secs = 2");
+        assert!(camera.validate().is_err());
+        let mut config = camera_config("retry_max_secs = 9223372036854775807");
+        assert!(config.inherit_globals().is_ok());
+    }
+
+    #[test]
+    fn test_serialize_round_trip() {
+        let config: Config = toml::from_str(
+            r#"
+bind = "0.0.0.0"
+certificate = "/etc/neolink/rtsp.pem"
+dscp = 34
+
+[mqtt]
+broker_addr = "127.0.0.1"
+port = 1883
+credentials = ["mqtt", "secret"]
+
+[min_firmware]
+"RLC-510A" = "v3.0.0.2356_23062000"
+
+[[users]]
+name = "me"
+pass = "mepass"
+
+[[cameras]]
+name = "Garage"
+username = "admin"
+address = "192.168.1.10"
+stream = ["main", "sub"]
+  [cameras.pause]
+  on_motion = true
+  timeout = 2.5
+  [cameras.pause.schedule]
+  windows = ["06:30-09:00"]
+  [[cameras.tokens]]
+  token = "c2VjcmV0LXRva2Vu"
+  expires = "2025-12-31"
+"#,
+        )
+        .unwrap();
+        let toml = toml::to_string(&config).unwrap();
+        let round_trip: Config = toml::from_str(&toml).unwrap();
+        assert_eq!(round_trip, config);
+    }
+
+    #[test]
+    fn test_http_certificate() {
+        let config: Config = toml::from_str(
+            r#"
+certificate = "/etc/neolink/rtsp.pem"
+
+[http]
+tls = true
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.http_certificate(),
+            Some(("/etc/neolink/rtsp.pem", None))
+        );
+
+        let config: Config = toml::from_str(
+            r#"
+certificate = "/etc/neolink/rtsp.pem"
+
+[http]
+certificate = "/etc/neolink/http.p12"
+certificate_password = "secret"
+"#,
+        )
+        .unwrap();
+        assert!(!config.http.tls);
+        assert_eq!(
+            config.http_certificate(),
+            Some(("/etc/neolink/http.p12", Some("secret")))
+        );
+
+        let config: Config = toml::from_str("").unwrap();
+        assert_eq!(config.http_certificate(), None);
+    }
+
+    #[test]
+    fn test_sni_certificates() {
+        let config: Config = toml::from_str(
+            r#"
+certificate = "/etc/neolink/rtsp.pem"
+
+[sni_certificates]
+"cams.example.com" = "/etc/neolink/cams.pem"
+"*.example.org" = "/etc/neolink/example-org.pem"
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            config
+                .sni_certificates
+                .get("cams.example.com")
+                .map(String::as_str),
+            Some("/etc/neolink/cams.pem")
+        );
+        assert_eq!(config.sni_certificates.len(), 2);
+        assert!(toml::from_str::<Config>("")
+            .unwrap()
+            .sni_certificates
+            .is_empty());
+    }
+
+    #[test]
+    fn test_client_queue() {
+        let config = camera_config("client_queue_kb = 2048");
+        assert_eq!(config.cameras[0].client_queue_kb, Some(2048));
+        assert!(config.validate().is_ok());
+        assert_eq!(camera("").client_queue_kb, None);
+        assert!(camera_config("client_queue_kb = 8").validate().is_err());
+    }
+
+    #[test]
+    fn test_min_firmware() {
+        let mut config: Config = toml::from_str(
+            r#"
+[min_firmware]
+"RLC-510A" = "v3.0.0.2356_23062000"
+"E1 Zoom" = "v3.0.0.1000_22000000"
+
+[[cameras]]
+name = "Garage"
+username = "admin"
+address = "192.168.1.10"
+  [cameras.min_firmware]
+  "E1 Zoom" = "v3.1.0.2000_23000000"
+"#,
+        )
+        .unwrap();
+        config.inherit_globals().unwrap();
+        let min_firmware = &config.cameras[0].min_firmware;
+        assert_eq!(min_firmware["RLC-510A"], "v3.0.0.2356_23062000");
+        // The camera's own comes first
+        assert_eq!(min_firmware["E1 Zoom"], "v3.1.0.2000_23000000");
+    }
+
+    #[test]
+    fn test_user_stream_limits() {
+        let user: UserConfig = toml::from_str(
+            r#"
+            name = "guest"
+            pass = "guestpass"
+            streams = { driveway = "subStream" }
+            "#,
+        )
+        .unwrap();
+
+        assert!(!user.can_view("driveway", StreamKind::Main));
+        assert!(user.can_view("driveway", StreamKind::Sub));
+        // Unlisted cameras are not limited
+        assert!(user.can_view("shed", StreamKind::Main));
+    }
+
+    #[test]
+    fn test_user_allowed_cameras() {
+        let users: Config = toml::from_str(
+            r#"
+            [[users]]
+            name = "alice"
+            pass = "alicepass"
+            allowed_cameras = ["Garage"]
+
+            [[users]]
+            name = "bob"
+            pass = "bobpass"
+            allowed_cameras = []
+            "#,
+        )
+        .unwrap();
+        let (alice, bob) = (&users.users[0], &users.users[1]);
+
+        assert!(alice.can_view("Garage", StreamKind::Main));
+        assert!(alice.can_view("Garage", StreamKind::Sub));
+        // A restricted user is denied on a camera not in their list
+        assert!(!alice.can_view("Driveway", StreamKind::Main));
+        // An empty list allows all cameras
+        assert!(bob.can_view("Driveway", StreamKind::Main));
+    }
+
+    #[test]
+    fn test_rtsp_path() {
+        let mut config: Config = toml::from_str(
+            r#"
+            [[cameras]]
+            name = "Front Door"
+            username = "admin"
+            address = "192.168.1.10"
+
+            [[cameras]]
+            name = "garage"
+            username = "admin"
+            address = "192.168.1.11"
+            rtsp_path = "/outside/garage"
+            "#,
+        )
+        .unwrap();
+        assert!(config.validate().is_ok());
+        assert!(config.resolve_duplicate_names().is_ok());
+        assert_eq!(config.cameras[0].rtsp_base_path(), "/Front%20Door");
+        assert!(config.cameras[1]
+            .rtsp_paths(StreamKind::Main)
+            .contains(&"/outside/garage/main".to_string()));
+
+        // An explicit path is never renamed, even with suffixing enabled
+        config.duplicate_names = DuplicateNames::Suffix;
+        config.cameras[0].rtsp_path = Some("/outside/garage".to_string());
+        assert!(config.resolve_duplicate_names().is_err());
+
+        config.cameras[0].rtsp_path = Some("no/leading/slash".to_string());
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_tokens() {
+        let config = camera_config(
+            r#"
+              [[cameras.tokens]]
+              token = "c2VjcmV0dG9rZW4"
+              [[cameras.tokens]]
+              token = "an-other_token"
+              expires = "2024-02-29T12:30:15Z"
+            "#,
+        );
+        assert!(config.validate().is_ok());
+        let tokens = &config.cameras[0].tokens;
+        assert_eq!(tokens[0].expires_at(), None);
+        assert_eq!(tokens[1].expires_at(), Some(1709209815));
+        assert_eq!(parse_utc("1970-01-01"), Some(0));
+        assert_eq!(parse_utc("2024-01-01"), Some(1704067200));
+        assert_eq!(parse_utc("2024-13-01"), None);
+        assert_eq!(parse_utc("tomorrow"), None);
+        assert!(validate_token("short").is_err());
+        assert!(validate_token("has spaces in it").is_err());
+    }
+
+    #[test]
+    fn test_address_or_uid() {
+        let mut config = camera_config("");
+        assert!(config.validate().is_ok());
+        config.cameras[0].camera_uid = Some("95270000ABCDEFGH".to_string());
+        assert!(config.validate().is_err());
+        config.cameras[0].camera_addr = None;
+        assert!(config.validate().is_ok());
+        config.cameras[0].camera_uid = None;
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_disabled_camera() {
+        let mut config: Config = toml::from_str(
+            r#"
+            [[cameras]]
+            name = "garage"
+            username = "admin"
+            address = "192.168.1.10"
+
+            [[cameras]]
+            name = "garage"
+            username = "admin"
+            address = "192.168.1.11"
+            enabled = false
+            "#,
+        )
+        .unwrap();
+        assert!(config.cameras[0].enabled);
+        assert!(!config.cameras[1].enabled);
+        // A disabled camera does not claim its rtsp paths
+        assert!(config.resolve_duplicate_names().is_ok());
+        assert_eq!(config.cameras[0].name, "garage");
+        // Nor is it started
+        config.cameras[1].name = "shed".to_string();
+        assert_eq!(
+            config.enabled_names(),
+            HashSet::from(["garage".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_stream_aliases() {
+        let camera = camera("substream_suffix = \"1\"\nmainstream_alias = \"0\"");
+        assert!(camera.validate().is_ok());
+
+        // The alias is served by the same stream as the canonical path
+        let main = camera.rtsp_paths(StreamKind::Main);
+        assert!(main.contains(&"/Garage/main".to_string()));
+        assert!(main.contains(&"/Garage/0".to_string()));
+        let sub = camera.rtsp_paths(StreamKind::Sub);
+        assert!(sub.contains(&"/Garage/subStream".to_string()));
+        assert!(sub.contains(&"/Garage/1".to_string()));
+        assert!(!main.contains(&"/Garage/1".to_string()));
+    }
+
+    #[test]
+    fn test_keyframe_only_paths() {
+        let mut camera = camera("keyframe_only = true");
+        assert!(camera.validate().is_ok());
+        assert_eq!(
+            camera.rtsp_keyframe_paths(StreamKind::Main),
+            vec!["/Garage/lowbw".to_string()]
+        );
+        // Only the stream on the base path, and not among its normal paths
+        assert!(camera.rtsp_keyframe_paths(StreamKind::Sub).is_empty());
+        assert!(!camera
+            .rtsp_paths(StreamKind::Main)
+            .contains(&"/Garage/lowbw".to_string()));
+        assert!(camera
+            .all_rtsp_paths()
+            .contains(&"/Garage/lowbw".to_string()));
+
+        camera.keyframe_only_suffix = "subStream".to_string();
+        assert!(camera.validate().is_err());
+    }
+
+    #[test]
+    fn test_stream_of_path() {
+        let camera = camera("keyframe_only = true");
+        assert_eq!(camera.stream_of_path("/Garage"), Some(StreamKind::Main));
+        assert_eq!(
+            camera.stream_of_path("/Garage/lowbw"),
+            Some(StreamKind::Main)
+        );
+        assert_eq!(
+            camera.stream_of_path("/Garage/subStream"),
+            Some(StreamKind::Sub)
+        );
+        assert_eq!(camera.stream_of_path("/Driveway"), None);
+    }
+
+    #[test]
+    fn test_serve_substream() {
+        let mut camera = camera("serve_substream = false");
+        assert!(camera.validate().is_ok());
+        assert_eq!(
+            camera.stream_kinds(),
+            vec![StreamKind::Main, StreamKind::Extern]
+        );
+        // The main stream keeps its paths and the substream has none
+        let paths = camera.all_rtsp_paths();
+        assert!(paths.contains(&"/Garage".to_string()));
+        assert!(paths.contains(&"/Garage/mainStream".to_string()));
+        assert!(!paths.contains(&"/Garage/subStream".to_string()));
+
+        camera.stream = StreamConfig::Sub;
+        assert!(camera.validate().is_err());
+        camera.serve_substream = true;
+        assert!(camera.all_rtsp_paths().contains(&"/Garage".to_string()));
+    }
+
+    #[test]
+    fn test_not_ready_image() {
+        assert_eq!(camera("").not_ready_image, None);
+        let with_image = camera(r#"not_ready_image = "/etc/neolink/Connecting.PNG""#);
+        assert!(with_image.validate().is_ok());
+        assert_eq!(
+            image_decoder(with_image.not_ready_image.as_deref().unwrap()),
+            Some("pngdec")
+        );
+        assert_eq!(image_decoder("logo.jpeg"), Some("jpegdec"));
+        assert!(camera(r#"not_ready_image = "/etc/neolink/logo.gif""#)
+            .validate()
+            .is_err());
+    }
+
+    #[test]
+    fn test_jitterbuffer() {
+        assert_eq!(camera("").jitterbuffer_ms, None);
+        let tuned = camera("jitterbuffer_ms = 500");
+        assert!(tuned.validate().is_ok());
+        assert_eq!(tuned.jitterbuffer_ms, Some(500));
+        assert!(camera("jitterbuffer_ms = 60000").validate().is_err());
+    }
+
+    #[test]
+    fn test_restream() {
+        // The old alias of push_notifications still works
+        let quiet = camera("push = false");
+        assert!(!quiet.push_notifications);
+        assert_eq!(quiet.restream, None);
+
+        let pushed = camera("[restream]\nurl = \"srt://192.168.1.60:9000\"\nprotocol = \"srt\"");
+        assert!(pushed.push_notifications);
+        let restream = pushed.restream.unwrap();
+        assert_eq!(restream.url, "srt://192.168.1.60:9000");
+        assert_eq!(restream.protocol, PushProtocol::Srt);
+    }
+
+    #[test]
+    fn test_max_retries() {
+        assert_eq!(camera("").max_retries, 0);
+        // No longer an alias of max_discovery_retries
+        let limited = camera("max_retries = 3");
+        assert_eq!(limited.max_retries, 3);
+        assert_eq!(
+            limited.max_discovery_retries,
+            default_max_discovery_retries()
+        );
+        assert_eq!(camera("retries = 3").max_discovery_retries, 3);
+    }
+
+    #[test]
+    fn test_failure_window() {
+        let default = camera("");
+        assert_eq!(default.max_failures_in_window, 0);
+        assert_eq!(default.failure_window_secs, 60);
+        let budget = camera("max_failures_in_window = 5\nfailure_window_secs = 120");
+        assert!(budget.validate().is_ok());
+        assert_eq!(budget.max_failures_in_window, 5);
+        assert!(camera("failure_window_secs = 0").validate().is_err());
+    }
+
+    #[test]
+    fn test_stream_list() {
+        assert_eq!(camera(r#"streams = ["sub"]"#).stream, StreamConfig::Sub);
+        assert_eq!(
+            camera(r#"streams = ["main", "sub"]"#).stream,
+            StreamConfig::Both
+        );
+        // The old single value form still works
+        assert_eq!(
+            camera(r#"stream = "mainStream""#).stream,
+            StreamConfig::Main
+        );
+    }
+
+    #[test]
+    fn test_expand_channels() {
+        let mut config: Config = toml::from_str(
+            r#"
+            [[cameras]]
+            name = "Garage"
+            username = "admin"
+            address = "192.168.1.10"
+            channels = [0, 1]
+
+            [[cameras]]
+            name = "Shed"
+            username = "admin"
+            address = "192.168.1.11"
+            "#,
+        )
+        .unwrap();
+        assert!(config.validate().is_ok());
+        config.expand_channels();
+        let cameras = config
+            .cameras
+            .iter()
+            .map(|camera| {
+                (
+                    camera.name.as_str(),
+                    camera.channel_id,
+                    camera.rtsp_base_path(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            cameras,
+            vec![
+                ("Garage-channel0", 0, "/Garage/channel0".to_string()),
+                ("Garage-channel1", 1, "/Garage/channel1".to_string()),
+                ("Shed", 0, "/Shed".to_string()),
+            ]
+        );
+        assert!(config.resolve_duplicate_names().is_ok());
+
+        config.cameras[2].channels = Some(vec![1, 1]);
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_connection_changed() {
+        let camera = camera("");
+        let mut other = camera.clone();
+        other.pause.on_motion = !camera.pause.on_motion;
+        other.rtsp_path = Some("/garage".to_string());
+        assert!(!camera.connection_changed(&other));
+        other.password = Some("secret".to_string());
+        assert!(camera.connection_changed(&other));
+    }
+
+    #[test]
+    fn test_source_addr() {
+        let camera = camera(r#"source_addr = "192.168.20.2""#);
+        assert_eq!(camera.source_addr, Some([192, 168, 20, 2].into()));
+        let mut other = camera.clone();
+        other.source_addr = None;
+        assert!(camera.connection_changed(&other));
+
+        assert!(toml::from_str::<CameraConfig>(
+            "name = \"Garage\"\nusername = \"admin\"\nsource_addr = \"eth0\"\n",
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_overlay_timezone() {
+        let overlay =
+            |timezone: &str| camera(&format!("[overlay]\ntimezone = \"{timezone}\"")).validate();
+        assert!(overlay("UTC").is_ok());
+        assert!(overlay("Mars/Olympus_Mons").is_err());
+        assert!(overlay("../../etc/passwd").is_err());
+        assert!(overlay("").is_err());
+
+        let overlay = camera("[overlay]").overlay.unwrap();
+        assert!(overlay.enabled);
+        assert_eq!(overlay.format, "%Y-%m-%d %H:%M:%S");
+    }
+
+    #[test]
+    fn test_schedule_windows() {
+        assert_eq!(parse_window("07:00-19:30"), Some((420, 1170)));
+        assert_eq!(parse_window("22:00 - 24:00"), Some((1320, 1440)));
+        assert_eq!(parse_window("07:00-07:00"), None);
+        assert_eq!(parse_window("24:00-01:00"), None);
+        assert_eq!(parse_window("7-19"), None);
+        assert_eq!(parse_window("07:60-19:00"), None);
+
+        let scheduled = camera("[pause.schedule]\nwindows = [\"06:00-08:00\", \"22:30-01:00\"]");
+        assert!(scheduled.validate().is_ok());
+        assert!(scheduled.pause.pauses());
+        let schedule = scheduled.pause.schedule.unwrap();
+        // Minutes of the day
+        assert!(!schedule.contains(5 * 60 + 59));
+        assert!(schedule.contains(6 * 60));
+        assert!(!schedule.contains(8 * 60));
+        assert!(schedule.contains(23 * 60));
+        // Past midnight
+        assert!(schedule.contains(30));
+        assert!(!schedule.contains(60));
+
+        assert!(camera("[pause.schedule]\nwindows = [\"late\"]")
+            .validate()
+            .is_err());
+    }
+
+    #[test]
+    fn test_camera_defaults() {
+        let config = Config::from_toml(
+            r#"
+[camera_defaults]
+username = "admin"
+password = "shared"
+  [camera_defaults.pause]
+  on_motion = true
+  mode = "black"
+
+[[cameras]]
+name = "Garage"
+address = "192.168.1.10"
+
+[[cameras]]
+name = "Driveway"
+address = "192.168.1.11"
+password = "different"
+  [cameras.pause]
+  mode = "none"
+"#,
+            None,
+        )
+        .unwrap();
+        let (garage, driveway) = (&config.cameras[0], &config.cameras[1]);
+        assert_eq!(garage.username, "admin");
+        assert_eq!(garage.password.as_deref(), Some("shared"));
+        assert!(garage.pause.on_motion);
+        assert_eq!(garage.pause.mode, "black");
+        // Only what the camera sets is overridden
+        assert_eq!(driveway.username, "admin");
+        assert_eq!(driveway.password.as_deref(), Some("different"));
+        assert!(driveway.pause.on_motion);
+        assert_eq!(driveway.pause.mode, "none");
+
+        // The other files of a config directory get the defaults of the main file
+        let other = Config::from_toml(
+            "[[cameras]]\nname = \"Porch\"\naddress = \"192.168.1.12\"\n",
+            config.camera_defaults.as_ref(),
+        )
+        .unwrap();
+        assert_eq!(other.cameras[0].username, "admin");
+
+        let e = Config::from_toml("[camera_defaults]\nname = \"Garage\"\n", None).unwrap_err();
+        assert!(format!("{e}").contains("cannot have a `name`"));
+    }
+
+    #[test]
+    fn test_camera_defaults_aliases() {
+        // A camera that sets a key under another name than the defaults keeps its own
+        let config = Config::from_toml(
+            r#"
+[camera_defaults]
+username = "admin"
+stream = "sub"
+  [camera_defaults.pause]
+  on_disconnect = true
+  timeout = 5.0
+
+[[cameras]]
+name = "Garage"
+address = "192.168.1.10"
+streams = ["main"]
+  [cameras.pause]
+  on_client = false
+  motion_timeout = 2.0
+"#,
+            None,
+        )
+        .unwrap();
+        let garage = &config.cameras[0];
+        assert_eq!(garage.stream, StreamConfig::Main);
+        assert!(!garage.pause.on_disconnect);
+        assert_eq!(garage.pause.motion_timeout, 2.0);
+        assert_eq!(canonical_key("", "idle_disc"), "idle_disconnect");
+        assert_eq!(canonical_key("pause", "on_client"), "pause.on_disconnect");
+    }
+
+    #[test]
+    fn test_expand_env() {
+        let lookup = |var: &str| match var {
+            "GARAGE_PW" => Some("secret".to_string()),
+            "EMPTY" => Some(String::new()),
+            _ => None,
+        };
+        assert_eq!(expand_env("plain", lookup), Ok(None));
+        assert_eq!(
+            expand_env("${GARAGE_PW}", lookup),
+            Ok(Some("secret".to_string()))
+        );
+        assert_eq!(
+            expand_env("a-${GARAGE_PW}-${UNSET:-b}-${EMPTY:-c}", lookup),
+            Ok(Some("a-secret-b-c".to_string()))
+        );
+        assert_eq!(
+            expand_env("$${GARAGE_PW} ${unclosed", lookup),
+            Ok(Some("${GARAGE_PW} ${unclosed".to_string()))
+        );
+        assert_eq!(expand_env("${UNSET}", lookup), Err("UNSET".to_string()));
+
+        std::env::set_var("NEOLINK_TEST_GARAGE_PW", "from-env");
+        let config = Config::from_toml(
+            r#"
+[[cameras]]
+name = "Garage"
+username = "admin"
+password = "${NEOLINK_TEST_GARAGE_PW}"
+address = "192.168.1.10"
+"#,
+            None,
+        )
+        .unwrap();
+        assert_eq!(config.cameras[0].password.as_deref(), Some("from-env"));
+
+        let e = Config::from_toml(
+            "[[cameras]]\nname = \"Garage\"\nusername = \"admin\"\npassword = \"${NEOLINK_TEST_UNSET}\"\n",
+            None,
+        )
+        .unwrap_err();
+        assert_eq!(
+            format!("{e}"),
+            "The environment variable `NEOLINK_TEST_UNSET` used by `cameras[0].password` is not set"
+        );
+    }
+
+    #[test]
+    fn test_config_dir() {
+        let dir = std::env::temp_dir().join(format!("neolink-config-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let camera = |name: &str| {
+            format!(
+                "[[cameras]]\nname = \"{}\"\nusername = \"admin\"\naddress = \"192.168.1.10\"\n",
+                name
+            )
+        };
+        fs::write(dir.join("a.toml"), camera("Garage")).unwrap();
+        fs::write(dir.join("neolink.toml"), "bind_port = 9554\n").unwrap();
+        fs::write(dir.join("notes.txt"), "not a config").unwrap();
+
+        let config = Config::load(&dir).unwrap();
+        assert_eq!(config.bind_port, 9554);
+        assert_eq!(config.cameras.len(), 1);
+
+        fs::write(dir.join("b.toml"), camera("Garage")).unwrap();
+        let e = Config::load(&dir).unwrap_err();
+        assert!(format!("{e}").contains("is in both"));
+
+        // A single file may have no cameras, a directory may not
+        let main_file = dir.join("neolink.toml");
+        assert!(Config::load(&main_file).unwrap().cameras.is_empty());
+        fs::remove_file(dir.join("a.toml")).unwrap();
+        fs::remove_file(dir.join("b.toml")).unwrap();
+        let e = Config::load(&dir).unwrap_err();
+        assert!(format!("{e}").contains("No [[cameras]]"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,19 @@
+use clap::{Parser, Subcommand};
+
+/// The config command shows the config as neolink understands it
+#[derive(Parser, Debug)]
+pub struct Opt {
+    #[command(subcommand)]
+    pub cmd: ConfigCommand,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ConfigCommand {
+    /// Print the config as toml after the defaults, environment variables and
+    /// `camera_defaults` have been applied
+    Dump {
+        /// Replace the passwords, tokens and push urls with `REDACTED`
+        #[arg(long)]
+        redact: bool,
+    },
+}
@@ -0,0 +1,119 @@
+///
+/// # Neolink Config Dump
+///
+/// This module prints the config that neolink runs with
+///
+/// It is the config after the `${VAR}` environment variables, the
+/// `camera_defaults`, the files of a config directory and the defaults of every
+/// setting have been applied, and after each camera has inherited the global
+/// settings. Cameras with `channels` are shown as the one camera per channel
+/// that they are served as
+///
+/// # Usage
+///
+/// ```bash
+/// neolink config --config=config.toml dump
+/// # Without the secrets, e.g. to share it in an issue
+/// neolink config --config=config.toml dump --redact
+/// ```
+///
+use anyhow::{Context, Result};
+
+mod cmdline;
+
+use crate::config::Config;
+pub(crate) use cmdline::{ConfigCommand, Opt};
+
+const REDACTED: &str = "REDACTED";
+
+/// Entry point for the config subcommand
+///
+/// Opt is the command line options
+pub(crate) fn main(opt: &Opt, config: &Config) -> Result<()> {
+    match opt.cmd {
+        ConfigCommand::Dump { redact } => {
+            let mut config = config.clone();
+            if redact {
+                redact_secrets(&mut config);
+            }
+            let toml = toml::to_string(&config).context("Could not write the config as toml")?;
+            print!("{toml}");
+        }
+    }
+    Ok(())
+}
+
+/// Replaces everything that lets someone into the cameras or the streams
+fn redact_secrets(config: &mut Config) {
+    let redacted = || REDACTED.to_string();
+    for secret in [
+        &mut config.certificate_password,
+        &mut config.http.certificate_password,
+    ] {
+        if secret.is_some() {
+            *secret = Some(redacted());
+        }
+    }
+    if let Some((_, pass)) = config
+        .mqtt
+        .as_mut()
+        .and_then(|mqtt| mqtt.credentials.as_mut())
+    {
+        *pass = redacted();
+    }
+    for user in config.users.iter_mut() {
+        user.pass = redacted();
+    }
+    for camera in config.cameras.iter_mut() {
+        if camera.password.is_some() {
+            camera.password = Some(redacted());
+        }
+        for token in camera.tokens.iter_mut() {
+            token.token = redacted();
+        }
+        // Ingest urls usually have the stream key in them
+        if let Some(push) = camera.restream.as_mut() {
+            push.url = redacted();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_redact_secrets() {
+        let mut config: Config = toml::from_str(
+            r#"
+certificate_password = "bundle"
+
+[mqtt]
+broker_addr = "127.0.0.1"
+port = 1883
+credentials = ["mqtt", "secret"]
+
+[[users]]
+name = "me"
+pass = "mepass"
+
+[[cameras]]
+name = "Garage"
+username = "admin"
+password = "hunter2"
+address = "192.168.1.10"
+  [[cameras.tokens]]
+  token = "c2VjcmV0LXRva2Vu"
+"#,
+        )
+        .unwrap();
+        redact_secrets(&mut config);
+        let toml = toml::to_string(&config).unwrap();
+        for secret in ["bundle", "secret", "mepass", "hunter2", "c2VjcmV0LXRva2Vu"] {
+            assert!(!toml.contains(secret), "{secret} is in\n{toml}");
+        }
+        assert_eq!(config.users[0].name, "me");
+        assert_eq!(config.cameras[0].username, "admin");
+        assert_eq!(config.http.certificate_password, None);
+    }
+}
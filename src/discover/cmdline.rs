@@ -0,0 +1,18 @@
+use clap::Parser;
+
+/// The discover command looks for cameras on the network and prints them as config
+#[derive(Parser, Debug)]
+pub struct Opt {
+    /// Look up these UIDs rather than every camera that answers the broadcast
+    #[arg(long = "uid")]
+    pub uids: Vec<String>,
+    /// Also ask the reolink p2p servers where the UIDs are registered
+    #[arg(long, requires = "uids")]
+    pub remote: bool,
+    /// Print the cameras as json rather than as config
+    #[arg(long)]
+    pub json: bool,
+    /// Seconds to wait for the cameras to answer
+    #[arg(long, default_value_t = 5)]
+    pub timeout: u64,
+}
@@ -0,0 +1,166 @@
+///
+/// # Neolink Discover
+///
+/// This module handles the discover subcommand
+///
+/// The subcommand finds cameras without a config. By default the discovery
+/// packet is broadcast on every local network and all the cameras that answer
+/// are listed. With `--uid` only those UIDs are looked for, which also works
+/// for cameras that ignore the broadcast, and with `--remote` the reolink p2p
+/// servers are asked where the cameras are registered. The cameras are printed
+/// as `[[cameras]]` tables to paste into the config or as json with `--json`
+///
+/// # Usage
+///
+/// ```bash
+/// neolink discover
+/// # Look for one camera, also through the reolink servers
+/// neolink discover --uid=95270000ABCDEFGH --remote --timeout=15
+/// ```
+///
+use anyhow::{anyhow, Context, Result};
+use futures::future::try_join_all;
+use neolink_core::bc_protocol::{discover_local, discover_uid, DiscoveredCamera, DiscoveryMethods};
+use serde::Serialize;
+use std::fmt::Write;
+use tokio::time::Duration;
+
+mod cmdline;
+
+pub(crate) use cmdline::Opt;
+
+/// The port that reolink cameras take their connections on unless it was changed
+const DEFAULT_PORT: u16 = 9000;
+
+/// A camera as it is printed
+#[derive(Debug, Serialize, PartialEq, Eq)]
+struct Found {
+    uid: Option<String>,
+    ip: String,
+    /// `local`, `remote` or `map` like `discovery` in the config
+    method: &'static str,
+}
+
+impl From<DiscoveredCamera> for Found {
+    fn from(camera: DiscoveredCamera) -> Self {
+        Self {
+            uid: camera.uid,
+            ip: camera.addr.ip().to_string(),
+            method: match camera.method {
+                DiscoveryMethods::Remote => "remote",
+                DiscoveryMethods::Map => "map",
+                _ => "local",
+            },
+        }
+    }
+}
+
+/// Entry point for the discover subcommand
+///
+/// Unlike the other subcommands there is no config to load
+pub(crate) async fn main(opt: &Opt) -> Result<()> {
+    let wait = Duration::from_secs(opt.timeout);
+    let cameras = if opt.uids.is_empty() {
+        discover_local(wait)
+            .await
+            .context("Failed to broadcast the discovery")?
+    } else {
+        try_join_all(
+            opt.uids
+                .iter()
+                .map(|uid| discover_uid(uid, opt.remote, wait)),
+        )
+        .await
+        .context("Failed to look up the UIDs")?
+        .into_iter()
+        .flatten()
+        .collect()
+    };
+    let found = cameras.into_iter().map(Found::from).collect::<Vec<_>>();
+
+    if opt.json {
+        println!("{}", serde_json::to_string_pretty(&found)?);
+    } else if found.is_empty() {
+        eprintln!("No cameras answered within {}s", opt.timeout);
+    } else {
+        print!("{}", to_config(&found));
+    }
+    if found.is_empty() {
+        return Err(anyhow!("No cameras found"));
+    }
+    Ok(())
+}
+
+/// The `[[cameras]]` tables of the cameras
+///
+/// A camera found more than one way is listed once for each, the comment says
+/// which. The name, username and password still have to be filled in
+fn to_config(found: &[Found]) -> String {
+    let mut config = String::new();
+    for (n, camera) in found.iter().enumerate() {
+        let _ = writeln!(config, "# Found at {} ({})", camera.ip, camera.method);
+        let _ = writeln!(config, "[[cameras]]");
+        let _ = writeln!(config, "name = \"Camera{}\"", n + 1);
+        let _ = writeln!(config, "username = \"admin\"");
+        let _ = writeln!(config, "password = \"password\"");
+        match camera.uid.as_ref() {
+            Some(uid) => {
+                let _ = writeln!(config, "uid = \"{uid}\"");
+                let _ = writeln!(config, "# address = \"{}:{DEFAULT_PORT}\"", camera.ip);
+                if camera.method != "local" {
+                    let _ = writeln!(config, "discovery = \"{}\"", camera.method);
+                }
+            }
+            None => {
+                let _ = writeln!(config, "address = \"{}:{DEFAULT_PORT}\"", camera.ip);
+            }
+        }
+        config.push('\n');
+    }
+    config
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_config() {
+        let found = vec![
+            Found {
+                uid: Some("95270000ABCDEFGH".to_string()),
+                ip: "203.0.113.7".to_string(),
+                method: "map",
+            },
+            Found {
+                uid: None,
+                ip: "192.168.1.10".to_string(),
+                method: "local",
+            },
+        ];
+        let config = to_config(&found);
+        assert_eq!(
+            config,
+            r#"# Found at 203.0.113.7 (map)
+[[cameras]]
+name = "Camera1"
+username = "admin"
+password = "password"
+uid = "95270000ABCDEFGH"
+# address = "203.0.113.7:9000"
+discovery = "map"
+
+# Found at 192.168.1.10 (local)
+[[cameras]]
+name = "Camera2"
+username = "admin"
+password = "password"
+address = "192.168.1.10:9000"
+
+"#
+        );
+        // And it is a valid config
+        let parsed: crate::config::Config = toml::from_str(&config).unwrap();
+        assert_eq!(parsed.cameras.len(), 2);
+    }
+}
@@ -0,0 +1,81 @@
+//! Exit codes of the process
+//!
+//! So that scripts can tell why neolink stopped, errors are tagged with an
+//! [`ExitError`] (as an anyhow context) and the tag decides the exit code
+//!
+//! | Code | Meaning                                          |
+//! |------|--------------------------------------------------|
+//! | 0    | Clean shutdown                                   |
+//! | 1    | Any other error                                  |
+//! | 2    | The config could not be loaded or is invalid     |
+//! | 3    | Every camera failed with an error that is fatal  |
+//! | 4    | A server could not bind to its address           |
+//! | 5    | Required gstreamer plugins are not installed     |
+use std::{fmt, process::ExitCode};
+
+/// The categories of failure that have their own exit code
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub(crate) enum ExitError {
+    Config,
+    AllCamerasFatal,
+    Bind,
+    Plugins,
+}
+
+impl ExitError {
+    fn code(&self) -> u8 {
+        match self {
+            ExitError::Config => 2,
+            ExitError::AllCamerasFatal => 3,
+            ExitError::Bind => 4,
+            ExitError::Plugins => 5,
+        }
+    }
+}
+
+impl fmt::Display for ExitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExitError::Config => write!(f, "Invalid configuration"),
+            ExitError::AllCamerasFatal => write!(f, "All cameras failed and will not be retried"),
+            ExitError::Bind => write!(f, "Failed to bind the server"),
+            ExitError::Plugins => write!(f, "Required gstreamer plugins are missing"),
+        }
+    }
+}
+
+impl std::error::Error for ExitError {}
+
+/// The exit code for an error
+pub(crate) fn exit_code(error: &anyhow::Error) -> ExitCode {
+    exit_error(error)
+        .map(|tagged| ExitCode::from(tagged.code()))
+        .unwrap_or(ExitCode::FAILURE)
+}
+
+/// The outermost tag in the contexts of the error
+fn exit_error(error: &anyhow::Error) -> Option<ExitError> {
+    error.downcast_ref::<ExitError>().copied()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::{anyhow, Context};
+
+    #[test]
+    fn test_exit_error() {
+        let untagged = anyhow!("Camera not found");
+        assert_eq!(exit_error(&untagged), None);
+
+        let config = Err::<(), _>(anyhow!("Missing field"))
+            .context("Failed to validate the config file")
+            .context(ExitError::Config)
+            .unwrap_err();
+        assert_eq!(exit_error(&config), Some(ExitError::Config));
+
+        let fatal = anyhow!(ExitError::AllCamerasFatal).context("Camera Manager");
+        assert_eq!(exit_error(&fatal), Some(ExitError::AllCamerasFatal));
+        assert_eq!(ExitError::AllCamerasFatal.code(), 3);
+    }
+}
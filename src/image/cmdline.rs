@@ -6,8 +6,17 @@ use std::str::FromStr;
 #[derive(Parser, Debug)]
 pub struct Opt {
     /// The name of the camera to get the image from. Must be a name in the config
-    pub camera: String,
+    /// or a glob like `Front*` to get an image of all the cameras it matches
+    #[arg(required_unless_present = "all")]
+    pub camera: Option<String>,
+    /// Get an image of every enabled camera in the config
+    #[arg(long, conflicts_with = "camera")]
+    pub all: bool,
     /// The path of the output.
+    ///
+    /// `{camera}` in it is replaced by the name of the camera. When there is
+    /// more than one camera and it lacks `{camera}` the name is added to the
+    /// file name, e.g. `snap-Garage.jpeg`
     #[structopt(short, long, value_parser = PathBuf::from_str)]
     pub file_path: PathBuf,
     /// If set then the image will pull from the live stream, if not it will be pulled from the cameras snap feature
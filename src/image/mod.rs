@@ -17,24 +17,72 @@
 /// neolink image --config=config.toml --use_stream --file-path=filepath CameraName
 /// ```
 ///
+/// A glob or `--all` gets an image of each camera at the same time, the name
+/// of the camera goes in place of `{camera}` in the path or at the end of the
+/// file name. A summary is printed and it only fails if every camera failed
+///
+/// ```bash
+/// neolink snapshot --config=config.toml --file-path='snaps/{camera}' '*'
+/// ```
+///
 use anyhow::{Context, Result};
 use futures::stream::StreamExt;
 use log::*;
 use neolink_core::bc_protocol::*;
+use std::path::{Path, PathBuf};
 use tokio::{fs::File, io::AsyncWriteExt};
 use tokio_stream::wrappers::BroadcastStream;
 
 mod cmdline;
 mod gst;
 
-use crate::common::{NeoReactor, StampedData};
+use crate::{
+    common::{NeoReactor, StampedData},
+    utils::CameraSelection,
+};
 pub(crate) use cmdline::Opt;
 
 /// Entry point for the image subcommand
 ///
 /// Opt is the command line options
 pub(crate) async fn main(opt: Opt, reactor: NeoReactor) -> Result<()> {
-    let camera = reactor.get(&opt.camera).await?;
+    let config = reactor.config().await?.borrow().clone();
+    let selection = CameraSelection::new(&config, opt.camera.as_deref(), opt.all)?;
+    let many = matches!(selection, CameraSelection::Many(_));
+    let (opt, reactor) = (&opt, &reactor);
+    selection
+        .run(|name| {
+            let file_path = camera_path(&opt.file_path, &name, many);
+            image(opt, reactor, name, file_path)
+        })
+        .await
+}
+
+/// Where the image of `camera` is saved
+///
+/// `{camera}` in the path is replaced by its name, without it the name is
+/// added to the file name when there are `many` cameras
+fn camera_path(file_path: &Path, camera: &str, many: bool) -> PathBuf {
+    let path = file_path.to_string_lossy();
+    if path.contains("{camera}") {
+        return PathBuf::from(path.replace("{camera}", camera));
+    }
+    if !many {
+        return file_path.to_path_buf();
+    }
+    let stem = file_path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let mut file_name = format!("{stem}-{camera}");
+    if let Some(extension) = file_path.extension() {
+        file_name = format!("{file_name}.{}", extension.to_string_lossy());
+    }
+    file_path.with_file_name(file_name)
+}
+
+async fn image(opt: &Opt, reactor: &NeoReactor, name: String, file_path: PathBuf) -> Result<()> {
+    let camera = reactor.get(&name).await?;
 
     if opt.use_stream {
         let stream_data = camera
@@ -64,7 +112,7 @@ pub(crate) async fn main(opt: Opt, reactor: NeoReactor) -> Result<()> {
             }
         };
 
-        let mut sender = gst::from_input(vid_type, &opt.file_path).await?;
+        let mut sender = gst::from_input(vid_type, &file_path).await?;
         sender.send(buf).await?; // Send first iframe
 
         // Keep sending both IFrame or PFrame until finished
@@ -89,7 +137,7 @@ pub(crate) async fn main(opt: Opt, reactor: NeoReactor) -> Result<()> {
     } else {
         // Simply use the snap command
         debug!("Using the snap command");
-        let file_path = opt.file_path.with_extension("jpeg");
+        let file_path = file_path.with_extension("jpeg");
         let mut buffer = File::create(file_path).await?;
         let jpeg_data = camera
             .run_task(|camera| Box::pin(async move { Ok(camera.get_snapshot().await?) }))
@@ -103,3 +151,22 @@ pub(crate) async fn main(opt: Opt, reactor: NeoReactor) -> Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_camera_path() {
+        let path = Path::new("snaps/snap.jpeg");
+        assert_eq!(camera_path(path, "Garage", false), path);
+        assert_eq!(
+            camera_path(path, "Garage", true),
+            Path::new("snaps/snap-Garage.jpeg")
+        );
+        assert_eq!(
+            camera_path(Path::new("snaps/{camera}/latest"), "Front Door", true),
+            Path::new("snaps/Front Door/latest")
+        );
+    }
+}
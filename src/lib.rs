@@ -0,0 +1,341 @@
+#![warn(unused_crate_dependencies)]
+#![warn(missing_docs)]
+#![warn(clippy::todo)]
+//!
+//! # Neolink
+//!
+//! Neolink is a small program that acts a general contol interface for Reolink IP cameras.
+//!
+//! It contains sub commands for running an rtsp proxy which can be used on Reolink cameras
+//! that do not nativly support RTSP.
+//!
+//! The rtsp proxy can also be embedded in another program with [`NeolinkServer`]
+//!
+//! ```no_run
+//! # async fn example() -> anyhow::Result<()> {
+//! let config: neolink::Config = toml::from_str(
+//!     r#"
+//! [[cameras]]
+//! name = "Garage"
+//! username = "admin"
+//! password = "password"
+//! uid = "ABCDEF0123456789"
+//! "#,
+//! )?;
+//! let server = neolink::NeolinkServer::builder()
+//!     .config(config)
+//!     .status_port(8080)
+//!     .build()?;
+//! server.start().await?;
+//! if let Some(status) = server.camera_status("Garage") {
+//!     println!("Garage is {}", status.state);
+//! }
+//! server.stop().await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! This program is free software: you can redistribute it and/or modify it under the terms of the
+//! GNU General Public License as published by the Free Software Foundation, either version 3 of
+//! the License, or (at your option) any later version.
+//!
+//! This program is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+//! without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+//! the GNU General Public License for more details.
+//!
+//! You should have received a copy of the GNU General Public License along with this program. If
+//! not, see <https://www.gnu.org/licenses/>.
+//!
+//! Neolink source code is available online at <https://github.com/thirtythreeforty/neolink>
+//!
+use anyhow::{Context, Result};
+use clap::Parser;
+use log::*;
+use std::{path::PathBuf, process::ExitCode};
+use tokio::runtime::Runtime;
+// Only the binary sets the allocator
+#[cfg(not(target_env = "msvc"))]
+use tikv_jemallocator as _;
+
+mod battery;
+mod check;
+mod checkdeps;
+mod cmdline;
+mod common;
+mod config;
+mod configdump;
+mod discover;
+mod exit;
+mod image;
+mod light;
+mod logging;
+mod mqtt;
+#[cfg(feature = "onvif")]
+mod onvif;
+mod pir;
+mod ptz;
+mod reboot;
+mod rtsp;
+mod server;
+mod statusled;
+mod talk;
+mod utils;
+mod validate;
+
+use cmdline::{Command, Opt};
+use common::NeoReactor;
+use console_subscriber as _;
+use exit::{exit_code, ExitError};
+
+pub use config::{CameraConfig, Config};
+pub use rtsp::{
+    metrics::{CameraStatus, StreamStatus},
+    SessionStats,
+};
+pub use server::{NeolinkServer, NeolinkServerBuilder};
+
+pub(crate) type AnyResult<T> = Result<T, anyhow::Error>;
+
+#[cfg(tokio_unstable)]
+fn tokio_console_enable() {
+    info!("Tokio Console Enabled");
+    console_subscriber::init();
+}
+
+#[cfg(not(tokio_unstable))]
+fn tokio_console_enable() {
+    debug!("Tokio Console Disabled");
+}
+
+/// Runs neolink with the command line arguments of the process
+///
+/// This is all that the `neolink` binary does. The tokio runtime is made here
+/// so that it follows the `worker_threads` and `max_blocking_threads` of the
+/// config. The exit codes are documented in the `exit` module
+pub fn cli() -> ExitCode {
+    let opt = Opt::parse();
+    logging::init(opt.log_format);
+    info!(
+        "Neolink {} {}",
+        env!("NEOLINK_VERSION"),
+        env!("NEOLINK_PROFILE")
+    );
+
+    let result = initial_config(&opt).and_then(|config| {
+        build_runtime(config.as_ref())
+            .context("Failed to start the tokio runtime")?
+            .block_on(run(opt, config))
+    });
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {:?}", e);
+            exit_code(&e)
+        }
+    }
+}
+
+/// The config that the command runs with, loaded once before the runtime
+///
+/// None for the commands that do without one or, like validate, load it
+/// themselves
+fn initial_config(opt: &Opt) -> Result<Option<Config>> {
+    match opt.cmd.as_ref() {
+        Some(Command::Validate(_)) | Some(Command::Discover(_)) => Ok(None),
+        Some(Command::CheckDeps(_)) if opt.config.is_none() => Ok(None),
+        _ => Ok(Some(
+            load_config(opt.config.clone()).context(ExitError::Config)?,
+        )),
+    }
+}
+
+/// The tokio runtime with the thread limits of the config
+fn build_runtime(config: Option<&Config>) -> Result<Runtime> {
+    let mut builder = tokio::runtime::Builder::new_multi_thread();
+    builder.enable_all();
+    if let Some(worker_threads) = config.and_then(|config| config.worker_threads) {
+        builder.worker_threads(worker_threads.max(1));
+    }
+    if let Some(max_blocking_threads) = config.and_then(|config| config.max_blocking_threads) {
+        builder.max_blocking_threads(max_blocking_threads.max(1));
+    }
+    Ok(builder.build()?)
+}
+
+async fn run(opt: Opt, config: Option<Config>) -> Result<()> {
+    // Validate reports every problem of the config so it loads it itself
+    if let Some(Command::Validate(opts)) = opt.cmd.as_ref() {
+        return validate::main(opts, opt.config);
+    }
+    // Discover is for before there is a config
+    if let Some(Command::Discover(opts)) = opt.cmd.as_ref() {
+        return discover::main(opts).await;
+    }
+
+    // Check deps works with or without a config
+    if let Some(Command::CheckDeps(opts)) = opt.cmd.as_ref() {
+        return checkdeps::main(opts, config.as_ref());
+    }
+
+    let conf_path = opt.config.clone();
+    let Some(config) = config else {
+        unreachable!("Loaded before the runtime is built");
+    };
+    if let Some(Command::Config(opts)) = opt.cmd.as_ref() {
+        return configdump::main(opts, &config);
+    }
+    // The gstreamer elements are looked up before anything is served
+    let serves_rtsp = matches!(
+        opt.cmd,
+        None | Some(Command::Rtsp(_)) | Some(Command::MqttRtsp(_))
+    );
+    let config = if serves_rtsp {
+        rtsp::deps::prepare(config)?
+    } else {
+        config
+    };
+
+    logging::set_cameras(
+        config
+            .cameras
+            .iter()
+            .map(|cam| (cam.name.clone(), cam.log_level.clone())),
+    );
+    for cam in config.cameras.iter().filter(|cam| !cam.enabled) {
+        info!(
+            "{}: Disabled in the config, it will not be started",
+            cam.name
+        );
+    }
+
+    if config.tokio_console {
+        tokio_console_enable();
+    }
+
+    let neo_reactor = NeoReactor::new(config.clone()).await;
+
+    // The long running commands pick up changes to the config on SIGHUP
+    let reloads = matches!(
+        opt.cmd,
+        None | Some(Command::Rtsp(_)) | Some(Command::Mqtt(_)) | Some(Command::MqttRtsp(_))
+    );
+    tokio::select! {
+        v = run_command(opt.cmd, config, &neo_reactor) => v,
+        v = reload_on_hangup(conf_path, serves_rtsp, &neo_reactor), if reloads => v,
+    }
+}
+
+async fn run_command(cmd: Option<Command>, config: Config, neo_reactor: &NeoReactor) -> Result<()> {
+    match cmd {
+        None => {
+            warn!(
+                "Deprecated command line option. Please use: `neolink rtsp --config={:?}`",
+                config
+            );
+            rtsp::main(Default::default(), neo_reactor.clone()).await?;
+        }
+        Some(Command::Rtsp(opts)) => {
+            rtsp::main(opts, neo_reactor.clone()).await?;
+        }
+        Some(Command::StatusLight(opts)) => {
+            statusled::main(opts, neo_reactor.clone()).await?;
+        }
+        Some(Command::Light(opts)) => {
+            light::main(opts, neo_reactor.clone()).await?;
+        }
+        Some(Command::Reboot(opts)) => {
+            reboot::main(opts, neo_reactor.clone()).await?;
+        }
+        Some(Command::Pir(opts)) => {
+            pir::main(opts, neo_reactor.clone()).await?;
+        }
+        Some(Command::Ptz(opts)) => {
+            ptz::main(opts, neo_reactor.clone()).await?;
+        }
+        Some(Command::Talk(opts)) => {
+            talk::main(opts, neo_reactor.clone()).await?;
+        }
+        Some(Command::Mqtt(opts)) => {
+            mqtt::main(opts, neo_reactor.clone()).await?;
+        }
+        Some(Command::MqttRtsp(opts)) => {
+            tokio::select! {
+                v = mqtt::main(opts, neo_reactor.clone()) => v,
+                v = rtsp::main(Default::default(), neo_reactor.clone()) => v,
+            }?;
+        }
+        Some(Command::Image(opts)) => {
+            image::main(opts, neo_reactor.clone()).await?;
+        }
+        Some(Command::Battery(opts)) => {
+            battery::main(opts, neo_reactor.clone()).await?;
+        }
+        Some(Command::Check(opts)) => {
+            check::main(opts, config).await?;
+        }
+        Some(Command::Validate(_))
+        | Some(Command::Discover(_))
+        | Some(Command::Config(_))
+        | Some(Command::CheckDeps(_)) => {
+            unreachable!("Handled before the cameras are started")
+        }
+    }
+
+    Ok(())
+}
+
+/// Loads the config again on SIGHUP and hands it to the cameras
+///
+/// Cameras that were added or removed are started or stopped, the others keep
+/// streaming and only reconnect if how they connect has changed. An invalid
+/// config is logged and the current one is kept. With `serves_rtsp` the
+/// features that miss a gstreamer element are turned off again
+async fn reload_on_hangup(
+    conf_path: Option<PathBuf>,
+    serves_rtsp: bool,
+    reactor: &NeoReactor,
+) -> Result<()> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::hangup()) {
+            Ok(mut hangup) => {
+                while hangup.recv().await.is_some() {
+                    info!("Reloading the config on SIGHUP");
+                    let config = load_config(conf_path.clone()).and_then(|config| {
+                        if serves_rtsp {
+                            rtsp::deps::prepare(config)
+                        } else {
+                            Ok(config)
+                        }
+                    });
+                    match config {
+                        Ok(config) => {
+                            logging::set_cameras(
+                                config
+                                    .cameras
+                                    .iter()
+                                    .map(|cam| (cam.name.clone(), cam.log_level.clone())),
+                            );
+                            reactor.update_config(config).await?;
+                            info!("Reloaded the config");
+                        }
+                        Err(e) => error!("Keeping the current config: {:?}", e),
+                    }
+                }
+            }
+            Err(e) => warn!("Cannot reload the config on SIGHUP: {e}"),
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = (conf_path, serves_rtsp, reactor);
+    futures::future::pending().await
+}
+
+fn load_config(conf_path: Option<PathBuf>) -> Result<Config> {
+    let conf_path = conf_path.context("Must supply --config file")?;
+    let config = Config::load(&conf_path)?;
+    config
+        .prepare()
+        .with_context(|| format!("Failed to validate the {:?} config file", conf_path))
+}
@@ -0,0 +1,34 @@
+use anyhow::{anyhow, Result};
+use clap::Parser;
+
+fn onoff_parse(src: &str) -> Result<bool> {
+    match src {
+        "true" | "on" | "yes" => Ok(true),
+        "false" | "off" | "no" => Ok(false),
+        _ => Err(anyhow!(
+            "Could not understand {}, check your input, should be true/false, on/off or yes/no",
+            src
+        )),
+    }
+}
+
+/// The light command will control the floodlight and the status led of the camera
+///
+/// With no options it prints their current state
+#[derive(Parser, Debug)]
+pub struct Opt {
+    /// The name of the camera to change the lights of. Must be a name in the config
+    pub camera: String,
+    /// Whether to turn the floodlight on or off
+    #[arg(long, value_parser = onoff_parse, value_name = "on|off")]
+    pub floodlight: Option<bool>,
+    /// How many seconds the floodlight stays on for once turned on
+    #[arg(long, default_value_t = 180)]
+    pub duration: u16,
+    /// The brightness of the floodlight from 0 to 100
+    #[arg(long, value_parser = clap::value_parser!(u8).range(0..=100))]
+    pub brightness: Option<u8>,
+    /// Whether to turn the status led on or off
+    #[arg(long, value_parser = onoff_parse, value_name = "on|off")]
+    pub led: Option<bool>,
+}
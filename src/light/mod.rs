@@ -0,0 +1,130 @@
+///
+/// # Neolink Light
+///
+/// This module handles the controls of the floodlight and the status led
+///
+/// The floodlight is turned on for `--duration` seconds, after which the
+/// camera goes back to its own floodlight schedule. Cameras without a
+/// floodlight or status led give an error rather than ignoring the command
+///
+/// # Usage
+///
+/// ```bash
+/// # To turn the floodlight on at half brightness
+/// neolink light --config=config.toml CameraName --floodlight=on --brightness=50
+/// # To turn the status led off
+/// neolink light --config=config.toml CameraName --led=off
+/// # To print the state of the lights
+/// neolink light --config=config.toml CameraName
+/// ```
+///
+use anyhow::{anyhow, Context, Result};
+
+mod cmdline;
+
+use crate::common::NeoReactor;
+pub(crate) use cmdline::Opt;
+
+/// Entry point for the light subcommand
+///
+/// Opt is the command line options
+pub(crate) async fn main(opt: Opt, reactor: NeoReactor) -> Result<()> {
+    let camera = reactor.get(&opt.camera).await?;
+
+    if opt.led.is_none() && opt.floodlight.is_none() && opt.brightness.is_none() {
+        let (led, floodlight) = camera
+            .run_task(|cam| {
+                Box::pin(async move {
+                    let led = match cam.get_ledstate().await {
+                        Err(neolink_core::Error::MissingAbility { name, .. })
+                            if name == "ledState" =>
+                        {
+                            None
+                        }
+                        led => Some(led.context("Unable to get camera LED state")?),
+                    };
+                    let floodlight = cam.get_flightlight_tasks().await.ok();
+                    Ok((led, floodlight))
+                })
+            })
+            .await?;
+        match led {
+            Some(led) => {
+                println!("led: {}", onoff(led.light_state == "open"));
+                println!("ir: {}", led.state);
+            }
+            None => println!("led: none"),
+        }
+        match floodlight {
+            Some(floodlight) => {
+                println!("floodlight auto: {}", onoff(floodlight.enable == 1));
+                println!("floodlight brightness: {}", floodlight.brightness_cur);
+            }
+            None => println!("floodlight: none"),
+        }
+        return Ok(());
+    }
+
+    if let Some(on) = opt.led {
+        camera
+            .run_task(|cam| {
+                Box::pin(async move {
+                    cam.led_light_set(on)
+                        .await
+                        .context("Unable to set camera LED state")
+                })
+            })
+            .await
+            .map_err(|e| match is_missing_led(&e) {
+                true => anyhow!("{}: This camera does not have a status LED", opt.camera),
+                false => e,
+            })?;
+    }
+
+    if opt.floodlight.is_some() || opt.brightness.is_some() {
+        let (floodlight, brightness, duration) = (opt.floodlight, opt.brightness, opt.duration);
+        camera
+            .run_task(|cam| {
+                Box::pin(async move {
+                    // Cameras without a floodlight refuse its settings but may
+                    // silently accept the manual control
+                    let mut tasks = cam
+                        .get_flightlight_tasks()
+                        .await
+                        .map_err(|_| anyhow!("This camera does not have a floodlight"))?;
+                    if let Some(brightness) = brightness {
+                        tasks.brightness_cur = (brightness as u32)
+                            .max(tasks.brightness_min.unwrap_or(0))
+                            .min(tasks.brightness_max.unwrap_or(100));
+                        cam.set_flightlight_tasks(tasks)
+                            .await
+                            .context("Unable to set camera floodlight brightness")?;
+                    }
+                    if let Some(on) = floodlight {
+                        cam.set_floodlight_manual(on, duration)
+                            .await
+                            .context("Unable to set camera floodlight state")?;
+                    }
+                    Ok(())
+                })
+            })
+            .await
+            .with_context(|| format!("{}: Could not control the floodlight", opt.camera))?;
+    }
+
+    Ok(())
+}
+
+fn onoff(on: bool) -> &'static str {
+    match on {
+        true => "on",
+        false => "off",
+    }
+}
+
+fn is_missing_led(e: &anyhow::Error) -> bool {
+    matches!(
+        e.downcast_ref::<neolink_core::Error>(),
+        Some(neolink_core::Error::MissingAbility { name, .. }) if name == "ledState"
+    )
+}
@@ -0,0 +1,173 @@
+//! Sets up the logger
+//!
+//! By default logs are human readable text. With `--log-format json` (or
+//! `NEOLINK_LOG_FORMAT=json`) each log is a single line of json with the fields
+//! `timestamp`, `level`, `target`, `camera` and `message`
+//!
+//! The camera is taken from the `"<camera name>: "` prefix used by the log messages
+//!
+//! The logs of a camera are given the target `neolink::camera::<camera name>`
+//! so that `RUST_LOG` can pick out one camera, e.g.
+//! `RUST_LOG=info,neolink::camera::Garage=debug`. The `log_level` of a camera
+//! in the config adds the same filter and wins over `RUST_LOG`
+use env_logger::Env;
+use lazy_static::lazy_static;
+use log::{LevelFilter, Log, Metadata, Record};
+use std::{io::Write, str::FromStr, sync::RwLock};
+
+/// The targets of the camera logs start with this
+const CAMERA_TARGET: &str = "neolink::camera::";
+
+lazy_static! {
+    /// Names of the cameras so they can be split out of the messages
+    static ref CAMERAS: RwLock<Vec<String>> = RwLock::new(vec![]);
+    /// The logger, rebuilt when the `log_level` of the cameras change
+    static ref LOGGER: RwLock<Option<(LogFormat, env_logger::Logger)>> = RwLock::new(None);
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum LogFormat {
+    /// Human readable text
+    #[default]
+    Human,
+    /// One json object per line
+    Json,
+}
+
+pub(crate) fn init(format: LogFormat) {
+    let logger = builder(format, &[]).build();
+    log::set_max_level(logger.filter());
+    *LOGGER.write().unwrap() = Some((format, logger));
+    let _ = log::set_logger(&CameraLogger);
+}
+
+/// The env_logger with the filters of `RUST_LOG` and the `(camera, level)` overrides
+fn builder(format: LogFormat, levels: &[(String, LevelFilter)]) -> env_logger::Builder {
+    let mut builder = env_logger::Builder::from_env(Env::default().default_filter_or("info"));
+    for (camera, level) in levels.iter() {
+        builder.filter_module(&format!("{CAMERA_TARGET}{camera}"), *level);
+    }
+    if format == LogFormat::Json {
+        builder.format(|buf, record| {
+            let message = record.args().to_string();
+            let cameras = CAMERAS.read().unwrap();
+            let (camera, message) = split_camera(&message, &cameras);
+            let line = serde_json::json!({
+                "timestamp": buf.timestamp().to_string(),
+                "level": record.level().to_string(),
+                "target": record.target(),
+                "camera": camera,
+                "message": message,
+            });
+            writeln!(buf, "{}", line)
+        });
+    }
+    builder
+}
+
+/// Gives the logs of each camera its own target before they are filtered
+struct CameraLogger;
+
+impl Log for CameraLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        let logger = LOGGER.read().unwrap();
+        let Some((_, logger)) = logger.as_ref() else {
+            return;
+        };
+        let message = record.args().to_string();
+        // Not held while logging as the json format reads it too
+        let camera = {
+            let cameras = CAMERAS.read().unwrap();
+            split_camera(&message, &cameras)
+                .0
+                .map(|camera| camera.to_string())
+        };
+        match camera {
+            Some(camera) => {
+                let target = format!("{CAMERA_TARGET}{camera}");
+                logger.log(
+                    &Record::builder()
+                        .args(*record.args())
+                        .level(record.level())
+                        .target(&target)
+                        .module_path(record.module_path())
+                        .file(record.file())
+                        .line(record.line())
+                        .build(),
+                );
+            }
+            None => logger.log(record),
+        }
+    }
+
+    fn flush(&self) {
+        if let Some((_, logger)) = LOGGER.read().unwrap().as_ref() {
+            logger.flush();
+        }
+    }
+}
+
+/// Set the names of the cameras that are split into the `camera` field and
+/// their `log_level`, if they have one
+pub(crate) fn set_cameras<I: IntoIterator<Item = (String, Option<String>)>>(cameras: I) {
+    let cameras = cameras.into_iter().collect::<Vec<_>>();
+    let levels = cameras
+        .iter()
+        .filter_map(|(name, level)| {
+            let level = LevelFilter::from_str(level.as_ref()?).ok()?;
+            Some((name.clone(), level))
+        })
+        .collect::<Vec<_>>();
+    let mut names = cameras
+        .into_iter()
+        .map(|(name, _)| name)
+        .collect::<Vec<_>>();
+    // Longest first so that `Garage Side` is not taken as `Garage`
+    names.sort_by_key(|name| std::cmp::Reverse(name.len()));
+    *CAMERAS.write().unwrap() = names;
+
+    let mut logger = LOGGER.write().unwrap();
+    if let Some((format, _)) = logger.as_ref() {
+        let new = builder(*format, &levels).build();
+        log::set_max_level(new.filter());
+        *logger = Some((*format, new));
+    }
+}
+
+fn split_camera<'a>(message: &'a str, cameras: &[String]) -> (Option<&'a str>, &'a str) {
+    for camera in cameras.iter() {
+        if let Some(rest) = message
+            .strip_prefix(camera.as_str())
+            .and_then(|rest| rest.strip_prefix(": "))
+        {
+            return (Some(&message[..camera.len()]), rest);
+        }
+    }
+    (None, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_camera() {
+        let cameras = vec!["Garage Side".to_string(), "Garage".to_string()];
+        assert_eq!(
+            split_camera("Garage: Connected and logged in", &cameras),
+            (Some("Garage"), "Connected and logged in")
+        );
+        assert_eq!(
+            split_camera("Garage Side: Logging in", &cameras),
+            (Some("Garage Side"), "Logging in")
+        );
+        assert_eq!(
+            split_camera("Garage door opened", &cameras),
+            (None, "Garage door opened")
+        );
+    }
+}
@@ -1,6 +1,4 @@
-#![warn(unused_crate_dependencies)]
 #![warn(missing_docs)]
-#![warn(clippy::todo)]
 //!
 //! # Neolink
 //!
@@ -29,116 +27,9 @@ use tikv_jemallocator::Jemalloc;
 #[global_allocator]
 static GLOBAL: Jemalloc = Jemalloc;
 
-use anyhow::{Context, Result};
-use clap::Parser;
-use env_logger::Env;
-use log::*;
-use std::fs;
-use validator::Validate;
+use std::process::ExitCode;
 
-mod battery;
-mod cmdline;
-mod common;
-mod config;
-mod image;
-mod mqtt;
-mod pir;
-mod ptz;
-mod reboot;
-mod rtsp;
-mod statusled;
-mod talk;
-mod utils;
-
-use cmdline::{Command, Opt};
-use common::NeoReactor;
-use config::Config;
-use console_subscriber as _;
-
-pub(crate) type AnyResult<T> = Result<T, anyhow::Error>;
-
-#[cfg(tokio_unstable)]
-fn tokio_console_enable() {
-    info!("Tokio Console Enabled");
-    console_subscriber::init();
-}
-
-#[cfg(not(tokio_unstable))]
-fn tokio_console_enable() {
-    debug!("Tokio Console Disabled");
-}
-
-#[tokio::main]
-async fn main() -> Result<()> {
-    env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
-
-    info!(
-        "Neolink {} {}",
-        env!("NEOLINK_VERSION"),
-        env!("NEOLINK_PROFILE")
-    );
-
-    let opt = Opt::parse();
-
-    let conf_path = opt.config.context("Must supply --config file")?;
-    let config: Config = toml::from_str(
-        &fs::read_to_string(&conf_path)
-            .with_context(|| format!("Failed to read {:?}", conf_path))?,
-    )
-    .with_context(|| format!("Failed to parse the {:?} config file", conf_path))?;
-
-    config
-        .validate()
-        .with_context(|| format!("Failed to validate the {:?} config file", conf_path))?;
-
-    if config.tokio_console {
-        tokio_console_enable();
-    }
-
-    let neo_reactor = NeoReactor::new(config.clone()).await;
-
-    match opt.cmd {
-        None => {
-            warn!(
-                "Deprecated command line option. Please use: `neolink rtsp --config={:?}`",
-                config
-            );
-            rtsp::main(rtsp::Opt {}, neo_reactor.clone()).await?;
-        }
-        Some(Command::Rtsp(opts)) => {
-            rtsp::main(opts, neo_reactor.clone()).await?;
-        }
-        Some(Command::StatusLight(opts)) => {
-            statusled::main(opts, neo_reactor.clone()).await?;
-        }
-        Some(Command::Reboot(opts)) => {
-            reboot::main(opts, neo_reactor.clone()).await?;
-        }
-        Some(Command::Pir(opts)) => {
-            pir::main(opts, neo_reactor.clone()).await?;
-        }
-        Some(Command::Ptz(opts)) => {
-            ptz::main(opts, neo_reactor.clone()).await?;
-        }
-        Some(Command::Talk(opts)) => {
-            talk::main(opts, neo_reactor.clone()).await?;
-        }
-        Some(Command::Mqtt(opts)) => {
-            mqtt::main(opts, neo_reactor.clone()).await?;
-        }
-        Some(Command::MqttRtsp(opts)) => {
-            tokio::select! {
-                v = mqtt::main(opts, neo_reactor.clone()) => v,
-                v = rtsp::main(rtsp::Opt {}, neo_reactor.clone()) => v,
-            }?;
-        }
-        Some(Command::Image(opts)) => {
-            image::main(opts, neo_reactor.clone()).await?;
-        }
-        Some(Command::Battery(opts)) => {
-            battery::main(opts, neo_reactor.clone()).await?;
-        }
-    }
-
-    Ok(())
+/// The exit codes are documented in the `exit` module
+fn main() -> ExitCode {
+    neolink::cli()
 }
@@ -14,6 +14,7 @@
 //! - `/control/pir [on|off]` Turns PIR on/off
 //! - `/control/ir [on|off|auto]` Turn IR lights on/off or automatically via light detection
 //! - `/control/reboot` Reboot the camera
+//! - `/control/pause [pause|resume|auto]` Force the streams to pause or stream whatever the `[pause]` config says, `auto` follows it again
 //! - `/control/ptz` [up|down|left|right|in|out] (amount) Control the PTZ movements, amount defaults to 32.0
 //! - `/control/ptz/preset` [id] Move the camera to a known preset
 //! - `/control/ptz/assign` [id] [name] Assign the current ptz position to an ID and name
@@ -22,6 +23,7 @@
 //!
 //! `/status offline` Sent when the neolink goes offline this is a LastWill message
 //! `/status disconnected` Sent when the camera goes offline
+//! `/status/stream [streaming|paused]` Sent when the camera starts or stops sending video
 //! `/status/battery` Sent in reply to a `/query/battery`
 //! `/status/pir` Sent in reply to a `/query/pir`
 //! `/status/ptz/preset` Sent in reply to a `/query/ptz/preset`
@@ -77,7 +79,7 @@ mod discovery;
 mod mqttc;
 
 use crate::{
-    common::{MdState, NeoInstance, NeoReactor},
+    common::{ForcePause, MdState, NeoInstance, NeoReactor},
     config::Config,
     AnyResult,
 };
@@ -117,10 +119,9 @@ pub(crate) async fn main(_: Opt, reactor: NeoReactor) -> Result<()> {
                 let mut config_names = HashSet::new();
                 loop {
                     thread_config.wait_for(|config| {
-                        let current_names = config.cameras.iter().filter(|a| a.enabled).map(|cam_config| cam_config.name.clone()).collect::<HashSet<_>>();
-                        current_names != config_names
+                        config.enabled_names() != config_names
                     }).await.with_context(|| "Camera Config Watcher")?;
-                    config_names = thread_config.borrow().clone().cameras.iter().filter(|a| a.enabled).map(|cam_config| cam_config.name.clone()).collect::<HashSet<_>>();
+                    config_names = thread_config.borrow().enabled_names();
 
                     for name in config_names.iter() {
                         log::info!("{name}: MQTT Staring");
@@ -204,7 +205,7 @@ pub(crate) async fn main(_: Opt, reactor: NeoReactor) -> Result<()> {
             v = async {
                 while let Ok(msg) = thread_instance.recv().await {
                     if msg.topic == "config" {
-                        let config: Result<Config> = toml::from_str(&msg.message).with_context(|| {
+                        let config: Result<Config> = Config::from_toml(&msg.message, None).with_context(|| {
                             format!("Failed to parse the MQTT {:?} config file", msg.topic)
                         });
                         if let Err(e) = config {
@@ -213,7 +214,7 @@ pub(crate) async fn main(_: Opt, reactor: NeoReactor) -> Result<()> {
                                 .await?;
                             continue;
                         }
-                        let config = config?;
+                        let mut config = config?;
 
                         let validate = config.validate().with_context(|| {
                             format!("Failed to validate the MQTT {:?} config file", msg.topic)
@@ -225,6 +226,21 @@ pub(crate) async fn main(_: Opt, reactor: NeoReactor) -> Result<()> {
                             continue;
                         }
 
+                        if let Err(e) = config.inherit_globals() {
+                            thread_instance
+                                .send_message("config/status", &format!("{:?}", e), false)
+                                .await?;
+                            continue;
+                        }
+
+                        config.expand_channels();
+                        if let Err(e) = config.resolve_duplicate_names() {
+                            thread_instance
+                                .send_message("config/status", &format!("{:?}", e), false)
+                                .await?;
+                            continue;
+                        }
+
                         if (*thread_config.borrow()) == config {
                             continue;
                         }
@@ -294,6 +310,9 @@ async fn listen_on_camera(camera: NeoInstance, mqtt_instance: MqttInstance) -> R
                 let mut camera_watch = camera.camera();
                 let mqtt_watch = mqtt_instance.resubscribe().await?;
 
+                let mut active_streams = camera.active_streams().await?;
+                let mqtt_active = mqtt_instance.resubscribe().await?;
+
                 let camera_floodlight = camera.clone();
                 let mqtt_floodlight = mqtt_instance.resubscribe().await?;
 
@@ -373,6 +392,19 @@ async fn listen_on_camera(camera: NeoInstance, mqtt_instance: MqttInstance) -> R
                         log::debug!("CamConnection returned: {v:?}");
                         v
                     },
+                    // Handle the stream being paused/resumed
+                    v = async {
+                        loop {
+                            let streaming = !active_streams.borrow_and_update().is_empty();
+                            let state = if streaming { "streaming" } else { "paused" };
+                            mqtt_active.send_message("status/stream", state, true).await.with_context(|| {
+                                format!("{}: Failed to publish stream state", camera_name)
+                            })?;
+                            active_streams.changed().await.with_context(|| {
+                                format!("{}: Stream State Watch Dropped", camera_name)
+                            })?;
+                        }
+                    } => v,
                     // Handle the floodlight
                     v = async {
                         let (tx, mut rx) = mpsc(100);
@@ -819,6 +851,31 @@ async fn handle_mqtt_message(
                 .await
                 .with_context(|| "Failed to publish reboot on the camera")?;
         }
+        MqttReplyRef {
+            topic: "control/pause",
+            message,
+        } => {
+            let force = match message {
+                "pause" => Some(Some(ForcePause::Pause)),
+                "resume" => Some(Some(ForcePause::Resume)),
+                "auto" => Some(None),
+                _ => None,
+            };
+            let reply = match force {
+                Some(force) => match camera.set_force_pause(force).await {
+                    Ok(()) => "OK",
+                    Err(e) => {
+                        error!("Failed to force the pause: {:?}", e);
+                        "FAIL"
+                    }
+                },
+                None => "FAIL",
+            }
+            .to_string();
+            mqtt.send_message("control/pause", &reply, false)
+                .await
+                .with_context(|| "Failed to publish pause")?;
+        }
         MqttReplyRef {
             topic: "control/zoom",
             message,
@@ -18,7 +18,7 @@ use tokio::{
         oneshot::{channel as oneshot, Sender as OneshotSender},
         watch::Receiver as WatchReceiver,
     },
-    time::{sleep, Duration},
+    time::{sleep, Duration, Instant},
 };
 use tokio_stream::{wrappers::BroadcastStream, StreamExt};
 use tokio_util::sync::CancellationToken;
@@ -43,8 +43,13 @@ impl Mqtt {
         let thread_incoming_tx = incoming_tx;
         let thread_outgoing_tx = outgoing_tx.clone();
         set.spawn(async move {
+            // Broker reconnects backoff on their own schedule, separate from the cameras
+            const MIN_BACKOFF: Duration = Duration::from_secs(2);
+            const MAX_BACKOFF: Duration = Duration::from_secs(60);
+            let mut backoff = MIN_BACKOFF;
             let mut mqtt_config = thread_config.borrow().mqtt.clone();
             let r = loop {
+                let started = Instant::now();
                 break tokio::select! {
                     _ = thread_cancel.cancelled() => AnyResult::Ok(()),
                     v = thread_config.wait_for(|config| config.mqtt != mqtt_config).map(|res| res.map(|r| r.clone())) =>
@@ -63,8 +68,14 @@ impl Mqtt {
                         backend.run().await
                     }, if mqtt_config.is_some() => {
                         if let Err(e) = &v {
+                            if started.elapsed() > MAX_BACKOFF {
+                                // Was connected long enough to be considered a success
+                                backoff = MIN_BACKOFF;
+                            }
                             log::error!("MQTT Client Connection Failed: {:?}", e);
-                            sleep(Duration::from_secs(2)).await;
+                            log::info!("MQTT reconnecting in {:?}", backoff);
+                            sleep(backoff).await;
+                            backoff = std::cmp::min(backoff * 2, MAX_BACKOFF);
                             continue;
                         }
                         v
@@ -0,0 +1,110 @@
+//! Answers the WS-Discovery probes that NVRs multicast to find cameras
+use anyhow::Context;
+use std::net::Ipv4Addr;
+use tokio::net::UdpSocket;
+use tokio_util::sync::CancellationToken;
+
+use super::{element_text, envelope, escape, message_id, Device};
+use crate::AnyResult;
+
+const MULTICAST_ADDR: Ipv4Addr = Ipv4Addr::new(239, 255, 255, 250);
+const DISCOVERY_PORT: u16 = 3702;
+
+/// Answers probes until cancelled
+pub(super) async fn respond(device: &Device, cancel: CancellationToken) -> AnyResult<()> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, DISCOVERY_PORT))
+        .await
+        .with_context(|| format!("Cannot listen for ONVIF discovery on port {DISCOVERY_PORT}"))?;
+    socket
+        .join_multicast_v4(MULTICAST_ADDR, Ipv4Addr::UNSPECIFIED)
+        .context("Cannot join the ONVIF discovery multicast group")?;
+    log::info!("Answering ONVIF discovery as urn:uuid:{}", device.uuid);
+
+    let mut buf = vec![0u8; 65536];
+    loop {
+        let (len, from) = tokio::select! {
+            _ = cancel.cancelled() => return Ok(()),
+            v = socket.recv_from(&mut buf) => v?,
+        };
+        let message = String::from_utf8_lossy(&buf[..len]);
+        if let Some(reply) = probe_match(&message, device) {
+            log::debug!("ONVIF discovery probe from {from}");
+            if let Err(e) = socket.send_to(reply.as_bytes(), from).await {
+                log::debug!("Could not answer the ONVIF probe from {from}: {e}");
+            }
+        }
+    }
+}
+
+/// The reply to a probe, if the message is a probe for a device like us
+fn probe_match(message: &str, device: &Device) -> Option<String> {
+    element_text(message, "Probe")?;
+    // No types matches any device
+    let types = element_text(message, "Types").unwrap_or_default();
+    let is_probed = types.is_empty()
+        || types.split_whitespace().any(|probed| {
+            matches!(
+                probed.rsplit(':').next(),
+                Some("NetworkVideoTransmitter") | Some("Device")
+            )
+        });
+    if !is_probed {
+        return None;
+    }
+    let relates_to = element_text(message, "MessageID").unwrap_or_default();
+
+    let header = format!(
+        concat!(
+            "<wsa:MessageID>{}</wsa:MessageID>",
+            "<wsa:RelatesTo>{}</wsa:RelatesTo>",
+            "<wsa:To>http://schemas.xmlsoap.org/ws/2004/08/addressing/role/anonymous</wsa:To>",
+            "<wsa:Action>http://schemas.xmlsoap.org/ws/2005/04/discovery/ProbeMatches</wsa:Action>",
+        ),
+        message_id(),
+        escape(relates_to)
+    );
+    let body = format!(
+        concat!(
+            "<d:ProbeMatches><d:ProbeMatch>",
+            "<wsa:EndpointReference><wsa:Address>urn:uuid:{}</wsa:Address></wsa:EndpointReference>",
+            "<d:Types>dn:NetworkVideoTransmitter</d:Types>",
+            "<d:Scopes>onvif://www.onvif.org/type/video_encoder onvif://www.onvif.org/Profile/Streaming",
+            " onvif://www.onvif.org/name/Neolink onvif://www.onvif.org/hardware/Neolink</d:Scopes>",
+            "<d:XAddrs>{}</d:XAddrs>",
+            "<d:MetadataVersion>1</d:MetadataVersion>",
+            "</d:ProbeMatch></d:ProbeMatches>",
+        ),
+        device.uuid,
+        escape(&device.xaddr("device_service"))
+    );
+    Some(envelope(&header, &body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::OnvifConfig;
+
+    #[test]
+    fn test_probe_match() {
+        let device = Device::new(&OnvifConfig {
+            address: "192.168.1.2".to_string(),
+            port: 8000,
+            discovery: true,
+        });
+        let probe = r#"<?xml version="1.0" encoding="utf-8"?>
+            <Envelope xmlns:dn="http://www.onvif.org/ver10/network/wsdl" xmlns="http://www.w3.org/2003/05/soap-envelope">
+            <Header><wsa:MessageID xmlns:wsa="http://schemas.xmlsoap.org/ws/2004/08/addressing">uuid:84ede3de-7dec-11d0-c360-f01234567890</wsa:MessageID></Header>
+            <Body><Probe xmlns="http://schemas.xmlsoap.org/ws/2005/04/discovery"><Types>dn:NetworkVideoTransmitter</Types><Scopes /></Probe></Body>
+            </Envelope>"#;
+        let reply = probe_match(probe, &device).unwrap();
+        assert!(reply
+            .contains("<wsa:RelatesTo>uuid:84ede3de-7dec-11d0-c360-f01234567890</wsa:RelatesTo>"));
+        assert!(reply.contains("<d:XAddrs>http://192.168.1.2:8000/onvif/device_service</d:XAddrs>"));
+
+        // Our own reply and probes for other kinds of device are ignored
+        assert!(probe_match(&reply, &device).is_none());
+        let printer = probe.replace("dn:NetworkVideoTransmitter", "wprt:PrintDeviceType");
+        assert!(probe_match(&printer, &device).is_none());
+    }
+}
@@ -0,0 +1,183 @@
+//! ONVIF discovery and media service
+//!
+//! NVRs such as Blue Iris and Synology Surveillance Station find and add
+//! cameras over ONVIF. When neolink is built with the `onvif` feature and the
+//! config has an `[onvif]` section, neolink answers WS-Discovery probes and
+//! serves a minimal ONVIF device and media service. Every stream of every
+//! enabled camera is an ONVIF profile whose stream uri is its rtsp url
+//!
+//! Only the calls NVRs make to add a camera are implemented:
+//!
+//! - Device: `GetSystemDateAndTime`, `GetDeviceInformation`, `GetCapabilities`, `GetServices`
+//! - Media: `GetProfiles`, `GetStreamUri`
+//!
+//! The WS-Security header is not checked, the rtsp streams are still
+//! protected by the users of the config
+use std::{net::SocketAddr, sync::Arc};
+use tokio::sync::watch::Receiver as WatchReceiver;
+use tokio_util::sync::CancellationToken;
+
+mod discovery;
+mod service;
+
+use crate::{
+    config::{Config, OnvifConfig},
+    rtsp::metrics::Metrics,
+    AnyResult,
+};
+
+/// How neolink presents itself as an ONVIF device
+struct Device {
+    /// Stays the same across restarts so NVRs recognise the device
+    uuid: String,
+    /// The address NVRs reach neolink on
+    address: String,
+    port: u16,
+}
+
+impl Device {
+    fn new(onvif: &OnvifConfig) -> Self {
+        let digest = md5::compute(format!("neolink-onvif:{}:{}", onvif.address, onvif.port));
+        Self {
+            uuid: format_uuid(u128::from_be_bytes(digest.0)),
+            address: onvif.address.clone(),
+            port: onvif.port,
+        }
+    }
+
+    fn xaddr(&self, service: &str) -> String {
+        format!("http://{}:{}/onvif/{}", self.address, self.port, service)
+    }
+}
+
+/// Runs the ONVIF service on `addr` and, if enabled, the discovery responder
+pub(crate) async fn main(
+    onvif: OnvifConfig,
+    addr: SocketAddr,
+    config: WatchReceiver<Config>,
+    metrics: Arc<Metrics>,
+    cancel: CancellationToken,
+) -> AnyResult<()> {
+    let device = Arc::new(Device::new(&onvif));
+    let discover = async {
+        if onvif.discovery {
+            discovery::respond(&device, cancel.clone()).await
+        } else {
+            Ok(())
+        }
+    };
+    tokio::try_join!(
+        discover,
+        service::serve(addr, device.clone(), config, metrics, cancel.clone())
+    )?;
+    Ok(())
+}
+
+/// Formats 128 bits as a uuid
+fn format_uuid(bits: u128) -> String {
+    let hex = format!("{:032x}", bits);
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[0..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..32]
+    )
+}
+
+/// A random uuid for a message
+fn message_id() -> String {
+    format!("urn:uuid:{}", format_uuid(rand::random()))
+}
+
+/// The text of the first element with this local name, whatever its namespace
+///
+/// This is only meant for the small, flat SOAP requests of ONVIF clients
+fn element_text<'a>(xml: &'a str, name: &str) -> Option<&'a str> {
+    let mut rest = xml;
+    while let Some(start) = rest.find('<') {
+        rest = &rest[start + 1..];
+        let end = rest.find(['>', ' ', '/', '\t', '\r', '\n'])?;
+        let tag = &rest[..end];
+        let local = tag.rsplit(':').next().unwrap_or(tag);
+        if local != name || tag.starts_with('/') {
+            continue;
+        }
+        let close = rest.find('>')?;
+        if rest[..close].ends_with('/') {
+            return Some("");
+        }
+        let text = &rest[close + 1..];
+        let text_end = text.find('<').unwrap_or(text.len());
+        return Some(text[..text_end].trim());
+    }
+    None
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+/// Wraps the body in a SOAP envelope with the ONVIF namespaces
+fn envelope(header: &str, body: &str) -> String {
+    format!(
+        concat!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>"#,
+            r#"<SOAP-ENV:Envelope xmlns:SOAP-ENV="http://www.w3.org/2003/05/soap-envelope""#,
+            r#" xmlns:wsa="http://schemas.xmlsoap.org/ws/2004/08/addressing""#,
+            r#" xmlns:d="http://schemas.xmlsoap.org/ws/2005/04/discovery""#,
+            r#" xmlns:dn="http://www.onvif.org/ver10/network/wsdl""#,
+            r#" xmlns:tds="http://www.onvif.org/ver10/device/wsdl""#,
+            r#" xmlns:trt="http://www.onvif.org/ver10/media/wsdl""#,
+            r#" xmlns:tt="http://www.onvif.org/ver10/schema""#,
+            r#" xmlns:ter="http://www.onvif.org/ver10/error">"#,
+            "<SOAP-ENV:Header>{}</SOAP-ENV:Header>",
+            "<SOAP-ENV:Body>{}</SOAP-ENV:Body>",
+            "</SOAP-ENV:Envelope>"
+        ),
+        header, body
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_element_text() {
+        let xml = r#"<s:Envelope><s:Header><a:MessageID>uuid:1234</a:MessageID></s:Header>
+            <s:Body><trt:GetStreamUri><trt:ProfileToken> Garage_mainStream </trt:ProfileToken>
+            <trt:Empty/></trt:GetStreamUri></s:Body></s:Envelope>"#;
+        assert_eq!(element_text(xml, "MessageID"), Some("uuid:1234"));
+        assert_eq!(element_text(xml, "ProfileToken"), Some("Garage_mainStream"));
+        assert_eq!(element_text(xml, "Empty"), Some(""));
+        assert_eq!(element_text(xml, "GetProfiles"), None);
+        // Without a namespace prefix
+        assert_eq!(
+            element_text(
+                "<Probe><Types>dn:NetworkVideoTransmitter</Types></Probe>",
+                "Types"
+            ),
+            Some("dn:NetworkVideoTransmitter")
+        );
+    }
+
+    #[test]
+    fn test_device_uuid() {
+        let onvif = OnvifConfig {
+            address: "192.168.1.2".to_string(),
+            port: 8000,
+            discovery: true,
+        };
+        let uuid = Device::new(&onvif).uuid;
+        assert_eq!(uuid.len(), 36);
+        // Stable so that NVRs do not see a new device each restart
+        assert_eq!(uuid, Device::new(&onvif).uuid);
+        assert_eq!(escape("<a & b>"), "&lt;a &amp; b&gt;");
+    }
+}
@@ -0,0 +1,341 @@
+//! The ONVIF device and media service
+//!
+//! Requests are matched on the element of the SOAP body rather than the url
+//! as clients differ in which service url they post to
+use hyper::{Body, Method, Request, Response, StatusCode};
+use neolink_core::bc_protocol::StreamKind;
+use std::{
+    net::SocketAddr,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tokio::sync::watch::Receiver as WatchReceiver;
+use tokio_util::sync::CancellationToken;
+
+use super::{element_text, envelope, escape, Device};
+use crate::{
+    config::Config,
+    rtsp::{
+        http::{self, not_found},
+        metrics::Metrics,
+    },
+    AnyResult,
+};
+
+const SOAP_CONTENT_TYPE: &str = "application/soap+xml; charset=utf-8";
+
+/// Serves the ONVIF service on `addr` until cancelled
+pub(super) async fn serve(
+    addr: SocketAddr,
+    device: Arc<Device>,
+    config: WatchReceiver<Config>,
+    metrics: Arc<Metrics>,
+    cancel: CancellationToken,
+) -> AnyResult<()> {
+    http::serve(
+        addr,
+        None,
+        move |req| {
+            let device = device.clone();
+            let config = config.clone();
+            let metrics = metrics.clone();
+            async move { handle(req, &device, &config, &metrics).await }
+        },
+        cancel,
+    )
+    .await
+}
+
+/// A camera stream as an ONVIF profile
+struct Profile {
+    token: String,
+    name: String,
+    uri: String,
+    width: u32,
+    height: u32,
+    fps: u32,
+}
+
+async fn handle(
+    req: Request<Body>,
+    device: &Device,
+    config: &WatchReceiver<Config>,
+    metrics: &Metrics,
+) -> Response<Body> {
+    if req.method() != Method::POST || !req.uri().path().starts_with("/onvif/") {
+        return not_found();
+    }
+    let body = match hyper::body::to_bytes(req.into_body()).await {
+        Ok(body) => body,
+        Err(e) => return fault("ter:InvalidArgVal", &format!("Could not read request: {e}")),
+    };
+    let request = String::from_utf8_lossy(&body);
+    let is = |action: &str| element_text(&request, action).is_some();
+
+    let reply = if is("GetSystemDateAndTime") {
+        system_date_and_time()
+    } else if is("GetDeviceInformation") {
+        device_information(device)
+    } else if is("GetCapabilities") {
+        capabilities(device)
+    } else if is("GetServices") {
+        services(device)
+    } else if is("GetProfiles") {
+        let profiles = profiles(device, &config.borrow(), metrics);
+        format!(
+            "<trt:GetProfilesResponse>{}</trt:GetProfilesResponse>",
+            profiles.iter().map(profile_xml).collect::<String>()
+        )
+    } else if is("GetStreamUri") {
+        let token = element_text(&request, "ProfileToken").unwrap_or_default();
+        let profiles = profiles(device, &config.borrow(), metrics);
+        match profiles.iter().find(|profile| profile.token == token) {
+            Some(profile) => stream_uri(profile),
+            None => {
+                return fault(
+                    "ter:NoProfile",
+                    &format!("There is no profile {}", escape(token)),
+                )
+            }
+        }
+    } else {
+        log::debug!("Unsupported ONVIF request: {request}");
+        return fault(
+            "ter:ActionNotSupported",
+            "This action is not supported by neolink",
+        );
+    };
+    http::response(StatusCode::OK, SOAP_CONTENT_TYPE, envelope("", &reply))
+}
+
+/// Every stream of every enabled camera
+fn profiles(device: &Device, config: &Config, metrics: &Metrics) -> Vec<Profile> {
+    let statuses = metrics.camera_status();
+    config
+        .cameras
+        .iter()
+        .filter(|camera| camera.enabled)
+        .flat_map(|camera| {
+            let (_, port) = camera.rtsp_bind(&config.bind_addr, config.bind_port);
+            let status = statuses.iter().find(|status| status.name == camera.name);
+            camera
+                .stream_kinds()
+                .into_iter()
+                .filter_map(move |kind: StreamKind| {
+                    let path = camera.rtsp_paths(kind).into_iter().next()?;
+                    // Zero until the stream has been started once
+                    let format = status
+                        .and_then(|status| {
+                            status
+                                .streams
+                                .iter()
+                                .find(|stream| stream.stream == kind.to_string())
+                        })
+                        .map(|stream| (stream.width, stream.height, stream.fps))
+                        .unwrap_or_default();
+                    Some(Profile {
+                        token: format!("{}_{}", camera.name, kind),
+                        name: format!("{} {}", camera.name, kind),
+                        uri: format!("rtsp://{}:{}{}", device.address, port, path),
+                        width: format.0,
+                        height: format.1,
+                        fps: format.2,
+                    })
+                })
+        })
+        .collect()
+}
+
+fn profile_xml(profile: &Profile) -> String {
+    let token = escape(&profile.token);
+    let name = escape(&profile.name);
+    format!(
+        concat!(
+            r#"<trt:Profiles token="{token}" fixed="true"><tt:Name>{name}</tt:Name>"#,
+            r#"<tt:VideoSourceConfiguration token="{token}_source"><tt:Name>{name}</tt:Name>"#,
+            "<tt:UseCount>1</tt:UseCount><tt:SourceToken>{token}_source</tt:SourceToken>",
+            r#"<tt:Bounds x="0" y="0" width="{width}" height="{height}"/>"#,
+            "</tt:VideoSourceConfiguration>",
+            r#"<tt:VideoEncoderConfiguration token="{token}_encoder"><tt:Name>{name}</tt:Name>"#,
+            "<tt:UseCount>1</tt:UseCount><tt:Encoding>H264</tt:Encoding>",
+            "<tt:Resolution><tt:Width>{width}</tt:Width><tt:Height>{height}</tt:Height></tt:Resolution>",
+            "<tt:Quality>5</tt:Quality>",
+            "<tt:RateControl><tt:FrameRateLimit>{fps}</tt:FrameRateLimit>",
+            "<tt:EncodingInterval>1</tt:EncodingInterval><tt:BitrateLimit>0</tt:BitrateLimit></tt:RateControl>",
+            "<tt:SessionTimeout>PT60S</tt:SessionTimeout>",
+            "</tt:VideoEncoderConfiguration></trt:Profiles>",
+        ),
+        token = token,
+        name = name,
+        width = profile.width,
+        height = profile.height,
+        fps = profile.fps,
+    )
+}
+
+fn stream_uri(profile: &Profile) -> String {
+    format!(
+        concat!(
+            "<trt:GetStreamUriResponse><trt:MediaUri><tt:Uri>{}</tt:Uri>",
+            "<tt:InvalidAfterConnect>false</tt:InvalidAfterConnect>",
+            "<tt:InvalidAfterReboot>false</tt:InvalidAfterReboot>",
+            "<tt:Timeout>PT0S</tt:Timeout></trt:MediaUri></trt:GetStreamUriResponse>",
+        ),
+        escape(&profile.uri)
+    )
+}
+
+fn device_information(device: &Device) -> String {
+    format!(
+        concat!(
+            "<tds:GetDeviceInformationResponse><tds:Manufacturer>Neolink</tds:Manufacturer>",
+            "<tds:Model>Neolink</tds:Model><tds:FirmwareVersion>{}</tds:FirmwareVersion>",
+            "<tds:SerialNumber>{}</tds:SerialNumber><tds:HardwareId>neolink</tds:HardwareId>",
+            "</tds:GetDeviceInformationResponse>",
+        ),
+        env!("NEOLINK_VERSION"),
+        device.uuid
+    )
+}
+
+fn capabilities(device: &Device) -> String {
+    format!(
+        concat!(
+            "<tds:GetCapabilitiesResponse><tds:Capabilities>",
+            "<tt:Device><tt:XAddr>{}</tt:XAddr></tt:Device>",
+            "<tt:Media><tt:XAddr>{}</tt:XAddr><tt:StreamingCapabilities>",
+            "<tt:RTPMulticast>false</tt:RTPMulticast><tt:RTP_TCP>true</tt:RTP_TCP>",
+            "<tt:RTP_RTSP_TCP>true</tt:RTP_RTSP_TCP></tt:StreamingCapabilities></tt:Media>",
+            "</tds:Capabilities></tds:GetCapabilitiesResponse>",
+        ),
+        escape(&device.xaddr("device_service")),
+        escape(&device.xaddr("media_service"))
+    )
+}
+
+fn services(device: &Device) -> String {
+    let service = |namespace: &str, xaddr: String| {
+        format!(
+            concat!(
+                "<tds:Service><tds:Namespace>{}</tds:Namespace><tds:XAddr>{}</tds:XAddr>",
+                "<tds:Version><tt:Major>2</tt:Major><tt:Minor>0</tt:Minor></tds:Version></tds:Service>",
+            ),
+            namespace,
+            escape(&xaddr)
+        )
+    };
+    format!(
+        "<tds:GetServicesResponse>{}{}</tds:GetServicesResponse>",
+        service(
+            "http://www.onvif.org/ver10/device/wsdl",
+            device.xaddr("device_service")
+        ),
+        service(
+            "http://www.onvif.org/ver10/media/wsdl",
+            device.xaddr("media_service")
+        ),
+    )
+}
+
+fn system_date_and_time() -> String {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|since| since.as_secs())
+        .unwrap_or_default() as i64;
+    let (year, month, day) = civil_from_days(secs.div_euclid(86400));
+    let time = secs.rem_euclid(86400);
+    format!(
+        concat!(
+            "<tds:GetSystemDateAndTimeResponse><tds:SystemDateAndTime>",
+            "<tt:DateTimeType>NTP</tt:DateTimeType><tt:DaylightSavings>false</tt:DaylightSavings>",
+            "<tt:TimeZone><tt:TZ>UTC</tt:TZ></tt:TimeZone><tt:UTCDateTime>",
+            "<tt:Time><tt:Hour>{}</tt:Hour><tt:Minute>{}</tt:Minute><tt:Second>{}</tt:Second></tt:Time>",
+            "<tt:Date><tt:Year>{}</tt:Year><tt:Month>{}</tt:Month><tt:Day>{}</tt:Day></tt:Date>",
+            "</tt:UTCDateTime></tds:SystemDateAndTime></tds:GetSystemDateAndTimeResponse>",
+        ),
+        time / 3600,
+        time % 3600 / 60,
+        time % 60,
+        year,
+        month,
+        day
+    )
+}
+
+/// The (year, month, day) of days since the unix epoch
+///
+/// From Howard Hinnant's date algorithms
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = (if mp < 10 { mp + 3 } else { mp - 9 }) as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+fn fault(subcode: &str, reason: &str) -> Response<Body> {
+    let body = format!(
+        concat!(
+            "<SOAP-ENV:Fault><SOAP-ENV:Code><SOAP-ENV:Value>SOAP-ENV:Sender</SOAP-ENV:Value>",
+            "<SOAP-ENV:Subcode><SOAP-ENV:Value>{}</SOAP-ENV:Value></SOAP-ENV:Subcode></SOAP-ENV:Code>",
+            r#"<SOAP-ENV:Reason><SOAP-ENV:Text xml:lang="en">{}</SOAP-ENV:Text></SOAP-ENV:Reason>"#,
+            "</SOAP-ENV:Fault>",
+        ),
+        subcode, reason
+    );
+    http::response(
+        StatusCode::BAD_REQUEST,
+        SOAP_CONTENT_TYPE,
+        envelope("", &body),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_civil_from_days() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(19723), (2024, 1, 1));
+        assert_eq!(civil_from_days(19782), (2024, 2, 29));
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+    }
+
+    #[test]
+    fn test_profiles() {
+        let config: Config = toml::from_str(
+            r#"
+            [[cameras]]
+            name = "Garage Door"
+            username = "admin"
+            address = "192.168.1.10"
+            stream = "mainStream"
+
+            [[cameras]]
+            name = "shed"
+            username = "admin"
+            address = "192.168.1.11"
+            enabled = false
+            "#,
+        )
+        .unwrap();
+        let device = Device {
+            uuid: "uuid".to_string(),
+            address: "192.168.1.2".to_string(),
+            port: 8000,
+        };
+        let profiles = profiles(&device, &config, &Metrics::default());
+        assert_eq!(profiles.len(), 1);
+        assert_eq!(profiles[0].token, "Garage Door_mainStream");
+        assert_eq!(
+            profiles[0].uri,
+            "rtsp://192.168.1.2:8554/Garage%20Door/main"
+        );
+    }
+}
@@ -20,4 +20,7 @@ pub struct Opt {
     /// Whether to turn the PIR ON or OFF
     #[arg(value_parser = onoff_parse, action = clap::ArgAction::Set, name = "on|off")]
     pub on: Option<bool>,
+    /// The sensitivity of the PIR sensor from 0 to 100
+    #[arg(long, value_parser = clap::value_parser!(u8).range(0..=100))]
+    pub sensitivity: Option<u8>,
 }
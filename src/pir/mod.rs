@@ -11,9 +11,11 @@
 /// neolink pir --config=config.toml CameraName on
 /// # Or off
 /// neolink pir --config=config.toml CameraName off
+/// # To change how sensitive it is
+/// neolink pir --config=config.toml CameraName on --sensitivity=50
 /// ```
 ///
-use anyhow::{Context, Result};
+use anyhow::{anyhow, Context, Result};
 
 mod cmdline;
 
@@ -26,7 +28,26 @@ pub(crate) use cmdline::Opt;
 pub(crate) async fn main(opt: Opt, reactor: NeoReactor) -> Result<()> {
     let camera = reactor.get(&opt.camera).await?;
 
-    if let Some(on) = opt.on {
+    let result = if let Some(sensitivity) = opt.sensitivity {
+        let on = opt.on;
+        camera
+            .run_task(|cam| {
+                Box::pin(async move {
+                    let mut pir_state = cam
+                        .get_pirstate()
+                        .await
+                        .context("Unable to get camera PIR state")?;
+                    if let Some(on) = on {
+                        pir_state.enable = on as u8;
+                    }
+                    pir_state.sensitivity = sensitivity;
+                    cam.set_pirstate(pir_state)
+                        .await
+                        .context("Unable to set camera PIR state")
+                })
+            })
+            .await
+    } else if let Some(on) = opt.on {
         camera
             .run_task(|cam| {
                 Box::pin(async move {
@@ -35,9 +56,9 @@ pub(crate) async fn main(opt: Opt, reactor: NeoReactor) -> Result<()> {
                         .context("Unable to set camera PIR state")
                 })
             })
-            .await?;
+            .await
     } else {
-        let pir_state = camera
+        camera
             .run_task(|cam| {
                 Box::pin(async move {
                     cam.get_pirstate()
@@ -45,14 +66,29 @@ pub(crate) async fn main(opt: Opt, reactor: NeoReactor) -> Result<()> {
                         .context("Unable to get camera PIR state")
                 })
             })
-            .await?;
-        let pir_ser = String::from_utf8(
-            yaserde::ser::serialize_with_writer(&pir_state, vec![], &Default::default())
-                .expect("Should Ser the struct"),
-        )
-        .expect("Should be UTF8");
-        println!("{}", pir_ser);
+            .await
+            .map(|pir_state| {
+                let pir_ser = String::from_utf8(
+                    yaserde::ser::serialize_with_writer(&pir_state, vec![], &Default::default())
+                        .expect("Should Ser the struct"),
+                )
+                .expect("Should be UTF8");
+                println!("{}", pir_ser);
+            })
+    };
+
+    match result {
+        Err(e) if is_missing_pir(&e) => Err(anyhow!(
+            "{}: This camera does not have a PIR sensor",
+            opt.camera
+        )),
+        r => r,
     }
+}
 
-    Ok(())
+fn is_missing_pir(e: &anyhow::Error) -> bool {
+    matches!(
+        e.downcast_ref::<neolink_core::Error>(),
+        Some(neolink_core::Error::MissingAbility { name, .. }) if name == "rfAlarm"
+    )
 }
@@ -3,6 +3,11 @@ use clap::Parser;
 /// The reboot command will reboot the camera
 #[derive(Parser, Debug)]
 pub struct Opt {
-    /// The name of the camera to change the lights of. Must be a name in the config
-    pub camera: String,
+    /// The name of the camera to reboot. Must be a name in the config or a glob
+    /// like `Front*` to reboot all the cameras it matches
+    #[arg(required_unless_present = "all")]
+    pub camera: Option<String>,
+    /// Reboot every enabled camera in the config
+    #[arg(long, conflicts_with = "camera")]
+    pub all: bool,
 }
@@ -5,24 +5,36 @@
 ///
 /// The subcommand attepts to reboot the camera.
 ///
+/// With a glob or `--all` the cameras are rebooted at the same time and a
+/// summary is printed, it only fails if every camera failed
+///
 /// # Usage
 ///
 /// ```bash
 /// neolink reboot --config=config.toml CameraName
+/// neolink reboot --config=config.toml 'Front*'
+/// neolink reboot --config=config.toml --all
 /// ```
 ///
 use anyhow::{Context, Result};
 
 mod cmdline;
 
-use crate::common::NeoReactor;
+use crate::{common::NeoReactor, utils::CameraSelection};
 pub(crate) use cmdline::Opt;
 
 /// Entry point for the reboot subcommand
 ///
 /// Opt is the command line options
 pub(crate) async fn main(opt: Opt, reactor: NeoReactor) -> Result<()> {
-    let camera = reactor.get(&opt.camera).await?;
+    let config = reactor.config().await?.borrow().clone();
+    let selection = CameraSelection::new(&config, opt.camera.as_deref(), opt.all)?;
+    let reactor = &reactor;
+    selection.run(|name| reboot(reactor, name)).await
+}
+
+async fn reboot(reactor: &NeoReactor, name: String) -> Result<()> {
+    let camera = reactor.get(&name).await?;
 
     camera
         .run_task(|camera| {
@@ -0,0 +1,170 @@
+//! Watches the RTCP receiver reports of the rtsp clients
+//!
+//! The packet loss and jitter that each client session reports are shown per
+//! stream in `/cameras` of the status server and in `/metrics`, together with
+//! the bitrate that is sent to it. Sessions without a report yet are left
+//! out. With `adaptive = true` a session of the main stream that keeps losing
+//! packets is closed so that the client can reconnect, hopefully to the
+//! substream.
+//!
+//! Limitations:
+//!
+//! - RTSP has no way to move a client to another stream. Closing the session
+//!   only helps clients that reconnect on their own and pick the substream
+//!   when the main stream fails, as many NVRs do
+//! - Only clients that send receiver reports are seen and over rtsp/tcp
+//!   nothing is lost so only the jitter shows a struggling client
+use serde::Serialize;
+use std::{collections::HashMap, sync::Arc};
+use tokio::{
+    sync::watch::Receiver as WatchReceiver,
+    time::{interval, Duration},
+};
+
+use neolink_core::bc_protocol::StreamKind;
+
+use super::{gst::NeoRtspServer, metrics::Metrics};
+use crate::{config::CameraConfig, AnyResult};
+
+/// How often the receiver reports are read
+const POLL: Duration = Duration::from_secs(5);
+/// How many reads in a row a session has to be over the loss limit to be closed
+const STRUGGLING_POLLS: u32 = 3;
+
+/// What the last receiver report of a client session says
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct SessionStats {
+    /// The id of the rtsp session
+    pub session: String,
+    /// Percentage of the packets lost since the previous report
+    pub loss_percent: f64,
+    /// Packets lost since the start of the session
+    pub packets_lost: i32,
+    /// The interarrival jitter in milliseconds
+    pub jitter_ms: u32,
+    /// The bitrate sent to the client in bits per second as estimated by gstreamer
+    pub bitrate: u64,
+}
+
+impl SessionStats {
+    /// From the fields of a report block, `fraction_lost` is out of 256 and the
+    /// jitter is in units of the rtp clock
+    pub(crate) fn from_report(
+        session: String,
+        fraction_lost: u32,
+        packets_lost: i32,
+        jitter: u32,
+        clock_rate: u32,
+        bitrate: u64,
+    ) -> Self {
+        Self {
+            session,
+            loss_percent: fraction_lost as f64 * 100.0 / 256.0,
+            packets_lost,
+            jitter_ms: (jitter as u64 * 1000 / clock_rate.max(1) as u64) as u32,
+            bitrate,
+        }
+    }
+}
+
+/// Counts how many reads in a row each session has been over the loss limit
+#[derive(Default)]
+struct Struggling {
+    polls: HashMap<String, u32>,
+}
+
+impl Struggling {
+    /// The sessions that have now struggled for long enough to be closed
+    fn update(&mut self, sessions: &[SessionStats], loss_percent: u8) -> Vec<SessionStats> {
+        let mut polls = HashMap::new();
+        let mut closing = vec![];
+        for session in sessions {
+            if session.loss_percent < loss_percent as f64 {
+                continue;
+            }
+            let count = self.polls.get(&session.session).copied().unwrap_or(0) + 1;
+            if count >= STRUGGLING_POLLS {
+                closing.push(session.clone());
+            } else {
+                polls.insert(session.session.clone(), count);
+            }
+        }
+        // Sessions that recovered or ended start again
+        self.polls = polls;
+        closing
+    }
+}
+
+/// Publishes the receiver reports of the stream's sessions and, with
+/// `adaptive`, closes the main stream sessions that keep losing packets
+pub(super) async fn adaptive_main(
+    name: &str,
+    stream: StreamKind,
+    rtsp: &NeoRtspServer,
+    paths: &[String],
+    metrics: &Arc<Metrics>,
+    config: WatchReceiver<CameraConfig>,
+) -> AnyResult<()> {
+    let mut struggling = Struggling::default();
+    let mut interval = interval(POLL);
+    loop {
+        interval.tick().await;
+        let sessions = rtsp.session_stats(paths);
+        let loss_percent = {
+            let config = config.borrow();
+            // Only when there is a substream to go to
+            (config.adaptive
+                && stream == StreamKind::Main
+                && config.stream_kinds().contains(&StreamKind::Sub))
+            .then_some(config.adaptive_loss_percent)
+        };
+        if let Some(loss_percent) = loss_percent {
+            for session in struggling.update(&sessions, loss_percent) {
+                log::warn!(
+                    "{name}: Rtsp session {} of the {stream} lost {:.0}% of its packets for {}s, closing it so that it can reconnect to the substream",
+                    session.session,
+                    session.loss_percent,
+                    (POLL * STRUGGLING_POLLS).as_secs()
+                );
+                rtsp.close_session(&session.session);
+            }
+        }
+        metrics.set_sessions(name, stream, sessions);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stats(session: &str, fraction_lost: u32) -> SessionStats {
+        SessionStats::from_report(session.to_string(), fraction_lost, 0, 0, 90000, 0)
+    }
+
+    #[test]
+    fn test_struggling() {
+        assert_eq!(
+            SessionStats::from_report("a".to_string(), 64, 10, 9000, 90000, 2_000_000),
+            SessionStats {
+                session: "a".to_string(),
+                loss_percent: 25.0,
+                packets_lost: 10,
+                jitter_ms: 100,
+                bitrate: 2_000_000,
+            }
+        );
+
+        let mut struggling = Struggling::default();
+        // 25% loss against a limit of 10%
+        assert!(struggling.update(&[stats("a", 64)], 10).is_empty());
+        assert!(struggling
+            .update(&[stats("a", 64), stats("b", 64)], 10)
+            .is_empty());
+        let closing = struggling.update(&[stats("a", 64), stats("b", 0)], 10);
+        assert_eq!(closing, vec![stats("a", 64)]);
+        // b recovered so starts again
+        assert!(struggling.update(&[stats("b", 64)], 10).is_empty());
+        assert!(struggling.update(&[stats("b", 64)], 10).is_empty());
+        assert_eq!(struggling.update(&[stats("b", 64)], 10).len(), 1);
+    }
+}
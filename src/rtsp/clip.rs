@@ -0,0 +1,188 @@
+//! Clips that are replayed while a stream is paused
+//!
+//! A clip is either loaded from a file, which is demuxed and parsed (not
+//! re-encoded) by gstreamer so it must use the same codec as the camera
+//! stream, or encoded once to match the camera stream for the `black`,
+//! `still` and `test` pause modes
+use anyhow::{anyhow, Context};
+use gstreamer::{prelude::*, Buffer, BufferFlags, ElementFactory, Pipeline, State};
+use gstreamer_app::{AppSink, AppSrc};
+use std::sync::Arc;
+use tokio::time::Duration;
+
+use crate::{
+    common::{StampedData, StreamConfig, VidFormat},
+    config::{PauseConfig, PauseEncoder},
+};
+
+use super::AnyResult;
+
+/// Used for frames that have no timestamps in the clip
+const FALLBACK_FRAME_TIME: Duration = Duration::from_millis(40);
+
+/// How long an encoded clip is before it repeats
+const ENCODED_CLIP_SECS: u32 = 2;
+
+/// Read all the frames of the clip into memory
+pub(super) async fn load_clip(path: &str, vid_format: &VidFormat) -> AnyResult<Vec<StampedData>> {
+    if !std::path::Path::new(path).is_file() {
+        return Err(anyhow!("File {:?} does not exist", path));
+    }
+    let (parser, caps) = parser_caps(vid_format)?;
+    let desc = format!(
+        "filesrc name=src ! parsebin ! {parser} config-interval=-1 ! {caps},stream-format=byte-stream,alignment=au ! appsink name=sink sync=false"
+    );
+    let path = path.to_string();
+    tokio::task::spawn_blocking(move || {
+        let pipeline = build_pipeline(&desc)?;
+        pipeline
+            .by_name("src")
+            .ok_or(anyhow!("Clip pipeline lacks a source"))?
+            .set_property("location", &path);
+        pull_frames(&pipeline).with_context(|| format!("Clip {:?}", path))
+    })
+    .await
+    .context("Clip loading panicked")?
+}
+
+/// What an encoded clip shows
+pub(super) enum ClipSource {
+    /// A `videotestsrc` pattern such as `black` or `smpte`
+    Pattern(&'static str),
+    /// A keyframe of the camera stream held still
+    Frame(StampedData),
+}
+
+/// Encodes a short clip that matches the format of the camera stream
+///
+/// The encoding happens once so the cost of the encoder is only paid when the
+/// stream starts (for a pattern) or pauses (for a frame)
+pub(super) async fn encode_clip(
+    name: &str,
+    source: ClipSource,
+    stream_config: &StreamConfig,
+    pause: &PauseConfig,
+) -> AnyResult<Vec<StampedData>> {
+    let (parser, caps) = parser_caps(&stream_config.vid_format)?;
+    let fps = stream_config.fps.max(1);
+    let frames = fps * ENCODED_CLIP_SECS;
+    let [width, height] = stream_config.resolution;
+    let encoder = encoder_element(name, &stream_config.vid_format, pause, frames);
+    let raw = format!("video/x-raw,width={width},height={height},framerate={fps}/1");
+    let input = match &source {
+        ClipSource::Pattern(pattern) => {
+            format!("videotestsrc pattern={pattern} num-buffers={frames} ! {raw}")
+        }
+        ClipSource::Frame(_) => format!(
+            "appsrc name=src caps={caps},stream-format=byte-stream ! {parser} ! decodebin ! videoconvert ! videoscale ! imagefreeze num-buffers={frames} ! {raw}"
+        ),
+    };
+    let desc = format!(
+        "{input} ! videoconvert ! {encoder} ! {parser} config-interval=-1 ! {caps},stream-format=byte-stream,alignment=au ! appsink name=sink sync=false"
+    );
+    tokio::task::spawn_blocking(move || {
+        let pipeline = build_pipeline(&desc)?;
+        if let ClipSource::Frame(frame) = source {
+            let src = pipeline
+                .by_name("src")
+                .ok_or(anyhow!("Clip pipeline lacks a source"))?
+                .dynamic_cast::<AppSrc>()
+                .map_err(|_| anyhow!("Cannot cast to appsrc"))?;
+            src.push_buffer(Buffer::from_slice(frame.data.to_vec()))?;
+            src.end_of_stream()?;
+        }
+        pull_frames(&pipeline).context("Encoded pause clip")
+    })
+    .await
+    .context("Clip encoding panicked")?
+}
+
+/// The encoder as a `parse_launch` description
+///
+/// Falls back to software if the chosen hardware encoder is not installed
+fn encoder_element(name: &str, vid_format: &VidFormat, pause: &PauseConfig, frames: u32) -> String {
+    let (codec, software) = match vid_format {
+        VidFormat::H265 => ("h265", "x265enc"),
+        _ => ("h264", "x264enc"),
+    };
+    let hardware = match pause.encoder {
+        PauseEncoder::X264 => None,
+        PauseEncoder::Vaapi => Some((
+            format!("vaapi{codec}enc"),
+            format!("bitrate={} keyframe-period={frames}", pause.bitrate),
+        )),
+        PauseEncoder::V4l2 => Some((
+            format!("v4l2{codec}enc"),
+            format!(
+                "extra-controls=\"controls,video_bitrate={}\"",
+                pause.bitrate * 1000
+            ),
+        )),
+    };
+    if let Some((element, properties)) = hardware {
+        if ElementFactory::find(&element).is_some() {
+            log::info!("{name}: Encoding the pause stream with {element}");
+            return format!("{element} {properties}");
+        }
+        log::warn!("{name}: {element} is not available, encoding the pause stream in software");
+    }
+    log::info!("{name}: Encoding the pause stream with {software}");
+    format!(
+        "{software} bitrate={} speed-preset={} key-int-max={frames}",
+        pause.bitrate, pause.preset
+    )
+}
+
+pub(super) fn parser_caps(vid_format: &VidFormat) -> AnyResult<(&'static str, &'static str)> {
+    match vid_format {
+        VidFormat::H264 => Ok(("h264parse", "video/x-h264")),
+        VidFormat::H265 => Ok(("h265parse", "video/x-h265")),
+        VidFormat::None => Err(anyhow!("Stream format is not known yet")),
+    }
+}
+
+pub(super) fn build_pipeline(desc: &str) -> AnyResult<Pipeline> {
+    gstreamer::parse_launch(desc)?
+        .dynamic_cast::<Pipeline>()
+        .map_err(|_| anyhow!("Clip pipeline should be a pipeline"))
+}
+
+/// Runs the pipeline to the end and collects the frames from its `sink`
+pub(super) fn pull_frames(pipeline: &Pipeline) -> AnyResult<Vec<StampedData>> {
+    let sink = pipeline
+        .by_name("sink")
+        .ok_or(anyhow!("Clip pipeline lacks a sink"))?
+        .dynamic_cast::<AppSink>()
+        .map_err(|_| anyhow!("Cannot cast to appsink"))?;
+
+    pipeline.set_state(State::Playing)?;
+    let mut frames = vec![];
+    // Errors once the end of the stream is reached
+    while let Ok(sample) = sink.pull_sample() {
+        let Some(buffer) = sample.buffer() else {
+            continue;
+        };
+        let ts = buffer
+            .dts_or_pts()
+            .map(|time| Duration::from_nanos(time.nseconds()))
+            .unwrap_or(FALLBACK_FRAME_TIME * frames.len() as u32);
+        let map = buffer.map_readable()?;
+        frames.push(StampedData {
+            keyframe: !buffer.flags().contains(BufferFlags::DELTA_UNIT),
+            data: Arc::new(map.to_vec()),
+            ts,
+        });
+    }
+    pipeline.set_state(State::Null)?;
+
+    // Start the clip at zero
+    let start = frames.first().map(|frame| frame.ts).unwrap_or_default();
+    for frame in frames.iter_mut() {
+        frame.ts = frame.ts.saturating_sub(start);
+    }
+    match frames.first() {
+        None => Err(anyhow!("Has no video frames")),
+        Some(frame) if !frame.keyframe => Err(anyhow!("Does not start on a keyframe")),
+        _ => Ok(frames),
+    }
+}
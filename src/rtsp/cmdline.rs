@@ -1,5 +1,23 @@
 use clap::Parser;
 
 /// The rtsp command will serve all cameras in the config over the rtsp protocol
-#[derive(Parser, Debug)]
-pub struct Opt {}
+#[derive(Parser, Debug, Default, Clone)]
+pub struct Opt {
+    /// Serve prometheus metrics over http at `/metrics` on this port
+    #[arg(long)]
+    pub metrics_port: Option<u16>,
+    /// Serve `/healthz`, `/cameras` and the `/{path}.sdp` of each stream over http on this port
+    #[arg(long)]
+    pub status_port: Option<u16>,
+    /// Also accept `POST /cameras/{name}/{command}` on the status port to take
+    /// snapshots, reboot or set the led over the open camera connection
+    #[arg(long, requires = "status_port")]
+    pub control: bool,
+    /// Turn on gstreamer's own logging, takes the same levels as `GST_DEBUG`
+    /// e.g. `3` or `rtspmedia:5,appsrc:4`
+    #[arg(long)]
+    pub gst_debug: Option<String>,
+    /// Write a graphviz dot file of the pipeline to this directory when it fails to build
+    #[arg(long)]
+    pub gst_dot_dir: Option<std::path::PathBuf>,
+}
@@ -0,0 +1,410 @@
+//! The gstreamer elements that each feature of the config needs
+//!
+//! Without them a factory only fails once a client connects, with an error
+//! that names an element rather than the package to install. So they are
+//! looked up before serving begins. A missing element of the streams
+//! themselves stops neolink, one of an optional feature turns that feature off
+//! with a warning
+use anyhow::{anyhow, Context, Result};
+use gstreamer::ElementFactory;
+use log::*;
+
+use crate::{
+    config::{
+        image_decoder, CameraConfig, Config, PauseEncoder, PushProtocol, RecordFormat, Transcode,
+    },
+    exit::ExitError,
+};
+
+/// What neolink does when an element of a feature is missing
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Fallback {
+    /// Nothing can be served, neolink does not start
+    Required,
+    /// The feature is turned off in the config of the camera
+    TurnOff,
+    /// Served anyway, but only cameras that do not need it will work
+    Warn,
+}
+
+/// A feature of the config and the elements it needs
+///
+/// An element written as `a|b` is satisfied by either of them
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Feature {
+    /// The camera it belongs to or None for the streams of every camera
+    pub(crate) camera: Option<String>,
+    pub(crate) name: &'static str,
+    pub(crate) elements: Vec<&'static str>,
+    pub(crate) fallback: Fallback,
+}
+
+/// The elements of the rtsp streams of every camera
+const STREAM: &[&str] = &[
+    "appsrc",
+    "queue",
+    "queue2",
+    "h264parse",
+    "h264timestamper",
+    "rtph264pay",
+    "h265parse",
+    "h265timestamper",
+    "rtph265pay",
+];
+/// Both audio codecs, the camera decides which one is used
+const AUDIO: &[&str] = &[
+    "aacparse",
+    "faad|avdec_aac",
+    "decodebin",
+    "adpcmdec",
+    "audioconvert",
+    "rtpL16pay",
+];
+const SPLASH: &[&str] = &["videotestsrc", "textoverlay", "jpegenc", "rtpjpegpay"];
+
+impl Feature {
+    fn new(
+        camera: Option<&str>,
+        name: &'static str,
+        elements: Vec<&'static str>,
+        fallback: Fallback,
+    ) -> Self {
+        Self {
+            camera: camera.map(|camera| camera.to_string()),
+            name,
+            elements,
+            fallback,
+        }
+    }
+
+    /// The elements of this feature that gstreamer lacks
+    pub(crate) fn missing<F: Fn(&str) -> bool>(&self, available: F) -> Vec<&'static str> {
+        self.elements
+            .iter()
+            .copied()
+            .filter(|element| !element.split('|').any(&available))
+            .collect()
+    }
+
+    /// Turns this feature off in the config of its camera
+    fn turn_off(&self, camera: &mut CameraConfig) {
+        match self.name {
+            "splash" => {
+                camera.use_splash = false;
+                camera.not_ready_image = None;
+            }
+            "not_ready_image" => camera.not_ready_image = None,
+            "pause" => camera.pause.mode = "none".to_string(),
+            "transcode" => camera.transcode = None,
+            "overlay" => camera.overlay = None,
+            "restream" => camera.restream = None,
+            "record" => camera.record = None,
+            "hls" => camera.hls = None,
+            _ => {}
+        }
+    }
+}
+
+/// Every feature that the config uses
+pub(crate) fn features(config: &Config) -> Vec<Feature> {
+    let mut features = vec![
+        Feature::new(None, "streams", STREAM.to_vec(), Fallback::Required),
+        Feature::new(None, "audio", AUDIO.to_vec(), Fallback::Warn),
+    ];
+    for camera in config.cameras.iter().filter(|camera| camera.enabled) {
+        features.extend(camera_features(camera));
+    }
+    features
+}
+
+fn camera_features(camera: &CameraConfig) -> Vec<Feature> {
+    let name = Some(camera.name.as_str());
+    let mut features = vec![];
+    if camera.use_splash {
+        features.push(Feature::new(
+            name,
+            "splash",
+            SPLASH.to_vec(),
+            Fallback::TurnOff,
+        ));
+    }
+    if let Some(image) = camera.not_ready_image.as_deref() {
+        let mut elements = vec!["filesrc", "imagefreeze", "videoconvert", "videoscale"];
+        elements.extend(image_decoder(image));
+        elements.extend(["jpegenc", "rtpjpegpay"]);
+        features.push(Feature::new(
+            name,
+            "not_ready_image",
+            elements,
+            Fallback::TurnOff,
+        ));
+    }
+    if camera.pause.pauses() {
+        let encoder = match camera.pause.encoder {
+            PauseEncoder::X264 => "x264enc|x265enc",
+            PauseEncoder::Vaapi => "vaapih264enc|vaapih265enc|x264enc|x265enc",
+            PauseEncoder::V4l2 => "v4l2h264enc|v4l2h265enc|x264enc|x265enc",
+        };
+        let elements = match camera.pause.mode.as_str() {
+            "black" | "test" => vec!["videotestsrc", "videoconvert", encoder, "appsink"],
+            "still" => vec![
+                "appsrc",
+                "decodebin",
+                "videoconvert",
+                "videoscale",
+                "imagefreeze",
+                encoder,
+                "appsink",
+            ],
+            "loop" => vec!["filesrc", "parsebin", "appsink"],
+            _ => vec![],
+        };
+        if !elements.is_empty() {
+            features.push(Feature::new(name, "pause", elements, Fallback::TurnOff));
+        }
+    }
+    if let Some(transcode) = camera.transcode {
+        // Only a camera of the other codec is transcoded
+        let elements = match transcode {
+            Transcode::H264 => vec!["avdec_h265", "videoconvert", "x264enc"],
+            Transcode::H265 => vec!["avdec_h264", "videoconvert", "x265enc"],
+        };
+        features.push(Feature::new(name, "transcode", elements, Fallback::TurnOff));
+    }
+    if camera
+        .overlay
+        .as_ref()
+        .is_some_and(|overlay| overlay.enabled)
+    {
+        features.push(Feature::new(
+            name,
+            "overlay",
+            vec![
+                "avdec_h264|avdec_h265",
+                "videoconvert",
+                "textoverlay",
+                "x264enc|x265enc",
+            ],
+            Fallback::TurnOff,
+        ));
+    }
+    if let Some(push) = camera.restream.as_ref() {
+        let elements = match push.protocol {
+            PushProtocol::Rtmp => vec!["flvmux", "rtmpsink"],
+            PushProtocol::Srt => vec!["mpegtsmux", "srtsink"],
+        };
+        features.push(Feature::new(name, "restream", elements, Fallback::TurnOff));
+    }
+    if let Some(record) = camera.record.as_ref().filter(|record| record.enabled) {
+        let muxer = match record.format {
+            RecordFormat::Mp4 => "mp4mux",
+            RecordFormat::Mkv => "matroskamux",
+        };
+        features.push(Feature::new(
+            name,
+            "record",
+            vec!["splitmuxsink", muxer],
+            Fallback::TurnOff,
+        ));
+    }
+    if camera.hls.as_ref().is_some_and(|hls| hls.enabled) {
+        features.push(Feature::new(
+            name,
+            "hls",
+            vec!["hlssink2"],
+            Fallback::TurnOff,
+        ));
+    }
+    features
+}
+
+/// Every feature of neolink, for when there is no config to look at
+pub(crate) fn all_features() -> Vec<Feature> {
+    let optional = |name, elements: &[&'static str]| {
+        Feature::new(None, name, elements.to_vec(), Fallback::TurnOff)
+    };
+    vec![
+        Feature::new(None, "streams", STREAM.to_vec(), Fallback::Required),
+        Feature::new(None, "audio", AUDIO.to_vec(), Fallback::Warn),
+        optional("splash", SPLASH),
+        optional(
+            "not_ready_image",
+            &["filesrc", "jpegdec", "pngdec", "imagefreeze", "videoscale"],
+        ),
+        optional(
+            "pause",
+            &[
+                "videotestsrc",
+                "videoconvert",
+                "imagefreeze",
+                "parsebin",
+                "appsink",
+                "x264enc",
+                "x265enc",
+            ],
+        ),
+        optional("transcode", &["avdec_h264", "avdec_h265"]),
+        optional("overlay", &["textoverlay"]),
+        optional("restream", &["flvmux", "rtmpsink", "mpegtsmux", "srtsink"]),
+        optional("record", &["splitmuxsink", "mp4mux", "matroskamux"]),
+        optional("hls", &["hlssink2"]),
+    ]
+}
+
+/// Looks up the elements of the config before it is served
+///
+/// Errors, listing the packages to install, if an element of the streams is
+/// missing. Optional features that miss an element are turned off
+pub(crate) fn prepare(mut config: Config) -> Result<Config> {
+    gstreamer::init().context("Gstreamer failed to initialise")?;
+    let required = degrade(&mut config, |element| {
+        ElementFactory::find(element).is_some()
+    });
+    if required.is_empty() {
+        Ok(config)
+    } else {
+        Err(anyhow!(
+            "Missing required gstreamer plugins, install them and start neolink again:\n{}",
+            required
+                .iter()
+                .map(|element| format!("  `{}` from {}", element, plugin_of(element)))
+                .collect::<Vec<_>>()
+                .join("\n")
+        ))
+        .context(ExitError::Plugins)
+    }
+}
+
+/// Turns off the features of the config that miss an element, returns the
+/// missing elements of the required ones
+fn degrade<F: Fn(&str) -> bool>(config: &mut Config, available: F) -> Vec<&'static str> {
+    let mut required = vec![];
+    for feature in features(config) {
+        let missing = feature.missing(&available);
+        if missing.is_empty() {
+            continue;
+        }
+        let plugins = missing
+            .iter()
+            .map(|element| format!("`{}` from {}", element, plugin_of(element)))
+            .collect::<Vec<_>>()
+            .join(", ");
+        match (feature.fallback, feature.camera.as_deref()) {
+            (Fallback::Required, _) => required.extend(missing),
+            (Fallback::Warn, _) => warn!(
+                "The {} of some cameras will not play without {}",
+                feature.name, plugins
+            ),
+            (Fallback::TurnOff, Some(name)) => {
+                warn!(
+                    "{}: Turning off {}, it needs {}",
+                    name, feature.name, plugins
+                );
+                for camera in config
+                    .cameras
+                    .iter_mut()
+                    .filter(|camera| camera.name == name)
+                {
+                    feature.turn_off(camera);
+                }
+            }
+            (Fallback::TurnOff, None) => {}
+        }
+    }
+    required
+}
+
+/// The plugin and package of an element, to tell users what to install
+pub(crate) fn plugin_of(element: &str) -> &'static str {
+    match element.split('|').next().unwrap_or_default() {
+        "appsrc" | "appsink" => "app (gst-plugins-base)",
+        "audioconvert" => "audioconvert (gst-plugins-base)",
+        "adpcmdec" => "adpcmdec (gst-plugins-bad)",
+        "queue" | "queue2" | "filesrc" => "coreelements (gstreamer)",
+        "h264parse" | "h265parse" => "videoparsersbad (gst-plugins-bad)",
+        "h264timestamper" | "h265timestamper" => "codectimestamper (gst-plugins-bad)",
+        "rtph264pay" | "rtph265pay" | "rtpjitterbuffer" | "rtpL16pay" | "rtpjpegpay" => {
+            "rtp (gst-plugins-good)"
+        }
+        "aacparse" => "audioparsers (gst-plugins-good)",
+        "faad" => "faad (gst-plugins-bad)",
+        "fallbackswitch" => "fallbackswitch (gst-plugins-rs)",
+        "x264enc" => "x264 (gst-plugins-ugly)",
+        "x265enc" => "x265 (gst-plugins-bad)",
+        "avdec_h264" | "avdec_h265" | "avdec_aac" => "libav (gst-libav)",
+        "vaapih264enc" | "vaapih265enc" => "vaapi (gstreamer-vaapi)",
+        "v4l2h264enc" | "v4l2h265enc" => "video4linux2 (gst-plugins-good)",
+        "videotestsrc" => "videotestsrc (gst-plugins-base)",
+        "jpegdec" | "jpegenc" => "jpeg (gst-plugins-good)",
+        "pngdec" => "png (gst-plugins-good)",
+        "videoscale" => "videoscale (gst-plugins-base)",
+        "videoconvert" => "videoconvert (gst-plugins-base)",
+        "textoverlay" => "pango (gst-plugins-base)",
+        "imagefreeze" => "imagefreeze (gst-plugins-good)",
+        "audiotestsrc" => "audiotestsrc (gst-plugins-base)",
+        "decodebin" | "parsebin" => "playback (gst-plugins-base)",
+        "flvmux" => "flv (gst-plugins-good)",
+        "rtmpsink" => "rtmp (gst-plugins-bad)",
+        "mpegtsmux" => "mpegtsmux (gst-plugins-bad)",
+        "srtsink" => "srt (gst-plugins-bad)",
+        "splitmuxsink" => "multifile (gst-plugins-good)",
+        "mp4mux" => "isomp4 (gst-plugins-good)",
+        "matroskamux" => "matroska (gst-plugins-good)",
+        "hlssink2" => "hls (gst-plugins-bad)",
+        _ => "Unknown",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> Config {
+        toml::from_str(
+            r#"
+[[cameras]]
+name = "Garage"
+username = "admin"
+address = "192.168.1.10"
+transcode = "h264"
+  [cameras.pause]
+  on_motion = true
+  mode = "black"
+
+[[cameras]]
+name = "Driveway"
+username = "admin"
+address = "192.168.1.11"
+  [cameras.pause]
+  on_motion = true
+  mode = "freeze"
+"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_degrade() {
+        // Everything but the encoders
+        let mut degraded = config();
+        let required = degrade(&mut degraded, |element| !element.ends_with("enc"));
+        assert!(required.is_empty());
+        let garage = &degraded.cameras[0];
+        assert_eq!(garage.pause.mode, "none");
+        assert_eq!(garage.transcode, None);
+        // The splash only needs jpegenc
+        assert!(!garage.use_splash);
+        assert_eq!(degraded.cameras[1].pause.mode, "freeze");
+
+        // Either aac decoder will do
+        let mut degraded = config();
+        let required = degrade(&mut degraded, |element| element != "faad");
+        assert!(required.is_empty());
+        assert_eq!(degraded, config());
+
+        let mut degraded = config();
+        let required = degrade(&mut degraded, |element| element != "h264parse");
+        assert_eq!(required, vec!["h264parse"]);
+        assert_eq!(plugin_of(required[0]), "videoparsersbad (gst-plugins-bad)");
+    }
+}
@@ -1,15 +1,39 @@
 use anyhow::{anyhow, Context, Result};
-use gstreamer::{prelude::*, Bin, Caps, Element, ElementFactory, GhostPad};
+use gstreamer::{
+    glib, prelude::*, Bin, Caps, Element, ElementFactory, GhostPad, PadProbeReturn, PadProbeType,
+};
 use gstreamer_app::{AppSrc, AppSrcCallbacks, AppStreamType};
+use gstreamer_rtsp::RTSPLowerTrans;
+use gstreamer_rtsp_server::prelude::*;
 use log::*;
-use tokio::sync::mpsc::{channel as mpsc, Receiver as MpscReceiver};
+use std::{
+    path::Path,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc, Mutex as StdMutex,
+    },
+    time::{Duration, Instant},
+};
+use tokio::sync::{
+    mpsc::{channel as mpsc, Receiver as MpscReceiver},
+    watch::Receiver as WatchReceiver,
+};
 
 use crate::{
     common::{AudFormat, StreamConfig, VidFormat},
-    rtsp::gst::NeoMediaFactory,
+    config::{image_decoder, CameraConfig, Latency, OverlayConfig, RtspTransport, Transcode},
+    rtsp::{
+        deps::plugin_of,
+        gst::{NeoMediaFactory, NOT_READY_SOURCE},
+    },
     AnyResult,
 };
 
+/// The rtpbin latency used in low latency mode, gstreamer's default is 200ms
+const LOW_LATENCY_MS: u32 = 20;
+/// How often the dropped data of a client that cannot keep up is logged
+const DROP_LOG_INTERVAL: Duration = Duration::from_secs(10);
+
 pub(super) struct ClientSourceData {
     pub(super) app: AppSrc,
 }
@@ -17,42 +41,149 @@ pub(super) struct ClientSourceData {
 pub(super) struct ClientData {
     pub(super) vid: Option<ClientSourceData>,
     pub(super) aud: Option<ClientSourceData>,
+    /// Only the keyframes are to be sent to this client
+    pub(super) keyframe_only: bool,
+    /// Counts the client towards `max_clients` until it is dropped
+    pub(super) slot: ClientSlot,
+}
+
+/// Keeps the clients of all the streams of a camera within its `max_clients`
+#[derive(Clone)]
+pub(super) struct ClientLimit {
+    config: WatchReceiver<CameraConfig>,
+    clients: Arc<AtomicU32>,
+}
+
+impl ClientLimit {
+    pub(super) fn new(config: WatchReceiver<CameraConfig>) -> Self {
+        Self {
+            config,
+            clients: Default::default(),
+        }
+    }
+
+    /// A slot for a new client, `None` if the camera already has `max_clients`
+    ///
+    /// The media of a client without a slot is not created so the server
+    /// answers it with `503 Service Unavailable`
+    fn acquire(&self) -> Option<ClientSlot> {
+        let (name, max) = {
+            let config = self.config.borrow();
+            (config.name.clone(), config.max_clients.unwrap_or(u32::MAX))
+        };
+        match self
+            .clients
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |clients| {
+                (clients < max).then_some(clients + 1)
+            }) {
+            Ok(_) => Some(ClientSlot {
+                clients: self.clients.clone(),
+            }),
+            Err(_) => {
+                log::info!("{name}: Rejecting a client, already serving max_clients ({max})");
+                None
+            }
+        }
+    }
+}
+
+pub(super) struct ClientSlot {
+    clients: Arc<AtomicU32>,
+}
+
+impl Drop for ClientSlot {
+    fn drop(&mut self) {
+        self.clients.fetch_sub(1, Ordering::SeqCst);
+    }
 }
 
+/// Makes the factory that serves the placeholder until the stream is ready
+///
+/// It is the `not_ready_image` if there is one and it can be read, or else the
+/// test `pattern`
 pub(super) async fn make_dummy_factory(
     use_splash: bool,
     pattern: String,
+    not_ready_image: Option<String>,
 ) -> AnyResult<NeoMediaFactory> {
     NeoMediaFactory::new_with_callback(move |element| {
         clear_bin(&element)?;
         if !use_splash {
-            Ok(None)
-        } else {
-            build_unknown(&element, &pattern)?;
-            Ok(Some(element))
+            return Ok(None);
+        }
+        if let Some(image) = not_ready_image.as_deref() {
+            match build_not_ready_image(&element, image) {
+                Ok(()) => return Ok(Some(element)),
+                Err(e) => {
+                    log::warn!("Could not show the not_ready_image {image}, showing the test pattern: {e:?}");
+                    clear_bin(&element)?;
+                }
+            }
         }
+        build_unknown(&element, &pattern)?;
+        Ok(Some(element))
     })
     .await
 }
 
+/// Makes the factory of the video and audio of the stream
+///
+/// With `keyframe_only` it serves no audio and its clients are marked so that
+/// only the keyframes are pushed to them. With `repeat_parameter_sets` the
+/// parser of an H265 stream puts the parameter sets in front of every keyframe
+#[allow(clippy::too_many_arguments)]
 pub(super) async fn make_factory(
+    name: &str,
     stream_config: &StreamConfig,
+    latency: Latency,
+    jitterbuffer_ms: Option<u32>,
+    buffer_duration: Duration,
+    client_queue: Option<u32>,
+    transcode: Option<Transcode>,
+    overlay: Option<OverlayConfig>,
+    transport: RtspTransport,
+    client_limit: ClientLimit,
+    keyframe_only: bool,
+    repeat_parameter_sets: bool,
 ) -> AnyResult<(NeoMediaFactory, MpscReceiver<ClientData>)> {
     let (client_tx, client_rx) = mpsc(100);
     let factory = {
+        let name = name.to_string();
         let stream_config = stream_config.clone();
+        let buffer_size = client_queue
+            .unwrap_or_else(|| buffer_size(stream_config.bitrate, latency, buffer_duration));
+        log::debug!("buffer_size: {buffer_size}");
+        let target = match transcode_target(&stream_config.vid_format, transcode) {
+            // The overlay needs the video decoded so it is encoded again in the same codec
+            None if overlay.is_some() && stream_config.vid_format != VidFormat::None => {
+                Some(stream_config.vid_format.clone())
+            }
+            target => target,
+        };
 
         NeoMediaFactory::new_with_callback(move |element| {
+            let Some(slot) = client_limit.acquire() else {
+                return Ok(None);
+            };
             clear_bin(&element)?;
-            let vid = match stream_config.vid_format {
-                VidFormat::None => {
+            let vid = match (&stream_config.vid_format, &target) {
+                (from, Some(to)) => {
+                    let app = build_transcode(&element, buffer_size, from, to, overlay.as_ref())?;
+                    app.set_callbacks(
+                        AppSrcCallbacks::builder()
+                            .seek_data(move |_, _seek_pos| true)
+                            .build(),
+                    );
+                    AnyResult::Ok(Some(app))
+                }
+                (VidFormat::None, None) => {
                     // This should not be reachable
                     log::debug!("Building unknown during normal make factory");
                     build_unknown(&element, "black")?;
                     AnyResult::Ok(None)
                 }
-                VidFormat::H264 => {
-                    let app = build_h264(&element, &stream_config)?;
+                (VidFormat::H264, None) => {
+                    let app = build_h264(&element, buffer_size)?;
                     app.set_callbacks(
                         AppSrcCallbacks::builder()
                             .seek_data(move |_, _seek_pos| true)
@@ -60,8 +191,8 @@ pub(super) async fn make_factory(
                     );
                     AnyResult::Ok(Some(app))
                 }
-                VidFormat::H265 => {
-                    let app = build_h265(&element, &stream_config)?;
+                (VidFormat::H265, None) => {
+                    let app = build_h265(&element, buffer_size, repeat_parameter_sets)?;
 
                     app.set_callbacks(
                         AppSrcCallbacks::builder()
@@ -71,44 +202,96 @@ pub(super) async fn make_factory(
                     AnyResult::Ok(Some(app))
                 }
             }?;
-            let aud = if matches!(stream_config.vid_format, VidFormat::None) {
+            let aud = if keyframe_only || matches!(stream_config.vid_format, VidFormat::None) {
                 None
             } else {
-                match stream_config.aud_format {
-                    AudFormat::None => AnyResult::Ok(None),
-                    AudFormat::Aac => {
-                        let app = build_aac(&element, &stream_config)?;
-                        app.set_callbacks(
-                            AppSrcCallbacks::builder()
-                                .seek_data(move |_, _seek_pos| true)
-                                .build(),
-                        );
-                        AnyResult::Ok(Some(app))
-                    }
-                    AudFormat::Adpcm(block_size) => {
-                        let app = build_adpcm(&element, block_size, &stream_config)?;
-                        app.set_callbacks(
-                            AppSrcCallbacks::builder()
-                                .seek_data(move |_, _seek_pos| true)
-                                .build(),
-                        );
-                        AnyResult::Ok(Some(app))
-                    }
-                }?
+                build_aud(&element, &stream_config, buffer_size, "pay1")?
             };
+            log_drops(&element, &name)?;
 
             client_tx.blocking_send(ClientData {
                 vid: vid.map(|app| ClientSourceData { app }),
                 aud: aud.map(|app| ClientSourceData { app }),
+                keyframe_only,
+                slot,
+            })?;
+            Ok(Some(element))
+        })
+        .await
+    }?;
+    factory.keep_sdp();
+    factory.set_protocols(lower_transports(transport));
+    if let Some(latency_ms) = rtp_latency(latency, jitterbuffer_ms) {
+        factory.set_latency(latency_ms);
+    }
+
+    Ok((factory, client_rx))
+}
+
+/// Makes a factory that serves only the audio of the stream
+pub(super) async fn make_audio_factory(
+    name: &str,
+    stream_config: &StreamConfig,
+    latency: Latency,
+    jitterbuffer_ms: Option<u32>,
+    buffer_duration: Duration,
+    client_queue: Option<u32>,
+    transport: RtspTransport,
+    client_limit: ClientLimit,
+) -> AnyResult<(NeoMediaFactory, MpscReceiver<ClientData>)> {
+    let (client_tx, client_rx) = mpsc(100);
+    let factory = {
+        let name = name.to_string();
+        let stream_config = stream_config.clone();
+        let buffer_size = client_queue
+            .unwrap_or_else(|| buffer_size(stream_config.bitrate, latency, buffer_duration));
+
+        NeoMediaFactory::new_with_callback(move |element| {
+            let Some(slot) = client_limit.acquire() else {
+                return Ok(None);
+            };
+            clear_bin(&element)?;
+            // With no video the audio is the first and only payload
+            let aud = build_aud(&element, &stream_config, buffer_size, "pay0")?;
+            log_drops(&element, &name)?;
+            client_tx.blocking_send(ClientData {
+                vid: None,
+                aud: aud.map(|app| ClientSourceData { app }),
+                keyframe_only: false,
+                slot,
             })?;
             Ok(Some(element))
         })
         .await
     }?;
+    factory.keep_sdp();
+    factory.set_protocols(lower_transports(transport));
+    if let Some(latency_ms) = rtp_latency(latency, jitterbuffer_ms) {
+        factory.set_latency(latency_ms);
+    }
 
     Ok((factory, client_rx))
 }
 
+fn build_aud(
+    element: &Element,
+    stream_config: &StreamConfig,
+    buffer_size: u32,
+    pay_name: &str,
+) -> AnyResult<Option<AppSrc>> {
+    let app = match stream_config.aud_format {
+        AudFormat::None => return Ok(None),
+        AudFormat::Aac => build_aac(element, buffer_size, pay_name)?,
+        AudFormat::Adpcm(block_size) => build_adpcm(element, block_size, buffer_size, pay_name)?,
+    };
+    app.set_callbacks(
+        AppSrcCallbacks::builder()
+            .seek_data(move |_, _seek_pos| true)
+            .build(),
+    );
+    Ok(Some(app))
+}
+
 fn clear_bin(bin: &Element) -> Result<()> {
     let bin = bin
         .clone()
@@ -129,7 +312,7 @@ fn build_unknown(bin: &Element, pattern: &str) -> Result<()> {
         .dynamic_cast::<Bin>()
         .map_err(|_| anyhow!("Media source's element should be a bin"))?;
     log::debug!("Building Unknown Pipeline");
-    let source = make_element("videotestsrc", "testvidsrc")?;
+    let source = make_element("videotestsrc", NOT_READY_SOURCE)?;
     source.set_property_from_str("pattern", pattern);
     source.set_property("num-buffers", 500i32); // Send buffers then EOS
     let queue = make_queue("queue0", 1024 * 1024 * 4)?;
@@ -157,9 +340,47 @@ fn build_unknown(bin: &Element, pattern: &str) -> Result<()> {
     Ok(())
 }
 
-fn build_h264(bin: &Element, stream_config: &StreamConfig) -> Result<AppSrc> {
-    let buffer_size = buffer_size(stream_config.bitrate);
-    log::debug!("buffer_size: {buffer_size}");
+/// Shows the image until the stream is ready, scaled to the size of the test
+/// pattern
+fn build_not_ready_image(bin: &Element, image: &str) -> Result<()> {
+    let bin = bin
+        .clone()
+        .dynamic_cast::<Bin>()
+        .map_err(|_| anyhow!("Media source's element should be a bin"))?;
+    log::debug!("Building Not Ready Image Pipeline");
+    let decoder = image_decoder(image).ok_or(anyhow!("Not a .jpg, .jpeg or .png"))?;
+    if !Path::new(image).is_file() {
+        return Err(anyhow!("File not found"));
+    }
+    let source = make_element("filesrc", NOT_READY_SOURCE)?;
+    source.set_property("location", image);
+    let decoder = make_element(decoder, "imagedecoder")?;
+    let freeze = make_element("imagefreeze", "freeze")?;
+    freeze.set_property("num-buffers", 500i32); // Send buffers then EOS like the test pattern
+    let convert = make_element("videoconvert", "convert")?;
+    let scale = make_element("videoscale", "scale")?;
+    let queue = make_queue("queue0", 1024 * 1024 * 4)?;
+    let encoder = make_element("jpegenc", "encoder")?;
+    let payload = make_element("rtpjpegpay", "pay0")?;
+
+    bin.add_many([
+        &source, &decoder, &freeze, &convert, &scale, &queue, &encoder, &payload,
+    ])?;
+    Element::link_many([&source, &decoder, &freeze, &convert, &scale])?;
+    scale.link_filtered(
+        &queue,
+        &Caps::builder("video/x-raw")
+            .field("width", 896i32)
+            .field("height", 512i32)
+            .field("framerate", gstreamer::Fraction::new(25, 1))
+            .build(),
+    )?;
+    Element::link_many([&queue, &encoder, &payload])?;
+
+    Ok(())
+}
+
+fn build_h264(bin: &Element, buffer_size: u32) -> Result<AppSrc> {
     let bin = bin
         .clone()
         .dynamic_cast::<Bin>()
@@ -193,9 +414,7 @@ fn build_h264(bin: &Element, stream_config: &StreamConfig) -> Result<AppSrc> {
     Ok(source)
 }
 
-fn build_h265(bin: &Element, stream_config: &StreamConfig) -> Result<AppSrc> {
-    let buffer_size = buffer_size(stream_config.bitrate);
-    log::debug!("buffer_size: {buffer_size}");
+fn build_h265(bin: &Element, buffer_size: u32, repeat_parameter_sets: bool) -> Result<AppSrc> {
     let bin = bin
         .clone()
         .dynamic_cast::<Bin>()
@@ -217,6 +436,9 @@ fn build_h265(bin: &Element, stream_config: &StreamConfig) -> Result<AppSrc> {
         .map_err(|_| anyhow!("Cannot cast back"))?;
     let queue = make_queue("source_queue", buffer_size)?;
     let parser = make_element("h265parse", "parser")?;
+    if repeat_parameter_sets {
+        parser.set_property("config-interval", -1i32);
+    }
     let stamper = make_element("h265timestamper", "stamper")?;
     let payload = make_element("rtph265pay", "pay0")?;
     bin.add_many([&source, &queue, &parser, &stamper, &payload])?;
@@ -228,9 +450,118 @@ fn build_h265(bin: &Element, stream_config: &StreamConfig) -> Result<AppSrc> {
     Ok(source)
 }
 
-fn build_aac(bin: &Element, stream_config: &StreamConfig) -> Result<AppSrc> {
-    let buffer_size = buffer_size(stream_config.bitrate);
-    log::debug!("buffer_size: {buffer_size}");
+/// The codec to re-encode to, or None when the camera's codec is served as is
+pub(super) fn transcode_target(
+    from: &VidFormat,
+    transcode: Option<Transcode>,
+) -> Option<VidFormat> {
+    match (from, transcode) {
+        (VidFormat::H264, Some(Transcode::H265)) => Some(VidFormat::H265),
+        (VidFormat::H265, Some(Transcode::H264)) => Some(VidFormat::H264),
+        _ => None,
+    }
+}
+
+/// Decodes the camera's video and encodes it again in another codec or with
+/// the overlay drawn on it
+///
+/// This costs a lot of cpu so it is only built when the codecs differ or there
+/// is an overlay
+fn build_transcode(
+    bin: &Element,
+    buffer_size: u32,
+    from: &VidFormat,
+    to: &VidFormat,
+    overlay: Option<&OverlayConfig>,
+) -> Result<AppSrc> {
+    let bin = bin
+        .clone()
+        .dynamic_cast::<Bin>()
+        .map_err(|_| anyhow!("Media source's element should be a bin"))?;
+    log::debug!("Building {:?} to {:?} Transcode Pipeline", from, to);
+    let (in_parser, decoder) = match from {
+        VidFormat::H264 => ("h264parse", "avdec_h264"),
+        VidFormat::H265 => ("h265parse", "avdec_h265"),
+        VidFormat::None => return Err(anyhow!("Cannot transcode an unknown video format")),
+    };
+    let (encoder, out_parser, payload) = match to {
+        VidFormat::H264 => ("x264enc", "h264parse", "rtph264pay"),
+        VidFormat::H265 => ("x265enc", "h265parse", "rtph265pay"),
+        VidFormat::None => return Err(anyhow!("Cannot transcode to an unknown video format")),
+    };
+
+    let source = make_element("appsrc", "vidsrc")?
+        .dynamic_cast::<AppSrc>()
+        .map_err(|_| anyhow!("Cannot cast to appsrc."))?;
+    source.set_is_live(true);
+    source.set_block(false);
+    source.set_min_latency(0);
+    source.set_property("emit-signals", false);
+    source.set_max_bytes(buffer_size as u64);
+    source.set_do_timestamp(true);
+    source.set_stream_type(AppStreamType::Seekable);
+
+    let source = source
+        .dynamic_cast::<Element>()
+        .map_err(|_| anyhow!("Cannot cast back"))?;
+    let queue = make_queue("source_queue", buffer_size)?;
+    let in_parser = make_element(in_parser, "parser")?;
+    let decoder = make_element(decoder, "decoder")?;
+    let convert = make_element("videoconvert", "convert")?;
+    let overlay = overlay.map(build_clock_overlay).transpose()?;
+    let encoder = make_element(encoder, "encoder")?;
+    encoder.set_property_from_str("tune", "zerolatency");
+    encoder.set_property_from_str("speed-preset", "ultrafast");
+    let out_parser = make_element(out_parser, "outparser")?;
+    // Repeat the parameter sets so that clients can join at any keyframe
+    out_parser.set_property("config-interval", -1i32);
+    let payload = make_element(payload, "pay0")?;
+    let mut elements = vec![&source, &queue, &in_parser, &decoder, &convert];
+    elements.extend(overlay.as_ref());
+    elements.extend([&encoder, &out_parser, &payload].iter().copied());
+    bin.add_many(elements.iter().copied())?;
+    Element::link_many(elements.iter().copied())?;
+
+    let source = source
+        .dynamic_cast::<AppSrc>()
+        .map_err(|_| anyhow!("Cannot convert appsrc"))?;
+    Ok(source)
+}
+
+/// A textoverlay showing the time, which is updated as the frames pass
+fn build_clock_overlay(config: &OverlayConfig) -> Result<Element> {
+    let overlay = make_element("textoverlay", "overlay")?;
+    overlay.set_property_from_str("valignment", "top");
+    overlay.set_property_from_str("halignment", "left");
+    overlay.set_property("font-desc", "Sans, 16");
+    overlay.set_property("shaded-background", true);
+
+    let timezone = config.timezone.clone();
+    let format = config.format.clone();
+    let pad = overlay
+        .static_pad("video_sink")
+        .context("textoverlay has no video_sink pad")?;
+    let weak_overlay = overlay.downgrade();
+    pad.add_probe(PadProbeType::BUFFER, move |_, _| {
+        let zone = match timezone.as_deref() {
+            Some(timezone) => glib::TimeZone::new(Some(timezone)),
+            None => glib::TimeZone::local(),
+        };
+        if let (Some(overlay), Ok(text)) = (
+            weak_overlay.upgrade(),
+            glib::DateTime::now(&zone).and_then(|now| now.format(&format)),
+        ) {
+            // Only once a second rather than every frame
+            if overlay.property::<Option<String>>("text").as_deref() != Some(text.as_str()) {
+                overlay.set_property("text", text.as_str());
+            }
+        }
+        PadProbeReturn::Ok
+    });
+    Ok(overlay)
+}
+
+fn build_aac(bin: &Element, buffer_size: u32, pay_name: &str) -> Result<AppSrc> {
     let bin = bin
         .clone()
         .dynamic_cast::<Bin>()
@@ -269,7 +600,7 @@ fn build_aac(bin: &Element, stream_config: &StreamConfig) -> Result<AppSrc> {
     }
 
     let encoder = make_element("audioconvert", "audencoder")?;
-    let payload = make_element("rtpL16pay", "pay1")?;
+    let payload = make_element("rtpL16pay", pay_name)?;
 
     bin.add_many([&source, &queue, &parser, &decoder, &encoder, &payload])?;
     if let Ok(fallback_switch) = fallback_switch.as_ref() {
@@ -294,9 +625,7 @@ fn build_aac(bin: &Element, stream_config: &StreamConfig) -> Result<AppSrc> {
     Ok(source)
 }
 
-fn build_adpcm(bin: &Element, block_size: u32, stream_config: &StreamConfig) -> Result<AppSrc> {
-    let buffer_size = buffer_size(stream_config.bitrate);
-    log::debug!("buffer_size: {buffer_size}");
+fn build_adpcm(bin: &Element, block_size: u32, buffer_size: u32, pay_name: &str) -> Result<AppSrc> {
     let bin = bin
         .clone()
         .dynamic_cast::<Bin>()
@@ -336,7 +665,7 @@ fn build_adpcm(bin: &Element, block_size: u32, stream_config: &StreamConfig) ->
     let queue = make_queue("audqueue", buffer_size)?;
     let decoder = make_element("decodebin", "auddecoder")?;
     let encoder = make_element("audioconvert", "audencoder")?;
-    let payload = make_element("rtpL16pay", "pay1")?;
+    let payload = make_element("rtpL16pay", pay_name)?;
 
     bin.add_many([&source, &queue, &decoder, &encoder, &payload])?;
     Element::link_many([&source, &queue, &decoder])?;
@@ -360,35 +689,17 @@ fn build_adpcm(bin: &Element, block_size: u32, stream_config: &StreamConfig) ->
 // about what plugin is missing
 fn make_element(kind: &str, name: &str) -> AnyResult<Element> {
     ElementFactory::make_with_name(kind, Some(name)).with_context(|| {
-        let plugin = match kind {
-            "appsrc" => "app (gst-plugins-base)",
-            "audioconvert" => "audioconvert (gst-plugins-base)",
-            "adpcmdec" => "Required for audio",
-            "h264parse" => "videoparsersbad (gst-plugins-bad)",
-            "h265parse" => "videoparsersbad (gst-plugins-bad)",
-            "rtph264pay" => "rtp (gst-plugins-good)",
-            "rtph265pay" => "rtp (gst-plugins-good)",
-            "rtpjitterbuffer" => "rtp (gst-plugins-good)",
-            "aacparse" => "audioparsers (gst-plugins-good)",
-            "rtpL16pay" => "rtp (gst-plugins-good)",
-            "x264enc" => "x264 (gst-plugins-ugly)",
-            "x265enc" => "x265 (gst-plugins-bad)",
-            "avdec_h264" => "libav (gst-libav)",
-            "avdec_h265" => "libav (gst-libav)",
-            "videotestsrc" => "videotestsrc (gst-plugins-base)",
-            "imagefreeze" => "imagefreeze (gst-plugins-good)",
-            "audiotestsrc" => "audiotestsrc (gst-plugins-base)",
-            "decodebin" => "playback (gst-plugins-good)",
-            _ => "Unknown",
-        };
         format!(
             "Missing required gstreamer plugin `{}` for `{}` element",
-            plugin, kind
+            plugin_of(kind),
+            kind
         )
     })
 }
 fn make_queue(name: &str, buffer_size: u32) -> AnyResult<Element> {
     let queue = make_element("queue", &format!("queue1_{}", name))?;
+    // A client that cannot keep up loses its oldest data instead of growing without end
+    queue.set_property_from_str("leaky", "downstream");
     queue.set_property("max-size-bytes", buffer_size);
     queue.set_property("max-size-buffers", 0u32);
     queue.set_property("max-size-time", 0u64);
@@ -433,6 +744,134 @@ fn make_queue(name: &str, buffer_size: u32) -> AnyResult<Element> {
     Ok(bin)
 }
 
-fn buffer_size(bitrate: u32) -> u32 {
-    std::cmp::max(bitrate * 15u32 / 8u32, 4u32 * 1024u32 * 1024u32)
+/// Warns when the queues of a client drop data because the client is not keeping up
+///
+/// Logged at most every [`DROP_LOG_INTERVAL`] per queue with the count since the last one
+fn log_drops(element: &Element, name: &str) -> AnyResult<()> {
+    let bin = element
+        .clone()
+        .dynamic_cast::<Bin>()
+        .map_err(|_| anyhow!("Media source's element should be a bin"))?;
+    for (queue, kind) in [("source_queue", "video"), ("audqueue", "audio")] {
+        let Some(queue) = bin.by_name(&format!("queue1_{queue}")) else {
+            continue;
+        };
+        let name = name.to_string();
+        let drops = StdMutex::new((0u64, None::<Instant>));
+        queue.connect("overrun", false, move |_| {
+            let mut drops = drops.lock().unwrap();
+            drops.0 += 1;
+            if drops
+                .1
+                .map_or(true, |last| last.elapsed() >= DROP_LOG_INTERVAL)
+            {
+                log::warn!(
+                    "{name}: Dropped {} {kind} buffers of a client that cannot keep up",
+                    drops.0
+                );
+                *drops = (0, Some(Instant::now()));
+            }
+            None
+        });
+    }
+    Ok(())
+}
+
+/// The transports a client may set up, others are answered with 461 Unsupported Transport
+fn lower_transports(transport: RtspTransport) -> RTSPLowerTrans {
+    match transport {
+        // The default of gst-rtsp-server
+        RtspTransport::Any => RTSPLowerTrans::UDP | RTSPLowerTrans::UDP_MCAST | RTSPLowerTrans::TCP,
+        RtspTransport::Tcp => RTSPLowerTrans::TCP,
+        RtspTransport::Udp => RTSPLowerTrans::UDP | RTSPLowerTrans::UDP_MCAST,
+    }
+}
+
+/// The jitterbuffer latency of the factory or None for gstreamer's default
+fn rtp_latency(latency: Latency, jitterbuffer_ms: Option<u32>) -> Option<u32> {
+    jitterbuffer_ms.or((latency == Latency::Low).then_some(LOW_LATENCY_MS))
+}
+
+/// Roughly the buffer duration of data normally or at most 2s of data in low latency mode
+fn buffer_size(bitrate: u32, latency: Latency, buffer_duration: Duration) -> u32 {
+    let millis = buffer_duration.as_millis() as u64;
+    let bytes = |millis: u64| (bitrate as u64 * millis / 8000u64).min(u32::MAX as u64) as u32;
+    match latency {
+        Latency::Normal => std::cmp::max(bytes(millis), 4u32 * 1024u32 * 1024u32),
+        Latency::Low => std::cmp::max(bytes(millis.min(2000)), 512u32 * 1024u32),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transcode_target() {
+        // Matching codecs are passed through
+        assert_eq!(
+            transcode_target(&VidFormat::H264, Some(Transcode::H264)),
+            None
+        );
+        assert_eq!(transcode_target(&VidFormat::H265, None), None);
+        assert_eq!(
+            transcode_target(&VidFormat::H265, Some(Transcode::H264)),
+            Some(VidFormat::H264)
+        );
+        assert_eq!(
+            transcode_target(&VidFormat::H264, Some(Transcode::H265)),
+            Some(VidFormat::H265)
+        );
+        assert_eq!(
+            transcode_target(&VidFormat::None, Some(Transcode::H264)),
+            None
+        );
+    }
+
+    #[test]
+    fn test_rtp_latency() {
+        assert_eq!(rtp_latency(Latency::Normal, None), None);
+        assert_eq!(rtp_latency(Latency::Low, None), Some(LOW_LATENCY_MS));
+        // The camera's own setting wins
+        assert_eq!(rtp_latency(Latency::Low, Some(500)), Some(500));
+        assert_eq!(rtp_latency(Latency::Normal, Some(0)), Some(0));
+    }
+
+    #[test]
+    fn test_lower_transports() {
+        let transport = |text: &str| {
+            let config: CameraConfig =
+                toml::from_str(&format!("name = \"Garage\"\nusername = \"admin\"\n{text}"))
+                    .unwrap();
+            lower_transports(config.rtsp_transport)
+        };
+        let any = transport("");
+        assert!(any.contains(RTSPLowerTrans::TCP | RTSPLowerTrans::UDP));
+        assert_eq!(transport("rtsp_transport = \"tcp\""), RTSPLowerTrans::TCP);
+        let udp = transport("rtsp_transport = \"udp\"");
+        assert!(udp.contains(RTSPLowerTrans::UDP));
+        assert!(!udp.contains(RTSPLowerTrans::TCP));
+    }
+
+    #[test]
+    fn test_client_limit() {
+        let config: CameraConfig =
+            toml::from_str("name = \"Garage\"\nusername = \"admin\"\nmax_clients = 2\n").unwrap();
+        let (config_tx, config_rx) = tokio::sync::watch::channel(config);
+        let limit = ClientLimit::new(config_rx);
+
+        // The streams of a camera share one count
+        let first = limit.acquire().unwrap();
+        let second = limit.clone().acquire().unwrap();
+        assert!(limit.acquire().is_none());
+        // A rejected client is not counted
+        assert_eq!(limit.clients.load(Ordering::SeqCst), 2);
+
+        drop(first);
+        let third = limit.acquire().unwrap();
+        config_tx.send_modify(|config| config.max_clients = None);
+        assert!(limit.acquire().is_some());
+        drop((second, third));
+        assert_eq!(limit.clients.load(Ordering::SeqCst), 0);
+    }
 }
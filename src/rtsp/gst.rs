@@ -1,12 +1,15 @@
 //! This module provides an "RtspServer" abstraction that allows consumers of its API to feed it
 //! data using an ordinary std::io::Write interface.
 
+mod auth;
+mod client;
 mod factory;
 mod server;
 mod shared;
+mod sni;
 
 pub(crate) use factory::*;
 
-pub(crate) use self::server::NeoRtspServer;
+pub(crate) use self::server::{NeoRtspServer, UNIX_PREFIX};
 
 type AnyResult<T> = std::result::Result<T, anyhow::Error>;
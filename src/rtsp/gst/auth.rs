@@ -0,0 +1,237 @@
+//! Attempts to subclass RtspAuth
+//!
+//! We are now messing with gstreamer glib objects
+//! expect issues
+//!
+//! On top of basic auth a client may give an access token in the query of the
+//! url, e.g. `rtsp://host:8554/Garage?token=XYZ`. A valid token is given the
+//! role of its camera
+//!
+//! With more than one certificate the one given to a connection is picked by
+//! the hostname that the client asked for. The server reads the hostname
+//! before it hands the connection over, see [`super::sni`]
+
+use gstreamer::glib::{
+    self, object_subclass, subclass::types::ObjectSubclass, translate::*, Object,
+};
+use gstreamer_rtsp_server::{
+    gio::{prelude::*, TlsCertificate},
+    subclass::prelude::*,
+    RTSPAuth, RTSPContext, RTSPToken, RTSP_AUTH_CHECK_CONNECT, RTSP_TOKEN_MEDIA_FACTORY_ROLE,
+};
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use super::sni;
+use crate::config::TokenConfig;
+
+glib::wrapper! {
+    /// The wrapped RTSPAuth
+    pub(crate) struct NeoRtspAuth(ObjectSubclass<NeoRtspAuthImpl>) @extends RTSPAuth;
+}
+
+impl Default for NeoRtspAuth {
+    fn default() -> Self {
+        Object::new::<NeoRtspAuth>()
+    }
+}
+
+impl NeoRtspAuth {
+    /// Replaces the access tokens with these tokens and the role each one gives
+    pub(crate) fn set_tokens(&self, tokens: &[(TokenConfig, String)]) {
+        let mut locked_tokens = self.imp().tokens.lock().unwrap();
+        locked_tokens.clear();
+        for (token, role) in tokens {
+            let access = AccessToken {
+                token: RTSPToken::new(&[(RTSP_TOKEN_MEDIA_FACTORY_ROLE, &role.as_str())]),
+                expires: token.expires_at(),
+            };
+            if locked_tokens.insert(token.token.clone(), access).is_some() {
+                log::warn!(
+                    "An access token is used by more than one camera, only the last one is used"
+                );
+            }
+        }
+    }
+
+    /// Replaces the certificates that are given instead of the default one to
+    /// clients that ask for these lowercase hostnames
+    pub(crate) fn set_sni_certificates(&self, certificates: HashMap<String, TlsCertificate>) {
+        *self.imp().sni_certificates.lock().unwrap() = certificates;
+    }
+
+    /// Whether any hostname has a certificate of its own
+    pub(crate) fn has_sni_certificates(&self) -> bool {
+        !self.imp().sni_certificates.lock().unwrap().is_empty()
+    }
+
+    /// Keeps the certificate of the hostname for the connection on the socket
+    /// `fd` until it connects, or forgets an older one when there is none
+    pub(crate) fn choose_certificate(&self, fd: i32, hostname: Option<&str>) {
+        let certificate = hostname.and_then(|hostname| {
+            let certificates = self.imp().sni_certificates.lock().unwrap();
+            match sni::certificate_for(&certificates, hostname) {
+                Some(certificate) => Some(certificate.clone()),
+                None => {
+                    log::debug!("No certificate for {hostname}, using the default certificate");
+                    None
+                }
+            }
+        });
+        let mut chosen = self.imp().chosen_certificates.lock().unwrap();
+        match certificate {
+            Some(certificate) => chosen.insert(fd, certificate),
+            None => chosen.remove(&fd),
+        };
+    }
+}
+
+unsafe impl Send for NeoRtspAuth {}
+unsafe impl Sync for NeoRtspAuth {}
+
+struct AccessToken {
+    token: RTSPToken,
+    /// Seconds since the unix epoch
+    expires: Option<u64>,
+}
+
+#[derive(Default)]
+pub(crate) struct NeoRtspAuthImpl {
+    tokens: Mutex<HashMap<String, AccessToken>>,
+    sni_certificates: Mutex<HashMap<String, TlsCertificate>>,
+    /// The certificate of each socket that is about to connect
+    chosen_certificates: Mutex<HashMap<i32, TlsCertificate>>,
+}
+
+impl ObjectImpl for NeoRtspAuthImpl {}
+impl RTSPAuthImpl for NeoRtspAuthImpl {
+    fn authenticate(&self, ctx: &RTSPContext) -> bool {
+        if let Some(query_token) = request_uri(ctx).as_deref().and_then(query_token) {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|since| since.as_secs())
+                .unwrap_or_default();
+            let tokens = self.tokens.lock().unwrap();
+            match tokens.get(query_token) {
+                Some(access) if access.expires.map_or(true, |expires| now < expires) => {
+                    // Like the basic auth of gstreamer the context borrows the
+                    // token which is kept alive by the map
+                    unsafe {
+                        (*ctx.as_ptr()).token = access.token.as_ptr();
+                    }
+                    return true;
+                }
+                Some(_) => log::info!("Rejected an expired access token"),
+                None => log::info!("Rejected an unknown access token"),
+            }
+        }
+        // Basic auth or the anonymous default
+        self.parent_authenticate(ctx)
+    }
+
+    fn check(&self, ctx: &RTSPContext, check: &glib::GString) -> bool {
+        // The default sets up the tls of a new connection with the default
+        // certificate
+        if !self.parent_check(ctx, check) {
+            return false;
+        }
+        if check.as_str() == RTSP_AUTH_CHECK_CONNECT.as_str() {
+            self.use_chosen_certificate(ctx);
+        }
+        true
+    }
+}
+
+impl NeoRtspAuthImpl {
+    /// Gives the connection the certificate that was chosen for its socket
+    #[cfg(unix)]
+    fn use_chosen_certificate(&self, ctx: &RTSPContext) {
+        use gstreamer_rtsp_server::gio::{Socket, TlsConnection};
+        use std::os::unix::io::AsRawFd;
+
+        let conn = unsafe { (*ctx.as_ptr()).conn };
+        if conn.is_null() {
+            return;
+        }
+        let socket: Option<Socket> = unsafe {
+            from_glib_none(gstreamer_rtsp::ffi::gst_rtsp_connection_get_read_socket(
+                conn,
+            ))
+        };
+        let Some(socket) = socket else {
+            return;
+        };
+        let Some(certificate) = self
+            .chosen_certificates
+            .lock()
+            .unwrap()
+            .remove(&socket.as_raw_fd())
+        else {
+            return;
+        };
+        let tls: Option<TlsConnection> = unsafe {
+            from_glib_none(gstreamer_rtsp::ffi::gst_rtsp_connection_get_tls(
+                conn,
+                std::ptr::null_mut(),
+            ))
+        };
+        match tls {
+            Some(tls) => tls.set_certificate(&certificate),
+            None => log::warn!("Could not set the certificate of a connection"),
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn use_chosen_certificate(&self, _ctx: &RTSPContext) {}
+}
+
+#[object_subclass]
+impl ObjectSubclass for NeoRtspAuthImpl {
+    const NAME: &'static str = "NeoRtspAuth";
+    type Type = NeoRtspAuth;
+    type ParentType = RTSPAuth;
+}
+
+/// The full uri of the request, including its query
+fn request_uri(ctx: &RTSPContext) -> Option<String> {
+    unsafe {
+        let uri = (*ctx.as_ptr()).uri;
+        if uri.is_null() {
+            return None;
+        }
+        let request_uri: glib::GString =
+            from_glib_full(gstreamer_rtsp::ffi::gst_rtsp_url_get_request_uri(uri));
+        Some(request_uri.to_string())
+    }
+}
+
+/// The value of `token=` in the query of the uri
+fn query_token(uri: &str) -> Option<&str> {
+    let (_, query) = uri.split_once('?')?;
+    query
+        .split('&')
+        .find_map(|pair| pair.strip_prefix("token="))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_query_token() {
+        assert_eq!(
+            query_token("rtsp://host:8554/Garage?token=abcdefgh"),
+            Some("abcdefgh")
+        );
+        // Setup requests put the control before the query
+        assert_eq!(
+            query_token("rtsp://host:8554/Garage/stream=0?a=1&token=abcdefgh"),
+            Some("abcdefgh")
+        );
+        assert_eq!(query_token("rtsp://host:8554/Garage"), None);
+        assert_eq!(query_token("rtsp://host:8554/Garage?tokens=abcdefgh"), None);
+    }
+}
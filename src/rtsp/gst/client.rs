@@ -0,0 +1,97 @@
+//! Attempts to subclass RtspClient
+//!
+//! We are now messing with gstreamer glib objects
+//! expect issues
+
+use gstreamer::{
+    glib::{self, object_subclass, subclass::types::ObjectSubclass, Object},
+    prelude::*,
+    Bin, Element, LoggableError,
+};
+use gstreamer_rtsp::RTSPUrl;
+use gstreamer_rtsp_server::{
+    prelude::*, subclass::prelude::*, RTSPClient, RTSPContext, RTSPMedia, RTSPStream,
+};
+use std::sync::Mutex as StdMutex;
+
+glib::wrapper! {
+    /// The wrapped RTSPClient
+    pub(crate) struct NeoRtspClient(ObjectSubclass<NeoRtspClientImpl>) @extends RTSPClient;
+}
+
+impl Default for NeoRtspClient {
+    fn default() -> Self {
+        Object::new::<NeoRtspClient>()
+    }
+}
+
+impl NeoRtspClient {
+    /// The DSCP to mark the rtp over udp with, unmarked if `None`
+    pub(crate) fn set_dscp(&self, dscp: Option<u8>) {
+        *self.imp().dscp.lock().unwrap() = dscp;
+    }
+}
+
+unsafe impl Send for NeoRtspClient {}
+unsafe impl Sync for NeoRtspClient {}
+
+#[derive(Default)]
+pub(crate) struct NeoRtspClientImpl {
+    dscp: StdMutex<Option<u8>>,
+}
+
+impl ObjectImpl for NeoRtspClientImpl {}
+impl RTSPClientImpl for NeoRtspClientImpl {
+    fn make_path_from_uri(&self, url: &RTSPUrl) -> Option<glib::GString> {
+        // The default keeps the query, e.g. `?token=`, which then never
+        // matches a mount point
+        self.parent_make_path_from_uri(url)
+            .map(|path| strip_query(&path).into())
+    }
+
+    fn configure_client_media(
+        &self,
+        media: &RTSPMedia,
+        stream: &RTSPStream,
+        ctx: &RTSPContext,
+    ) -> Result<(), LoggableError> {
+        if let Some(dscp) = *self.dscp.lock().unwrap() {
+            mark_dscp(media, dscp);
+        }
+        self.parent_configure_client_media(media, stream, ctx)
+    }
+}
+
+#[object_subclass]
+impl ObjectSubclass for NeoRtspClientImpl {
+    const NAME: &'static str = "NeoRtspClient";
+    type Type = NeoRtspClient;
+    type ParentType = RTSPClient;
+}
+
+/// Sets `qos-dscp` on the udp sinks of the media, including the ones that
+/// gstreamer only adds once the transport of the client is known
+///
+/// Rtp interleaved in the rtsp connection over tcp is left unmarked
+fn mark_dscp(media: &RTSPMedia, dscp: u8) {
+    let Some(pipeline) = media
+        .element()
+        .parent()
+        .and_then(|parent| parent.downcast::<Bin>().ok())
+    else {
+        return;
+    };
+    let mark = move |element: &Element| {
+        if element.find_property("qos-dscp").is_some() {
+            element.set_property("qos-dscp", dscp as i32);
+        }
+    };
+    for element in pipeline.iterate_recurse().into_iter().flatten() {
+        mark(&element);
+    }
+    pipeline.connect_deep_element_added(move |_, _, element| mark(element));
+}
+
+fn strip_query(path: &str) -> &str {
+    path.split_once('?').map(|(path, _)| path).unwrap_or(path)
+}
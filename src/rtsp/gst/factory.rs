@@ -6,11 +6,11 @@
 use super::AnyResult;
 use gstreamer::glib::object_subclass;
 use gstreamer::glib::subclass::types::ObjectSubclass;
-use gstreamer::Element;
 use gstreamer::{
     glib::{self, Object},
     Structure,
 };
+use gstreamer::{prelude::*, Bin, DebugGraphDetails, Element, MessageView};
 use gstreamer_rtsp::RTSPUrl;
 use gstreamer_rtsp_server::prelude::*;
 use gstreamer_rtsp_server::subclass::prelude::*;
@@ -19,8 +19,17 @@ use gstreamer_rtsp_server::RTSPTransportMode;
 use gstreamer_rtsp_server::{RTSP_PERM_MEDIA_FACTORY_ACCESS, RTSP_PERM_MEDIA_FACTORY_CONSTRUCT};
 use log::*;
 use std::collections::HashSet;
-use std::sync::Arc;
-use tokio::sync::Mutex;
+use std::sync::{Arc, Mutex as StdMutex};
+use tokio::sync::{
+    watch::{channel as watch, Receiver as WatchReceiver, Sender as WatchSender},
+    Mutex,
+};
+
+use crate::rtsp::sdp::{caps_fields, make_sdp};
+
+/// The name of the source of the placeholder that is served until the stream
+/// is ready, it is how the sessions still on the placeholder are found
+pub(crate) const NOT_READY_SOURCE: &str = "notreadysrc";
 
 glib::wrapper! {
     /// The wrapped RTSPMediaFactory
@@ -43,9 +52,67 @@ impl NeoMediaFactory {
         factory.set_suspend_mode(gstreamer_rtsp_server::RTSPSuspendMode::Reset);
         factory.set_launch("videotestsrc pattern=\"snow\" ! video/x-raw,width=896,height=512,framerate=25/1 ! textoverlay name=\"inittextoverlay\" text=\"Stream not Ready\" valignment=top halignment=left font-desc=\"Sans, 32\" ! jpegenc ! rtpjpegpay name=pay0");
         factory.set_transport_mode(RTSPTransportMode::PLAY);
+        if log_enabled!(Level::Debug) {
+            factory.log_caps();
+        }
         factory
     }
 
+    /// Logs the caps that each payloader negotiates and, when the pipeline
+    /// fails, what the failing element has and could take
+    ///
+    /// This is for streams that play in one client but not in another
+    fn log_caps(&self) {
+        self.connect_media_configure(|factory, media| {
+            debug!(
+                "Media configured with profiles {:?} and protocols {:?}",
+                factory.profiles(),
+                factory.protocols()
+            );
+            let element = media.element();
+            if let Some(bin) = element.downcast_ref::<Bin>() {
+                for n in 0..media.n_streams() {
+                    let Some(pad) = bin
+                        .by_name(&format!("pay{n}"))
+                        .and_then(|pay| pay.static_pad("src"))
+                    else {
+                        continue;
+                    };
+                    pad.connect_notify(Some("caps"), move |pad, _| {
+                        if let Some(caps) = pad.current_caps() {
+                            debug!("Stream {n} negotiated {caps}");
+                        }
+                    });
+                }
+            }
+            if let Some(bus) = element.bus() {
+                bus.enable_sync_message_emission();
+                bus.connect_sync_message(None, |_, message| {
+                    let (MessageView::Error(_) | MessageView::Warning(_)) = message.view() else {
+                        return;
+                    };
+                    debug!("Media pipeline reported: {:?}", message);
+                    if let Some(failed) =
+                        message.src().and_then(|src| src.downcast_ref::<Element>())
+                    {
+                        for pad in failed.pads() {
+                            debug!(
+                                "  {}:{} has {}, can take {} and its peer can take {}",
+                                failed.name(),
+                                pad.name(),
+                                pad.current_caps()
+                                    .map(|caps| caps.to_string())
+                                    .unwrap_or_else(|| "no caps".to_string()),
+                                pad.query_caps(None),
+                                pad.peer_query_caps(None)
+                            );
+                        }
+                    }
+                });
+            }
+        });
+    }
+
     pub(crate) async fn new_with_callback<F>(callback: F) -> AnyResult<Self>
     where
         F: Fn(Element) -> AnyResult<Option<Element>> + Send + Sync + 'static,
@@ -55,6 +122,44 @@ impl NeoMediaFactory {
         Ok(factory)
     }
 
+    /// Keeps the sdp of each media once it is prepared so it can be served without a DESCRIBE
+    pub(crate) fn keep_sdp(&self) {
+        let sdp = self.imp().sdp.clone();
+        self.connect_media_configure(move |_, media| {
+            let sdp = sdp.clone();
+            media.connect_prepared(move |media| {
+                // Only once every stream has negotiated its caps
+                let streams = (0..media.n_streams())
+                    .map(|n| {
+                        media
+                            .stream(n)
+                            .and_then(|stream| stream.caps())
+                            .and_then(|caps| caps_fields(&caps))
+                    })
+                    .collect::<Option<Vec<_>>>();
+                if let Some(streams) = streams {
+                    *sdp.lock().unwrap() = Some(make_sdp(&streams));
+                }
+            });
+        });
+    }
+
+    /// The sdp of the last prepared media, if any
+    pub(crate) fn sdp(&self) -> Option<String> {
+        self.imp().sdp.lock().unwrap().clone()
+    }
+
+    /// Asks the clients of this factory to drop what they are behind on and
+    /// jump to the latest keyframe
+    pub(crate) fn jump_to_live(&self) {
+        self.imp().jumps.send_modify(|jumps| *jumps += 1);
+    }
+
+    /// Changes each time [`NeoMediaFactory::jump_to_live`] is called
+    pub(crate) fn jumps(&self) -> WatchReceiver<u64> {
+        self.imp().jumps.subscribe()
+    }
+
     pub(crate) fn add_permitted_roles<T: AsRef<str>>(&self, permitted_roles: &HashSet<T>) {
         for permitted_role in permitted_roles {
             let s = permitted_role.as_ref();
@@ -101,6 +206,8 @@ unsafe impl Sync for NeoMediaFactory {}
 pub(crate) struct NeoMediaFactoryImpl {
     #[allow(clippy::type_complexity)]
     call_back: Arc<Mutex<Option<Arc<dyn Fn(Element) -> AnyResult<Option<Element>> + Send + Sync>>>>,
+    sdp: Arc<StdMutex<Option<String>>>,
+    jumps: WatchSender<u64>,
 }
 
 impl Default for NeoMediaFactoryImpl {
@@ -109,6 +216,8 @@ impl Default for NeoMediaFactoryImpl {
         // Prepare thread that sends data into the appsrcs
         Self {
             call_back: Arc::new(Mutex::new(None)),
+            sdp: Arc::new(StdMutex::new(None)),
+            jumps: watch(0).0,
         }
     }
 }
@@ -123,11 +232,19 @@ impl NeoMediaFactoryImpl {
     fn build_pipeline(&self, media: Element) -> AnyResult<Option<Element>> {
         match self.call_back.blocking_lock().as_ref() {
             Some(call) => {
-                let new_media = call(media);
+                let new_media = call(media.clone());
                 match new_media {
                     Ok(new_media) => Ok(new_media),
                     Err(e) => {
                         log::debug!("Media source is currently restarting: {e:?}");
+                        // Only written when --gst-dot-dir is given
+                        if let Some(bin) = media.downcast_ref::<Bin>() {
+                            gstreamer::debug_bin_to_dot_file_with_ts(
+                                bin,
+                                DebugGraphDetails::all(),
+                                "neolink-factory-error",
+                            );
+                        }
                         Ok(None)
                     }
                 }
@@ -3,31 +3,52 @@
 //! We are now messing with gstreamer glib objects
 //! expect issues
 
-use super::AnyResult;
-use crate::config::*;
+#[cfg(unix)]
+use super::sni::{peek_client_hello, ClientHello};
+use super::{
+    auth::NeoRtspAuth, client::NeoRtspClient, AnyResult, NeoMediaFactory, NOT_READY_SOURCE,
+};
+use crate::{config::*, rtsp::adaptive::SessionStats};
 
-use anyhow::Context;
-use gstreamer::glib::{self, object_subclass, subclass::types::ObjectSubclass, MainLoop, Object};
+use anyhow::{anyhow, Context};
+use gstreamer::{
+    glib::{self, object_subclass, subclass::types::ObjectSubclass, MainLoop, Object},
+    Bin, Structure,
+};
 use gstreamer_rtsp::RTSPAuthMethod;
 use gstreamer_rtsp_server::{
-    gio::{TlsAuthenticationMode, TlsCertificate},
+    gio::{Socket, TlsAuthenticationMode, TlsCertificate, TlsError},
     prelude::*,
     subclass::prelude::*,
-    RTSPAuth, RTSPFilterResult, RTSPServer, RTSPToken, RTSP_TOKEN_MEDIA_FACTORY_ROLE,
+    RTSPAuth, RTSPClient, RTSPFilterResult, RTSPMedia, RTSPServer, RTSPSessionMedia, RTSPToken,
+    RTSP_TOKEN_MEDIA_FACTORY_ROLE,
 };
 use log::*;
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, HashMap, HashSet},
     fs,
-    sync::Arc,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc, Mutex as StdMutex,
+    },
 };
 use tokio::{
-    sync::RwLock,
-    task::JoinSet,
-    time::{timeout, Duration},
+    sync::{
+        watch::{channel as watch, Receiver as WatchReceiver, Sender as WatchSender},
+        RwLock,
+    },
+    task::{JoinHandle, JoinSet},
+    time::{interval, timeout, Duration, Instant},
 };
 use tokio_util::sync::CancellationToken;
 
+/// Seconds without a keepalive before a session is closed unless configured
+const DEFAULT_IDLE_TIMEOUT: u32 = 5;
+
+/// A bind address of `unix:/path/to.sock` serves on a unix socket
+pub(crate) const UNIX_PREFIX: &str = "unix:";
+
 glib::wrapper! {
     /// The wrapped RTSPServer
     pub(crate) struct NeoRtspServer(ObjectSubclass<NeoRtspServerImpl>) @extends RTSPServer;
@@ -45,7 +66,7 @@ impl NeoRtspServer {
         let factory = Object::new::<NeoRtspServer>();
 
         // Setup auth
-        let auth = factory.auth().unwrap_or_default();
+        let auth = NeoRtspAuth::default();
         auth.set_supported_methods(RTSPAuthMethod::Basic);
         let mut un_authtoken = RTSPToken::new(&[
             //RTSP_TOKEN_MEDIA_FACTORY_ROLE: Means look inside the media factory settings and use the same permissions this user (`"anonymous"`) has
@@ -54,10 +75,13 @@ impl NeoRtspServer {
         auth.set_default_token(Some(&mut un_authtoken));
         factory.set_auth(Some(&auth));
 
-        factory.connect_client_connected(|_, client| {
-            client.connect_new_session(|_, session| {
+        let idle_timeout = factory.imp().idle_timeout.clone();
+        idle_timeout.store(DEFAULT_IDLE_TIMEOUT, Ordering::Relaxed);
+        factory.connect_client_connected(move |_, client| {
+            let idle_timeout = idle_timeout.clone();
+            client.connect_new_session(move |_, session| {
                 log::debug!("New Session");
-                session.set_timeout(5);
+                session.set_timeout(idle_timeout.load(Ordering::Relaxed));
             });
         });
 
@@ -66,53 +90,45 @@ impl NeoRtspServer {
 
     pub(crate) async fn run(&self, bind_addr: &str, bind_port: u16) -> AnyResult<()> {
         let server = self;
-        server.set_address(bind_addr);
-        server.set_service(&format!("{}", bind_port));
-        // Attach server to default Glib context
-        let _ = server.attach(None);
-        let main_loop = Arc::new(MainLoop::new(None, false));
-
-        // Run the Glib main loop.
-        let main_loop_thread = main_loop.clone();
-        let main_loop_cancel = CancellationToken::new();
-        let main_loop_gaurd = main_loop_cancel.clone().drop_guard();
-        let handle = tokio::task::spawn_blocking(move || {
-            main_loop_thread.run();
-            drop(main_loop_gaurd);
-            AnyResult::Ok(())
-        });
-        timeout(Duration::from_secs(5), self.imp().threads.write())
-            .await
-            .with_context(|| "Timeout waiting to lock Server threads")?
-            .spawn(async move { handle.await? });
+        if let Some(socket_path) = bind_addr.strip_prefix(UNIX_PREFIX) {
+            self.listen_unix(socket_path).await?;
+        } else if self.sni_auth().is_some() {
+            self.listen_sni(bind_addr, bind_port).await?;
+        } else {
+            server.set_address(bind_addr);
+            server.set_service(&format!("{}", bind_port));
+            // Attach server to default Glib context
+            let source = server
+                .attach(None)
+                .with_context(|| format!("Cannot listen for rtsp on {bind_addr}:{bind_port}"))?;
+            self.imp().listening.lock().unwrap().replace(source);
+        }
+
+        // Every server is attached to the default context so one main loop
+        // runs them all
+        let ((main_loop, stopped), handle) = shared_main_loop()?;
+        if let Some(handle) = handle {
+            timeout(Duration::from_secs(5), self.imp().threads.write())
+                .await
+                .with_context(|| "Timeout waiting to lock Server threads")?
+                .spawn(async move { Ok(handle.await?) });
+        }
 
         let clean_up_server = server.clone();
-        let handle = tokio::task::spawn_blocking(move || {
-            while !main_loop_cancel.is_cancelled() {
-                if let Some(sessions) = clean_up_server.session_pool() {
-                    let cleanups = sessions.cleanup();
-                    if cleanups > 0 {
-                        log::debug!("Cleaned up {cleanups} sessions");
-                    }
-                    sessions.filter(Some(&mut |_, session| {
-                        let remaining = session.next_timeout_usec(glib::monotonic_time());
-                        log::debug!(
-                            "{:?}: {}/{}",
-                            session.sessionid(),
-                            remaining,
-                            session.timeout(),
-                        );
-                        RTSPFilterResult::Keep
-                    }));
-                }
-                std::thread::sleep(Duration::from_secs(5));
-            }
-            AnyResult::Ok(())
-        });
         timeout(Duration::from_secs(5), self.imp().threads.write())
             .await
             .with_context(|| "Timeout waiting to lock Server threads")?
-            .spawn(async move { handle.await? });
+            .spawn(async move {
+                let mut interval = interval(Duration::from_secs(5));
+                loop {
+                    tokio::select! {
+                        _ = stopped.cancelled() => break,
+                        _ = interval.tick() => {},
+                    }
+                    clean_up_sessions(&clean_up_server);
+                }
+                AnyResult::Ok(())
+            });
 
         // Put copy of main loop inside the rtsp server
         timeout(Duration::from_secs(5), self.imp().main_loop.write())
@@ -122,13 +138,175 @@ impl NeoRtspServer {
         Ok(())
     }
 
+    /// Accepts clients on a unix socket instead of a tcp port
+    ///
+    /// Each accepted connection is handed over to the rtsp server which
+    /// then treats it like any other client
+    #[cfg(unix)]
+    async fn listen_unix(&self, socket_path: &str) -> AnyResult<()> {
+        use std::os::unix::fs::FileTypeExt;
+        use tokio::net::UnixListener;
+
+        // Left over from a previous run
+        if fs::symlink_metadata(socket_path).is_ok_and(|meta| meta.file_type().is_socket()) {
+            fs::remove_file(socket_path)
+                .with_context(|| format!("Cannot remove the old socket at {socket_path}"))?;
+        }
+        let listener = UnixListener::bind(socket_path)
+            .with_context(|| format!("Cannot create the rtsp socket at {socket_path}"))?;
+
+        let server = self.clone();
+        let socket_path = socket_path.to_string();
+        let accepting = self.imp().accepting.clone();
+        timeout(Duration::from_secs(5), self.imp().threads.write())
+            .await
+            .with_context(|| "Timeout waiting to lock Server threads")?
+            .spawn(async move {
+                loop {
+                    let (stream, _) = tokio::select! {
+                        _ = accepting.cancelled() => return Ok(()),
+                        v = listener.accept() => v?,
+                    };
+                    let socket = unsafe { Socket::from_fd(stream.into_std()?) }?;
+                    if let Err(e) = server.transfer_connection(socket, "127.0.0.1", 0, None) {
+                        log::warn!("Could not serve rtsp client from {socket_path}: {e}");
+                    }
+                }
+            });
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    async fn listen_unix(&self, _socket_path: &str) -> AnyResult<()> {
+        Err(anyhow!("Unix sockets are not supported on this platform"))
+    }
+
+    /// The auth of the server when it has certificates by hostname
+    fn sni_auth(&self) -> Option<NeoRtspAuth> {
+        self.auth()
+            .and_then(|auth| auth.downcast::<NeoRtspAuth>().ok())
+            .filter(|auth| auth.has_sni_certificates())
+    }
+
+    /// Accepts the tcp clients itself to read the hostname that each asks for
+    ///
+    /// The ClientHello is waited for here rather than on the main loop that
+    /// serves every client, then the connection is handed over to the rtsp
+    /// server with the certificate of that hostname
+    #[cfg(unix)]
+    async fn listen_sni(&self, bind_addr: &str, bind_port: u16) -> AnyResult<()> {
+        use std::os::unix::io::AsRawFd;
+        use tokio::net::TcpListener;
+
+        let listener = TcpListener::bind((bind_addr, bind_port))
+            .await
+            .with_context(|| format!("Cannot listen for rtsp on {bind_addr}:{bind_port}"))?;
+        let auth = self.sni_auth().ok_or(anyhow!("Server lacks its auth"))?;
+
+        let server = self.clone();
+        let accepting = self.imp().accepting.clone();
+        timeout(Duration::from_secs(5), self.imp().threads.write())
+            .await
+            .with_context(|| "Timeout waiting to lock Server threads")?
+            .spawn(async move {
+                loop {
+                    let (stream, peer) = tokio::select! {
+                        _ = accepting.cancelled() => return Ok(()),
+                        v = listener.accept() => v?,
+                    };
+                    let server = server.clone();
+                    let auth = auth.clone();
+                    // A slow client only holds up its own connection
+                    tokio::task::spawn(async move {
+                        let hostname = match peek_client_hello(&stream).await {
+                            ClientHello::Sni(hostname) => Some(hostname),
+                            ClientHello::NoSni => None,
+                            ClientHello::Incomplete => {
+                                log::debug!(
+                                    "No ClientHello from {peer} in time, using the default certificate"
+                                );
+                                None
+                            }
+                        };
+                        auth.choose_certificate(stream.as_raw_fd(), hostname.as_deref());
+                        let socket = unsafe { Socket::from_fd(stream.into_std()?) }?;
+                        if let Err(e) = server.transfer_connection(
+                            socket,
+                            &peer.ip().to_string(),
+                            peer.port() as i32,
+                            None,
+                        ) {
+                            log::warn!("Could not serve rtsp client {peer}: {e}");
+                        }
+                        AnyResult::Ok(())
+                    });
+                }
+            });
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    async fn listen_sni(&self, bind_addr: &str, bind_port: u16) -> AnyResult<()> {
+        log::warn!("Picking the certificate by hostname is not supported on this platform");
+        self.set_address(bind_addr);
+        self.set_service(&format!("{}", bind_port));
+        let source = self
+            .attach(None)
+            .with_context(|| format!("Cannot listen for rtsp on {bind_addr}:{bind_port}"))?;
+        self.imp().listening.lock().unwrap().replace(source);
+        Ok(())
+    }
+
+    /// Quits the main loop, which all of the servers share
     pub(crate) async fn quit(&self) -> AnyResult<()> {
         if let Some(main_loop) = self.imp().main_loop.read().await.as_ref() {
             main_loop.quit();
+            // The next server to run starts a new one
+            if let Ok(mut shared) = MAIN_LOOP.lock() {
+                if shared
+                    .as_ref()
+                    .is_some_and(|(shared, _)| Arc::ptr_eq(shared, main_loop))
+                {
+                    shared.take();
+                }
+            }
         }
         Ok(())
     }
 
+    /// Whether the glib main loop of the server is running
+    pub(crate) async fn is_running(&self) -> bool {
+        self.imp()
+            .main_loop
+            .read()
+            .await
+            .as_ref()
+            .is_some_and(|main_loop| main_loop.is_running())
+    }
+
+    /// Turns new clients away, the connected ones carry on
+    pub(crate) fn stop_accepting(&self) {
+        if let Some(source) = self.imp().listening.lock().unwrap().take() {
+            source.remove();
+        }
+        self.imp().accepting.cancel();
+    }
+
+    /// Wait until all clients have disconnected or the deadline has passed
+    pub(crate) async fn drain(&self, deadline: Instant) {
+        loop {
+            let clients = self.client_filter(None).len();
+            if clients == 0 {
+                break;
+            }
+            if Instant::now() >= deadline {
+                log::info!("Closing {clients} remaining rtsp clients");
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    }
+
     pub(crate) async fn join(&self) -> AnyResult<()> {
         let mut threads = self.imp().threads.write().await;
         while let Some(thread) = threads.join_next().await {
@@ -141,6 +319,16 @@ impl NeoRtspServer {
         self.imp().set_up_tls(config)
     }
 
+    /// Seconds without a keepalive before a new session is closed
+    pub(crate) fn set_idle_timeout(&self, secs: u32) {
+        self.imp().idle_timeout.store(secs, Ordering::Relaxed);
+    }
+
+    /// The DSCP that the rtp over udp of new clients is marked with
+    pub(crate) fn set_dscp(&self, dscp: Option<u8>) {
+        *self.imp().dscp.lock().unwrap() = dscp;
+    }
+
     pub(crate) async fn add_user(&self, username: &str, password: &str) -> AnyResult<()> {
         self.imp().add_user(username, password).await
     }
@@ -152,20 +340,364 @@ impl NeoRtspServer {
     pub(crate) async fn get_users(&self) -> AnyResult<HashSet<String>> {
         self.imp().get_users().await
     }
+
+    /// The sdp of the media mounted at `path`, once a client has prepared it
+    ///
+    /// The path must be one that has been mounted
+    pub(crate) fn sdp(&self, path: &str) -> Option<String> {
+        self.factory(path)?.sdp()
+    }
+
+    /// Makes the clients of the `paths` jump to live, false if none of the
+    /// paths are being served
+    pub(crate) fn jump_to_live(&self, paths: &[String]) -> bool {
+        let factories = paths
+            .iter()
+            .filter_map(|path| self.factory(path))
+            .collect::<Vec<_>>();
+        for factory in factories.iter() {
+            factory.jump_to_live();
+        }
+        !factories.is_empty()
+    }
+
+    /// The factory mounted at exactly `path`
+    fn factory(&self, path: &str) -> Option<NeoMediaFactory> {
+        let (factory, matched) = self.mount_points()?.match_(path);
+        if matched as usize != path.len() {
+            return None;
+        }
+        factory.downcast::<NeoMediaFactory>().ok()
+    }
+
+    /// The last rtcp receiver report of each session playing one of the `paths`
+    ///
+    /// This is the report about the video, sessions that have not sent one
+    /// yet are left out
+    pub(crate) fn session_stats(&self, paths: &[String]) -> Vec<SessionStats> {
+        let Some(pool) = self.session_pool() else {
+            return vec![];
+        };
+        let mut stats = vec![];
+        for session in pool.filter(None) {
+            let Some(id) = session.sessionid() else {
+                continue;
+            };
+            for session_media in session.filter(None) {
+                if !plays_any(&session_media, paths) {
+                    continue;
+                }
+                if let Some(report) = session_media
+                    .media()
+                    .and_then(|media| receiver_report(id.to_string(), &media))
+                {
+                    stats.push(report);
+                }
+            }
+        }
+        stats
+    }
+
+    /// The ids of the sessions playing one of the `paths`
+    pub(crate) fn session_ids(&self, paths: &[String]) -> Vec<String> {
+        let Some(pool) = self.session_pool() else {
+            return vec![];
+        };
+        pool.filter(None)
+            .iter()
+            .filter(|session| {
+                session
+                    .filter(None)
+                    .iter()
+                    .any(|session_media| plays_any(session_media, paths))
+            })
+            .filter_map(|session| session.sessionid().map(|id| id.to_string()))
+            .collect()
+    }
+
+    /// Closes the rtsp session with this id
+    pub(crate) fn close_session(&self, id: &str) {
+        if let Some(pool) = self.session_pool() {
+            pool.filter(Some(&mut |_, session| {
+                if session.sessionid().is_some_and(|other| other == id) {
+                    RTSPFilterResult::Remove
+                } else {
+                    RTSPFilterResult::Keep
+                }
+            }));
+        }
+    }
+
+    /// Closes the sessions of the `paths` that are still being served the
+    /// placeholder from before the stream was ready, so that their clients
+    /// reconnect to the stream
+    pub(crate) fn clear_session_notready(&self, paths: &[String]) {
+        if let Some(pool) = self.session_pool() {
+            pool.filter(Some(&mut |_, session| {
+                if session.filter(None).iter().any(|session_media| {
+                    plays_any(session_media, paths)
+                        && session_media
+                            .media()
+                            .is_some_and(|media| is_not_ready(&media))
+                }) {
+                    log::debug!("Closing not ready rtsp session {:?}", session.sessionid());
+                    RTSPFilterResult::Remove
+                } else {
+                    RTSPFilterResult::Keep
+                }
+            }));
+        }
+    }
+
+    /// Replaces the jpeg snapshot of the `paths`
+    pub(crate) fn set_snapshot(&self, paths: &[String], jpeg: Arc<Vec<u8>>) {
+        let mut snapshots = self.imp().snapshots.lock().unwrap();
+        for path in paths.iter() {
+            match snapshots.get(path) {
+                Some(snapshot) => {
+                    snapshot.send_replace(jpeg.clone());
+                }
+                None => {
+                    snapshots.insert(path.clone(), watch(jpeg.clone()).0);
+                }
+            }
+        }
+    }
+
+    /// The jpeg snapshots of the `path` as they are replaced, `None` if it has
+    /// not had one yet
+    pub(crate) fn snapshots(&self, path: &str) -> Option<WatchReceiver<Arc<Vec<u8>>>> {
+        self.imp()
+            .snapshots
+            .lock()
+            .unwrap()
+            .get(path)
+            .map(|snapshots| snapshots.subscribe())
+    }
+
+    /// Serves the HLS playlist in `directory` at the `paths`
+    pub(crate) fn set_hls(&self, paths: &[String], directory: &Path) {
+        let mut hls = self.imp().hls.lock().unwrap();
+        for path in paths.iter() {
+            hls.insert(path.clone(), directory.to_path_buf());
+        }
+    }
+
+    /// The directory of the HLS playlist of the `path`
+    pub(crate) fn hls_directory(&self, path: &str) -> Option<PathBuf> {
+        self.imp().hls.lock().unwrap().get(path).cloned()
+    }
+
+    /// Stops serving the paths and closes the sessions that are playing them
+    pub(crate) fn remove_paths(&self, paths: &[String]) {
+        {
+            // Ends the mjpeg streams of the paths
+            let mut snapshots = self.imp().snapshots.lock().unwrap();
+            let mut hls = self.imp().hls.lock().unwrap();
+            for path in paths.iter() {
+                snapshots.remove(path);
+                hls.remove(path);
+            }
+        }
+        if let Some(mounts) = self.mount_points() {
+            for path in paths.iter() {
+                log::debug!("Removing path: {}", path);
+                mounts.remove_factory(path);
+            }
+        }
+        if let Some(pool) = self.session_pool() {
+            pool.filter(Some(&mut |_, session| {
+                if session
+                    .filter(None)
+                    .iter()
+                    .any(|session_media| plays_any(session_media, paths))
+                {
+                    RTSPFilterResult::Remove
+                } else {
+                    RTSPFilterResult::Keep
+                }
+            }));
+        }
+    }
+
+    /// Replaces the access tokens with these tokens and the role each one gives
+    pub(crate) fn set_tokens(&self, tokens: &[(TokenConfig, String)]) -> AnyResult<()> {
+        self.auth()
+            .and_then(|auth| auth.downcast::<NeoRtspAuth>().ok())
+            .ok_or(anyhow!("Server lacks its auth"))?
+            .set_tokens(tokens);
+        Ok(())
+    }
 }
 
 unsafe impl Send for NeoRtspServer {}
 unsafe impl Sync for NeoRtspServer {}
 
+/// The glib main loop shared by the servers and a token that is cancelled
+/// once it stops running
+type SharedMainLoop = (Arc<MainLoop>, CancellationToken);
+
+/// The main loop of all of the servers, shared so that they take one blocking
+/// thread between them rather than one each
+static MAIN_LOOP: StdMutex<Option<SharedMainLoop>> = StdMutex::new(None);
+
+/// The main loop that the servers run on, started in a blocking thread by the
+/// first server to ask for it and again after it has quit
+fn shared_main_loop() -> AnyResult<(SharedMainLoop, Option<JoinHandle<()>>)> {
+    let mut shared = MAIN_LOOP
+        .lock()
+        .map_err(|_| anyhow!("Main loop lock poisoned"))?;
+    if let Some(running) = shared
+        .as_ref()
+        .filter(|(_, stopped)| !stopped.is_cancelled())
+    {
+        return Ok((running.clone(), None));
+    }
+    let (main_loop, stopped) = (
+        Arc::new(MainLoop::new(None, false)),
+        CancellationToken::new(),
+    );
+    *shared = Some((main_loop.clone(), stopped.clone()));
+
+    let thread_main_loop = main_loop.clone();
+    let thread_stopped = stopped.clone();
+    let handle = tokio::task::spawn_blocking(move || {
+        thread_main_loop.run();
+        thread_stopped.cancel();
+    });
+    Ok(((main_loop, stopped), Some(handle)))
+}
+
+/// Closes the sessions whose client stopped sending keepalives without a TEARDOWN
+fn clean_up_sessions(server: &NeoRtspServer) {
+    if let Some(sessions) = server.session_pool() {
+        let cleanups = sessions.cleanup();
+        if cleanups > 0 {
+            log::debug!("Cleaned up {cleanups} sessions");
+        }
+        sessions.filter(Some(&mut |_, session| {
+            let remaining = session.next_timeout_usec(glib::monotonic_time());
+            log::debug!(
+                "{:?}: {}/{}",
+                session.sessionid(),
+                remaining,
+                session.timeout(),
+            );
+            if remaining <= 0 {
+                log::info!("Closing idle rtsp session {:?}", session.sessionid());
+                RTSPFilterResult::Remove
+            } else {
+                RTSPFilterResult::Keep
+            }
+        }));
+    }
+}
+
+/// Whether the session media is of exactly one of the paths, not a longer one
+fn plays_any(session_media: &RTSPSessionMedia, paths: &[String]) -> bool {
+    paths.iter().any(|path| {
+        session_media
+            .matches(path)
+            .is_some_and(|matched| matched as usize == path.len())
+    })
+}
+
+/// Whether the media is the placeholder from before the stream was ready
+///
+/// Found by the name of its source anywhere in the pipeline, whichever element
+/// the source is
+fn is_not_ready(media: &RTSPMedia) -> bool {
+    media
+        .element()
+        .downcast_ref::<Bin>()
+        .and_then(|bin| bin.by_name(NOT_READY_SOURCE))
+        .is_some()
+}
+
+/// The receiver report block about the first stream, the video, of the media
+///
+/// `None` until the client has sent a report
+fn receiver_report(session: String, media: &RTSPMedia) -> Option<SessionStats> {
+    let stream = media.stream(0)?;
+    let stats = stream.rtpsession()?.property::<Structure>("stats");
+    let sources = stats.get::<glib::ValueArray>("source-stats").ok()?;
+    // Our own source is the one the client reports on
+    let source = sources
+        .iter()
+        .filter_map(|source| source.get::<Structure>().ok())
+        .find(|source| {
+            source.get::<bool>("internal").unwrap_or(false)
+                && source.get::<bool>("have-rb").unwrap_or(false)
+        })?;
+    let clock_rate = source
+        .get::<i32>("clock-rate")
+        .ok()
+        .filter(|rate| *rate > 0)
+        .unwrap_or(90000);
+    Some(SessionStats::from_report(
+        session,
+        source.get::<u32>("rb-fractionlost").ok()?,
+        source.get::<i32>("rb-packetslost").ok()?,
+        source.get::<u32>("rb-jitter").ok()?,
+        clock_rate as u32,
+        source.get::<u64>("bitrate").unwrap_or(0),
+    ))
+}
+
+/// Reads a PEM, or a PKCS#12 bundle if it ends in `.p12` or `.pfx`
+fn load_certificate(cert_file: &str, cert_password: Option<&str>) -> AnyResult<TlsCertificate> {
+    let is_pkcs12 = Path::new(cert_file)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("p12") || ext.eq_ignore_ascii_case("pfx"));
+    // We seperate reading the file and changing to a PEM so that we get different error messages.
+    if is_pkcs12 {
+        let cert_contents = fs::read(cert_file).with_context(|| "TLS file not found")?;
+        TlsCertificate::from_pkcs12(&cert_contents, cert_password).map_err(|e| {
+            if e.matches(TlsError::BadCertificatePassword) {
+                anyhow!("Wrong certificate_password for the PKCS#12 bundle")
+            } else {
+                anyhow!("Not a valid PKCS#12 bundle: {}", e)
+            }
+        })
+    } else {
+        let cert_contents = fs::read_to_string(cert_file).with_context(|| "TLS file not found")?;
+        TlsCertificate::from_pem(&cert_contents).with_context(|| "Not a valid TLS certificate")
+    }
+}
+
 #[derive(Default)]
 pub(crate) struct NeoRtspServerImpl {
     threads: RwLock<JoinSet<AnyResult<()>>>,
     users: RwLock<HashMap<String, String>>,
     main_loop: RwLock<Option<Arc<MainLoop>>>,
+    idle_timeout: Arc<AtomicU32>,
+    dscp: StdMutex<Option<u8>>,
+    /// The source on the main loop that accepts the tcp clients
+    listening: StdMutex<Option<glib::SourceId>>,
+    /// Cancelled to stop the listeners that accept the clients themselves
+    accepting: CancellationToken,
+    /// The latest jpeg of each path with `http_snapshots`
+    snapshots: StdMutex<HashMap<String, WatchSender<Arc<Vec<u8>>>>>,
+    /// The directory of the HLS playlist of each path with `hls`
+    hls: StdMutex<HashMap<String, PathBuf>>,
 }
 
 impl ObjectImpl for NeoRtspServerImpl {}
-impl RTSPServerImpl for NeoRtspServerImpl {}
+impl RTSPServerImpl for NeoRtspServerImpl {
+    fn create_client(&self) -> Option<RTSPClient> {
+        let server = self.obj();
+        let client = NeoRtspClient::default();
+        // Duplicated from the default implementation
+        client.set_session_pool(server.session_pool().as_ref());
+        client.set_mount_points(server.mount_points().as_ref());
+        client.set_auth(server.auth().as_ref());
+        client.set_thread_pool(server.thread_pool().as_ref());
+        client.set_content_length_limit(server.content_length_limit());
+        client.set_dscp(*self.dscp.lock().unwrap());
+        Some(client.upcast())
+    }
+}
 
 #[object_subclass]
 impl ObjectSubclass for NeoRtspServerImpl {
@@ -175,35 +707,52 @@ impl ObjectSubclass for NeoRtspServerImpl {
 }
 
 impl NeoRtspServerImpl {
+    /// Serves tls with the certificate in `cert_file`, or the one in
+    /// `sni_certificates` of the hostname that the client asks for
     pub(crate) fn set_tls(
         &self,
         cert_file: &str,
+        sni_certificates: &BTreeMap<String, String>,
+        cert_password: Option<&str>,
         client_auth: TlsAuthenticationMode,
     ) -> AnyResult<()> {
         debug!("Setting up TLS using {}", cert_file);
-        let auth = self.obj().auth().unwrap_or_default();
-
-        // We seperate reading the file and changing to a PEM so that we get different error messages.
-        let cert_contents = fs::read_to_string(cert_file).with_context(|| "TLS file not found")?;
-        let cert = TlsCertificate::from_pem(&cert_contents)
-            .with_context(|| "Not a valid TLS certificate")?;
+        let auth = self
+            .obj()
+            .auth()
+            .and_then(|auth| auth.downcast::<NeoRtspAuth>().ok())
+            .ok_or(anyhow!("Server lacks its auth"))?;
+
+        let cert = load_certificate(cert_file, cert_password)?;
+        let mut by_hostname = HashMap::new();
+        for (hostname, sni_file) in sni_certificates.iter() {
+            debug!("Setting up TLS for {} using {}", hostname, sni_file);
+            let sni_cert = load_certificate(sni_file, cert_password)
+                .with_context(|| format!("Failed to load the certificate of {hostname}"))?;
+            by_hostname.insert(hostname.to_ascii_lowercase(), sni_cert);
+        }
         auth.set_tls_certificate(Some(&cert));
         auth.set_tls_authentication_mode(client_auth);
+        auth.set_sni_certificates(by_hostname);
 
         self.obj().set_auth(Some(&auth));
         Ok(())
     }
 
     pub(crate) fn set_up_tls(&self, config: &Config) -> AnyResult<()> {
-        let tls_client_auth = match &config.tls_client_auth as &str {
-            "request" => TlsAuthenticationMode::Requested,
-            "require" => TlsAuthenticationMode::Required,
-            "none" => TlsAuthenticationMode::None,
-            _ => unreachable!(),
+        let tls_client_auth = match config.tls_client_auth {
+            TlsClientAuth::Request => TlsAuthenticationMode::Requested,
+            TlsClientAuth::Require => TlsAuthenticationMode::Required,
+            TlsClientAuth::None => TlsAuthenticationMode::None,
         };
         if let Some(cert_path) = &config.certificate {
-            self.set_tls(cert_path, tls_client_auth)
-                .with_context(|| "Failed to set up TLS")?;
+            self.set_tls(
+                cert_path,
+                &config.sni_certificates,
+                config.certificate_password.as_deref(),
+                tls_client_auth,
+            )
+            .with_context(|| "Failed to set up TLS")?;
         }
         Ok(())
     }
@@ -248,3 +797,46 @@ impl NeoRtspServerImpl {
         Ok(locked_users.keys().cloned().collect())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gstreamer_rtsp_server::RTSPMediaFactory;
+
+    #[test]
+    fn test_remove_paths() {
+        let mut camera: CameraConfig = toml::from_str(
+            r#"
+            name = "Garage"
+            username = "admin"
+            address = "192.168.1.10"
+            "#,
+        )
+        .unwrap();
+        let server = NeoRtspServer::new().unwrap();
+        let mounts = server.mount_points().unwrap();
+        let served = camera.all_rtsp_paths();
+        for path in served.iter() {
+            mounts.add_factory(path, RTSPMediaFactory::new());
+        }
+        // The factory at /Garage stays, so every path matches at least that
+        // prefix and only a factory at the whole path serves it
+        let serves = |path: &str| mounts.match_(path).1 as usize == path.len();
+        assert!(serves("/Garage/subStream"));
+
+        // Like camera_main once serve_substream is turned off
+        camera.serve_substream = false;
+        let paths = camera.all_rtsp_paths();
+        server.remove_paths(
+            &served
+                .iter()
+                .filter(|path| !paths.contains(path))
+                .cloned()
+                .collect::<Vec<_>>(),
+        );
+        assert!(!serves("/Garage/subStream"));
+        assert!(!serves("/Garage/sub"));
+        assert!(serves("/Garage"));
+        assert!(serves("/Garage/mainStream"));
+    }
+}
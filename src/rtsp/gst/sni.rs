@@ -0,0 +1,287 @@
+//! Reads the server name (SNI) that an rtsps client asks for
+//!
+//! The tls of gio has no way to pick the certificate of a server connection by
+//! the name the client asked for, so the name is read from the ClientHello
+//! before the connection is handed to gstreamer
+
+use gstreamer_rtsp_server::gio::TlsCertificate;
+use std::collections::HashMap;
+#[cfg(unix)]
+use tokio::{
+    net::TcpStream,
+    time::{sleep, timeout, Duration},
+};
+
+/// How long a new connection may take to send its ClientHello before it is
+/// given the default certificate
+#[cfg(unix)]
+const CLIENT_HELLO_TIMEOUT: Duration = Duration::from_millis(200);
+/// How long to wait for more of a ClientHello that came in parts
+#[cfg(unix)]
+const PARTIAL_WAIT: Duration = Duration::from_millis(5);
+
+/// The type of a tls record that carries a handshake
+const HANDSHAKE_RECORD: u8 = 22;
+/// The type of the handshake message that starts it
+const CLIENT_HELLO: u8 = 1;
+/// The extension that holds the server name
+const SERVER_NAME_EXTENSION: u16 = 0;
+/// The type of a server name that is a dns hostname
+const HOST_NAME: u8 = 0;
+
+/// What the start of a connection says about the server name
+#[derive(Debug, PartialEq, Eq)]
+pub(super) enum ClientHello {
+    /// More of the ClientHello is needed
+    Incomplete,
+    /// The client asked for this hostname, in lowercase
+    Sni(String),
+    /// A ClientHello without a server name, or not a ClientHello at all
+    NoSni,
+}
+
+/// Waits for the ClientHello of a new connection without reading it, so that
+/// gio still sees it when it starts the handshake
+#[cfg(unix)]
+pub(super) async fn peek_client_hello(stream: &TcpStream) -> ClientHello {
+    let mut buf = [0; 4096];
+    let peek = async {
+        loop {
+            match stream.peek(&mut buf).await {
+                Ok(0) | Err(_) => return ClientHello::NoSni,
+                Ok(len) => match parse_client_hello(&buf[..len]) {
+                    // Peeked data keeps the socket readable so waiting on it would spin
+                    ClientHello::Incomplete if len < buf.len() => sleep(PARTIAL_WAIT).await,
+                    ClientHello::Incomplete => return ClientHello::NoSni,
+                    parsed => return parsed,
+                },
+            }
+        }
+    };
+    timeout(CLIENT_HELLO_TIMEOUT, peek)
+        .await
+        .unwrap_or(ClientHello::Incomplete)
+}
+
+/// Reads the server name from the first tls record of a connection
+///
+/// Only the first record is read, a ClientHello split over several records is
+/// treated as one without a name
+pub(super) fn parse_client_hello(data: &[u8]) -> ClientHello {
+    if data.len() < 5 {
+        return ClientHello::Incomplete;
+    }
+    if data[0] != HANDSHAKE_RECORD {
+        return ClientHello::NoSni;
+    }
+    let record_len = u16::from_be_bytes([data[3], data[4]]) as usize;
+    let Some(record) = data.get(5..5 + record_len) else {
+        return ClientHello::Incomplete;
+    };
+    match server_name(record) {
+        Some(name) => ClientHello::Sni(name),
+        None => ClientHello::NoSni,
+    }
+}
+
+fn server_name(record: &[u8]) -> Option<String> {
+    let mut reader = Reader(record);
+    if reader.u8()? != CLIENT_HELLO {
+        return None;
+    }
+    let hello_len = reader.u24()?;
+    let mut hello = Reader(reader.take(hello_len)?);
+    // Version and random
+    hello.take(2 + 32)?;
+    // Session id, cipher suites and compression methods
+    let session_id_len = hello.u8()? as usize;
+    hello.take(session_id_len)?;
+    let cipher_suites_len = hello.u16()? as usize;
+    hello.take(cipher_suites_len)?;
+    let compression_len = hello.u8()? as usize;
+    hello.take(compression_len)?;
+
+    let extensions_len = hello.u16()? as usize;
+    let mut extensions = Reader(hello.take(extensions_len)?);
+    while !extensions.0.is_empty() {
+        let kind = extensions.u16()?;
+        let len = extensions.u16()? as usize;
+        let extension = extensions.take(len)?;
+        if kind != SERVER_NAME_EXTENSION {
+            continue;
+        }
+        let mut list = Reader(extension);
+        let list_len = list.u16()? as usize;
+        let mut names = Reader(list.take(list_len)?);
+        while !names.0.is_empty() {
+            let name_type = names.u8()?;
+            let name_len = names.u16()? as usize;
+            let name = names.take(name_len)?;
+            if name_type == HOST_NAME {
+                return std::str::from_utf8(name)
+                    .ok()
+                    .map(|name| name.to_ascii_lowercase());
+            }
+        }
+        return None;
+    }
+    None
+}
+
+/// Reads big endian numbers and slices off the front
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.0.len() < len {
+            return None;
+        }
+        let (taken, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Some(taken)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.take(1).map(|bytes| bytes[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        self.take(2)
+            .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u24(&mut self) -> Option<usize> {
+        self.take(3)
+            .map(|bytes| u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]) as usize)
+    }
+}
+
+/// The certificate of the hostname, keyed by lowercase hostnames
+///
+/// A key of `*.example.com` is used for any one label in front of
+/// `example.com` that has no certificate of its own
+pub(super) fn certificate_for<'a>(
+    certificates: &'a HashMap<String, TlsCertificate>,
+    hostname: &str,
+) -> Option<&'a TlsCertificate> {
+    certificate_key(certificates.keys().map(|key| key.as_str()), hostname)
+        .and_then(|key| certificates.get(key))
+}
+
+fn certificate_key<'a>(
+    mut keys: impl Iterator<Item = &'a str> + Clone,
+    hostname: &str,
+) -> Option<&'a str> {
+    if let Some(key) = keys.clone().find(|key| *key == hostname) {
+        return Some(key);
+    }
+    let (_, parent) = hostname.split_once('.')?;
+    keys.find(|key| key.strip_prefix("*.") == Some(parent))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A ClientHello with only the server name extension
+    fn client_hello(hostname: Option<&str>) -> Vec<u8> {
+        let mut extensions = vec![];
+        // Another extension before it, supported_groups with x25519
+        extensions.extend_from_slice(&[0x00, 0x0a, 0x00, 0x04, 0x00, 0x02, 0x00, 0x1d]);
+        if let Some(hostname) = hostname {
+            let name = hostname.as_bytes();
+            let entry_len = 3 + name.len();
+            extensions.extend_from_slice(&[0x00, 0x00]);
+            extensions.extend_from_slice(&((entry_len + 2) as u16).to_be_bytes());
+            extensions.extend_from_slice(&(entry_len as u16).to_be_bytes());
+            extensions.push(HOST_NAME);
+            extensions.extend_from_slice(&(name.len() as u16).to_be_bytes());
+            extensions.extend_from_slice(name);
+        }
+
+        let mut hello = vec![0x03, 0x03];
+        hello.extend_from_slice(&[7; 32]);
+        // Empty session id, one cipher suite and no compression
+        hello.extend_from_slice(&[0x00, 0x00, 0x02, 0x13, 0x01, 0x01, 0x00]);
+        hello.extend_from_slice(&(extensions.len() as u16).to_be_bytes());
+        hello.extend_from_slice(&extensions);
+
+        let mut handshake = vec![CLIENT_HELLO];
+        handshake.extend_from_slice(&(hello.len() as u32).to_be_bytes()[1..]);
+        handshake.extend_from_slice(&hello);
+
+        let mut record = vec![HANDSHAKE_RECORD, 0x03, 0x01];
+        record.extend_from_slice(&(handshake.len() as u16).to_be_bytes());
+        record.extend_from_slice(&handshake);
+        record
+    }
+
+    #[test]
+    fn test_parse_client_hello() {
+        assert_eq!(
+            parse_client_hello(&client_hello(Some("Cams.Example.com"))),
+            ClientHello::Sni("cams.example.com".to_string())
+        );
+        assert_eq!(parse_client_hello(&client_hello(None)), ClientHello::NoSni);
+
+        let hello = client_hello(Some("cams.example.com"));
+        assert_eq!(parse_client_hello(&hello[..3]), ClientHello::Incomplete);
+        assert_eq!(
+            parse_client_hello(&hello[..hello.len() - 1]),
+            ClientHello::Incomplete
+        );
+        // A plain rtsp request on the tls port
+        assert_eq!(
+            parse_client_hello(b"OPTIONS rtsp://host:8554/Garage RTSP/1.0\r\n"),
+            ClientHello::NoSni
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_peek_client_hello() {
+        use tokio::{
+            io::AsyncWriteExt,
+            net::{TcpListener, TcpStream},
+        };
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        // Sent in two parts
+        let hello = client_hello(Some("cams.example.com"));
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        let (server, _) = listener.accept().await.unwrap();
+        client.write_all(&hello[..20]).await.unwrap();
+        let rest = hello[20..].to_vec();
+        tokio::spawn(async move {
+            sleep(Duration::from_millis(20)).await;
+            client.write_all(&rest).await.unwrap();
+            // Kept open until the server is done with it
+            sleep(Duration::from_secs(1)).await;
+        });
+        assert_eq!(
+            peek_client_hello(&server).await,
+            ClientHello::Sni("cams.example.com".to_string())
+        );
+        // Nothing was read so gio still sees all of it
+        let mut buf = vec![0; hello.len()];
+        assert_eq!(server.peek(&mut buf).await.unwrap(), hello.len());
+
+        // A client that sends nothing is given up on
+        let _silent = TcpStream::connect(addr).await.unwrap();
+        let (server, _) = listener.accept().await.unwrap();
+        assert_eq!(peek_client_hello(&server).await, ClientHello::Incomplete);
+    }
+
+    #[test]
+    fn test_certificate_key() {
+        let keys = ["cams.example.com", "*.example.org"];
+        let key = |hostname| certificate_key(keys.iter().copied(), hostname);
+        assert_eq!(key("cams.example.com"), Some("cams.example.com"));
+        assert_eq!(key("garage.example.org"), Some("*.example.org"));
+        assert_eq!(key("example.org"), None);
+        assert_eq!(key("a.b.example.org"), None);
+        assert_eq!(key("other.example.com"), None);
+    }
+}
@@ -0,0 +1,264 @@
+//! HLS of the streams over http for browsers
+//!
+//! With `[cameras.hls]` each stream of the camera is remuxed by a `hlssink2`
+//! into mpeg-ts segments and a playlist in the temporary directory, which the
+//! status server serves as:
+//!
+//! - `GET /{path}/index.m3u8`: The playlist, e.g. `/Garage/index.m3u8`
+//! - `GET /{path}/{segment}.ts`: The segments it lists
+//!
+//! Both follow the auth of the camera, see [`super::http::authorized`]. Only
+//! the last `segments` files are kept. Like recording, HLS is not a client of
+//! the stream so while it is paused there is no playlist
+use anyhow::{anyhow, Context};
+use gstreamer::{prelude::*, Buffer, ClockTime, FlowError, MessageView, State};
+use gstreamer_app::AppSrc;
+use hyper::{header, Body, Response, StatusCode};
+use std::{
+    collections::VecDeque,
+    fs,
+    path::{Path, PathBuf},
+};
+use tokio::{
+    sync::{broadcast::Receiver as BroadcastReceiver, watch::Receiver as WatchReceiver},
+    time::{sleep, Instant},
+};
+use tokio_stream::{wrappers::BroadcastStream, StreamExt};
+
+use crate::{
+    common::{StampedData, VidFormat},
+    config::HlsConfig,
+    AnyResult,
+};
+
+use super::{
+    clip::{build_pipeline, parser_caps},
+    gst::NeoRtspServer,
+    http::{not_found, response},
+    record::Stamper,
+    stream::output_backoff,
+};
+
+const PLAYLIST: &str = "index.m3u8";
+
+/// The directory of the playlist, emptied when the stream stops or pauses
+struct HlsDirectory(PathBuf);
+
+impl HlsDirectory {
+    fn clear(&self) {
+        if let Ok(entries) = fs::read_dir(&self.0) {
+            for entry in entries.flatten() {
+                let _ = fs::remove_file(entry.path());
+            }
+        }
+    }
+}
+
+impl Drop for HlsDirectory {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Keeps the HLS playlist of the `paths` going for as long as the stream is not paused
+///
+/// The segments of a failed run are removed and a new run is started after a
+/// while, rtsp clients of the same stream carry on
+#[allow(clippy::too_many_arguments)]
+pub(super) async fn hls_main(
+    name: &str,
+    stream: &str,
+    rtsp: &NeoRtspServer,
+    paths: &[String],
+    hls: &HlsConfig,
+    vid_format: VidFormat,
+    vid: &BroadcastReceiver<StampedData>,
+    history: &WatchReceiver<VecDeque<StampedData>>,
+    mut paused: WatchReceiver<bool>,
+) -> AnyResult<()> {
+    let directory = HlsDirectory(
+        std::env::temp_dir()
+            .join("neolink-hls")
+            .join(name)
+            .join(stream),
+    );
+    directory.clear();
+    fs::create_dir_all(&directory.0)
+        .with_context(|| format!("Could not create the hls directory {:?}", directory.0))?;
+    rtsp.set_hls(paths, &directory.0);
+
+    let mut backoff = output_backoff();
+    // Segments of each run are named apart so that players never mix them up
+    let mut run = 0u32;
+    loop {
+        paused.wait_for(|paused| !*paused).await?;
+        log::debug!("{name}: Serving {stream} as hls from {:?}", directory.0);
+        run += 1;
+        let started = Instant::now();
+        let result = hls_run(
+            &directory.0,
+            run,
+            hls,
+            &vid_format,
+            vid.resubscribe(),
+            history,
+            paused.clone(),
+        )
+        .await;
+        directory.clear();
+
+        backoff.reset_after(started.elapsed());
+        if let Err(e) = result {
+            log::warn!("{name}: Hls of the {stream} failed: {e:?}");
+            let delay = backoff.next_delay();
+            log::info!("{name}: Retrying the hls in {:?}", delay);
+            sleep(delay).await;
+        }
+    }
+}
+
+/// Writes the segments until the stream pauses
+async fn hls_run(
+    directory: &Path,
+    run: u32,
+    hls: &HlsConfig,
+    vid_format: &VidFormat,
+    vid: BroadcastReceiver<StampedData>,
+    history: &WatchReceiver<VecDeque<StampedData>>,
+    mut paused: WatchReceiver<bool>,
+) -> AnyResult<()> {
+    let (parser, caps) = parser_caps(vid_format)?;
+    let pipeline = build_pipeline(&format!(
+        "appsrc name=src is-live=true do-timestamp=false format=time caps={caps},stream-format=byte-stream ! {parser} config-interval=-1 ! hlssink2 name=sink"
+    ))
+    .context("Could not build the hls pipeline, it needs hlssink2 from gst-plugins-bad")?;
+    let sink = pipeline
+        .by_name("sink")
+        .ok_or(anyhow!("Hls pipeline lacks a sink"))?;
+    sink.set_property(
+        "location",
+        directory
+            .join(format!("{run}-%05d.ts"))
+            .to_string_lossy()
+            .to_string(),
+    );
+    sink.set_property(
+        "playlist-location",
+        directory.join(PLAYLIST).to_string_lossy().to_string(),
+    );
+    sink.set_property("target-duration", hls.segment_secs);
+    sink.set_property("playlist-length", hls.segments);
+    // A player may still be fetching the segments that just left the playlist
+    sink.set_property("max-files", hls.segments + 2);
+    let appsrc = pipeline
+        .by_name("src")
+        .ok_or(anyhow!("Hls pipeline lacks a source"))?
+        .dynamic_cast::<AppSrc>()
+        .map_err(|_| anyhow!("Cannot cast to appsrc"))?;
+    let bus = pipeline.bus().ok_or(anyhow!("Hls pipeline lacks a bus"))?;
+
+    // Start at the latest keyframe so the first segment is there at once
+    let start = {
+        let history = history.borrow();
+        let keyframe = history.iter().rposition(|frame| frame.keyframe);
+        keyframe
+            .map(|keyframe| history.iter().skip(keyframe).cloned().collect::<Vec<_>>())
+            .unwrap_or_default()
+    };
+    let start_end = start.last().map(|frame| frame.ts);
+    let live = BroadcastStream::new(vid)
+        .filter_map(|frame| frame.ok())
+        .skip_while(move |frame| start_end.is_some_and(|end| frame.ts <= end));
+    let mut frames = Box::pin(tokio_stream::iter(start).chain(live));
+
+    pipeline.set_state(State::Playing)?;
+    let result = async {
+        let mut stamper = Stamper::default();
+        let mut found_key = false;
+        loop {
+            let frame = tokio::select! {
+                v = paused.wait_for(|paused| *paused) => {
+                    v?;
+                    return Ok(());
+                },
+                v = frames.next() => v.ok_or(anyhow!("The camera stream ended"))?,
+            };
+            // Segments need to start on a keyframe
+            found_key |= frame.keyframe;
+            if !found_key {
+                continue;
+            }
+            let mut buffer = Buffer::from_slice(frame.data.to_vec());
+            let pts = stamper.stamp(frame.ts);
+            buffer
+                .make_mut()
+                .set_pts(ClockTime::from_nseconds(pts.as_nanos() as u64));
+            match appsrc.push_buffer(buffer) {
+                Ok(_) | Err(FlowError::Flushing) => {}
+                Err(e) => return Err(anyhow!("Error pushing to the pipeline: {e:?}")),
+            }
+            while let Some(msg) = bus.pop() {
+                if let MessageView::Error(err) = msg.view() {
+                    return Err(anyhow!("{}", err.error()));
+                }
+            }
+        }
+    }
+    .await;
+    let _ = pipeline.set_state(State::Null);
+    result
+}
+
+/// `GET /{path}/{file}` of the HLS in `directory`
+pub(super) async fn serve(directory: Option<PathBuf>, file: &str) -> Response<Body> {
+    let (Some(directory), Some(content_type)) = (directory, content_type(file)) else {
+        return not_found();
+    };
+    match tokio::fs::read(directory.join(file)).await {
+        Ok(data) => Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, content_type)
+            .header(header::CACHE_CONTROL, "no-cache")
+            // So that a page on another host can play it
+            .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+            .body(Body::from(data))
+            .expect("Response should be valid"),
+        Err(_) if file == PLAYLIST => response(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "text/plain",
+            "The stream is paused or the first segment is not ready yet".to_string(),
+        ),
+        Err(_) => not_found(),
+    }
+}
+
+/// The content type of the files that are served, `None` for any other
+fn content_type(file: &str) -> Option<&'static str> {
+    if file == PLAYLIST {
+        return Some("application/vnd.apple.mpegurl");
+    }
+    let segment = file.strip_suffix(".ts")?;
+    (!segment.is_empty()
+        && segment
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-'))
+    .then_some("video/mp2t")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_content_type() {
+        assert_eq!(
+            content_type("index.m3u8"),
+            Some("application/vnd.apple.mpegurl")
+        );
+        assert_eq!(content_type("1-00004.ts"), Some("video/mp2t"));
+        assert_eq!(content_type("other.m3u8"), None);
+        assert_eq!(content_type(".ts"), None);
+        assert_eq!(content_type("..%2Fsecret.ts"), None);
+        assert_eq!(content_type("snapshot.jpg"), None);
+    }
+}
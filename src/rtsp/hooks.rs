@@ -0,0 +1,127 @@
+//! Runs user commands and webhooks on motion transitions
+//!
+//! Configured per camera in `[cameras.motion]`
+use serde_json::json;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::{process::Command, sync::watch::Receiver as WatchReceiver};
+
+use super::AnyResult;
+use crate::{
+    common::{MdState, NeoInstance},
+    config::CameraConfig,
+};
+
+/// Watches the motion of the camera and fires the configured hooks
+///
+/// This runs regardless of the pause settings. The motion is only listened
+/// to while the camera has a hook, so that it is not kept up for nothing
+pub(super) async fn motion_hooks(camera: NeoInstance) -> AnyResult<()> {
+    let mut config = camera.config().await?;
+    loop {
+        config.wait_for(|config| config.motion.has_hooks()).await?;
+        let hook_config = config.clone();
+        tokio::select! {
+            v = config.wait_for(|config| !config.motion.has_hooks()) => {
+                v?;
+            },
+            v = fire_hooks(&camera, hook_config) => return v,
+        }
+    }
+}
+
+async fn fire_hooks(camera: &NeoInstance, config: WatchReceiver<CameraConfig>) -> AnyResult<()> {
+    let mut motion = camera.motion().await?;
+    let mut was_motion = None;
+    loop {
+        let is_motion = matches!(
+            *motion
+                .wait_for(|md| match md {
+                    MdState::Start(_) => was_motion != Some(true),
+                    MdState::Stop(_) => was_motion != Some(false),
+                    MdState::Unknown => false,
+                })
+                .await?,
+            MdState::Start(_)
+        );
+        let first = was_motion.is_none();
+        was_motion = Some(is_motion);
+        if first && !is_motion {
+            // Not a transition, we just learned the initial state
+            continue;
+        }
+
+        let (name, hooks) = {
+            let config = config.borrow();
+            (config.name.clone(), config.motion.clone())
+        };
+        let event = if is_motion { "start" } else { "stop" };
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let command = if is_motion {
+            hooks.on_start_command
+        } else {
+            hooks.on_stop_command
+        };
+
+        if let Some(command) = command {
+            run_command(&name, event, timestamp, command);
+        }
+        if let Some(url) = hooks.webhook_url {
+            post_webhook(&name, event, timestamp, url);
+        }
+    }
+}
+
+fn run_command(name: &str, event: &'static str, timestamp: u64, command: String) {
+    let name = name.to_string();
+    tokio::task::spawn(async move {
+        #[cfg(unix)]
+        let mut shell = Command::new("sh");
+        #[cfg(unix)]
+        shell.arg("-c");
+        #[cfg(not(unix))]
+        let mut shell = Command::new("cmd");
+        #[cfg(not(unix))]
+        shell.arg("/C");
+
+        let status = shell
+            .arg(&command)
+            .env("NEOLINK_CAMERA", &name)
+            .env("NEOLINK_MOTION", event)
+            .env("NEOLINK_TIMESTAMP", timestamp.to_string())
+            .status()
+            .await;
+        match status {
+            Ok(status) if status.success() => {
+                log::debug!("{name}: Motion {event} command finished");
+            }
+            Ok(status) => {
+                log::warn!("{name}: Motion {event} command `{command}` exited with {status}");
+            }
+            Err(e) => {
+                log::warn!("{name}: Could not run motion {event} command `{command}`: {e}");
+            }
+        }
+    });
+}
+
+fn post_webhook(name: &str, event: &'static str, timestamp: u64, url: String) {
+    let name = name.to_string();
+    tokio::task::spawn(async move {
+        let result = reqwest::Client::new()
+            .post(&url)
+            .json(&json!({
+                "camera": name,
+                "event": event,
+                "timestamp": timestamp,
+            }))
+            .send()
+            .await
+            .and_then(|response| response.error_for_status());
+        if let Err(e) = result {
+            log::warn!("{name}: Motion {event} webhook to {url} failed: {e}");
+        }
+    });
+}
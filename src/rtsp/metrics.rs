@@ -0,0 +1,388 @@
+//! Prometheus metrics for the rtsp server
+//!
+//! Served as plain text at `/metrics` when `--metrics-port` is given
+//!
+//! | Metric                                | Type    | Labels             | Meaning                                                  |
+//! |---------------------------------------|---------|--------------------|----------------------------------------------------------|
+//! | `neolink_clients`                     | gauge   | `camera`, `stream` | Number of rtsp clients currently using the stream        |
+//! | `neolink_buffer_ready`                | gauge   | `camera`, `stream` | `1` once the stream format is known and can be served    |
+//! | `neolink_stream_state`                | gauge   | `camera`, `stream` | `0` stopped, `1` paused, `2` streaming                   |
+//! | `neolink_pause_encodes_total`         | counter | `camera`, `stream` | Number of times a `black`, `still` or `test` pause clip was encoded |
+//! | `neolink_retryable_failures_total`    | counter | `camera`           | Number of times the camera connection was lost and retried |
+//! | `neolink_session_loss_percent`        | gauge   | `camera`, `stream`, `session` | Packets the client lost since its previous receiver report |
+//! | `neolink_session_jitter_ms`           | gauge   | `camera`, `stream`, `session` | Interarrival jitter that the client reports |
+//! | `neolink_session_bitrate`             | gauge   | `camera`, `stream`, `session` | Bits per second sent to the client |
+//!
+//! The session metrics are only there for clients that have sent a receiver report
+//!
+use hyper::{Body, Request, Response, StatusCode};
+use neolink_core::bc_protocol::StreamKind;
+use serde::Serialize;
+use std::{collections::HashMap, fmt::Write, sync::Mutex};
+
+use super::{
+    adaptive::SessionStats,
+    http::{not_found, response},
+};
+
+/// The state of a stream as exposed by `neolink_stream_state`
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+pub(crate) enum StreamState {
+    #[default]
+    Stopped = 0,
+    Paused = 1,
+    Streaming = 2,
+}
+
+#[derive(Debug, Clone, Default)]
+struct StreamMetrics {
+    clients: u32,
+    buffer_ready: bool,
+    state: StreamState,
+    resolution: [u32; 2],
+    fps: u32,
+    sessions: Vec<SessionStats>,
+    pause_encodes: u64,
+}
+
+#[derive(Debug, Clone, Default)]
+struct CameraMetrics {
+    connected: bool,
+    given_up: bool,
+    failures: u64,
+    last_error: Option<String>,
+    model: Option<String>,
+    firmware: Option<String>,
+}
+
+/// The current status of a camera as reported by the status server
+#[derive(Debug, Clone, Serialize)]
+pub struct CameraStatus {
+    /// The name of the camera in the config
+    pub name: String,
+    /// One of `disconnected`, `connected`, `paused`, `streaming` or
+    /// `given_up` when it will not be connected to again
+    pub state: &'static str,
+    /// The number of rtsp clients over all of its streams
+    pub clients: u32,
+    /// Why the connection to the camera was last lost
+    pub last_error: Option<String>,
+    /// The model as the camera reports it, `None` until it has connected or
+    /// if it does not say
+    pub model: Option<String>,
+    /// The firmware version as the camera reports it, e.g. `v3.0.0.2356_23062000`
+    pub firmware: Option<String>,
+    /// Each stream that is served
+    pub streams: Vec<StreamStatus>,
+}
+
+/// What a stream of the camera is delivering, zero until it is known
+#[derive(Debug, Clone, Serialize)]
+pub struct StreamStatus {
+    /// e.g. `mainStream`
+    pub stream: String,
+    /// The width of the video in pixels
+    pub width: u32,
+    /// The height of the video in pixels
+    pub height: u32,
+    /// The frame rate of the video
+    pub fps: u32,
+    /// The rtcp receiver report of each client session
+    pub sessions: Vec<SessionStats>,
+}
+
+/// The collection of all metrics
+///
+/// Shared in an `Arc` with the camera tasks so that they can update it
+#[derive(Default)]
+pub(crate) struct Metrics {
+    streams: Mutex<HashMap<(String, StreamKind), StreamMetrics>>,
+    cameras: Mutex<HashMap<String, CameraMetrics>>,
+}
+
+impl Metrics {
+    fn update_stream<F: FnOnce(&mut StreamMetrics)>(&self, camera: &str, stream: StreamKind, f: F) {
+        let mut streams = self.streams.lock().unwrap();
+        f(streams.entry((camera.to_string(), stream)).or_default());
+    }
+
+    fn update_camera<F: FnOnce(&mut CameraMetrics)>(&self, camera: &str, f: F) {
+        let mut cameras = self.cameras.lock().unwrap();
+        f(cameras.entry(camera.to_string()).or_default());
+    }
+
+    pub(crate) fn set_clients(&self, camera: &str, stream: StreamKind, clients: u32) {
+        self.update_stream(camera, stream, |m| m.clients = clients);
+    }
+
+    pub(crate) fn set_buffer_ready(&self, camera: &str, stream: StreamKind, ready: bool) {
+        self.update_stream(camera, stream, |m| m.buffer_ready = ready);
+    }
+
+    pub(crate) fn set_state(&self, camera: &str, stream: StreamKind, state: StreamState) {
+        self.update_stream(camera, stream, |m| m.state = state);
+    }
+
+    pub(crate) fn set_format(
+        &self,
+        camera: &str,
+        stream: StreamKind,
+        resolution: [u32; 2],
+        fps: u32,
+    ) {
+        self.update_stream(camera, stream, |m| {
+            m.resolution = resolution;
+            m.fps = fps;
+        });
+    }
+
+    pub(crate) fn set_sessions(
+        &self,
+        camera: &str,
+        stream: StreamKind,
+        sessions: Vec<SessionStats>,
+    ) {
+        self.update_stream(camera, stream, |m| m.sessions = sessions);
+    }
+
+    /// A pause clip is being encoded, this is shared by all clients of the stream
+    pub(crate) fn add_pause_encode(&self, camera: &str, stream: StreamKind) {
+        self.update_stream(camera, stream, |m| m.pause_encodes += 1);
+    }
+
+    pub(crate) fn set_failures(&self, camera: &str, failures: u64, last_error: Option<String>) {
+        self.update_camera(camera, |m| {
+            m.failures = failures;
+            m.last_error = last_error;
+        });
+    }
+
+    pub(crate) fn set_version(
+        &self,
+        camera: &str,
+        model: Option<String>,
+        firmware: Option<String>,
+    ) {
+        self.update_camera(camera, |m| {
+            m.model = model;
+            m.firmware = firmware;
+        });
+    }
+
+    pub(crate) fn set_connected(&self, camera: &str, connected: bool) {
+        self.update_camera(camera, |m| m.connected = connected);
+    }
+
+    /// The camera failed for good, its streams are forgotten but it is still listed
+    pub(crate) fn set_given_up(&self, camera: &str) {
+        self.streams
+            .lock()
+            .unwrap()
+            .retain(|(name, _), _| name != camera);
+        self.update_camera(camera, |m| {
+            m.connected = false;
+            m.given_up = true;
+        });
+    }
+
+    /// Summary of each camera, sorted by name
+    pub(crate) fn camera_status(&self) -> Vec<CameraStatus> {
+        let streams = self.streams.lock().unwrap();
+        let cameras = self.cameras.lock().unwrap();
+        let mut statuses = cameras
+            .iter()
+            .map(|(name, camera)| {
+                let mut camera_streams = streams
+                    .iter()
+                    .filter(|((stream_camera, _), _)| stream_camera == name)
+                    .map(|((_, stream), m)| (stream.to_string(), m))
+                    .collect::<Vec<_>>();
+                camera_streams.sort_by(|a, b| a.0.cmp(&b.0));
+                let state = if camera.given_up {
+                    "given_up"
+                } else if !camera.connected {
+                    "disconnected"
+                } else if camera_streams
+                    .iter()
+                    .any(|(_, m)| m.state == StreamState::Streaming)
+                {
+                    "streaming"
+                } else if camera_streams
+                    .iter()
+                    .any(|(_, m)| m.state == StreamState::Paused)
+                {
+                    "paused"
+                } else {
+                    "connected"
+                };
+                CameraStatus {
+                    name: name.clone(),
+                    state,
+                    clients: camera_streams.iter().map(|(_, m)| m.clients).sum(),
+                    last_error: camera.last_error.clone(),
+                    model: camera.model.clone(),
+                    firmware: camera.firmware.clone(),
+                    streams: camera_streams
+                        .iter()
+                        .map(|(stream, m)| StreamStatus {
+                            stream: stream.clone(),
+                            width: m.resolution[0],
+                            height: m.resolution[1],
+                            fps: m.fps,
+                            sessions: m.sessions.clone(),
+                        })
+                        .collect(),
+                }
+            })
+            .collect::<Vec<_>>();
+        statuses.sort_by(|a, b| a.name.cmp(&b.name));
+        statuses
+    }
+
+    /// Forget a camera that is no longer served
+    pub(crate) fn remove_camera(&self, camera: &str) {
+        self.streams
+            .lock()
+            .unwrap()
+            .retain(|(name, _), _| name != camera);
+        self.cameras.lock().unwrap().remove(camera);
+    }
+
+    /// Render in the prometheus text exposition format
+    pub(crate) fn render(&self) -> String {
+        let mut out = String::new();
+        let streams = self.streams.lock().unwrap();
+        let mut streams = streams.iter().collect::<Vec<_>>();
+        streams.sort_by_key(|((camera, stream), _)| (camera.clone(), stream.to_string()));
+
+        let _ = writeln!(
+            out,
+            "# HELP neolink_clients Number of rtsp clients using the stream"
+        );
+        let _ = writeln!(out, "# TYPE neolink_clients gauge");
+        for ((camera, stream), m) in streams.iter() {
+            let _ = writeln!(
+                out,
+                "neolink_clients{{{}}} {}",
+                stream_labels(camera, *stream),
+                m.clients
+            );
+        }
+
+        let _ = writeln!(
+            out,
+            "# HELP neolink_buffer_ready Whether the stream is ready to be served"
+        );
+        let _ = writeln!(out, "# TYPE neolink_buffer_ready gauge");
+        for ((camera, stream), m) in streams.iter() {
+            let _ = writeln!(
+                out,
+                "neolink_buffer_ready{{{}}} {}",
+                stream_labels(camera, *stream),
+                m.buffer_ready as u8
+            );
+        }
+
+        let _ = writeln!(
+            out,
+            "# HELP neolink_stream_state State of the stream 0=stopped 1=paused 2=streaming"
+        );
+        let _ = writeln!(out, "# TYPE neolink_stream_state gauge");
+        for ((camera, stream), m) in streams.iter() {
+            let _ = writeln!(
+                out,
+                "neolink_stream_state{{{}}} {}",
+                stream_labels(camera, *stream),
+                m.state as u8
+            );
+        }
+
+        let _ = writeln!(
+            out,
+            "# HELP neolink_pause_encodes_total Number of times a pause clip was encoded"
+        );
+        let _ = writeln!(out, "# TYPE neolink_pause_encodes_total counter");
+        for ((camera, stream), m) in streams.iter() {
+            let _ = writeln!(
+                out,
+                "neolink_pause_encodes_total{{{}}} {}",
+                stream_labels(camera, *stream),
+                m.pause_encodes
+            );
+        }
+
+        type SessionValue = fn(&SessionStats) -> String;
+        let session_metrics: [(&str, &str, SessionValue); 3] = [
+            (
+                "neolink_session_loss_percent",
+                "Percentage of the packets the client lost since its previous report",
+                |stats| stats.loss_percent.to_string(),
+            ),
+            (
+                "neolink_session_jitter_ms",
+                "Interarrival jitter that the client reports in milliseconds",
+                |stats| stats.jitter_ms.to_string(),
+            ),
+            (
+                "neolink_session_bitrate",
+                "Bits per second sent to the client",
+                |stats| stats.bitrate.to_string(),
+            ),
+        ];
+        for (name, help, value) in session_metrics.iter() {
+            let _ = writeln!(out, "# HELP {name} {help}");
+            let _ = writeln!(out, "# TYPE {name} gauge");
+            for ((camera, stream), m) in streams.iter() {
+                for stats in m.sessions.iter() {
+                    let _ = writeln!(
+                        out,
+                        "{name}{{{},session=\"{}\"}} {}",
+                        stream_labels(camera, *stream),
+                        escape_label(&stats.session),
+                        value(stats)
+                    );
+                }
+            }
+        }
+
+        let cameras = self.cameras.lock().unwrap();
+        let mut failures = cameras
+            .iter()
+            .map(|(camera, m)| (camera, m.failures))
+            .collect::<Vec<_>>();
+        failures.sort();
+        let _ = writeln!(
+            out,
+            "# HELP neolink_retryable_failures_total Number of times the camera connection was retried"
+        );
+        let _ = writeln!(out, "# TYPE neolink_retryable_failures_total counter");
+        for (camera, count) in failures.iter() {
+            let _ = writeln!(
+                out,
+                "neolink_retryable_failures_total{{camera=\"{}\"}} {}",
+                escape_label(camera),
+                count
+            );
+        }
+        out
+    }
+
+    /// Handles the http requests for the metrics server
+    pub(crate) fn handle(&self, req: &Request<Body>) -> Response<Body> {
+        match req.uri().path() {
+            "/metrics" => response(StatusCode::OK, "text/plain; version=0.0.4", self.render()),
+            _ => not_found(),
+        }
+    }
+}
+
+fn stream_labels(camera: &str, stream: StreamKind) -> String {
+    format!("camera=\"{}\",stream=\"{}\"", escape_label(camera), stream)
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
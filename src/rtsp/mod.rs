@@ -28,6 +28,22 @@
 /// neolink rtsp --config=config.toml
 /// ```
 ///
+/// Add `--status-port=8080` to serve `/healthz` and `/cameras` over http
+///
+/// With `--control` as well the cameras take commands over the same port,
+/// reusing their open connection:
+///
+/// ```bash
+/// curl -X POST -u admin:password http://127.0.0.1:8080/cameras/Garage/reboot
+/// ```
+///
+/// A camera that is stuck can be bounced with `/cameras/Garage/restart`
+/// without dropping the streams of the other cameras
+///
+/// When filing a bug about a stream that will not play add `--gst-debug=3` for
+/// gstreamer's own logs and `--gst-dot-dir=/tmp` to dump the graph of pipelines
+/// that fail to build. Both are off by default
+///
 /// # Example Config
 ///
 /// ```toml
@@ -39,82 +55,281 @@
 //   [cameras.pause]
 //   on_motion = false
 //   on_client = false
+//   require = "all"
 //   mode = "none"
 //   timeout = 1.0
 // ```
 //
 // - When `on_motion` is true the camera will pause streaming when motion is stopped and resume it when motion is started
 // - When `on_client` is true the camera will pause while there is no client connected.
+// - `require` decides how they combine when both are true: `"all"` (the default) streams only with motion and a client, `"any"` streams with either
+// - `schedule` with `windows = ["07:00-19:00"]` and an optional `timezone` pauses the camera outside of those times of day
 // - `timeout` handels how long to wait after motion stops before pausing the stream
+// - `bind` and `bind_port` can be set on a camera to serve it from a different address or port than the global one
 // - `mode` has the following values:
-//   - `"black"`: Switches to a black screen. Requires more cpu as the stream is fully reencoded
-//   - `"still"`: Switches to a still image. Requires more cpu as the stream is fully reencoded
-//   - `"test"`: Switches to the gstreamer test image. Requires more cpu as the stream is fully reencoded
+//   - `"black"`: Switches to a black screen. A short clip is encoded when the stream starts
+//   - `"still"`: Switches to a still of the last frame. A short clip is encoded each time the stream pauses
+//   - `"test"`: Switches to the gstreamer test image. A short clip is encoded when the stream starts
+//   - `"freeze"`: Repeats the last keyframe of the live stream so the paused picture stays clean. This does not reencode at all
 //   - `"none"`: Resends the last iframe the camera. This does not reencode at all.  **Most use cases should use this one as it has the least effort on the cpu and gives what you would expect**
+// - `encoder` picks the encoder of the `"black"`, `"still"` and `"test"` modes: `"x264"` (default), `"vaapi"` or `"v4l2"`.
+//   If the hardware encoder is not installed the software one is used. `bitrate` (kbit/s) and
+//   `preset` (the x264 speed preset) tune it
 //
 use anyhow::{anyhow, Context, Result};
 use gstreamer_rtsp_server::prelude::*;
 use log::*;
 use neolink_core::bc_protocol::StreamKind;
-use std::collections::{HashMap, HashSet};
+use std::collections::{hash_map::Entry, HashMap, HashSet};
+use std::future::Future;
+use std::net::ToSocketAddrs;
 use std::sync::Arc;
 use tokio::{
-    sync::watch::channel as watch,
+    sync::watch::{channel as watch, Receiver as WatchReceiver},
     task::JoinSet,
-    time::{interval, Duration},
+    time::{interval, Duration, Instant},
 };
 use tokio_stream::wrappers::IntervalStream;
 use tokio_stream::StreamExt;
 use tokio_util::sync::CancellationToken;
 
+mod adaptive;
+mod clip;
 mod cmdline;
+mod control;
+pub(crate) mod deps;
 mod factory;
 mod gst;
+mod hls;
+mod hooks;
+pub(crate) mod http;
+pub(crate) mod metrics;
+mod pause;
+mod push;
+mod record;
+mod sdp;
+mod session_limit;
+mod snapshot;
+mod status;
 mod stream;
+mod tls;
 
 use crate::common::{NeoInstance, NeoReactor};
+use crate::exit::ExitError;
 use factory::*;
+use metrics::Metrics;
 use stream::*;
 
-use super::config::UserConfig;
+use super::config::{Config, TokenConfig, UserConfig};
+pub use adaptive::SessionStats;
 pub(crate) use cmdline::Opt;
 use gst::NeoRtspServer;
+pub(crate) use gst::UNIX_PREFIX;
 
 type AnyResult<T> = anyhow::Result<T, anyhow::Error>;
 
 /// Entry point for the rtsp subcommand
 ///
 /// Opt is the command line options
-pub(crate) async fn main(_opt: Opt, reactor: NeoReactor) -> Result<()> {
-    let rtsp = Arc::new(NeoRtspServer::new()?);
+pub(crate) async fn main(opt: Opt, reactor: NeoReactor) -> Result<()> {
+    serve(
+        opt,
+        reactor,
+        Arc::new(Metrics::default()),
+        shutdown_signal(),
+    )
+    .await
+}
 
+/// Serves the cameras of the reactor until `shutdown` resolves
+///
+/// New clients are then turned away and the current ones, whose streams keep
+/// running, are given the `shutdown_grace` of the config to disconnect
+pub(crate) async fn serve<F: Future<Output = AnyResult<()>>>(
+    opt: Opt,
+    reactor: NeoReactor,
+    metrics: Arc<Metrics>,
+    shutdown: F,
+) -> Result<()> {
     let global_cancel = CancellationToken::new();
 
     let mut set = JoinSet::new();
 
+    // Must be set before gstreamer is initialised
+    if let Some(dot_dir) = opt.gst_dot_dir.as_ref() {
+        std::env::set_var("GST_DEBUG_DUMP_DOT_DIR", dot_dir);
+    }
+    // Does nothing when it was already initialised, e.g. by a program that
+    // embeds neolink or by a previous start of the server
+    gstreamer::init().context("Gstreamer failed to initialise")?;
+    if let Some(gst_debug) = opt.gst_debug.as_ref() {
+        gstreamer::debug_set_active(true);
+        gstreamer::debug_set_threshold_from_string(gst_debug, true);
+    }
+
+    // One server for the global bind address and one for each distinct
+    // per camera override
+    let rtsp_config = reactor.config().await?.borrow().clone();
+    let default_bind = (rtsp_config.bind_addr.clone(), rtsp_config.bind_port);
+    // The http servers cannot share a unix socket so they fall back to localhost
+    let http_bind = if default_bind.0.starts_with(UNIX_PREFIX) {
+        "127.0.0.1"
+    } else {
+        default_bind.0.as_str()
+    };
+    let mut servers: HashMap<(String, u16), Arc<NeoRtspServer>> = Default::default();
+    servers.insert(default_bind.clone(), Arc::new(NeoRtspServer::new()?));
+    for cam_config in rtsp_config.cameras.iter().filter(|a| a.enabled) {
+        let bind = cam_config.rtsp_bind(&default_bind.0, default_bind.1);
+        if let Entry::Vacant(vac) = servers.entry(bind) {
+            vac.insert(Arc::new(NeoRtspServer::new()?));
+        }
+    }
+    for server in servers.values() {
+        server.set_idle_timeout(rtsp_config.client_idle_timeout_secs);
+        server.set_dscp(rtsp_config.dscp);
+    }
+    let servers = Arc::new(servers);
+
+    let http_tls = if opt.metrics_port.is_some() || opt.status_port.is_some() {
+        http::tls_acceptor(&rtsp_config)?
+    } else {
+        None
+    };
+    if rtsp_config.http.require_auth && rtsp_config.users.is_empty() {
+        warn!("The http servers require auth but there are no [[users]], every request is refused");
+    }
+
+    // Thread for the prometheus metrics
+    if let Some(metrics_port) = opt.metrics_port {
+        let addr = (http_bind, metrics_port)
+            .to_socket_addrs()?
+            .next()
+            .ok_or(anyhow!("Could not resolve the metrics address"))?;
+        info!(
+            "Starting metrics server at {}://{}",
+            if http_tls.is_some() { "https" } else { "http" },
+            addr
+        );
+        let thread_metrics = metrics.clone();
+        let thread_config = reactor.config().await?;
+        let thread_tls = http_tls.clone();
+        let thread_cancel = global_cancel.clone();
+        set.spawn(async move {
+            http::serve(
+                addr,
+                thread_tls,
+                move |req| {
+                    let thread_metrics = thread_metrics.clone();
+                    let refused = {
+                        let config = thread_config.borrow();
+                        config.http.require_auth && http::user(&req, &config).is_none()
+                    };
+                    async move {
+                        if refused {
+                            return http::unauthorized();
+                        }
+                        thread_metrics.handle(&req)
+                    }
+                },
+                thread_cancel,
+            )
+            .await
+        });
+    }
+
+    // Thread for the health and status
+    if let Some(status_port) = opt.status_port {
+        let addr = (http_bind, status_port)
+            .to_socket_addrs()?
+            .next()
+            .ok_or(anyhow!("Could not resolve the status address"))?;
+        info!(
+            "Starting status server at {}://{}",
+            if http_tls.is_some() { "https" } else { "http" },
+            addr
+        );
+        let thread_metrics = metrics.clone();
+        let thread_servers = servers.clone();
+        let thread_config = reactor.config().await?;
+        // Commands reuse the connections of the cameras through the reactor
+        let thread_reactor = opt.control.then(|| reactor.clone());
+        if opt.control {
+            info!("Accepting camera commands on the status server");
+        }
+        let thread_cancel = global_cancel.clone();
+        // Ends the mjpeg streams so that the server can shut down
+        let handler_cancel = global_cancel.clone();
+        let thread_tls = http_tls.clone();
+        set.spawn(async move {
+            http::serve(
+                addr,
+                thread_tls,
+                move |req| {
+                    let thread_metrics = thread_metrics.clone();
+                    let thread_servers = thread_servers.clone();
+                    let thread_reactor = thread_reactor.clone();
+                    let config = thread_config.borrow().clone();
+                    let thread_cancel = handler_cancel.clone();
+                    async move {
+                        if config.http.require_auth && http::user(&req, &config).is_none() {
+                            return http::unauthorized();
+                        }
+                        status::handle(
+                            &req,
+                            &thread_metrics,
+                            &thread_servers,
+                            &config,
+                            thread_reactor.as_ref(),
+                            &thread_cancel,
+                        )
+                        .await
+                    }
+                },
+                thread_cancel,
+            )
+            .await
+        });
+    }
+
+    // Thread for the ONVIF discovery and media service
+    #[cfg(feature = "onvif")]
+    if let Some(onvif) = rtsp_config.onvif.clone() {
+        let addr = (http_bind, onvif.port)
+            .to_socket_addrs()?
+            .next()
+            .ok_or(anyhow!("Could not resolve the onvif address"))?;
+        info!("Starting onvif service at {}", addr);
+        let thread_config = reactor.config().await?;
+        let thread_metrics = metrics.clone();
+        let thread_cancel = global_cancel.clone();
+        set.spawn(async move {
+            crate::onvif::main(onvif, addr, thread_config, thread_metrics, thread_cancel).await
+        });
+    }
+    #[cfg(not(feature = "onvif"))]
+    if rtsp_config.onvif.is_some() {
+        warn!("The config has an [onvif] section but neolink was built without the onvif feature");
+    }
+
     // Thread for the TLS from the config
     let mut thread_config = reactor.config().await?;
     let thread_cancel = global_cancel.clone();
-    let thread_rtsp = rtsp.clone();
-    thread_rtsp.set_up_tls(&thread_config.borrow_and_update().clone())?;
+    let thread_servers = servers.clone();
+    for thread_rtsp in thread_servers.values() {
+        thread_rtsp.set_up_tls(&thread_config.borrow_and_update().clone())?;
+    }
     set.spawn(async move {
         tokio::select! {
             _ = thread_cancel.cancelled() => AnyResult::Ok(()),
-            v = async {
-                loop {
-                    thread_config.changed().await?;
-                    if let Err(e) = thread_rtsp.set_up_tls(&thread_config.borrow().clone()) {
-                        log::error!("Could not seup TLS: {e}");
-                    }
-                }
-            } => v
+            v = tls::keep_tls_updated(thread_servers, thread_config) => v,
         }
     });
 
     // Thread for the Users from the config
     let mut thread_config = reactor.config().await?;
     let thread_cancel = global_cancel.clone();
-    let thread_rtsp = rtsp.clone();
+    let thread_servers = servers.clone();
     set.spawn(async move {
         tokio::select! {
             _ = thread_cancel.cancelled() => AnyResult::Ok(()),
@@ -127,8 +342,10 @@ pub(crate) async fn main(_opt: Opt, reactor: NeoReactor) -> Result<()> {
                     ).await?.users.iter().cloned().collect::<HashSet<_>>();
 
                     let config = thread_config.borrow().clone();
-                    if let Err(e) = apply_users(&thread_rtsp, &curr_users).await {
-                        log::error!("Could not seup TLS: {e}");
+                    for thread_rtsp in thread_servers.values() {
+                        if let Err(e) = apply_users(thread_rtsp, &curr_users).await {
+                            log::error!("Could not seup TLS: {e}");
+                        }
                     }
 
                     if config.certificate.is_none() && !curr_users.is_empty() {
@@ -141,85 +358,182 @@ pub(crate) async fn main(_opt: Opt, reactor: NeoReactor) -> Result<()> {
         }
     });
 
+    // Thread for the access tokens from the config
+    let mut thread_config = reactor.config().await?;
+    let thread_cancel = global_cancel.clone();
+    let thread_servers = servers.clone();
+    set.spawn(async move {
+        tokio::select! {
+            _ = thread_cancel.cancelled() => AnyResult::Ok(()),
+            v = async {
+                let mut curr_tokens = vec![];
+                loop {
+                    curr_tokens = access_tokens(
+                        &*thread_config
+                            .wait_for(|new_config| access_tokens(new_config) != curr_tokens)
+                            .await?,
+                    );
+                    for thread_rtsp in thread_servers.values() {
+                        thread_rtsp.set_tokens(&curr_tokens)?;
+                    }
+                }
+            } => v
+        }
+    });
+
     // Startup and stop cameras as they are added/removed to the config
     let mut thread_config = reactor.config().await?;
     let thread_cancel = global_cancel.clone();
-    let thread_rtsp = rtsp.clone();
+    let thread_servers = servers.clone();
     let thread_reactor = reactor.clone();
+    let thread_metrics = metrics.clone();
     set.spawn(async move {
-        let mut set = JoinSet::<AnyResult<()>>::new();
+        // Each camera reports its name if it stopped with a fatal error
+        let mut set = JoinSet::<AnyResult<Option<String>>>::new();
         let thread_cancel2 = thread_cancel.clone();
-        tokio::select!{
+        let r = tokio::select!{
             _ = thread_cancel.cancelled() => AnyResult::Ok(()),
             v = async {
                 let mut cameras: HashMap<String, CancellationToken> = Default::default();
                 let mut config_names = HashSet::new();
+                let mut fatal_names = HashSet::new();
                 loop {
-                    config_names = thread_config.wait_for(|config| {
-                        let current_names = config.cameras.iter().filter(|a| a.enabled).map(|cam_config| cam_config.name.clone()).collect::<HashSet<_>>();
-                        current_names != config_names
-                    }).await.with_context(|| "Camera Config Watcher")?.clone().cameras.iter().filter(|a| a.enabled).map(|cam_config| cam_config.name.clone()).collect::<HashSet<_>>();
+                    tokio::select! {
+                        v = thread_config.wait_for(|config| {
+                            config.enabled_names() != config_names
+                        }) => {
+                            config_names = v.with_context(|| "Camera Config Watcher")?.enabled_names();
+                        },
+                        Some(Ok(Ok(Some(name)))) = set.join_next() => {
+                            fatal_names.insert(name);
+                            if config_names.iter().all(|name| fatal_names.contains(name)) {
+                                return Err(anyhow!(ExitError::AllCamerasFatal));
+                            }
+                            continue;
+                        },
+                    }
+
+                    // Forget removed cameras so that they start again if they are added back
+                    cameras.retain(|running_name, token| {
+                        if config_names.contains(running_name) {
+                            true
+                        } else {
+                            log::info!("{running_name}: Rtsp Stopping");
+                            token.cancel();
+                            // A camera that gave up has no task left to do this
+                            thread_metrics.remove_camera(running_name);
+                            false
+                        }
+                    });
 
                     for name in config_names.iter() {
                         if ! cameras.contains_key(name) {
                             log::info!("{name}: Rtsp Staring");
-                            let local_cancel = CancellationToken::new();
+                            let bind = {
+                                let config = thread_config.borrow();
+                                config.cameras.iter().find(|a| &a.name == name).map(|cam_config| cam_config.rtsp_bind(&config.bind_addr, config.bind_port))
+                            };
+                            let thread_rtsp2 = match bind.as_ref().and_then(|bind| thread_servers.get(bind)) {
+                                Some(rtsp) => rtsp.clone(),
+                                None => {
+                                    log::warn!("{name}: Bind address changed since startup. Restart to apply it. Serving on {}:{}", default_bind.0, default_bind.1);
+                                    thread_servers.get(&default_bind).expect("Default server should exist").clone()
+                                }
+                            };
+                            // Child token so that it is also cancelled on shutdown
+                            let local_cancel = thread_cancel2.child_token();
                             cameras.insert(name.clone(),local_cancel.clone() );
-                            let thread_global_cancel = thread_cancel2.clone();
-                            let thread_rtsp2 = thread_rtsp.clone();
                             let thread_reactor2 = thread_reactor.clone();
+                            let thread_metrics2 = thread_metrics.clone();
+                            let thread_config2 = thread_config.clone();
                             let name = name.clone();
                             set.spawn(async move {
                                 let camera = thread_reactor2.get(&name).await?;
-                                tokio::select!(
-                                    _ = thread_global_cancel.cancelled() => {
-                                        AnyResult::Ok(())
-                                    },
-                                    _ = local_cancel.cancelled() => {
-                                        AnyResult::Ok(())
+                                let mut failures = camera.connection_failures().await?;
+                                let r = tokio::select! {
+                                    v = camera_main(camera, &thread_rtsp2, &thread_metrics2, thread_config2, local_cancel) => v.map(|_| None),
+                                    v = failures.wait_for(|failures| failures.fatal) => {
+                                        v?;
+                                        Ok(Some(name.clone()))
                                     },
-                                    v = camera_main(camera, &thread_rtsp2) => v,
-                                )
+                                };
+                                if matches!(r, Ok(Some(_))) {
+                                    thread_metrics2.set_given_up(&name);
+                                } else {
+                                    thread_metrics2.remove_camera(&name);
+                                }
+                                r
                             }) ;
                         }
                     }
-
-                    for (running_name, token) in cameras.iter() {
-                        if ! config_names.contains(running_name) {
-                            log::debug!("Rtsp::main Cancel1");
-                            token.cancel();
-                        }
-                    }
                 }
             } => v,
-        }
+        };
+        // The cameras observe the cancel token, let them finish cleanly
+        while set.join_next().await.is_some() {}
+        r
     });
 
-    let rtsp_config = reactor.config().await?.borrow().clone();
-    info!(
-        "Starting RTSP Server at {}:{}",
-        &rtsp_config.bind_addr, rtsp_config.bind_port,
-    );
-
-    let bind_addr = rtsp_config.bind_addr.clone();
-    let bind_port = rtsp_config.bind_port;
-    rtsp.run(&bind_addr, bind_port).await?;
-    let thread_rtsp = rtsp.clone();
-    set.spawn(async move { thread_rtsp.join().await });
-
-    while let Some(joined) = set
-        .join_next()
-        .await
-        .map(|s| s.map_err(anyhow::Error::from))
-    {
-        match &joined {
+    for ((bind_addr, bind_port), rtsp) in servers.iter() {
+        if bind_addr.starts_with(UNIX_PREFIX) {
+            info!("Starting RTSP Server at {}", bind_addr);
+        } else {
+            info!("Starting RTSP Server at {}:{}", bind_addr, bind_port);
+        }
+        rtsp.run(bind_addr, *bind_port)
+            .await
+            .context(ExitError::Bind)?;
+        let thread_rtsp = rtsp.clone();
+        set.spawn(async move { thread_rtsp.join().await });
+    }
+
+    tokio::pin!(shutdown);
+    let mut listening = true;
+    // The first error before a shutdown was requested, it decides the exit code
+    let mut failure = None;
+    loop {
+        let joined = tokio::select! {
+            v = set.join_next() => match v {
+                Some(v) => v.map_err(anyhow::Error::from),
+                None => break,
+            },
+            v = &mut shutdown, if listening => {
+                listening = false;
+                if let Err(e) = v {
+                    warn!("Could not listen for shutdown signals: {e}");
+                    continue;
+                }
+                info!("Shutting down, waiting for rtsp clients to disconnect");
+                // New clients are turned away while the streams keep feeding the current ones
+                for rtsp in servers.values() {
+                    rtsp.stop_accepting();
+                }
+                let grace = Duration::from_secs(reactor.config().await?.borrow().shutdown_grace);
+                // The servers share one main loop and one deadline
+                let deadline = Instant::now() + grace;
+                for rtsp in servers.values() {
+                    rtsp.drain(deadline).await;
+                }
+                global_cancel.cancel();
+                for rtsp in servers.values() {
+                    rtsp.quit().await?;
+                }
+                continue;
+            }
+        };
+        match joined {
             Err(e) | Ok(Err(e)) => {
                 // Panicked or error in task
                 // Cancel all and await terminate
                 log::error!("Error: {e}");
                 log::debug!("Rtsp::main Cancel2");
                 global_cancel.cancel();
-                rtsp.quit().await?;
+                for rtsp in servers.values() {
+                    rtsp.quit().await?;
+                }
+                if listening && failure.is_none() {
+                    failure = Some(e);
+                }
             }
             Ok(Ok(_)) => {
                 // All good
@@ -227,9 +541,44 @@ pub(crate) async fn main(_opt: Opt, reactor: NeoReactor) -> Result<()> {
         }
     }
 
+    match failure {
+        Some(e) => Err(e),
+        None => Ok(()),
+    }
+}
+
+/// Resolves on SIGINT or SIGTERM
+async fn shutdown_signal() -> AnyResult<()> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut terminate = signal(SignalKind::terminate())?;
+        tokio::select! {
+            v = tokio::signal::ctrl_c() => v?,
+            _ = terminate.recv() => {},
+        }
+    }
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await?;
     Ok(())
 }
 
+/// The access tokens of the enabled cameras with the role each one gives
+fn access_tokens(config: &Config) -> Vec<(TokenConfig, String)> {
+    config
+        .cameras
+        .iter()
+        .filter(|camera| camera.enabled)
+        .flat_map(|camera| {
+            let role = camera.token_role();
+            camera
+                .tokens
+                .iter()
+                .map(move |token| (token.clone(), role.clone()))
+        })
+        .collect()
+}
+
 /// This keeps the users in rtsp and the config in sync
 async fn apply_users(rtsp: &NeoRtspServer, curr_users: &HashSet<UserConfig>) -> AnyResult<()> {
     // Add those missing
@@ -248,16 +597,93 @@ async fn apply_users(rtsp: &NeoRtspServer, curr_users: &HashSet<UserConfig>) ->
     Ok(())
 }
 
+/// The roles that may view a stream of the camera
+///
+/// Users may be further limited to certain streams of this camera.
+/// Access tokens of this camera can view all of its streams
+fn stream_roles(
+    camera: &str,
+    stream: StreamKind,
+    permitted_users: &HashSet<String>,
+    users: &[UserConfig],
+    token_role: Option<&str>,
+) -> HashSet<String> {
+    permitted_users
+        .iter()
+        .filter(|permitted| {
+            users
+                .iter()
+                .find(|user| &&user.name == permitted)
+                .map(|user| user.can_view(camera, stream))
+                .unwrap_or(true)
+        })
+        .cloned()
+        .chain(token_role.map(|role| role.to_string()))
+        .collect()
+}
+
 /// Top level camera entry point
 ///
 /// It checks which streams are supported and then starts them
-async fn camera_main(camera: NeoInstance, rtsp: &NeoRtspServer) -> Result<()> {
+/// It stops once the cancel token is cancelled
+async fn camera_main(
+    camera: NeoInstance,
+    rtsp: &NeoRtspServer,
+    metrics: &Arc<Metrics>,
+    mut global_config: WatchReceiver<Config>,
+    cancel: CancellationToken,
+) -> Result<()> {
     let name = camera.config().await?.borrow().name.clone();
     log::debug!("{name}: Camera Main");
     let later_camera = camera.clone();
     let (supported_streams_tx, supported_streams) = watch(HashSet::<StreamKind>::new());
 
     let mut set = JoinSet::new();
+    let mut failures = camera.connection_failures().await?;
+    let thread_metrics = metrics.clone();
+    let thread_name = name.clone();
+    set.spawn(async move {
+        loop {
+            let current = failures.borrow_and_update().clone();
+            thread_metrics.set_failures(&thread_name, current.count, current.last_error);
+            if failures.changed().await.is_err() {
+                break AnyResult::Ok(());
+            }
+        }
+    });
+    let mut version = camera.camera_version().await?;
+    let thread_metrics = metrics.clone();
+    let thread_name = name.clone();
+    set.spawn(async move {
+        loop {
+            let current = version.borrow_and_update().clone();
+            thread_metrics.set_version(&thread_name, current.model, current.firmware);
+            if version.changed().await.is_err() {
+                break AnyResult::Ok(());
+            }
+        }
+    });
+    let hook_camera = camera.clone();
+    let hook_name = name.clone();
+    set.spawn(async move {
+        // The hooks must never stop the streams
+        if let Err(e) = hooks::motion_hooks(hook_camera).await {
+            log::error!("{hook_name}: Stopped running the motion hooks: {e:?}");
+        }
+        AnyResult::Ok(())
+    });
+    let mut connected = camera.camera();
+    let thread_metrics = metrics.clone();
+    let thread_name = name.clone();
+    set.spawn(async move {
+        loop {
+            let is_connected = connected.borrow_and_update().upgrade().is_some();
+            thread_metrics.set_connected(&thread_name, is_connected);
+            if connected.changed().await.is_err() {
+                break AnyResult::Ok(());
+            }
+        }
+    });
     set.spawn(async move {
         let mut i = IntervalStream::new(interval(Duration::from_secs(15)));
         while i.next().await.is_some() {
@@ -294,19 +720,47 @@ async fn camera_main(camera: NeoInstance, rtsp: &NeoRtspServer) -> Result<()> {
     log::debug!("{name}: Camera Main::Loop");
 
     let mut camera_config = camera.config().await?.clone();
-    loop {
+    // Shared by all streams so that max_clients counts every client of the camera
+    let client_limit = ClientLimit::new(camera_config.clone());
+    let mut served_paths = vec![];
+    let r = loop {
         let prev_stream_config = camera_config.borrow_and_update().stream;
         let prev_stream_users = camera_config.borrow().permitted_users.clone();
-        let active_streams = prev_stream_config
-            .as_stream_kinds()
+        let prev_paths = camera_config.borrow().all_rtsp_paths();
+        // Paths that the camera is no longer served under
+        rtsp.remove_paths(
+            &served_paths
+                .iter()
+                .filter(|path| !prev_paths.contains(path))
+                .cloned()
+                .collect::<Vec<_>>(),
+        );
+        served_paths = prev_paths.clone();
+        let prev_user_configs = global_config.borrow_and_update().users.clone();
+        let has_tokens = !camera_config.borrow().tokens.is_empty();
+        let token_role = camera_config.borrow().token_role();
+        let active_streams = camera_config
+            .borrow()
+            .stream_kinds()
             .drain(..)
             .collect::<HashSet<_>>();
         let use_splash = camera_config.borrow().use_splash;
         let splash_pattern = camera_config.borrow().splash_pattern.to_string();
+        let not_ready_image = camera_config.borrow().not_ready_image.clone();
+        let fallback = {
+            let config = camera_config.borrow();
+            config
+                .fallback_to_substream
+                .then_some(config.fallback_after_failures)
+        };
 
         // This select is for changes to camera_config.stream
         break tokio::select! {
-            v = camera_config.wait_for(|config| config.stream != prev_stream_config || config.permitted_users != prev_stream_users || config.use_splash != use_splash) => {
+            _ = cancel.cancelled() => {
+                log::debug!("{name}: Camera Main::Shutdown");
+                AnyResult::Ok(())
+            },
+            v = camera_config.wait_for(|config| config.stream != prev_stream_config || config.permitted_users != prev_stream_users || config.use_splash != use_splash || config.not_ready_image != not_ready_image || config.all_rtsp_paths() != prev_paths || config.fallback_to_substream.then_some(config.fallback_after_failures) != fallback || config.tokens.is_empty() == has_tokens) => {
                 if let Err(e) = v {
                     AnyResult::Err(e.into())
                 } else {
@@ -314,6 +768,14 @@ async fn camera_main(camera: NeoInstance, rtsp: &NeoRtspServer) -> Result<()> {
                     continue;
                 }
             },
+            v = global_config.wait_for(|config| config.users != prev_user_configs) => {
+                if let Err(e) = v {
+                    AnyResult::Err(e.into())
+                } else {
+                    // users changed restart
+                    continue;
+                }
+            },
             v = async {
                 // This select handles enabling the right stream
                 log::debug!("{name}: Camera Main::Select Stream");
@@ -332,27 +794,18 @@ async fn camera_main(camera: NeoInstance, rtsp: &NeoRtspServer) -> Result<()> {
                     None => ["anonymous".to_string()].iter().cloned().collect(),
                 };
 
-                // Create the dummy factory
-                let dummy_factory = make_dummy_factory(use_splash, splash_pattern).await?;
-                dummy_factory.add_permitted_roles(&permitted_users);
+                let token_role = has_tokens.then_some(token_role.as_str());
+                let stream_users = |stream: StreamKind| -> HashSet<String> {
+                    stream_roles(&name, stream, &permitted_users, &prev_user_configs, token_role)
+                };
                 let mut supported_streams_1 = supported_streams.clone();
                 let mut supported_streams_2 = supported_streams.clone();
                 let mut supported_streams_3 = supported_streams.clone();
                 tokio::select! {
                     v = async {
                         log::debug!("{name}: Camera Main::Select Main");
-                        let name = camera.config().await?.borrow().name.clone();
-                        let mut paths = vec![
-                            format!("/{name}/main"),
-                            format!("/{name}/Main"),
-                            format!("/{name}/mainStream"),
-                            format!("/{name}/MainStream"),
-                            format!("/{name}/Mainstream"),
-                            format!("/{name}/mainstream"),
-                        ];
-                        paths.push(
-                            format!("/{name}")
-                        );
+                        let paths = camera.config().await?.borrow().rtsp_paths(StreamKind::Main);
+                        let users = stream_users(StreamKind::Main);
                         // Create a dummy factory so that the URL will not return 404 while waiting
                         // for configuration to compete
                         //
@@ -361,6 +814,8 @@ async fn camera_main(camera: NeoInstance, rtsp: &NeoRtspServer) -> Result<()> {
                         let mounts = rtsp
                             .mount_points()
                             .ok_or(anyhow!("RTSP server lacks mount point"))?;
+                        let dummy_factory = make_dummy_factory(use_splash, splash_pattern.clone(), not_ready_image.clone()).await?;
+                        dummy_factory.add_permitted_roles(&users);
                         for path in paths.iter() {
                             log::debug!("Path: {}", path);
                             mounts.add_factory(path, dummy_factory.clone());
@@ -368,24 +823,16 @@ async fn camera_main(camera: NeoInstance, rtsp: &NeoRtspServer) -> Result<()> {
                         log::debug!("{}: Preparing at {}", name, paths.join(", "));
 
                         supported_streams_1.wait_for(|ss| ss.contains(&StreamKind::Main)).await?;
-                        stream_main(camera.stream(StreamKind::Main).await?, camera.clone(), rtsp, &permitted_users, &paths).await
+                        let can_fallback = supported_streams_1.borrow().contains(&StreamKind::Sub);
+                        match fallback {
+                            Some(after) if can_fallback => stream_main_with_fallback(&camera, rtsp, &users, &paths, metrics, &client_limit, after).await,
+                            _ => stream_main(camera.stream(StreamKind::Main).await?, camera.clone(), rtsp, &users, &paths, metrics, &client_limit).await,
+                        }
                     }, if active_streams.contains(&StreamKind::Main) => v,
                     v = async {
                         log::debug!("{name}: Camera Main::Select Sub");
-                        let name = camera.config().await?.borrow().name.clone();
-                        let mut paths = vec![
-                            format!("/{name}/sub"),
-                            format!("/{name}/Sub"),
-                            format!("/{name}/subStream"),
-                            format!("/{name}/SubStream"),
-                            format!("/{name}/Substream"),
-                            format!("/{name}/substream"),
-                        ];
-                        if ! active_streams.contains(&StreamKind::Main) {
-                            paths.push(
-                                format!("/{name}")
-                            );
-                        }
+                        let paths = camera.config().await?.borrow().rtsp_paths(StreamKind::Sub);
+                        let users = stream_users(StreamKind::Sub);
 
                         // Create a dummy factory so that the URL will not return 404 while waiting
                         // for configuration to compete
@@ -395,32 +842,27 @@ async fn camera_main(camera: NeoInstance, rtsp: &NeoRtspServer) -> Result<()> {
                         let mounts = rtsp
                             .mount_points()
                             .ok_or(anyhow!("RTSP server lacks mount point"))?;
-                        // Create the dummy factory
+                        let dummy_factory = make_dummy_factory(use_splash, splash_pattern.clone(), not_ready_image.clone()).await?;
+                        dummy_factory.add_permitted_roles(&users);
                         for path in paths.iter() {
                             log::debug!("Path: {}", path);
                             mounts.add_factory(path, dummy_factory.clone());
                         }
                         log::debug!("{}: Preparing at {}", name, paths.join(", "));
 
+                        // A camera without a substream gets a clean 404 rather than the dummy
+                        // that would never turn into the stream
+                        if !supported_streams_2.wait_for(|ss| !ss.is_empty()).await?.contains(&StreamKind::Sub) {
+                            log::info!("{name}: The camera has no substream, not serving {}", paths.join(", "));
+                            rtsp.remove_paths(&paths);
+                        }
                         supported_streams_2.wait_for(|ss| ss.contains(&StreamKind::Sub)).await?;
-                        stream_main(camera.stream(StreamKind::Sub).await?,camera.clone(), rtsp, &permitted_users, &paths).await
+                        stream_main(camera.stream(StreamKind::Sub).await?,camera.clone(), rtsp, &users, &paths, metrics, &client_limit).await
                     }, if active_streams.contains(&StreamKind::Sub) => v,
                     v = async {
                         log::debug!("{name}: Camera Main::Select Extern");
-                        let name = camera.config().await?.borrow().name.clone();
-                        let mut paths = vec![
-                            format!("/{name}/extern"),
-                            format!("/{name}/Extern"),
-                            format!("/{name}/externStream"),
-                            format!("/{name}/ExternStream"),
-                            format!("/{name}/Externstream"),
-                            format!("/{name}/externstream"),
-                        ];
-                        if ! active_streams.contains(&StreamKind::Main) && ! active_streams.contains(&StreamKind::Sub) {
-                            paths.push(
-                                format!("/{name}")
-                            );
-                        }
+                        let paths = camera.config().await?.borrow().rtsp_paths(StreamKind::Extern);
+                        let users = stream_users(StreamKind::Extern);
 
                         // Create a dummy factory so that the URL will not return 404 while waiting
                         // for configuration to compete
@@ -430,6 +872,8 @@ async fn camera_main(camera: NeoInstance, rtsp: &NeoRtspServer) -> Result<()> {
                         let mounts = rtsp
                             .mount_points()
                             .ok_or(anyhow!("RTSP server lacks mount point"))?;
+                        let dummy_factory = make_dummy_factory(use_splash, splash_pattern.clone(), not_ready_image.clone()).await?;
+                        dummy_factory.add_permitted_roles(&users);
                         for path in paths.iter() {
                             log::debug!("Path: {}", path);
                             mounts.add_factory(path, dummy_factory.clone());
@@ -437,7 +881,7 @@ async fn camera_main(camera: NeoInstance, rtsp: &NeoRtspServer) -> Result<()> {
                         log::debug!("{}: Preparing at {}", name, paths.join(", "));
 
                         supported_streams_3.wait_for(|ss| ss.contains(&StreamKind::Extern)).await?;
-                        stream_main(camera.stream(StreamKind::Extern).await?,camera.clone(), rtsp, &permitted_users, &paths).await
+                        stream_main(camera.stream(StreamKind::Extern).await?,camera.clone(), rtsp, &users, &paths, metrics, &client_limit).await
                     }, if active_streams.contains(&StreamKind::Extern) => v,
                     else => {
                         // all disabled just wait here until config is changed
@@ -446,7 +890,96 @@ async fn camera_main(camera: NeoInstance, rtsp: &NeoRtspServer) -> Result<()> {
                 }
             } => v,
         };
-    }?;
+    };
+    // Only when removed from the config, on shutdown the clients are drained instead
+    let removed = !global_config
+        .borrow()
+        .cameras
+        .iter()
+        .any(|cam_config| cam_config.enabled && cam_config.name == name);
+    if removed {
+        rtsp.remove_paths(&served_paths);
+    }
 
-    Ok(())
+    r
+}
+
+/// Serves the main stream and switches its paths over to the substream
+/// while the main stream fails `after` times in a row
+///
+/// The main stream keeps being pulled during the fallback so that it is
+/// restored as soon as it sends a keyframe again
+async fn stream_main_with_fallback(
+    camera: &NeoInstance,
+    rtsp: &NeoRtspServer,
+    users: &HashSet<String>,
+    paths: &[String],
+    metrics: &Arc<Metrics>,
+    client_limit: &ClientLimit,
+    after: u32,
+) -> Result<()> {
+    let name = camera.config().await?.borrow().name.clone();
+    loop {
+        let main = camera.stream(StreamKind::Main).await?;
+        let mut failures = main.failures.clone();
+        tokio::select! {
+            v = stream_main(main, camera.clone(), rtsp, users, paths, metrics, client_limit) => return v,
+            v = failures.wait_for(|count| *count >= after) => {
+                v?;
+            }
+        }
+        log::warn!(
+            "{name}: The main stream failed {after} times in a row, serving the substream in its place"
+        );
+
+        // Holding this keeps the main stream trying
+        let mut main = camera.stream(StreamKind::Main).await?;
+        tokio::select! {
+            v = stream_main(camera.stream(StreamKind::Sub).await?, camera.clone(), rtsp, users, paths, metrics, client_limit) => return v,
+            v = main.failures.wait_for(|count| *count == 0) => {
+                v?;
+            }
+        }
+        log::info!("{name}: The main stream is working again, restoring it");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stream_roles() {
+        let config: Config = toml::from_str(
+            r#"
+            [[users]]
+            name = "guest"
+            pass = "guestpass"
+            streams = { driveway = "subStream" }
+
+            [[users]]
+            name = "admin"
+            pass = "adminpass"
+            "#,
+        )
+        .unwrap();
+        let permitted = HashSet::from(["guest".to_string(), "admin".to_string()]);
+        let roles = |stream| stream_roles("driveway", stream, &permitted, &config.users, None);
+
+        // The factory of the main stream does not let the guest in, the substream one does
+        assert_eq!(
+            roles(StreamKind::Main),
+            HashSet::from(["admin".to_string()])
+        );
+        assert_eq!(roles(StreamKind::Sub), permitted);
+        // Tokens of the camera are let into every stream
+        let roles = stream_roles(
+            "driveway",
+            StreamKind::Main,
+            &permitted,
+            &config.users,
+            Some("token"),
+        );
+        assert!(roles.contains("token"));
+    }
 }
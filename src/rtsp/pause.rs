@@ -0,0 +1,749 @@
+//! Decides when the stream of a camera is paused
+//!
+//! Motion, push notifications, rtsp clients, the `schedule` and a force over
+//! the control channel each set an affector and [`should_stream`] turns them
+//! into whether the stream is paused. The camera is reached through
+//! [`PauseCamera`] so that the tests can drive it with a mock instead of a
+//! real camera. [`align_pause`] then holds the pause back to the next keyframe
+//! and [`count_sessions`] counts the clients that have set up a session before
+//! their media is fed
+use futures::future::{pending, BoxFuture};
+use gstreamer::glib;
+use tokio::{
+    sync::{
+        broadcast::{error::RecvError, Receiver as BroadcastReceiver},
+        watch::{channel as watch, Receiver as WatchReceiver, Sender as WatchSender},
+    },
+    time::{sleep, sleep_until, Duration},
+};
+
+use crate::{
+    common::{ForcePause, MdState, NeoInstance, Permit, PushNoti, StampedData},
+    config::{PauseConfig, PauseRequire, ScheduleConfig},
+    AnyResult,
+};
+
+/// How long a push notification streams for
+const PUSH_STREAM: Duration = Duration::from_secs(30);
+
+/// The parts of the camera that pause the stream
+pub(super) trait PauseCamera {
+    fn motion(&self) -> BoxFuture<'_, AnyResult<WatchReceiver<MdState>>>;
+    fn push_notifications(&self) -> BoxFuture<'_, AnyResult<WatchReceiver<Option<PushNoti>>>>;
+    fn force_pause(&self) -> BoxFuture<'_, AnyResult<WatchReceiver<Option<ForcePause>>>>;
+}
+
+impl PauseCamera for NeoInstance {
+    fn motion(&self) -> BoxFuture<'_, AnyResult<WatchReceiver<MdState>>> {
+        Box::pin(NeoInstance::motion(self))
+    }
+
+    fn push_notifications(&self) -> BoxFuture<'_, AnyResult<WatchReceiver<Option<PushNoti>>>> {
+        Box::pin(NeoInstance::push_notifications(self))
+    }
+
+    fn force_pause(&self) -> BoxFuture<'_, AnyResult<WatchReceiver<Option<ForcePause>>>> {
+        Box::pin(NeoInstance::force_pause(self))
+    }
+}
+
+#[derive(Clone)]
+struct PauseAffectors {
+    motion: bool,
+    push: bool,
+    client: bool,
+    /// Within the schedule, always true without one
+    scheduled: bool,
+    /// Set over the control channel, it overrides all the others
+    forced: Option<ForcePause>,
+}
+
+/// Keeps `paused` up to date with the camera and the `clients` of the stream
+///
+/// Only returns on an error of the camera or the client counter
+pub(super) async fn pause_main<C: PauseCamera>(
+    name: &str,
+    pause: &PauseConfig,
+    camera: &C,
+    clients: Permit,
+    paused: WatchSender<bool>,
+) -> AnyResult<()> {
+    let mut force_pause = camera.force_pause().await?;
+    let (mut motion, mut pn) = if pause.on_motion {
+        (
+            Some(camera.motion().await?),
+            Some(camera.push_notifications().await?),
+        )
+    } else {
+        (None, None)
+    };
+
+    let (affectors_tx, mut affectors) = watch(PauseAffectors {
+        motion: false,
+        push: false,
+        client: false,
+        scheduled: pause
+            .schedule
+            .as_ref()
+            .map_or(true, |schedule| schedule_now(schedule).0),
+        forced: *force_pause.borrow_and_update(),
+    });
+
+    // Client count affector
+    let client_affector = async {
+        if !pause.on_disconnect {
+            return pending::<AnyResult<()>>().await;
+        }
+        log::debug!("{}: Activating Client Pause", name);
+        loop {
+            clients.aquired_users().await?;
+            log::info!("{}: Enabling Client", name);
+            affectors_tx.send_modify(|current| {
+                current.client = true;
+            });
+
+            clients.dropped_users().await?;
+            log::info!("{}: Pausing Client", name);
+            affectors_tx.send_modify(|current| {
+                current.client = false;
+            });
+        }
+    };
+
+    // Motion affector
+    let motion_affector = async {
+        let Some(motion) = motion.as_mut() else {
+            return pending::<AnyResult<()>>().await;
+        };
+        let delta = Duration::from_secs_f64(pause.motion_timeout);
+        log::debug!("{}: Activating Motion Pause", name);
+        loop {
+            motion
+                .wait_for(|md| matches!(md, MdState::Start(_)))
+                .await?;
+            log::info!("{}: Enabling Motion", name);
+            affectors_tx.send_modify(|current| {
+                current.motion = true;
+            });
+
+            // The camera sends nothing more once it stops so the timeout has
+            // to be slept rather than waited for on the watch
+            loop {
+                let stopped = match &*motion.borrow_and_update() {
+                    MdState::Stop(at) => Some(*at),
+                    _ => None,
+                };
+                match stopped {
+                    Some(at) if at.elapsed() >= delta => break,
+                    Some(at) => tokio::select! {
+                        _ = sleep_until(at + delta) => {},
+                        v = motion.changed() => v?,
+                    },
+                    None => motion.changed().await?,
+                }
+            }
+            log::info!("{}: Pausing Motion", name);
+            affectors_tx.send_modify(|current| {
+                current.motion = false;
+            });
+        }
+    };
+
+    // Push notfications
+    let push_affector = async {
+        let Some(pn) = pn.as_mut() else {
+            return pending::<AnyResult<()>>().await;
+        };
+        log::debug!("{}: Activating Push Notification Pause", name);
+        let mut curr_pn = None;
+        loop {
+            curr_pn = pn
+                .wait_for(|pn| pn != &curr_pn && pn.is_some())
+                .await?
+                .clone();
+            log::info!("{}: Enabling Push Notification", name);
+            affectors_tx.send_modify(|current| {
+                current.push = true;
+            });
+            tokio::select! {
+                v = pn.wait_for(|pn| pn != &curr_pn && pn.is_some()) => {
+                    v?;
+                    // If another PN during wait then go back to wait more
+                    continue;
+                }
+                _ = sleep(PUSH_STREAM) => {}
+            }
+            log::info!("{}: Pausing Push Notification", name);
+            affectors_tx.send_modify(|current| {
+                current.push = false;
+            });
+        }
+    };
+
+    // Schedule affector
+    let schedule_affector = async {
+        let Some(schedule) = pause.schedule.as_ref() else {
+            return pending::<AnyResult<()>>().await;
+        };
+        log::debug!("{}: Activating Schedule Pause", name);
+        loop {
+            let (scheduled, next_minute) = schedule_now(schedule);
+            affectors_tx.send_if_modified(|current| {
+                if current.scheduled == scheduled {
+                    return false;
+                }
+                if scheduled {
+                    log::info!("{}: Enabling Schedule", name);
+                } else {
+                    log::info!("{}: Pausing Schedule", name);
+                }
+                current.scheduled = scheduled;
+                true
+            });
+            sleep(next_minute).await;
+        }
+    };
+
+    // Force affector, even without a `[pause]` the stream can be forced to pause
+    let force_affector = async {
+        loop {
+            force_pause.changed().await?;
+            let forced = *force_pause.borrow_and_update();
+            match forced {
+                Some(ForcePause::Pause) => log::info!("{}: Forced to pause", name),
+                Some(ForcePause::Resume) => log::info!("{}: Forced to stream", name),
+                None => log::info!("{}: Pausing automatically again", name),
+            }
+            affectors_tx.send_modify(|current| {
+                current.forced = forced;
+            });
+        }
+    };
+
+    let controller = async {
+        loop {
+            let should_stream = should_stream(pause, &affectors.borrow_and_update());
+            paused.send_if_modified(|paused| {
+                let changed = *paused == should_stream;
+                *paused = !should_stream;
+                changed
+            });
+            affectors.changed().await?;
+        }
+    };
+
+    tokio::select! {
+        v = client_affector => v,
+        v = motion_affector => v,
+        v = push_affector => v,
+        v = schedule_affector => v,
+        v = force_affector => v,
+        v = controller => v,
+    }
+}
+
+/// Passes `wanted` on to `paused`, holding each pause back until the next keyframe of `vid`
+///
+/// Clients then always get whole GOPs before the pause. A stream that sends no
+/// keyframe within `max_wait` is paused anyway. Resumes are passed on at once
+pub(super) async fn align_pause(
+    mut wanted: WatchReceiver<bool>,
+    paused: WatchSender<bool>,
+    mut vid: BroadcastReceiver<StampedData>,
+    max_wait: Duration,
+) -> AnyResult<()> {
+    loop {
+        let want = *wanted.borrow_and_update();
+        if want && !*paused.borrow() {
+            // Only the frames from now on count
+            vid = vid.resubscribe();
+            let keyframe = async {
+                loop {
+                    match vid.recv().await {
+                        Ok(frame) if frame.keyframe => return,
+                        Ok(_) | Err(RecvError::Lagged(_)) => {}
+                        Err(RecvError::Closed) => pending().await,
+                    }
+                }
+            };
+            tokio::select! {
+                v = wanted.wait_for(|want| !*want) => {
+                    // Wanted to stream again before the keyframe came
+                    v?;
+                    continue;
+                },
+                _ = keyframe => {},
+                _ = sleep(max_wait) => {},
+            }
+        }
+        paused.send_if_modified(|paused| std::mem::replace(paused, want) != want);
+        wanted.changed().await?;
+    }
+}
+
+/// Counts a client towards the client pause from its SETUP rather than from
+/// when its media starts being fed
+///
+/// The clients of the stream are counted by `permit`'s counter while their
+/// media is fed. A client that has a session, as `sessions` reports, without
+/// any fed media, e.g. between its SETUP and PLAY, keeps `permit` active so
+/// the stream is not paused under it. Once a media is fed `permit` steps back
+/// so that each client is only counted once
+pub(super) async fn count_sessions<F: Fn() -> usize>(
+    sessions: F,
+    mut permit: Permit,
+    poll: Duration,
+) -> AnyResult<()> {
+    let counter = permit.get_counter();
+    let mut active = false;
+    loop {
+        let counted = counter.borrow().saturating_sub(u32::from(active));
+        let wanted = sessions() > 0 && counted == 0;
+        if wanted != active {
+            if wanted {
+                log::debug!("Counting a client that has set up a session but is not fed yet");
+                permit.activate().await?;
+            } else {
+                permit.deactivate().await?;
+            }
+            active = wanted;
+        }
+        sleep(poll).await;
+    }
+}
+
+/// Whether the stream should be running rather than paused
+///
+/// | on_motion | on_disconnect | require | Streams while            |
+/// |-----------|---------------|---------|--------------------------|
+/// | true      | false         | -       | motion                   |
+/// | false     | true          | -       | a client                 |
+/// | true      | true          | all     | motion **and** a client  |
+/// | true      | true          | any     | motion **or** a client   |
+///
+/// A push notification counts as motion. Outside of the `schedule` it never
+/// streams. A pause or resume forced over the control channel comes before all
+/// of these
+fn should_stream(pause: &PauseConfig, state: &PauseAffectors) -> bool {
+    match state.forced {
+        Some(ForcePause::Pause) => return false,
+        Some(ForcePause::Resume) => return true,
+        None => {}
+    }
+    if !state.scheduled {
+        return false;
+    }
+    let motion = state.motion || state.push;
+    match (pause.on_motion, pause.on_disconnect, pause.require) {
+        (true, true, PauseRequire::All) => motion && state.client,
+        (true, true, PauseRequire::Any) => motion || state.client,
+        (true, false, _) => motion,
+        (false, true, _) => state.client,
+        (false, false, _) => true,
+    }
+}
+
+/// Whether the schedule is on now and how long until the next minute, when
+/// it is checked again
+///
+/// The windows are in the wall clock time of the timezone so they follow its
+/// daylight saving changes. If the time cannot be read it counts as on
+fn schedule_now(schedule: &ScheduleConfig) -> (bool, Duration) {
+    let zone = match schedule.timezone.as_deref() {
+        Some(timezone) => glib::TimeZone::new(Some(timezone)),
+        None => glib::TimeZone::local(),
+    };
+    match glib::DateTime::now(&zone) {
+        Ok(now) => {
+            let minute = (now.hour() * 60 + now.minute()) as u16;
+            let next_minute = Duration::from_secs_f64((60.0 - now.seconds()).max(0.1));
+            (schedule.contains(minute), next_minute)
+        }
+        Err(e) => {
+            log::warn!("Could not read the time for the schedule: {e}");
+            (true, Duration::from_secs(60))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::UseCounter;
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+    use tokio::{sync::broadcast::channel as broadcast, time::Instant};
+
+    /// Stands in for a camera, driven by the senders of [`MockEvents`]
+    struct MockCamera {
+        motion: WatchReceiver<MdState>,
+        push: WatchReceiver<Option<PushNoti>>,
+        force: WatchReceiver<Option<ForcePause>>,
+    }
+
+    struct MockEvents {
+        motion: WatchSender<MdState>,
+        push: WatchSender<Option<PushNoti>>,
+        force: WatchSender<Option<ForcePause>>,
+    }
+
+    impl MockEvents {
+        fn push(&self, id: &str) {
+            self.push.send_replace(Some(PushNoti {
+                message: "Motion".to_string(),
+                id: Some(id.to_string()),
+            }));
+        }
+    }
+
+    fn mock_camera() -> (MockCamera, MockEvents) {
+        let (motion_tx, motion) = watch(MdState::Unknown);
+        let (push_tx, push) = watch(None);
+        let (force_tx, force) = watch(None);
+        (
+            MockCamera {
+                motion,
+                push,
+                force,
+            },
+            MockEvents {
+                motion: motion_tx,
+                push: push_tx,
+                force: force_tx,
+            },
+        )
+    }
+
+    impl PauseCamera for MockCamera {
+        fn motion(&self) -> BoxFuture<'_, AnyResult<WatchReceiver<MdState>>> {
+            Box::pin(async move { Ok(self.motion.clone()) })
+        }
+
+        fn push_notifications(&self) -> BoxFuture<'_, AnyResult<WatchReceiver<Option<PushNoti>>>> {
+            Box::pin(async move { Ok(self.push.clone()) })
+        }
+
+        fn force_pause(&self) -> BoxFuture<'_, AnyResult<WatchReceiver<Option<ForcePause>>>> {
+            Box::pin(async move { Ok(self.force.clone()) })
+        }
+    }
+
+    fn pause(config: &str) -> PauseConfig {
+        toml::from_str(config).unwrap()
+    }
+
+    /// Whether it streams with (no activity, motion, client, motion and client)
+    fn streams(pause: &PauseConfig) -> [bool; 4] {
+        [(false, false), (true, false), (false, true), (true, true)].map(|(motion, client)| {
+            should_stream(
+                pause,
+                &PauseAffectors {
+                    motion,
+                    push: false,
+                    client,
+                    scheduled: true,
+                    forced: None,
+                },
+            )
+        })
+    }
+
+    #[test]
+    fn test_pause_on_motion() {
+        let pause = pause("on_motion = true\non_client = false");
+        assert_eq!(streams(&pause), [false, true, false, true]);
+        // A push notification counts as motion
+        assert!(should_stream(
+            &pause,
+            &PauseAffectors {
+                motion: false,
+                push: true,
+                client: false,
+                scheduled: true,
+                forced: None,
+            }
+        ));
+    }
+
+    #[test]
+    fn test_pause_on_client() {
+        let pause = pause("on_motion = false\non_client = true");
+        assert_eq!(streams(&pause), [false, false, true, true]);
+    }
+
+    #[test]
+    fn test_pause_require_all() {
+        let pause = pause("on_motion = true\non_client = true");
+        assert_eq!(pause.require, PauseRequire::All);
+        assert_eq!(streams(&pause), [false, false, false, true]);
+    }
+
+    #[test]
+    fn test_pause_require_any() {
+        let pause = pause("on_motion = true\non_client = true\nrequire = \"any\"");
+        assert_eq!(streams(&pause), [false, true, true, true]);
+    }
+
+    #[test]
+    fn test_pause_schedule() {
+        let state = |client, scheduled| PauseAffectors {
+            motion: false,
+            push: false,
+            client,
+            scheduled,
+            forced: None,
+        };
+        let with_client =
+            pause("on_motion = false\non_client = true\n[schedule]\nwindows = [\"07:00-19:00\"]");
+        assert!(should_stream(&with_client, &state(true, true)));
+        assert!(!should_stream(&with_client, &state(false, true)));
+        // Outside of the schedule even with a client
+        assert!(!should_stream(&with_client, &state(true, false)));
+
+        let only_schedule =
+            pause("on_motion = false\non_client = false\n[schedule]\nwindows = [\"07:00-19:00\"]");
+        assert!(should_stream(&only_schedule, &state(false, true)));
+        assert!(!should_stream(&only_schedule, &state(false, false)));
+    }
+
+    #[test]
+    fn test_pause_forced() {
+        let on_motion =
+            pause("on_motion = true\non_client = false\n[schedule]\nwindows = [\"07:00-19:00\"]");
+        let state = |motion, scheduled, forced| PauseAffectors {
+            motion,
+            push: false,
+            client: false,
+            scheduled,
+            forced,
+        };
+        assert!(!should_stream(
+            &on_motion,
+            &state(true, true, Some(ForcePause::Pause))
+        ));
+        // Even without motion and outside of the schedule
+        assert!(should_stream(
+            &on_motion,
+            &state(false, false, Some(ForcePause::Resume))
+        ));
+        assert!(should_stream(&on_motion, &state(true, true, None)));
+        assert!(!should_stream(&on_motion, &state(false, true, None)));
+        // No pause config streams unless forced
+        let never = pause("on_motion = false\non_client = false");
+        assert!(should_stream(&never, &state(false, true, None)));
+        assert!(!should_stream(
+            &never,
+            &state(false, true, Some(ForcePause::Pause))
+        ));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_pause_main_motion() {
+        let (camera, events) = mock_camera();
+        let clients = UseCounter::new().await;
+        let (paused_tx, paused) = watch(false);
+        let pause = pause("on_motion = true\non_client = false\ntimeout = 5.0");
+
+        let clients = clients.create_deactivated().await.unwrap();
+        tokio::select! {
+            v = pause_main("Mock", &pause, &camera, clients, paused_tx) => panic!("Ended with {v:?}"),
+            _ = async {
+                sleep(Duration::from_secs(1)).await;
+                assert!(*paused.borrow());
+
+                events.motion.send_replace(MdState::Start(Instant::now()));
+                sleep(Duration::from_secs(1)).await;
+                assert!(!*paused.borrow());
+
+                // Keeps streaming for the timeout after the motion stops, even
+                // though the camera sends nothing more
+                events.motion.send_replace(MdState::Stop(Instant::now()));
+                sleep(Duration::from_secs(4)).await;
+                assert!(!*paused.borrow());
+                sleep(Duration::from_secs(2)).await;
+                assert!(*paused.borrow());
+
+                // Motion within the timeout carries on streaming
+                events.motion.send_replace(MdState::Start(Instant::now()));
+                sleep(Duration::from_secs(1)).await;
+                events.motion.send_replace(MdState::Stop(Instant::now()));
+                sleep(Duration::from_secs(3)).await;
+                events.motion.send_replace(MdState::Start(Instant::now()));
+                sleep(Duration::from_secs(3)).await;
+                assert!(!*paused.borrow());
+            } => {},
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_pause_main_push() {
+        let (camera, events) = mock_camera();
+        let clients = UseCounter::new().await;
+        let (paused_tx, paused) = watch(true);
+        let pause = pause("on_motion = true\non_client = false");
+
+        let clients = clients.create_deactivated().await.unwrap();
+        tokio::select! {
+            v = pause_main("Mock", &pause, &camera, clients, paused_tx) => panic!("Ended with {v:?}"),
+            _ = async {
+                sleep(Duration::from_secs(1)).await;
+                assert!(*paused.borrow());
+                events.push("1");
+                sleep(Duration::from_secs(20)).await;
+                assert!(!*paused.borrow());
+                // Another notification starts the wait again
+                events.push("2");
+                sleep(Duration::from_secs(20)).await;
+                assert!(!*paused.borrow());
+                sleep(Duration::from_secs(11)).await;
+                assert!(*paused.borrow());
+            } => {},
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_pause_main_clients_and_force() {
+        let (camera, events) = mock_camera();
+        let clients = UseCounter::new().await;
+        let (paused_tx, paused) = watch(false);
+        let pause = pause("on_motion = false\non_client = true");
+
+        let permit = clients.create_deactivated().await.unwrap();
+        tokio::select! {
+            v = pause_main("Mock", &pause, &camera, permit, paused_tx) => panic!("Ended with {v:?}"),
+            _ = async {
+                sleep(Duration::from_secs(1)).await;
+                assert!(*paused.borrow());
+
+                let client = clients.create_activated().await.unwrap();
+                sleep(Duration::from_secs(1)).await;
+                assert!(!*paused.borrow());
+
+                // A forced pause wins over the client until it is cleared
+                events.force.send_replace(Some(ForcePause::Pause));
+                sleep(Duration::from_secs(1)).await;
+                assert!(*paused.borrow());
+                events.force.send_replace(None);
+                sleep(Duration::from_secs(1)).await;
+                assert!(!*paused.borrow());
+
+                drop(client);
+                sleep(Duration::from_secs(1)).await;
+                assert!(*paused.borrow());
+
+                events.force.send_replace(Some(ForcePause::Resume));
+                sleep(Duration::from_secs(1)).await;
+                assert!(!*paused.borrow());
+            } => {},
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_pause_main_setup_without_play() {
+        let (camera, _events) = mock_camera();
+        let clients = UseCounter::new().await;
+        let (paused_tx, paused) = watch(false);
+        let pause = pause("on_motion = false\non_client = true");
+        let sessions = Arc::new(AtomicUsize::new(0));
+
+        let permit = clients.create_deactivated().await.unwrap();
+        let session_permit = clients.create_deactivated().await.unwrap();
+        let thread_sessions = sessions.clone();
+        let counter = clients.create_deactivated().await.unwrap().get_counter();
+        tokio::select! {
+            v = pause_main("Mock", &pause, &camera, permit, paused_tx) => panic!("Ended with {v:?}"),
+            v = count_sessions(move || thread_sessions.load(Ordering::SeqCst), session_permit, Duration::from_millis(100)) => panic!("Ended with {v:?}"),
+            _ = async {
+                sleep(Duration::from_secs(1)).await;
+                assert!(*paused.borrow());
+
+                // SETUP without a PLAY, no media is fed yet
+                sessions.store(1, Ordering::SeqCst);
+                sleep(Duration::from_secs(1)).await;
+                assert!(!*paused.borrow());
+                assert_eq!(*counter.borrow(), 1);
+
+                // PLAY, the fed media takes over the count of the client
+                let media = clients.create_activated().await.unwrap();
+                sleep(Duration::from_secs(1)).await;
+                assert!(!*paused.borrow());
+                assert_eq!(*counter.borrow(), 1);
+
+                // TEARDOWN
+                sessions.store(0, Ordering::SeqCst);
+                drop(media);
+                sleep(Duration::from_secs(1)).await;
+                assert!(*paused.borrow());
+                assert_eq!(*counter.borrow(), 0);
+            } => {},
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_pause_main_disconnect() {
+        let (camera, events) = mock_camera();
+        let clients = UseCounter::new().await;
+        let (paused_tx, _paused) = watch(false);
+        let pause = pause("on_motion = true\non_client = false");
+
+        // The camera going away ends it with an error for the stream to handle
+        let clients = clients.create_deactivated().await.unwrap();
+        let result = tokio::select! {
+            v = pause_main("Mock", &pause, &camera, clients, paused_tx) => v,
+            _ = async {
+                sleep(Duration::from_secs(1)).await;
+                drop(events.motion);
+                sleep(Duration::from_secs(1)).await;
+            } => panic!("Still running without the camera"),
+        };
+        assert!(result.is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_align_pause() {
+        let frame = |keyframe| StampedData {
+            keyframe,
+            data: Arc::new(vec![]),
+            ts: Duration::ZERO,
+        };
+        let (wanted_tx, wanted) = watch(false);
+        let (paused_tx, paused) = watch(false);
+        let (vid_tx, vid) = broadcast(10);
+        tokio::select! {
+            v = align_pause(wanted, paused_tx, vid, Duration::from_secs(5)) => panic!("Ended with {v:?}"),
+            _ = async {
+                // Waits for the keyframe
+                wanted_tx.send_replace(true);
+                sleep(Duration::from_secs(1)).await;
+                vid_tx.send(frame(false)).unwrap();
+                sleep(Duration::from_secs(1)).await;
+                assert!(!*paused.borrow());
+                vid_tx.send(frame(true)).unwrap();
+                sleep(Duration::from_millis(10)).await;
+                assert!(*paused.borrow());
+
+                // Resumes at once
+                wanted_tx.send_replace(false);
+                sleep(Duration::from_millis(10)).await;
+                assert!(!*paused.borrow());
+
+                // Not paused if it is wanted again before the keyframe
+                wanted_tx.send_replace(true);
+                sleep(Duration::from_secs(1)).await;
+                wanted_tx.send_replace(false);
+                vid_tx.send(frame(true)).unwrap();
+                sleep(Duration::from_millis(10)).await;
+                assert!(!*paused.borrow());
+
+                // Paused anyway without a keyframe
+                wanted_tx.send_replace(true);
+                sleep(Duration::from_secs(4)).await;
+                assert!(!*paused.borrow());
+                sleep(Duration::from_secs(2)).await;
+                assert!(*paused.borrow());
+            } => {},
+        }
+    }
+}
@@ -0,0 +1,119 @@
+//! Pushes a camera stream to an external RTMP or SRT ingest
+//!
+//! The video is remuxed (not re-encoded) so RTMP requires an H264 camera
+use anyhow::{anyhow, Context};
+use gstreamer::{prelude::*, Buffer, FlowError, MessageView, Pipeline, State};
+use gstreamer_app::AppSrc;
+use tokio::{
+    sync::{broadcast::Receiver as BroadcastReceiver, watch::Receiver as WatchReceiver},
+    time::{sleep, Instant},
+};
+use tokio_stream::{wrappers::BroadcastStream, StreamExt};
+
+use super::{stream::output_backoff, AnyResult};
+use crate::{
+    common::{StampedData, VidFormat},
+    config::{PushConfig, PushProtocol},
+};
+
+/// Keeps pushing the stream for as long as it is not paused
+///
+/// The push is not counted as a client, so a stream with no local viewers is
+/// still paused. When the server at the url drops the push it is connected to
+/// again after a while
+pub(super) async fn push_main(
+    name: &str,
+    push: &PushConfig,
+    vid_format: VidFormat,
+    vid: &BroadcastReceiver<StampedData>,
+    mut paused: WatchReceiver<bool>,
+) -> AnyResult<()> {
+    let mut backoff = output_backoff();
+    loop {
+        paused.wait_for(|paused| !*paused).await?;
+        log::info!("{name}: Pushing to {}", push.url);
+        let started = Instant::now();
+        let result = tokio::select! {
+            v = paused.wait_for(|paused| *paused) => {
+                v?;
+                log::info!("{name}: Stream paused, stopped pushing");
+                Ok(())
+            },
+            v = push_run(push, &vid_format, vid.resubscribe()) => v,
+        };
+
+        backoff.reset_after(started.elapsed());
+        if let Err(e) = result {
+            log::warn!("{name}: Pushing to {} failed: {e:?}", push.url);
+            let delay = backoff.next_delay();
+            log::info!("{name}: Retrying the push in {:?}", delay);
+            sleep(delay).await;
+        }
+    }
+}
+
+async fn push_run(
+    push: &PushConfig,
+    vid_format: &VidFormat,
+    vid: BroadcastReceiver<StampedData>,
+) -> AnyResult<()> {
+    let (parser, caps) = match vid_format {
+        VidFormat::H264 => ("h264parse", "video/x-h264"),
+        VidFormat::H265 => ("h265parse", "video/x-h265"),
+        VidFormat::None => return Err(anyhow!("Stream format is not known yet")),
+    };
+    let (muxer, sink, property) = match push.protocol {
+        PushProtocol::Rtmp if matches!(vid_format, VidFormat::H265) => {
+            return Err(anyhow!("RTMP can only push H264 streams, try SRT instead"));
+        }
+        PushProtocol::Rtmp => ("flvmux streamable=true", "rtmpsink", "location"),
+        PushProtocol::Srt => ("mpegtsmux", "srtsink", "uri"),
+    };
+    let desc = format!(
+        "appsrc name=src is-live=true do-timestamp=true format=time caps={caps},stream-format=byte-stream ! {parser} config-interval=-1 ! {muxer} ! {sink} name=sink"
+    );
+    let pipeline = gstreamer::parse_launch(&desc)
+        .with_context(|| format!("Could not build the {sink} pipeline"))?
+        .dynamic_cast::<Pipeline>()
+        .map_err(|_| anyhow!("Push pipeline should be a pipeline"))?;
+    pipeline
+        .by_name("sink")
+        .ok_or(anyhow!("Push pipeline lacks a sink"))?
+        .set_property(property, &push.url);
+    let appsrc = pipeline
+        .by_name("src")
+        .ok_or(anyhow!("Push pipeline lacks a source"))?
+        .dynamic_cast::<AppSrc>()
+        .map_err(|_| anyhow!("Cannot cast to appsrc"))?;
+    let bus = pipeline.bus().ok_or(anyhow!("Push pipeline lacks a bus"))?;
+
+    pipeline.set_state(State::Playing)?;
+    let result = async {
+        let mut frames = BroadcastStream::new(vid).filter_map(|frame| frame.ok());
+        let mut found_key = false;
+        while let Some(frame) = frames.next().await {
+            // Muxers need to start on a keyframe
+            found_key |= frame.keyframe;
+            if !found_key {
+                continue;
+            }
+            match appsrc.push_buffer(Buffer::from_slice(frame.data.to_vec())) {
+                Ok(_) | Err(FlowError::Flushing) => {}
+                Err(e) => return Err(anyhow!("Error pushing to the pipeline: {e:?}")),
+            }
+            while let Some(msg) = bus.pop() {
+                match msg.view() {
+                    MessageView::Error(err) => {
+                        return Err(anyhow!("{}", err.error()));
+                    }
+                    MessageView::Eos(_) => return Err(anyhow!("The push target ended")),
+                    _ => {}
+                }
+            }
+        }
+        Err(anyhow!("The camera stream ended"))
+    }
+    .await;
+    let _ = pipeline.set_state(State::Null);
+    result
+}
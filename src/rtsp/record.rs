@@ -0,0 +1,487 @@
+//! Records a camera stream to rotating files on disk
+//!
+//! The video is remuxed (not re-encoded) by a `splitmuxsink` which starts a new
+//! file every `segment_minutes`. Files are named after the local time they were
+//! started at and are deleted once they are older than `retention_days`
+//!
+//! In motion mode a file is started when motion starts, beginning with the
+//! buffered frames of the last `pre_seconds`, and finished once there has been
+//! no motion for `post_seconds`
+use anyhow::{anyhow, Context};
+use gstreamer::{
+    glib, prelude::*, Buffer, ClockTime, ElementFactory, FlowError, MessageType, MessageView,
+    Pipeline, ResourceError, State,
+};
+use gstreamer_app::AppSrc;
+use std::{
+    collections::VecDeque,
+    fs,
+    path::{Path, PathBuf},
+};
+use tokio::{
+    sync::{
+        broadcast::Receiver as BroadcastReceiver,
+        watch::{channel as watch, Receiver as WatchReceiver, Sender as WatchSender},
+    },
+    task::spawn_blocking,
+    time::{interval, sleep, Duration, Instant},
+};
+use tokio_stream::{wrappers::BroadcastStream, StreamExt};
+
+use super::{stream::output_backoff, AnyResult};
+use crate::{
+    common::{MdState, StampedData, VidFormat},
+    config::{RecordConfig, RecordFormat, RecordMode},
+};
+
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+/// Used in place of the real gap when the camera timestamps jump
+const FRAME_GAP: Duration = Duration::from_millis(40);
+/// A bigger gap between frames is taken to be a jump of the camera clock
+const MAX_FRAME_GAP: Duration = Duration::from_secs(2);
+
+/// How a recording ended
+enum Stopped {
+    /// The stream was paused or, in motion mode, the motion ended
+    Idle,
+    /// The disk is full, recording is given up on
+    DiskFull,
+}
+
+/// Keeps recording the stream for as long as it is not paused
+///
+/// The recording is not counted as a client so it never keeps a paused stream awake.
+/// A failed recording is started again in a new file after a while
+///
+/// `motion` is only used in motion mode
+#[allow(clippy::too_many_arguments)]
+pub(super) async fn record_main(
+    name: &str,
+    stream: &str,
+    record: &RecordConfig,
+    vid_format: VidFormat,
+    vid: &BroadcastReceiver<StampedData>,
+    history: &WatchReceiver<VecDeque<StampedData>>,
+    motion: Option<WatchReceiver<MdState>>,
+    paused: WatchReceiver<bool>,
+) -> AnyResult<()> {
+    let directory = record.directory.join(name);
+    fs::create_dir_all(&directory)
+        .with_context(|| format!("Could not create the recording directory {:?}", directory))?;
+
+    let (active_tx, active) = watch(record.mode == RecordMode::Continuous);
+    let watch_motion = async {
+        match (record.mode, motion) {
+            (RecordMode::Motion, Some(motion)) => {
+                let post = Duration::from_secs(record.post_seconds);
+                motion_main(name, motion, post, active_tx).await
+            }
+            (RecordMode::Motion, None) => Err(anyhow!("Motion recording needs the motion")),
+            (RecordMode::Continuous, _) => {
+                let _active_tx = active_tx;
+                futures::future::pending().await
+            }
+        }
+    };
+    let pre = match record.mode {
+        RecordMode::Motion => Some(Duration::from_secs(record.pre_seconds)),
+        RecordMode::Continuous => None,
+    };
+    let (stopped_tx, stopped) = watch(true);
+
+    tokio::select! {
+        v = prune_main(name, &directory, record.retention_days) => v,
+        v = watch_motion => v,
+        v = gate(paused, active, stopped_tx) => v,
+        v = record_loop(name, stream, &directory, record, vid_format, vid, history, pre, stopped) => v,
+    }
+}
+
+/// Keeps `active_tx` true from the start of motion until `post` after it stops
+///
+/// Motion that starts again within `post` keeps it true so that overlapping
+/// events extend the current file rather than starting a new one
+async fn motion_main(
+    name: &str,
+    mut motion: WatchReceiver<MdState>,
+    post: Duration,
+    active_tx: WatchSender<bool>,
+) -> AnyResult<()> {
+    loop {
+        motion
+            .wait_for(|md| matches!(md, MdState::Start(_)))
+            .await?;
+        log::info!("{name}: Motion started, recording");
+        active_tx.send_replace(true);
+        loop {
+            motion.wait_for(|md| matches!(md, MdState::Stop(_))).await?;
+            tokio::select! {
+                v = motion.wait_for(|md| matches!(md, MdState::Start(_))) => {
+                    v?;
+                }
+                _ = sleep(post) => break,
+            }
+        }
+        log::info!("{name}: No motion for {:?}, finishing the recording", post);
+        active_tx.send_replace(false);
+    }
+}
+
+/// Keeps `stopped_tx` true while the stream is paused or inactive
+async fn gate(
+    mut paused: WatchReceiver<bool>,
+    mut active: WatchReceiver<bool>,
+    stopped_tx: WatchSender<bool>,
+) -> AnyResult<()> {
+    loop {
+        let stopped = *paused.borrow_and_update() || !*active.borrow_and_update();
+        stopped_tx.send_if_modified(|current| std::mem::replace(current, stopped) != stopped);
+        tokio::select! {
+            v = paused.changed() => v?,
+            v = active.changed() => v?,
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn record_loop(
+    name: &str,
+    stream: &str,
+    directory: &Path,
+    record: &RecordConfig,
+    vid_format: VidFormat,
+    vid: &BroadcastReceiver<StampedData>,
+    history: &WatchReceiver<VecDeque<StampedData>>,
+    pre: Option<Duration>,
+    mut stopped: WatchReceiver<bool>,
+) -> AnyResult<()> {
+    let mut backoff = output_backoff();
+    loop {
+        stopped.wait_for(|stopped| !*stopped).await?;
+        log::info!("{name}: Recording to {:?}", directory);
+        let started = Instant::now();
+        let result = record_run(
+            stream,
+            directory,
+            record,
+            &vid_format,
+            vid.resubscribe(),
+            history,
+            pre,
+            stopped.clone(),
+        )
+        .await;
+
+        backoff.reset_after(started.elapsed());
+        match result {
+            Ok(Stopped::Idle) => log::info!("{name}: Stopped recording"),
+            Ok(Stopped::DiskFull) => {
+                log::warn!(
+                    "{name}: The disk of {:?} is full, stopped recording",
+                    directory
+                );
+                // Old files are still pruned, recording starts again with the stream
+                futures::future::pending::<()>().await;
+            }
+            Err(e) => {
+                log::warn!("{name}: Recording failed: {e:?}");
+                let delay = backoff.next_delay();
+                log::info!("{name}: Retrying the recording in {:?}", delay);
+                sleep(delay).await;
+            }
+        }
+    }
+}
+
+/// The recording pipeline, the current file is finished when this is dropped
+struct Recording {
+    pipeline: Pipeline,
+    appsrc: AppSrc,
+}
+
+impl Drop for Recording {
+    fn drop(&mut self) {
+        // Without the EOS the muxer never writes the index and mp4 files cannot be played
+        let _ = self.appsrc.end_of_stream();
+        if let Some(bus) = self.pipeline.bus() {
+            let _ = bus.timed_pop_filtered(
+                ClockTime::from_seconds(5),
+                &[MessageType::Eos, MessageType::Error],
+            );
+        }
+        let _ = self.pipeline.set_state(State::Null);
+    }
+}
+
+/// Turns the camera timestamps into buffer timestamps that start at zero and never go back
+///
+/// The buffered frames of the pre-roll are pushed all at once so the time they
+/// are pushed at cannot be used
+#[derive(Default)]
+pub(super) struct Stamper {
+    /// The camera timestamp and buffer timestamp of the last frame
+    last: Option<(Duration, Duration)>,
+}
+
+impl Stamper {
+    pub(super) fn stamp(&mut self, ts: Duration) -> Duration {
+        let pts = match self.last {
+            None => Duration::ZERO,
+            Some((last_ts, last_pts)) if ts > last_ts && ts - last_ts < MAX_FRAME_GAP => {
+                last_pts + (ts - last_ts)
+            }
+            // The camera clock jumped, carry on a frame after the last one
+            Some((_, last_pts)) => last_pts + FRAME_GAP,
+        };
+        self.last = Some((ts, pts));
+        pts
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn record_run(
+    stream: &str,
+    directory: &Path,
+    record: &RecordConfig,
+    vid_format: &VidFormat,
+    vid: BroadcastReceiver<StampedData>,
+    history: &WatchReceiver<VecDeque<StampedData>>,
+    pre: Option<Duration>,
+    mut stopped: WatchReceiver<bool>,
+) -> AnyResult<Stopped> {
+    let (parser, caps) = match vid_format {
+        VidFormat::H264 => ("h264parse", "video/x-h264"),
+        VidFormat::H265 => ("h265parse", "video/x-h265"),
+        VidFormat::None => return Err(anyhow!("Stream format is not known yet")),
+    };
+    let muxer = match record.format {
+        RecordFormat::Mp4 => "mp4mux",
+        RecordFormat::Mkv => "matroskamux",
+    };
+    let desc = format!(
+        "appsrc name=src is-live=true do-timestamp=false format=time caps={caps},stream-format=byte-stream ! {parser} config-interval=-1 ! splitmuxsink name=sink"
+    );
+    let pipeline = gstreamer::parse_launch(&desc)
+        .context("Could not build the recording pipeline")?
+        .dynamic_cast::<Pipeline>()
+        .map_err(|_| anyhow!("Recording pipeline should be a pipeline"))?;
+    let sink = pipeline
+        .by_name("sink")
+        .ok_or(anyhow!("Recording pipeline lacks a sink"))?;
+    let muxer = ElementFactory::make(muxer)
+        .build()
+        .with_context(|| format!("Missing the gstreamer `{muxer}` element"))?;
+    sink.set_property("muxer", &muxer);
+    sink.set_property(
+        "max-size-time",
+        record.segment_minutes * 60 * ClockTime::SECOND.nseconds(),
+    );
+    let directory = directory.to_owned();
+    let stream = stream.to_owned();
+    let extension = record.format.extension();
+    sink.connect("format-location", false, move |_| {
+        let path = segment_path(&directory, &stream, extension);
+        log::debug!("Recording to {:?}", path);
+        Some(path.to_string_lossy().to_string().to_value())
+    });
+    let appsrc = pipeline
+        .by_name("src")
+        .ok_or(anyhow!("Recording pipeline lacks a source"))?
+        .dynamic_cast::<AppSrc>()
+        .map_err(|_| anyhow!("Cannot cast to appsrc"))?;
+    let bus = pipeline
+        .bus()
+        .ok_or(anyhow!("Recording pipeline lacks a bus"))?;
+
+    // The live frames were subscribed to before the history is read so none
+    // are missed, those that are also in the pre-roll are skipped
+    let pre_roll = pre
+        .map(|pre| pre_roll(&history.borrow(), pre))
+        .unwrap_or_default();
+    let pre_roll_end = pre_roll.last().map(|frame| frame.ts);
+    let live = BroadcastStream::new(vid)
+        .filter_map(|frame| frame.ok())
+        .skip_while(move |frame| pre_roll_end.is_some_and(|end| frame.ts <= end));
+    let mut frames = Box::pin(tokio_stream::iter(pre_roll).chain(live));
+
+    pipeline.set_state(State::Playing)?;
+    let recording = Recording { pipeline, appsrc };
+    let result = async {
+        let mut stamper = Stamper::default();
+        let mut found_key = false;
+        loop {
+            let frame = tokio::select! {
+                v = stopped.wait_for(|stopped| *stopped) => {
+                    v?;
+                    return Ok(Stopped::Idle);
+                },
+                v = frames.next() => v.ok_or(anyhow!("The camera stream ended"))?,
+            };
+            // Files need to start on a keyframe
+            found_key |= frame.keyframe;
+            if !found_key {
+                continue;
+            }
+            let mut buffer = Buffer::from_slice(frame.data.to_vec());
+            let pts = stamper.stamp(frame.ts);
+            buffer
+                .make_mut()
+                .set_pts(ClockTime::from_nseconds(pts.as_nanos() as u64));
+            match recording.appsrc.push_buffer(buffer) {
+                Ok(_) | Err(FlowError::Flushing) => {}
+                Err(e) => return Err(anyhow!("Error pushing to the pipeline: {e:?}")),
+            }
+            while let Some(msg) = bus.pop() {
+                if let MessageView::Error(err) = msg.view() {
+                    if err.error().matches(ResourceError::NoSpaceLeft) {
+                        return Ok(Stopped::DiskFull);
+                    }
+                    return Err(anyhow!("{}", err.error()));
+                }
+            }
+        }
+    }
+    .await;
+    drop(recording);
+    result
+}
+
+/// The buffered frames from the last keyframe at least `pre` before the newest frame
+///
+/// If the history is shorter than that it starts at its oldest keyframe
+fn pre_roll(history: &VecDeque<StampedData>, pre: Duration) -> Vec<StampedData> {
+    let newest = match history.back() {
+        Some(frame) => frame.ts,
+        None => return vec![],
+    };
+    let start = history
+        .iter()
+        .rposition(|frame| frame.keyframe && newest.saturating_sub(frame.ts) >= pre)
+        .or_else(|| history.iter().position(|frame| frame.keyframe));
+    match start {
+        Some(start) => history.iter().skip(start).cloned().collect(),
+        None => vec![],
+    }
+}
+
+/// The file that a new segment is written to
+fn segment_path(directory: &Path, stream: &str, extension: &str) -> PathBuf {
+    let stamp = glib::DateTime::now_local()
+        .and_then(|now| now.format("%Y%m%d-%H%M%S"))
+        .map(|stamp| stamp.to_string())
+        .unwrap_or_else(|_| "unknown".to_string());
+    let mut path = directory.join(format!("{stream}_{stamp}.{extension}"));
+    // Segments that start within the same second must not overwrite each other
+    let mut n = 1;
+    while path.exists() {
+        n += 1;
+        path = directory.join(format!("{stream}_{stamp}_{n}.{extension}"));
+    }
+    path
+}
+
+/// Deletes old recordings every hour
+async fn prune_main(name: &str, directory: &Path, retention_days: u64) -> AnyResult<()> {
+    if retention_days == 0 {
+        futures::future::pending::<()>().await;
+    }
+    let retention = Duration::from_secs(retention_days * 24 * 60 * 60);
+    let mut ticker = interval(PRUNE_INTERVAL);
+    loop {
+        ticker.tick().await;
+        let thread_directory = directory.to_owned();
+        match spawn_blocking(move || prune(&thread_directory, retention)).await? {
+            Ok(0) => {}
+            Ok(removed) => log::info!("{name}: Deleted {removed} old recordings"),
+            Err(e) => log::warn!("{name}: Could not delete old recordings: {e:?}"),
+        }
+    }
+}
+
+/// Deletes the recordings in the directory last written more than `retention` ago
+fn prune(directory: &Path, retention: Duration) -> AnyResult<usize> {
+    let mut removed = 0;
+    for entry in fs::read_dir(directory)? {
+        let path = entry?.path();
+        let is_recording = path
+            .extension()
+            .is_some_and(|ext| ext == "mp4" || ext == "mkv");
+        if !is_recording {
+            continue;
+        }
+        let age = fs::metadata(&path)?
+            .modified()?
+            .elapsed()
+            .unwrap_or_default();
+        if age >= retention {
+            fs::remove_file(&path).with_context(|| format!("Could not delete {:?}", path))?;
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prune() -> AnyResult<()> {
+        let directory = std::env::temp_dir().join(format!("neolink_prune_{}", std::process::id()));
+        fs::create_dir_all(&directory)?;
+        let recording = directory.join("mainStream_20240101-000000.mp4");
+        let other = directory.join("notes.txt");
+        fs::write(&recording, b"")?;
+        fs::write(&other, b"")?;
+
+        // Nothing is old enough yet
+        assert_eq!(prune(&directory, Duration::from_secs(60))?, 0);
+        assert!(recording.exists());
+
+        // Only recordings are deleted
+        assert_eq!(prune(&directory, Duration::ZERO)?, 1);
+        assert!(!recording.exists());
+        assert!(other.exists());
+
+        fs::remove_dir_all(&directory)?;
+        Ok(())
+    }
+
+    fn frame(ts_ms: u64, keyframe: bool) -> StampedData {
+        StampedData {
+            keyframe,
+            data: Default::default(),
+            ts: Duration::from_millis(ts_ms),
+        }
+    }
+
+    #[test]
+    fn test_pre_roll() {
+        let history = (0..10)
+            .map(|n| frame(n * 1000, n % 4 == 0))
+            .collect::<VecDeque<_>>();
+        let starts = |pre| {
+            pre_roll(&history, Duration::from_secs(pre))
+                .first()
+                .map(|f| f.ts)
+        };
+        // The last keyframe at least 5s before the newest at 9s
+        assert_eq!(starts(5), Some(Duration::from_secs(4)));
+        assert_eq!(starts(1), Some(Duration::from_secs(8)));
+        // Longer than the history starts at the oldest keyframe
+        assert_eq!(starts(60), Some(Duration::from_secs(0)));
+        assert!(pre_roll(&VecDeque::new(), Duration::from_secs(5)).is_empty());
+    }
+
+    #[test]
+    fn test_stamper() {
+        let mut stamper = Stamper::default();
+        let ms = Duration::from_millis;
+        assert_eq!(stamper.stamp(ms(5000)), ms(0));
+        assert_eq!(stamper.stamp(ms(5040)), ms(40));
+        // A jump back or far forward of the camera clock carries on
+        assert_eq!(stamper.stamp(ms(100)), ms(80));
+        assert_eq!(stamper.stamp(ms(140)), ms(120));
+        assert_eq!(stamper.stamp(ms(60000)), ms(160));
+    }
+}
@@ -0,0 +1,164 @@
+//! Renders the SDP of a media from the caps of its payloaders
+//!
+//! This follows what gst-rtsp-server sends in reply to a DESCRIBE so that
+//! tools can be set up from `GET /{path}.sdp` on the status server without
+//! starting an rtsp session
+use gstreamer::Caps;
+
+/// Fields of the rtp caps that are not part of the fmtp line
+const NOT_FMTP: &[&str] = &[
+    "media",
+    "payload",
+    "clock-rate",
+    "encoding-name",
+    "encoding-params",
+    "ssrc",
+    "timestamp-offset",
+    "seqnum-offset",
+    "clock-base",
+    "seqnum-base",
+];
+
+/// The fields of rtp caps as text in the order of the caps
+pub(super) fn caps_fields(caps: &Caps) -> Option<Vec<(String, String)>> {
+    let structure = caps.structure(0)?;
+    Some(
+        structure
+            .iter()
+            .filter_map(|(name, value)| {
+                value
+                    .get::<String>()
+                    .or_else(|_| value.get::<i32>().map(|value| value.to_string()))
+                    .or_else(|_| value.get::<u32>().map(|value| value.to_string()))
+                    .ok()
+                    .map(|value| (name.to_string(), value))
+            })
+            .collect(),
+    )
+}
+
+/// The session description of the streams, one set of caps fields for each
+///
+/// Streams whose caps lack a media, payload or encoding are left out
+pub(super) fn make_sdp(streams: &[Vec<(String, String)>]) -> String {
+    let mut sdp = [
+        "v=0",
+        "o=- 0 1 IN IP4 0.0.0.0",
+        "s=Session streamed by neolink",
+        "c=IN IP4 0.0.0.0",
+        "t=0 0",
+        "a=tool:neolink",
+        "a=type:broadcast",
+        "a=control:*",
+        "a=range:npt=now-",
+    ]
+    .iter()
+    .map(|line| line.to_string())
+    .collect::<Vec<_>>();
+
+    for (index, fields) in streams.iter().enumerate() {
+        let field = |key: &str| {
+            fields
+                .iter()
+                .find(|(name, _)| name == key)
+                .map(|(_, value)| value.as_str())
+        };
+        let (Some(media), Some(payload), Some(encoding)) =
+            (field("media"), field("payload"), field("encoding-name"))
+        else {
+            continue;
+        };
+        sdp.push(format!("m={media} 0 RTP/AVP {payload}"));
+        let mut rtpmap = format!("a=rtpmap:{payload} {encoding}");
+        if let Some(clock_rate) = field("clock-rate") {
+            rtpmap.push_str(&format!("/{clock_rate}"));
+            if let Some(params) = field("encoding-params") {
+                rtpmap.push_str(&format!("/{params}"));
+            }
+        }
+        sdp.push(rtpmap);
+
+        let fmtp = fields
+            .iter()
+            .filter(|(name, _)| {
+                !NOT_FMTP.contains(&name.as_str())
+                    && !name.starts_with("a-")
+                    && !name.starts_with("x-")
+                    && !name.starts_with("rtcp-fb-")
+                    && !name.starts_with("srtp")
+                    && !name.starts_with("srtcp")
+            })
+            .map(|(name, value)| format!("{name}={value}"))
+            .collect::<Vec<_>>();
+        if !fmtp.is_empty() {
+            sdp.push(format!("a=fmtp:{payload} {}", fmtp.join(";")));
+        }
+        for (name, value) in fields.iter() {
+            if let Some(attribute) = name.strip_prefix("a-") {
+                sdp.push(format!("a={attribute}:{value}"));
+            }
+        }
+        sdp.push(format!("a=control:stream={index}"));
+    }
+
+    let mut sdp = sdp.join("\r\n");
+    sdp.push_str("\r\n");
+    sdp
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields(fields: &[(&str, &str)]) -> Vec<(String, String)> {
+        fields
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_make_sdp() {
+        let video = fields(&[
+            ("media", "video"),
+            ("clock-rate", "90000"),
+            ("encoding-name", "H264"),
+            ("packetization-mode", "1"),
+            ("profile-level-id", "640033"),
+            ("sprop-parameter-sets", "Z2QAM6wVFKAoAPGQ,aO48sA=="),
+            ("a-framerate", "25"),
+            ("payload", "96"),
+            ("ssrc", "1234"),
+            ("seqnum-offset", "5"),
+        ]);
+        let audio = fields(&[
+            ("media", "audio"),
+            ("clock-rate", "16000"),
+            ("encoding-name", "MPEG4-GENERIC"),
+            ("encoding-params", "1"),
+            ("mode", "AAC-hbr"),
+            ("payload", "97"),
+        ]);
+        // Still negotiating
+        let unknown = fields(&[("media", "audio")]);
+
+        let sdp = make_sdp(&[video, audio, unknown]);
+        let media = sdp.split("\r\n").skip(9).collect::<Vec<_>>();
+        assert_eq!(
+            media,
+            vec![
+                "m=video 0 RTP/AVP 96",
+                "a=rtpmap:96 H264/90000",
+                "a=fmtp:96 packetization-mode=1;profile-level-id=640033;sprop-parameter-sets=Z2QAM6wVFKAoAPGQ,aO48sA==",
+                "a=framerate:25",
+                "a=control:stream=0",
+                "m=audio 0 RTP/AVP 97",
+                "a=rtpmap:97 MPEG4-GENERIC/16000/1",
+                "a=fmtp:97 mode=AAC-hbr",
+                "a=control:stream=1",
+                "",
+            ]
+        );
+        assert!(sdp.starts_with("v=0\r\n"));
+    }
+}
@@ -0,0 +1,99 @@
+//! Closes rtsp sessions that have played for longer than `max_session_secs`
+//!
+//! Gstreamer does not keep when a session started so it is taken as the first
+//! time that the session is seen, which is at most a second late
+use std::collections::HashMap;
+use tokio::{
+    sync::watch::Receiver as WatchReceiver,
+    time::{interval, Duration, Instant},
+};
+
+use neolink_core::bc_protocol::StreamKind;
+
+use super::gst::NeoRtspServer;
+use crate::{config::CameraConfig, AnyResult};
+
+/// How often the sessions are checked
+const POLL: Duration = Duration::from_secs(1);
+
+/// When each session was first seen
+#[derive(Default)]
+struct SessionAges {
+    started: HashMap<String, Instant>,
+}
+
+impl SessionAges {
+    /// The sessions that have now played for longer than `max`
+    ///
+    /// Sessions that are no longer playing are forgotten
+    fn update(&mut self, sessions: &[String], now: Instant, max: Duration) -> Vec<String> {
+        self.started.retain(|session, _| sessions.contains(session));
+        sessions
+            .iter()
+            .filter(|session| {
+                let started = *self.started.entry(session.to_string()).or_insert(now);
+                now.duration_since(started) > max
+            })
+            .cloned()
+            .collect()
+    }
+}
+
+/// Closes the sessions of the stream that exceed the `max_session_secs` of
+/// the camera
+pub(super) async fn session_limit_main(
+    name: &str,
+    stream: StreamKind,
+    rtsp: &NeoRtspServer,
+    paths: &[String],
+    config: WatchReceiver<CameraConfig>,
+) -> AnyResult<()> {
+    let mut ages = SessionAges::default();
+    let mut interval = interval(POLL);
+    loop {
+        interval.tick().await;
+        let sessions = rtsp.session_ids(paths);
+        let Some(max_secs) = config.borrow().max_session_secs else {
+            // Ages are still kept so that turning it on counts from the start
+            ages.update(&sessions, Instant::now(), Duration::MAX);
+            continue;
+        };
+        for session in ages.update(&sessions, Instant::now(), Duration::from_secs(max_secs)) {
+            log::info!(
+                "{name}: Rtsp session {session} of the {stream} reached max_session_secs of {max_secs}s, closing it"
+            );
+            rtsp.close_session(&session);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_session_ages() {
+        let max = Duration::from_secs(60);
+        let start = Instant::now();
+        let mut ages = SessionAges::default();
+        let sessions = vec!["a".to_string()];
+        assert!(ages.update(&sessions, start, max).is_empty());
+
+        // b joins later
+        let sessions = vec!["a".to_string(), "b".to_string()];
+        let later = start + Duration::from_secs(30);
+        assert!(ages.update(&sessions, later, max).is_empty());
+        assert!(ages
+            .update(&sessions, start + Duration::from_secs(60), max)
+            .is_empty());
+        let closing = ages.update(&sessions, start + Duration::from_secs(61), max);
+        assert_eq!(closing, vec!["a".to_string()]);
+
+        // a reconnects with a new id, b is still under the limit
+        let sessions = vec!["b".to_string(), "c".to_string()];
+        let now = start + Duration::from_secs(62);
+        assert!(ages.update(&sessions, now, max).is_empty());
+        let closing = ages.update(&sessions, later + Duration::from_secs(61), max);
+        assert_eq!(closing, vec!["b".to_string()]);
+    }
+}
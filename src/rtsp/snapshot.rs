@@ -0,0 +1,180 @@
+//! Jpeg snapshots of the streams over http
+//!
+//! With `http_snapshots = true` each keyframe of the streams of the camera is
+//! decoded to a jpeg, which the status server serves as:
+//!
+//! - `GET /{path}/snapshot.jpg`: The latest jpeg
+//! - `GET /{path}/mjpeg`: A `multipart/x-mixed-replace` stream of the jpegs
+//!   for dashboards that cannot play rtsp
+//!
+//! Both follow the auth of the camera, see [`super::http::authorized`]. The
+//! frame rate of the mjpeg is that of the keyframes, about one every 1-4s
+use anyhow::anyhow;
+use futures::StreamExt;
+use gstreamer::{prelude::*, Buffer};
+use gstreamer_app::AppSrc;
+use hyper::{header, Body, Response, StatusCode};
+use std::{collections::VecDeque, sync::Arc};
+use tokio::sync::watch::Receiver as WatchReceiver;
+use tokio_stream::wrappers::WatchStream;
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    common::{StampedData, VidFormat},
+    config::CameraConfig,
+    AnyResult,
+};
+
+use super::{
+    clip::{build_pipeline, parser_caps, pull_frames},
+    gst::NeoRtspServer,
+    http::response,
+};
+
+/// Separates the jpegs of the mjpeg stream
+const BOUNDARY: &str = "neolinkframe";
+
+/// Decodes each new keyframe of the stream to the jpeg of its `paths` while
+/// the camera has `http_snapshots`
+pub(super) async fn snapshot_main(
+    name: &str,
+    rtsp: &NeoRtspServer,
+    paths: &[String],
+    vid_format: VidFormat,
+    mut history: WatchReceiver<VecDeque<StampedData>>,
+    config: WatchReceiver<CameraConfig>,
+) -> AnyResult<()> {
+    let mut last_keyframe = None;
+    let mut failing = false;
+    loop {
+        history.changed().await?;
+        if !config.borrow().http_snapshots {
+            continue;
+        }
+        let Some(keyframe) = history
+            .borrow_and_update()
+            .iter()
+            .rev()
+            .find(|frame| frame.keyframe)
+            .cloned()
+        else {
+            continue;
+        };
+        if last_keyframe == Some(keyframe.ts) {
+            continue;
+        }
+        last_keyframe = Some(keyframe.ts);
+        match encode_jpeg(keyframe, &vid_format).await {
+            Ok(jpeg) => {
+                failing = false;
+                rtsp.set_snapshot(paths, Arc::new(jpeg));
+            }
+            Err(e) => {
+                // Only once until it works again
+                if !failing {
+                    log::warn!(
+                        "{name}: Could not decode the keyframe for the http snapshot: {e:?}"
+                    );
+                }
+                failing = true;
+            }
+        }
+    }
+}
+
+/// Decodes the keyframe to a jpeg as the `image` subcommand does
+async fn encode_jpeg(keyframe: StampedData, vid_format: &VidFormat) -> AnyResult<Vec<u8>> {
+    let (parser, caps) = parser_caps(vid_format)?;
+    let desc = format!(
+        "appsrc name=src caps={caps},stream-format=byte-stream ! {parser} ! decodebin ! videoconvert ! jpegenc snapshot=true ! appsink name=sink sync=false"
+    );
+    tokio::task::spawn_blocking(move || {
+        let pipeline = build_pipeline(&desc)?;
+        let src = pipeline
+            .by_name("src")
+            .ok_or(anyhow!("Snapshot pipeline lacks a source"))?
+            .dynamic_cast::<AppSrc>()
+            .map_err(|_| anyhow!("Cannot cast to appsrc"))?;
+        src.push_buffer(Buffer::from_slice(keyframe.data.to_vec()))?;
+        src.end_of_stream()?;
+        let jpeg = pull_frames(&pipeline)?
+            .into_iter()
+            .next()
+            .ok_or(anyhow!("Snapshot pipeline made no jpeg"))?;
+        AnyResult::Ok(jpeg.data.to_vec())
+    })
+    .await?
+}
+
+/// `GET /{path}/snapshot.jpg`
+pub(super) fn jpeg(snapshots: Option<WatchReceiver<Arc<Vec<u8>>>>) -> Response<Body> {
+    match snapshots {
+        Some(snapshots) => {
+            let jpeg = snapshots.borrow().clone();
+            Response::builder()
+                .status(StatusCode::OK)
+                .header(header::CONTENT_TYPE, "image/jpeg")
+                .header(header::CACHE_CONTROL, "no-cache")
+                .body(Body::from(jpeg.to_vec()))
+                .expect("Response should be valid")
+        }
+        None => no_snapshot(),
+    }
+}
+
+/// `GET /{path}/mjpeg`, which ends when the server shuts down or the path
+/// is no longer served
+pub(super) fn mjpeg(
+    snapshots: Option<WatchReceiver<Arc<Vec<u8>>>>,
+    cancel: &CancellationToken,
+) -> Response<Body> {
+    let Some(snapshots) = snapshots else {
+        return no_snapshot();
+    };
+    let parts = WatchStream::new(snapshots)
+        .map(|jpeg| AnyResult::Ok(multipart(&jpeg)))
+        .take_until(cancel.clone().cancelled_owned());
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(
+            header::CONTENT_TYPE,
+            format!("multipart/x-mixed-replace; boundary={BOUNDARY}"),
+        )
+        .header(header::CACHE_CONTROL, "no-cache")
+        .body(Body::wrap_stream(parts))
+        .expect("Response should be valid")
+}
+
+/// One part of the mjpeg stream
+fn multipart(jpeg: &[u8]) -> Vec<u8> {
+    let mut part = format!(
+        "--{BOUNDARY}\r\nContent-Type: image/jpeg\r\nContent-Length: {}\r\n\r\n",
+        jpeg.len()
+    )
+    .into_bytes();
+    part.extend_from_slice(jpeg);
+    part.extend_from_slice(b"\r\n");
+    part
+}
+
+fn no_snapshot() -> Response<Body> {
+    response(
+        StatusCode::SERVICE_UNAVAILABLE,
+        "text/plain",
+        "There is no snapshot of the stream yet".to_string(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_multipart() {
+        assert_eq!(
+            multipart(b"jpeg"),
+            b"--neolinkframe\r\nContent-Type: image/jpeg\r\nContent-Length: 4\r\n\r\njpeg\r\n"
+                .to_vec()
+        );
+    }
+}
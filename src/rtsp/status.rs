@@ -0,0 +1,189 @@
+//! Health and status of the rtsp server
+//!
+//! Served over http when `--status-port` is given
+//!
+//! - `GET /healthz`: `200` while the rtsp main loops are running, `503` otherwise
+//! - `GET /cameras`: JSON list of the cameras with their state, number of
+//!   clients, the last connection error, the model and firmware it reported
+//!   and the resolution, framerate and the packet loss, jitter and bitrate of
+//!   each client session of each stream
+//! - `GET /{path}.sdp`: The SDP of the stream at that rtsp path, e.g.
+//!   `/Garage.sdp` or `/Garage/subStream.sdp`. `404` for unknown paths and
+//!   `503` until a client has played the stream and its caps are known. With
+//!   `[[users]]` it needs the basic auth of a user who may view that stream,
+//!   like the snapshots and hls, otherwise `401`
+//! - `GET /{path}/snapshot.jpg` and `GET /{path}/mjpeg`: For cameras with
+//!   `http_snapshots`, see [`super::snapshot`]
+//! - `GET /{path}/index.m3u8` and its segments: For cameras with `[cameras.hls]`,
+//!   see [`super::hls`]
+//! - `POST /cameras/{name}/{command}`: With `--control`, see [`super::control`]
+use hyper::{Body, Method, Request, Response, StatusCode};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    common::NeoReactor,
+    config::{CameraConfig, Config},
+};
+
+use super::{
+    control,
+    gst::NeoRtspServer,
+    hls,
+    http::{authorized, not_found, response, unauthorized},
+    metrics::Metrics,
+    snapshot,
+};
+
+/// Handles the http requests for the status server
+pub(super) async fn handle(
+    req: &Request<Body>,
+    metrics: &Metrics,
+    servers: &HashMap<(String, u16), Arc<NeoRtspServer>>,
+    config: &Config,
+    control: Option<&NeoReactor>,
+    cancel: &CancellationToken,
+) -> Response<Body> {
+    match (req.method(), req.uri().path()) {
+        (&Method::GET, "/healthz") => {
+            let mut running = true;
+            for rtsp in servers.values() {
+                running &= rtsp.is_running().await;
+            }
+            if running {
+                response(StatusCode::OK, "text/plain", "ok".to_string())
+            } else {
+                response(
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    "text/plain",
+                    "rtsp server is not running".to_string(),
+                )
+            }
+        }
+        (&Method::GET, "/cameras") => match serde_json::to_string(&metrics.camera_status()) {
+            Ok(json) => response(StatusCode::OK, "application/json", json),
+            Err(e) => response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "text/plain",
+                format!("{e:?}"),
+            ),
+        },
+        (&Method::GET, path) if path.ends_with("/snapshot.jpg") || path.ends_with("/mjpeg") => {
+            let (path, mjpeg) = match path.strip_suffix("/mjpeg") {
+                Some(path) => (path, true),
+                None => (path.trim_end_matches("/snapshot.jpg"), false),
+            };
+            let Some((camera, server)) =
+                served(path, servers, config).filter(|(camera, _)| camera.http_snapshots)
+            else {
+                return not_found();
+            };
+            let streams = Vec::from_iter(camera.stream_of_path(path));
+            if !authorized(req, config, camera, &streams) {
+                return unauthorized();
+            }
+            let snapshots = server.snapshots(path);
+            if mjpeg {
+                snapshot::mjpeg(snapshots, cancel)
+            } else {
+                snapshot::jpeg(snapshots)
+            }
+        }
+        (&Method::GET, path) if path.ends_with(".m3u8") || path.ends_with(".ts") => {
+            let Some((path, file)) = path.rsplit_once('/') else {
+                return not_found();
+            };
+            let Some((camera, server)) = served(path, servers, config)
+                .filter(|(camera, _)| camera.hls.as_ref().is_some_and(|hls| hls.enabled))
+            else {
+                return not_found();
+            };
+            let streams = Vec::from_iter(camera.stream_of_path(path));
+            if !authorized(req, config, camera, &streams) {
+                return unauthorized();
+            }
+            hls::serve(server.hls_directory(path), file).await
+        }
+        (&Method::GET, path) if path.ends_with(".sdp") => {
+            sdp(req, path.trim_end_matches(".sdp"), servers, config)
+        }
+        (&Method::POST, path) if path.starts_with("/cameras/") => match control {
+            Some(reactor) => control::handle(req, reactor, servers, config).await,
+            None => not_found(),
+        },
+        _ => not_found(),
+    }
+}
+
+/// The enabled camera that has the rtsp `path` and the server it is on
+fn served<'a>(
+    path: &str,
+    servers: &'a HashMap<(String, u16), Arc<NeoRtspServer>>,
+    config: &'a Config,
+) -> Option<(&'a CameraConfig, &'a Arc<NeoRtspServer>)> {
+    let camera = config
+        .cameras
+        .iter()
+        .filter(|camera| camera.enabled)
+        .find(|camera| camera.all_rtsp_paths().iter().any(|other| other == path))?;
+    let server = servers.get(&camera.rtsp_bind(&config.bind_addr, config.bind_port))?;
+    Some((camera, server))
+}
+
+/// The sdp of the stream served at the rtsp `path`
+fn sdp(
+    req: &Request<Body>,
+    path: &str,
+    servers: &HashMap<(String, u16), Arc<NeoRtspServer>>,
+    config: &Config,
+) -> Response<Body> {
+    let Some((camera, server)) = served(path, servers, config) else {
+        return not_found();
+    };
+    let streams = Vec::from_iter(camera.stream_of_path(path));
+    if !authorized(req, config, camera, &streams) {
+        return unauthorized();
+    }
+    match server.sdp(path) {
+        Some(sdp) => response(StatusCode::OK, "application/sdp", sdp),
+        None => response(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "text/plain",
+            "The stream has not negotiated its caps yet".to_string(),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_served() {
+        let config: Config = toml::from_str(
+            r#"
+            [[cameras]]
+            name = "Garage"
+            username = "admin"
+            address = "192.168.1.10"
+
+            [[cameras]]
+            name = "Shed"
+            username = "admin"
+            address = "192.168.1.11"
+            enabled = false
+            "#,
+        )
+        .unwrap();
+        let servers = HashMap::from([(
+            (config.bind_addr.clone(), config.bind_port),
+            Arc::new(NeoRtspServer::new().unwrap()),
+        )]);
+
+        assert!(served("/Garage", &servers, &config).is_some());
+        // A disabled camera has no paths
+        assert!(served("/Shed", &servers, &config).is_none());
+        assert!(served("/Shed/subStream", &servers, &config).is_none());
+    }
+}
@@ -2,29 +2,125 @@ use anyhow::{anyhow, Result};
 use gstreamer::{prelude::*, ClockTime, FlowError};
 use gstreamer_app::AppSrc;
 use gstreamer_rtsp_server::prelude::*;
-use std::collections::HashSet;
-use std::sync::Arc;
+use std::collections::{HashSet, VecDeque};
+use std::{future::Future, pin::Pin, sync::Arc};
 use tokio::{
-    sync::{broadcast::channel as broadcast, watch::channel as watch},
+    sync::{
+        broadcast::{channel as broadcast, Sender as BroadcastSender},
+        watch::{channel as watch, Receiver as WatchReceiver, Sender as WatchSender},
+    },
     task::JoinSet,
     time::{sleep, sleep_until, Duration, Instant},
 };
-use tokio_stream::{wrappers::BroadcastStream, Stream, StreamExt};
+use tokio_stream::{
+    wrappers::{BroadcastStream, ReceiverStream},
+    Stream, StreamExt,
+};
 use tokio_util::sync::CancellationToken;
 
-use crate::common::{Permit, StampedData, UseCounter};
+use crate::common::{Backoff, ParameterSets, Permit, StampedData, UseCounter, VidFormat};
 use crate::{
-    common::{NeoInstance, StreamConfig, StreamInstance},
+    common::{AudFormat, NeoInstance, StreamConfig, StreamInstance},
+    config::{
+        CameraConfig, HlsConfig, Latency, OverlayConfig, PauseConfig, PushConfig, RecordConfig,
+        RecordMode, RtspTransport, Transcode,
+    },
     AnyResult,
 };
 
-use super::{factory::*, gst::NeoRtspServer};
+use super::{
+    adaptive::adaptive_main,
+    clip::{encode_clip, load_clip, ClipSource},
+    factory::*,
+    gst::NeoRtspServer,
+    hls::hls_main,
+    metrics::{Metrics, StreamState},
+    pause::{align_pause, count_sessions, pause_main},
+    push::push_main,
+    record::record_main,
+    session_limit::session_limit_main,
+    snapshot::snapshot_main,
+};
+
+/// How long the video format may take to be known before it is warned about
+const VID_READY_WARN: Duration = Duration::from_secs(30);
+/// How often the rtsp sessions are looked at for clients that are not fed yet
+const SESSION_POLL: Duration = Duration::from_secs(1);
+/// Bounds of the retries of the outputs that run next to the rtsp stream
+const OUTPUT_MIN_BACKOFF: Duration = Duration::from_secs(1);
+const OUTPUT_MAX_BACKOFF: Duration = Duration::from_secs(60);
 
+/// What is sent to the clients while the stream is paused
 #[derive(Clone)]
-struct PauseAffectors {
-    motion: bool,
-    push: bool,
-    client: bool,
+enum PauseSource {
+    /// Nothing, players keep showing the last frame
+    Hold,
+    /// A clip on repeat
+    Clip(Arc<Vec<StampedData>>),
+    /// The last keyframe of the live stream on repeat
+    Freeze,
+    /// The last keyframe re-encoded into a clip each time the stream pauses
+    Still(PauseConfig),
+}
+
+/// The settings of the camera that a stream is built with
+///
+/// A change to any of them restarts the stream
+#[derive(Clone, PartialEq)]
+struct StreamSettings {
+    pause: PauseConfig,
+    latency: Latency,
+    jitterbuffer_ms: Option<u32>,
+    buffer_duration_ms: u64,
+    transcode: Option<Transcode>,
+    overlay: Option<OverlayConfig>,
+    transport: RtspTransport,
+    max_drift_ms: Option<u64>,
+    client_queue_kb: Option<u32>,
+    fast_start: bool,
+    repeat_parameter_sets: bool,
+    restream: Option<PushConfig>,
+    record: Option<RecordConfig>,
+    hls: Option<HlsConfig>,
+}
+
+impl StreamSettings {
+    fn new(config: &CameraConfig) -> Self {
+        Self {
+            pause: config.pause.clone(),
+            latency: config.latency,
+            jitterbuffer_ms: config.jitterbuffer_ms,
+            buffer_duration_ms: config.buffer_duration_ms,
+            transcode: config.transcode,
+            overlay: config.overlay.clone(),
+            transport: config.rtsp_transport,
+            max_drift_ms: config.max_drift_ms,
+            client_queue_kb: config.client_queue_kb,
+            fast_start: config.fast_start,
+            repeat_parameter_sets: config.repeat_parameter_sets,
+            restream: config.restream.clone(),
+            record: config.record.clone(),
+            hls: config.hls.clone(),
+        }
+    }
+
+    fn buffer_duration(&self) -> Duration {
+        Duration::from_millis(self.buffer_duration_ms)
+    }
+
+    /// The client queue in bytes
+    fn client_queue(&self) -> Option<u32> {
+        self.client_queue_kb.map(|kb| kb.saturating_mul(1024))
+    }
+
+    /// The overlay if it is turned on
+    fn overlay(&self) -> Option<OverlayConfig> {
+        self.overlay.clone().filter(|overlay| overlay.enabled)
+    }
+
+    fn max_drift(&self) -> Option<Duration> {
+        self.max_drift_ms.map(Duration::from_millis)
+    }
 }
 
 /// This handles the stream by activating and deacivating it as required
@@ -34,53 +130,106 @@ pub(super) async fn stream_main(
     rtsp: &NeoRtspServer,
     users: &HashSet<String>,
     paths: &[String],
+    metrics: &Arc<Metrics>,
+    client_limit: &ClientLimit,
 ) -> Result<()> {
     let mut camera_config = camera.config().await?.clone();
     let name = camera_config.borrow().name.clone();
+    let stream_kind = stream_instance.name;
 
-    let mut curr_pause;
     loop {
         let this_loop_cancel = CancellationToken::new();
         let _drop_guard = this_loop_cancel.clone().drop_guard();
 
         log::debug!("{}: Activating Stream", &name);
+        metrics.set_buffer_ready(&name, stream_kind, false);
         stream_instance.activate().await?;
 
-        // Wait for a valid stream format to be detected
-        log::debug!("{}: Waiting for Valid Stream", &name);
-        stream_instance
-            .config
-            .wait_for(|config| {
-                log::debug!("{:?}", config);
-                config.vid_ready()
-            })
-            .await?;
-        log::debug!("{}: Waiting for Valid Audio", &name);
-        // After vid give it 1s to look for audio
-        // Ignore timeout but check err
-        if let Ok(v) = tokio::time::timeout(
-            Duration::from_secs(1),
-            stream_instance.config.wait_for(|config| {
-                log::debug!("{:?}", config);
-                config.aud_ready()
-            }),
-        )
-        .await
+        let settings = StreamSettings::new(&camera_config.borrow());
+        // After vid give it some time to look for audio
+        let audio_wait = match settings.latency {
+            Latency::Normal => Duration::from_secs(1),
+            Latency::Low => Duration::from_millis(250),
+        };
+        wait_for_buffer(&name, &mut stream_instance.config, audio_wait).await?;
+        metrics.set_buffer_ready(&name, stream_kind, true);
         {
-            v?;
+            // A change of resolution restarts this loop so this stays current
+            let config = stream_instance.config.borrow();
+            log::info!(
+                "{}: {} {}x{} @ {}fps",
+                &name,
+                stream_kind,
+                config.resolution[0],
+                config.resolution[1],
+                config.fps
+            );
+            metrics.set_format(&name, stream_kind, config.resolution, config.fps);
         }
 
-        curr_pause = camera_config.borrow().pause.clone();
+        let curr_pause = &settings.pause;
+        let audio_paths = camera_config.borrow().rtsp_audio_paths(stream_kind);
+        let keyframe_paths = camera_config.borrow().rtsp_keyframe_paths(stream_kind);
 
         let last_stream_config = stream_instance.config.borrow().clone();
         let mut thread_stream_config = stream_instance.config.clone();
 
-        let (pause_affector_tx, pause_affector) = watch(PauseAffectors {
-            motion: false,
-            push: false,
-            client: false,
-        });
-        let pause_affector_tx = Arc::new(pause_affector_tx);
+        // What to send while paused, if not avaliable we fallback to holding the last frame
+        let pause_source = if !curr_pause.pauses() {
+            PauseSource::Hold
+        } else {
+            match curr_pause.mode.as_str() {
+                "loop" => match curr_pause.loop_file.as_ref() {
+                    Some(loop_file) => {
+                        match load_clip(loop_file, &last_stream_config.vid_format).await {
+                            Ok(clip) => PauseSource::Clip(Arc::new(clip)),
+                            Err(e) => {
+                                log::warn!("{}: Could not load the pause loop_file, falling back to holding the last frame: {:?}", &name, e);
+                                PauseSource::Hold
+                            }
+                        }
+                    }
+                    None => {
+                        log::warn!(
+                            "{}: Pause mode is loop but no loop_file is set, falling back to holding the last frame",
+                            &name
+                        );
+                        PauseSource::Hold
+                    }
+                },
+                "black" | "test" => {
+                    let pattern = if curr_pause.mode == "black" {
+                        "black"
+                    } else {
+                        "smpte"
+                    };
+                    metrics.add_pause_encode(&name, stream_kind);
+                    match encode_clip(
+                        &name,
+                        ClipSource::Pattern(pattern),
+                        &last_stream_config,
+                        curr_pause,
+                    )
+                    .await
+                    {
+                        Ok(clip) => PauseSource::Clip(Arc::new(clip)),
+                        Err(e) => {
+                            log::warn!(
+                                "{}: Could not encode the pause stream, falling back to holding the last frame: {:?}",
+                                &name,
+                                e
+                            );
+                            PauseSource::Hold
+                        }
+                    }
+                }
+                "still" => PauseSource::Still(curr_pause.clone()),
+                "freeze" => PauseSource::Freeze,
+                _ => PauseSource::Hold,
+            }
+        };
+        // Paused until the first check if anything can pause it
+        let (paused_tx, paused) = watch(curr_pause.pauses());
 
         let mut set = JoinSet::<AnyResult<()>>::new();
         log::debug!("{}: Creating Client Counters", &name);
@@ -88,146 +237,83 @@ pub(super) async fn stream_main(
         let client_counter = UseCounter::new().await;
         let client_count = client_counter.create_deactivated().await?;
 
-        // Client count affector
-        if curr_pause.on_motion {
-            let thread_name = name.clone();
-            let client_count = client_counter.create_deactivated().await?;
-            let thread_pause_affector_tx = pause_affector_tx.clone();
+        // Even without a `[pause]` the stream can be forced to pause so this always runs
+        {
             let cancel = this_loop_cancel.clone();
-            set.spawn(async move {
-                tokio::select! {
-                    _ = cancel.cancelled() => AnyResult::Ok(()),
-                    v = async {
-                        log::debug!("{}: Activating Client Pause", thread_name);
-                        loop {
-                            client_count.aquired_users().await?;
-                            log::info!("{}: Enabling Client", thread_name);
-                            thread_pause_affector_tx.send_modify(|current| {
-                                current.client = true;
-                            });
-
-                            client_count.dropped_users().await?;
-                            log::info!("{}: Pausing Client", thread_name);
-                            thread_pause_affector_tx.send_modify(|current| {
-                                current.client = false;
-                            });
-                        }
-                    } => v,
-                }
-            });
-        }
-
-        // Motion affector
-        if curr_pause.on_motion {
             let thread_name = name.clone();
-            let thread_pause_affector_tx = pause_affector_tx.clone();
+            let thread_camera = camera.clone();
+            let thread_curr_pause = curr_pause.clone();
+            let clients = client_counter.create_deactivated().await?;
+            let paused_tx = if curr_pause.align_to_keyframe {
+                let (wanted_tx, wanted) = watch(*paused.borrow());
+                let thread_vid = stream_instance.vid.resubscribe();
+                let max_wait = Duration::from_secs_f64(curr_pause.motion_timeout.max(0.0));
+                set.spawn(async move {
+                    tokio::select! {
+                        _ = cancel.cancelled() => AnyResult::Ok(()),
+                        v = align_pause(wanted, paused_tx, thread_vid, max_wait) => v,
+                    }
+                });
+                wanted_tx
+            } else {
+                paused_tx
+            };
             let cancel = this_loop_cancel.clone();
-
-            let mut motion = camera.motion().await?;
-            let delta = Duration::from_secs_f64(curr_pause.motion_timeout);
-
             set.spawn(async move {
                 tokio::select! {
-                    _ = cancel.cancelled() => AnyResult::Ok(()),
-                    v = async {
-                        log::debug!("{}: Activating Motion Pause", &thread_name);
-                        loop {
-                            motion
-                                .wait_for(|md| matches!(md, crate::common::MdState::Start(_)))
-                                .await?;
-                            log::info!("{}: Enabling Motion", thread_name);
-                            thread_pause_affector_tx.send_modify(|current| {
-                                current.motion = true;
-                            });
-
-                            motion
-                                .wait_for(
-                                    |md| matches!(md, crate::common::MdState::Stop(n) if n.elapsed()>delta),
-                                )
-                                .await?;
-                            log::info!("{}: Pausing Motion", thread_name);
-                            thread_pause_affector_tx.send_modify(|current| {
-                                current.motion = false;
-                            });
+                    _ = cancel.cancelled() => {},
+                    v = pause_main(&thread_name, &thread_curr_pause, &thread_camera, clients, paused_tx) => {
+                        if let Err(e) = v {
+                            log::error!("{thread_name}: Stopped pausing: {e:?}");
                         }
-                    } => v,
+                    },
                 }
+                AnyResult::Ok(())
             });
-
-            // Push notfications
-            log::debug!("{}: Activating Push Notification Pause", &name);
-            let mut pn = camera.push_notifications().await?;
-            let mut curr_pn = None;
-            let thread_name = name.clone();
-            let thread_pause_affector_tx = pause_affector_tx.clone();
+        }
+        {
+            // Clients between their SETUP and PLAY have a session but no fed
+            // media yet, they are counted so the client pause waits for them
             let cancel = this_loop_cancel.clone();
+            let permit = client_counter.create_deactivated().await?;
+            let thread_rtsp = rtsp.clone();
+            let thread_paths = paths
+                .iter()
+                .chain(audio_paths.iter())
+                .chain(keyframe_paths.iter())
+                .cloned()
+                .collect::<Vec<_>>();
             set.spawn(async move {
                 tokio::select! {
                     _ = cancel.cancelled() => AnyResult::Ok(()),
-                    v = async {
-                        loop {
-                            curr_pn = pn
-                                .wait_for(|pn| pn != &curr_pn && pn.is_some())
-                                .await?
-                                .clone();
-                            log::info!("{}: Enabling Push Notification", thread_name);
-                            thread_pause_affector_tx.send_modify(|current| {
-                                current.push = true;
-                            });
-                            tokio::select! {
-                                v = pn.wait_for(|pn| pn != &curr_pn && pn.is_some()) => {
-                                    v?;
-                                    // If another PN during wait then go back to wait more
-                                    continue;
-                                }
-                                _ = sleep(Duration::from_secs(30)) => {}
-                            }
-                            log::info!("{}: Pausing Push Notification", thread_name);
-                            thread_pause_affector_tx.send_modify(|current| {
-                                current.push = false;
-                            });
-                        }
-                    } => v,
+                    v = count_sessions(|| thread_rtsp.session_ids(&thread_paths).len(), permit, SESSION_POLL) => v,
                 }
             });
         }
-
-        if curr_pause.on_motion || curr_pause.on_disconnect {
+        {
             // Take over activation
             let cancel = this_loop_cancel.clone();
             let mut client_activator = stream_instance.activator_handle().await;
             client_activator.deactivate().await?;
             stream_instance.deactivate().await?;
-            let mut pause_affector = tokio_stream::wrappers::WatchStream::new(pause_affector);
-            let thread_curr_pause = curr_pause.clone();
+            let mut thread_paused = paused.clone();
+            let thread_name = name.clone();
+            let thread_metrics = metrics.clone();
             set.spawn(async move {
                 tokio::select! {
                     _ = cancel.cancelled() => AnyResult::Ok(()),
                     v = async {
-                        while let Some(state) = pause_affector.next().await {
-                            if thread_curr_pause.on_motion && thread_curr_pause.on_disconnect {
-                                if state.client && (state.motion || state.push) {
-                                    client_activator.activate().await?;
-                                } else {
-                                    client_activator.deactivate().await?;
-                                }
-                            } else if thread_curr_pause.on_motion {
-                                if state.motion || state.push {
-                                    client_activator.activate().await?;
-                                } else {
-                                    client_activator.deactivate().await?;
-                                }
-                            } else if thread_curr_pause.on_disconnect {
-                                if state.client {
-                                    client_activator.activate().await?;
-                                } else {
-                                    client_activator.deactivate().await?;
-                                }
+                        loop {
+                            let paused = *thread_paused.borrow_and_update();
+                            if paused {
+                                client_activator.deactivate().await?;
+                                thread_metrics.set_state(&thread_name, stream_kind, StreamState::Paused);
                             } else {
-                                unreachable!()
+                                client_activator.activate().await?;
+                                thread_metrics.set_state(&thread_name, stream_kind, StreamState::Streaming);
                             }
+                            thread_paused.changed().await?;
                         }
-                        AnyResult::Ok(())
                     } => v,
                 }
             });
@@ -261,6 +347,7 @@ pub(super) async fn stream_main(
         let counter = client_counter.create_deactivated().await?;
         let mut cur_count = 0;
         let thread_name = name.clone();
+        let thread_metrics = metrics.clone();
         set.spawn(async move {
             tokio::select! {
                 _ = cancel.cancelled() => AnyResult::Ok(()),
@@ -268,11 +355,134 @@ pub(super) async fn stream_main(
                     loop {
                         cur_count = *counter.get_counter().wait_for(|v| v != &cur_count).await?;
                         log::debug!("{thread_name}: Number of rtsp clients: {cur_count}");
+                        thread_metrics.set_clients(&thread_name, stream_kind, cur_count);
                     }
                 } => v,
             }
         });
 
+        // Task to report the receiver reports of the clients, closing struggling ones with adaptive
+        let cancel = this_loop_cancel.clone();
+        let thread_name = name.clone();
+        let thread_metrics = metrics.clone();
+        let thread_rtsp = rtsp.clone();
+        let thread_paths = paths.to_vec();
+        let thread_config = camera_config.clone();
+        set.spawn(async move {
+            tokio::select! {
+                _ = cancel.cancelled() => AnyResult::Ok(()),
+                v = adaptive_main(&thread_name, stream_kind, &thread_rtsp, &thread_paths, &thread_metrics, thread_config) => v,
+            }
+        });
+
+        // Task to close the sessions that exceed max_session_secs
+        let cancel = this_loop_cancel.clone();
+        let thread_name = name.clone();
+        let thread_rtsp = rtsp.clone();
+        let thread_paths = paths.to_vec();
+        let thread_config = camera_config.clone();
+        set.spawn(async move {
+            tokio::select! {
+                _ = cancel.cancelled() => AnyResult::Ok(()),
+                v = session_limit_main(&thread_name, stream_kind, &thread_rtsp, &thread_paths, thread_config) => v,
+            }
+        });
+
+        // Task to keep the jpeg of http_snapshots up to date
+        let cancel = this_loop_cancel.clone();
+        let thread_name = name.clone();
+        let thread_rtsp = rtsp.clone();
+        let thread_paths = paths.to_vec();
+        let vid_format = last_stream_config.vid_format.clone();
+        let vid_history = stream_instance.vid_history.clone();
+        let thread_config = camera_config.clone();
+        set.spawn(async move {
+            tokio::select! {
+                _ = cancel.cancelled() => AnyResult::Ok(()),
+                v = snapshot_main(&thread_name, &thread_rtsp, &thread_paths, vid_format, vid_history, thread_config) => v,
+            }
+        });
+
+        // Pushes the highest quality stream to an external ingest
+        let push = settings
+            .restream
+            .clone()
+            .filter(|_| camera_config.borrow().is_base_stream(stream_kind));
+        if let Some(push) = push {
+            let cancel = this_loop_cancel.clone();
+            let thread_name = name.clone();
+            let vid_format = last_stream_config.vid_format.clone();
+            let vid = stream_instance.vid.resubscribe();
+            let thread_paused = paused.clone();
+            set.spawn(async move {
+                tokio::select! {
+                    _ = cancel.cancelled() => {},
+                    v = push_main(&thread_name, &push, vid_format, &vid, thread_paused) => {
+                        // Pushing must never stop the local rtsp stream
+                        if let Err(e) = v {
+                            log::error!("{thread_name}: Stopped pushing: {e:?}");
+                        }
+                    },
+                }
+                AnyResult::Ok(())
+            });
+        }
+
+        // Records the highest quality stream to disk
+        let record = settings
+            .record
+            .clone()
+            .filter(|record| record.enabled && camera_config.borrow().is_base_stream(stream_kind));
+        if let Some(record) = record {
+            let cancel = this_loop_cancel.clone();
+            let thread_name = name.clone();
+            let vid_format = last_stream_config.vid_format.clone();
+            let vid = stream_instance.vid.resubscribe();
+            let vid_history = stream_instance.vid_history.clone();
+            let motion = match record.mode {
+                RecordMode::Motion => Some(camera.motion().await?),
+                RecordMode::Continuous => None,
+            };
+            let thread_paused = paused.clone();
+            set.spawn(async move {
+                tokio::select! {
+                    _ = cancel.cancelled() => {},
+                    v = record_main(&thread_name, &stream_kind.to_string(), &record, vid_format, &vid, &vid_history, motion, thread_paused) => {
+                        // Recording must never stop the local rtsp stream
+                        if let Err(e) = v {
+                            log::error!("{thread_name}: Stopped recording: {e:?}");
+                        }
+                    },
+                }
+                AnyResult::Ok(())
+            });
+        }
+
+        // Serves the stream as hls over the status server
+        let hls = settings.hls.clone().filter(|hls| hls.enabled);
+        if let Some(hls) = hls {
+            let cancel = this_loop_cancel.clone();
+            let thread_name = name.clone();
+            let thread_rtsp = rtsp.clone();
+            let thread_paths = paths.to_vec();
+            let vid_format = last_stream_config.vid_format.clone();
+            let vid = stream_instance.vid.resubscribe();
+            let vid_history = stream_instance.vid_history.clone();
+            let thread_paused = paused.clone();
+            set.spawn(async move {
+                tokio::select! {
+                    _ = cancel.cancelled() => {},
+                    v = hls_main(&thread_name, &stream_kind.to_string(), &thread_rtsp, &thread_paths, &hls, vid_format, &vid, &vid_history, thread_paused) => {
+                        // Hls must never stop the local rtsp stream
+                        if let Err(e) = v {
+                            log::error!("{thread_name}: Stopped the hls: {e:?}");
+                        }
+                    },
+                }
+                AnyResult::Ok(())
+            });
+        }
+
         // This runs the actual stream.
         // The select will restart if the stream's config updates
         log::debug!("{}: Stream Activated", &name);
@@ -284,28 +494,53 @@ pub(super) async fn stream_main(
                 log::trace!("    From {:?} to {:?}", last_stream_config, v.clone());
                 continue;
             },
-            v = camera_config.wait_for(|new_conf| new_conf.pause != curr_pause ) => {
+            v = camera_config.wait_for(|new_conf| StreamSettings::new(new_conf) != settings) => {
                 v?;
-                // If pause config changes restart
-                log::info!("{}: Pause Configuration Changed. Reloading Streams", &name);
+                // If any of the settings that the stream is built with changes restart
+                log::info!("{}: Stream configuration changed. Reloading Streams", &name);
                 continue;
             },
-            v = stream_run(&name, &stream_instance, rtsp, &last_stream_config, users, paths, client_count) => v,
+            v = stream_run(&name, &stream_instance, rtsp, metrics, &last_stream_config, users, paths, &audio_paths, &keyframe_paths, client_count, paused, pause_source, &settings, client_limit) => v,
         };
     }
 }
 
 /// This handles the stream itself by creating the factory and pushing messages into it
+#[allow(clippy::too_many_arguments)]
 async fn stream_run(
     name: &str,
     stream_instance: &StreamInstance,
     rtsp: &NeoRtspServer,
+    metrics: &Arc<Metrics>,
     stream_config: &StreamConfig,
     users: &HashSet<String>,
     paths: &[String],
+    audio_paths: &[String],
+    keyframe_paths: &[String],
     client_count: Permit,
+    paused: WatchReceiver<bool>,
+    pause_source: PauseSource,
+    settings: &StreamSettings,
+    client_limit: &ClientLimit,
 ) -> AnyResult<()> {
+    let StreamSettings {
+        latency,
+        jitterbuffer_ms,
+        transcode,
+        transport,
+        fast_start,
+        repeat_parameter_sets,
+        ..
+    } = *settings;
+    let align_to_keyframe = settings.pause.align_to_keyframe;
+    let buffer_duration = settings.buffer_duration();
+    let client_queue = settings.client_queue();
+    let overlay = settings.overlay();
+    let max_drift = settings.max_drift();
     let vidstream = stream_instance.vid.resubscribe();
+    // Only the camera's H265 needs them, an encoder sends its own
+    let param_sets = (repeat_parameter_sets && stream_config.vid_format == VidFormat::H265)
+        .then(|| stream_instance.param_sets.clone());
     let audstream = stream_instance.aud.resubscribe();
     let vid_history = stream_instance.vid_history.clone();
     let aud_history = stream_instance.aud_history.clone();
@@ -315,25 +550,170 @@ async fn stream_run(
         .mount_points()
         .ok_or(anyhow!("RTSP server lacks mount point"))?;
     // Create the factory
-    let (factory, mut client_rx) = make_factory(stream_config).await?;
+    let (factory, client_rx) = make_factory(
+        name,
+        stream_config,
+        latency,
+        jitterbuffer_ms,
+        buffer_duration,
+        client_queue,
+        transcode,
+        overlay.clone(),
+        transport,
+        client_limit.clone(),
+        false,
+        repeat_parameter_sets,
+    )
+    .await?;
+    if overlay.is_some() {
+        log::info!(
+            "{}: Drawing the time onto {}, which decodes and encodes the video",
+            name,
+            stream_instance.name
+        );
+    }
+    if transcode.is_some() {
+        match transcode_target(&stream_config.vid_format, transcode) {
+            Some(target) => log::info!(
+                "{}: Transcoding {} from {:?} to {:?}",
+                name,
+                stream_instance.name,
+                stream_config.vid_format,
+                target
+            ),
+            None => log::info!(
+                "{}: {} is already {:?}, not transcoding",
+                name,
+                stream_instance.name,
+                stream_config.vid_format
+            ),
+        }
+    }
 
     factory.add_permitted_roles(users);
+    let jumps = factory.jumps();
+    // Audio only clients come from the audio factory
+    let mut audio_jumps = jumps.clone();
 
     for path in paths.iter() {
         log::debug!("Path: {}", path);
         mounts.add_factory(path, factory.clone());
     }
     log::info!("{}: Avaliable at {}", name, paths.join(", "));
+    // Clients that came before the stream was ready reconnect to it
+    rtsp.clear_session_notready(paths);
+
+    let mut clients: Pin<Box<dyn Stream<Item = ClientData> + Send>> =
+        Box::pin(ReceiverStream::new(client_rx));
+    if !audio_paths.is_empty() {
+        if matches!(stream_config.aud_format, AudFormat::None) {
+            log::info!("{}: Camera has no audio, not serving an audio stream", name);
+        } else {
+            let (audio_factory, audio_client_rx) = make_audio_factory(
+                name,
+                stream_config,
+                latency,
+                jitterbuffer_ms,
+                buffer_duration,
+                client_queue,
+                transport,
+                client_limit.clone(),
+            )
+            .await?;
+            audio_factory.add_permitted_roles(users);
+            audio_jumps = audio_factory.jumps();
+            for path in audio_paths.iter() {
+                log::debug!("Audio Path: {}", path);
+                mounts.add_factory(path, audio_factory.clone());
+            }
+            log::info!("{}: Audio avaliable at {}", name, audio_paths.join(", "));
+            clients = Box::pin(clients.merge(ReceiverStream::new(audio_client_rx)));
+        }
+    }
+    // Keyframe only clients come from their own factory
+    let mut keyframe_jumps = jumps.clone();
+    if !keyframe_paths.is_empty() {
+        let (keyframe_factory, keyframe_client_rx) = make_factory(
+            name,
+            stream_config,
+            latency,
+            jitterbuffer_ms,
+            buffer_duration,
+            client_queue,
+            transcode,
+            overlay.clone(),
+            transport,
+            client_limit.clone(),
+            true,
+            repeat_parameter_sets,
+        )
+        .await?;
+        keyframe_factory.add_permitted_roles(users);
+        keyframe_jumps = keyframe_factory.jumps();
+        for path in keyframe_paths.iter() {
+            log::debug!("Keyframe Path: {}", path);
+            mounts.add_factory(path, keyframe_factory.clone());
+        }
+        log::info!(
+            "{}: Keyframes only avaliable at {}",
+            name,
+            keyframe_paths.join(", ")
+        );
+        clients = Box::pin(clients.merge(ReceiverStream::new(keyframe_client_rx)));
+    }
 
     let stream_cancel = CancellationToken::new();
     let drop_guard = stream_cancel.clone().drop_guard();
     let mut set = JoinSet::new();
+
+    // The still is encoded once per pause and shared by all clients
+    let still = if let PauseSource::Still(pause) = &pause_source {
+        let (still_tx, still) = watch(None);
+        let thread_name = name.to_string();
+        let thread_stream_config = stream_config.clone();
+        let thread_pause = pause.clone();
+        let thread_vid_history = vid_history.clone();
+        let thread_paused = paused.clone();
+        let thread_metrics = metrics.clone();
+        let stream_kind = stream_instance.name;
+        let thread_stream_cancel = stream_cancel.clone();
+        set.spawn(async move {
+            let encode = || {
+                let keyframe = last_keyframe(&thread_vid_history);
+                let (name, stream_config, pause) = (
+                    thread_name.clone(),
+                    thread_stream_config.clone(),
+                    thread_pause.clone(),
+                );
+                thread_metrics.add_pause_encode(&name, stream_kind);
+                async move {
+                    match keyframe {
+                        Some(keyframe) => {
+                            encode_clip(&name, ClipSource::Frame(keyframe), &stream_config, &pause)
+                                .await
+                        }
+                        None => Err(anyhow!("No keyframe to show yet")),
+                    }
+                }
+            };
+            tokio::select! {
+                _ = thread_stream_cancel.cancelled() => AnyResult::Ok(()),
+                v = encode_stills(&thread_name, thread_paused, still_tx, encode) => v,
+            }
+        });
+        Some(still)
+    } else {
+        None
+    };
     // Wait for new media client data to come in from the factory
-    while let Some(mut client_data) = client_rx.recv().await {
+    while let Some(mut client_data) = clients.next().await {
         log::debug!("New media");
         // New media created
         let vid = client_data.vid.take().map(|data| data.app);
         let aud = client_data.aud.take().map(|data| data.app);
+        let keyframe_only = client_data.keyframe_only;
+        // Released once both the video and audio of this client end
+        let slot = Arc::new(client_data.slot);
 
         // This is the data that gets sent to gstreamer thread
         // It represents the combination of the camera stream and the appsrc seek messages
@@ -347,6 +727,7 @@ async fn stream_run(
         let thread_vid_data_tx = vid_data_tx.clone();
         let thread_stream_cancel = stream_cancel.clone();
         let thread_vid_history = vid_history.clone();
+        let thread_paused = paused.clone();
         set.spawn(async move {
             let r = tokio::select! {
                 _ = thread_stream_cancel.cancelled() => AnyResult::Ok(()),
@@ -355,7 +736,7 @@ async fn stream_run(
                     {
                         let history = thread_vid_history.borrow();
                         // let last_ts = history.back().map(|s| s.ts);
-                        for data in history.iter() {
+                        for data in history.iter().skip(prime_start(&history, latency, fast_start)) {
                             thread_vid_data_tx.send(
                                 // StampedData {
                                 //     keyframe: data.keyframe,
@@ -368,8 +749,17 @@ async fn stream_run(
                     }
 
                     // Send new
+                    let mut after_pause = false;
                     while let Some(frame) = vidstream.next().await {
                         if let Ok(data) = frame {
+                            // After a pause the live stream carries on from a keyframe
+                            if align_to_keyframe && *thread_paused.borrow() {
+                                after_pause = true;
+                            } else if after_pause && !data.keyframe {
+                                continue;
+                            } else {
+                                after_pause = false;
+                            }
                             thread_vid_data_tx.send(
                                 data
                             )?;
@@ -382,6 +772,48 @@ async fn stream_run(
             AnyResult::Ok(())
         });
 
+        // This thread sends the pause source into the stream while paused
+        let mut thread_paused = paused.clone();
+        let thread_vid_data_tx = vid_data_tx.clone();
+        let thread_stream_cancel = stream_cancel.clone();
+        match &pause_source {
+            PauseSource::Hold => {}
+            PauseSource::Clip(clip) => {
+                let clip = clip.clone();
+                set.spawn(async move {
+                    let r = tokio::select! {
+                        _ = thread_stream_cancel.cancelled() => AnyResult::Ok(()),
+                        v = replay_clip(&clip, &mut thread_paused, &thread_vid_data_tx) => v,
+                    };
+                    log::trace!("Stream Pause Clip End: {r:?}");
+                    AnyResult::Ok(())
+                });
+            }
+            PauseSource::Freeze => {
+                let thread_vid_history = vid_history.clone();
+                set.spawn(async move {
+                    let r = tokio::select! {
+                        _ = thread_stream_cancel.cancelled() => AnyResult::Ok(()),
+                        v = freeze_frame(&thread_vid_history, &mut thread_paused, &thread_vid_data_tx) => v,
+                    };
+                    log::trace!("Stream Freeze Frame End: {r:?}");
+                    AnyResult::Ok(())
+                });
+            }
+            PauseSource::Still(_) => {
+                if let Some(mut thread_still) = still.clone() {
+                    set.spawn(async move {
+                        let r = tokio::select! {
+                            _ = thread_stream_cancel.cancelled() => AnyResult::Ok(()),
+                            v = replay_still(&mut thread_still, &mut thread_paused, &thread_vid_data_tx) => v,
+                        };
+                        log::trace!("Stream Still End: {r:?}");
+                        AnyResult::Ok(())
+                    });
+                }
+            }
+        }
+
         // This thread takes the audio data from the cam and passed it into the stream
         let mut audstream = BroadcastStream::new(audstream.resubscribe());
         let thread_stream_cancel = stream_cancel.clone();
@@ -395,7 +827,7 @@ async fn stream_run(
                     {
                         let history = thread_aud_history.borrow();
                         // let last_ts = history.back().map(|s| s.ts);
-                        for data in history.iter() {
+                        for data in history.iter().skip(prime_start(&history, latency, fast_start)) {
                             thread_aud_data_tx.send(
                                 // StampedData {
                                 //     keyframe: data.keyframe,
@@ -425,9 +857,19 @@ async fn stream_run(
 
         // Handles sending the video data into gstreamer
         let thread_stream_cancel = stream_cancel.clone();
-        let vid_data_rx = BroadcastStream::new(vid_data_rx).filter(|f| f.is_ok()); // Filter to ignore lagged
+        // Filter to ignore lagged and, for the keyframe only clients, the other frames
+        let vid_data_rx = BroadcastStream::new(vid_data_rx)
+            .filter(move |f| matches!(f, Ok(frame) if frame.keyframe || !keyframe_only));
         let thread_vid = vid.clone();
         let mut thread_client_count = client_count.subscribe();
+        let thread_slot = slot.clone();
+        let thread_jumps = if keyframe_only {
+            keyframe_jumps.clone()
+        } else {
+            jumps.clone()
+        };
+        let thread_vid_history = vid_history.clone();
+        let thread_param_sets = param_sets.clone();
         log::debug!("stream_config.fps: {}", stream_config.fps);
         // let fallback_time = Duration::from_secs(3);
         // let fallback_framerate =
@@ -443,8 +885,16 @@ async fn stream_run(
                         // repeat_keyframe(
                             frametime_stream(
                                 hold_stream(
-                                    wait_for_keyframe(
-                                        vid_data_rx,
+                                    jump_to_live(
+                                        insert_parameter_sets(
+                                            wait_for_keyframe(
+                                                vid_data_rx,
+                                            ),
+                                            thread_param_sets,
+                                        ),
+                                        thread_jumps,
+                                        thread_vid_history,
+                                        max_drift,
                                     )
                                 )
                             ),
@@ -456,6 +906,7 @@ async fn stream_run(
                     },
                 };
                 drop(thread_client_count);
+                drop(thread_slot);
                 let _ = thread_vid.end_of_stream();
                 log::debug!("Vid Thread End: {:?}", r);
                 r
@@ -466,8 +917,21 @@ async fn stream_run(
         let thread_stream_cancel = stream_cancel.clone();
         let aud_data_rx = BroadcastStream::new(aud_data_rx).filter(|f| f.is_ok()); // Filter to ignore lagged
         let thread_aud = aud.clone();
+        // Audio only clients need to keep the stream active themselves
+        let audio_only = vid.is_none();
+        let mut thread_client_count = client_count.subscribe();
+        let thread_slot = slot;
+        let thread_jumps = if audio_only {
+            audio_jumps.clone()
+        } else {
+            jumps.clone()
+        };
+        let thread_aud_history = aud_history.clone();
         if let Some(thread_aud) = thread_aud {
             set.spawn(async move {
+                if audio_only {
+                    thread_client_count.activate().await?;
+                }
                 let r = tokio::select! {
                     _ = thread_stream_cancel.cancelled() => {
                         AnyResult::Ok(())
@@ -475,14 +939,21 @@ async fn stream_run(
                     v = send_to_appsrc(
                         frametime_stream(
                             hold_stream(
-                                wait_for_keyframe(
-                                    aud_data_rx
+                                jump_to_live(
+                                    wait_for_keyframe(
+                                        aud_data_rx
+                                    ),
+                                    thread_jumps,
+                                    thread_aud_history,
+                                    max_drift,
                                 )
                             )
                         ), &thread_aud) => {
                         v
                     },
                 };
+                drop(thread_client_count);
+                drop(thread_slot);
                 let _ = thread_aud.end_of_stream();
                 log::debug!("Aud Thread End: {:?}", r);
                 r
@@ -500,6 +971,187 @@ async fn stream_run(
     AnyResult::Ok(())
 }
 
+/// Waits for the stream thread to see the video format of the camera and then
+/// for up to `audio_wait` for the audio format
+///
+/// Both are woken by the stream config changing rather than polled. A camera
+/// that takes longer than `VID_READY_WARN` is warned about but still waited for
+async fn wait_for_buffer(
+    name: &str,
+    config: &mut WatchReceiver<StreamConfig>,
+    audio_wait: Duration,
+) -> AnyResult<()> {
+    log::debug!("{}: Waiting for Valid Stream", name);
+    let ready =
+        tokio::time::timeout(VID_READY_WARN, config.wait_for(|config| config.vid_ready())).await;
+    match ready {
+        Ok(v) => {
+            v?;
+        }
+        Err(_) => {
+            log::warn!(
+                "{}: No video format from the camera after {:?}, still waiting",
+                name,
+                VID_READY_WARN
+            );
+            config.wait_for(|config| config.vid_ready()).await?;
+        }
+    }
+    log::debug!("{}: Waiting for Valid Audio", name);
+    // Ignore timeout but check err
+    if let Ok(v) =
+        tokio::time::timeout(audio_wait, config.wait_for(|config| config.aud_ready())).await
+    {
+        v?;
+    }
+    Ok(())
+}
+
+/// The retry delays of the hls, recording and push of a stream
+///
+/// These fail on their own, e.g. a full disk or a gone ingest, and are retried
+/// without touching the rtsp stream
+pub(super) fn output_backoff() -> Backoff {
+    Backoff::new(OUTPUT_MIN_BACKOFF, OUTPUT_MAX_BACKOFF)
+}
+
+/// Where in the history new clients start from
+///
+/// In low latency mode or with fast start this is the latest keyframe so that
+/// the client is not first fed several seconds of old frames
+fn prime_start(history: &VecDeque<StampedData>, latency: Latency, fast_start: bool) -> usize {
+    match (latency, fast_start) {
+        (Latency::Normal, false) => 0,
+        _ => history.iter().rposition(|data| data.keyframe).unwrap_or(0),
+    }
+}
+
+/// Sends the clip on repeat for as long as the stream is paused
+async fn replay_clip(
+    clip: &[StampedData],
+    paused: &mut WatchReceiver<bool>,
+    data_tx: &BroadcastSender<StampedData>,
+) -> AnyResult<()> {
+    loop {
+        paused.wait_for(|paused| *paused).await?;
+        repeat_clip(clip, paused, data_tx).await?;
+    }
+}
+
+/// Encodes a still with `encode` each time the stream pauses and clears it on resume
+///
+/// This runs once per stream so the encoder runs once per pause however many
+/// clients there are, each client replays the shared still with `replay_still`
+async fn encode_stills<F, Fut>(
+    name: &str,
+    mut paused: WatchReceiver<bool>,
+    still_tx: WatchSender<Option<Arc<Vec<StampedData>>>>,
+    mut encode: F,
+) -> AnyResult<()>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = AnyResult<Vec<StampedData>>>,
+{
+    loop {
+        paused.wait_for(|paused| *paused).await?;
+        match encode().await {
+            Ok(clip) => {
+                still_tx.send_replace(Some(Arc::new(clip)));
+            }
+            Err(e) => {
+                log::warn!("{name}: Could not encode the still pause stream: {e:?}");
+            }
+        }
+        paused.wait_for(|paused| !*paused).await?;
+        still_tx.send_replace(None);
+    }
+}
+
+/// Sends the encoded still on repeat for as long as the stream is paused
+async fn replay_still(
+    still: &mut WatchReceiver<Option<Arc<Vec<StampedData>>>>,
+    paused: &mut WatchReceiver<bool>,
+    data_tx: &BroadcastSender<StampedData>,
+) -> AnyResult<()> {
+    loop {
+        let clip = still.wait_for(|still| still.is_some()).await?.clone();
+        if let Some(clip) = clip {
+            repeat_clip(&clip, paused, data_tx).await?;
+        }
+        // Wait for this pause's still to be cleared before taking the next one
+        still.wait_for(|still| still.is_none()).await?;
+    }
+}
+
+/// Sends the clip on repeat until the stream is resumed
+async fn repeat_clip(
+    clip: &[StampedData],
+    paused: &mut WatchReceiver<bool>,
+    data_tx: &BroadcastSender<StampedData>,
+) -> AnyResult<()> {
+    const FRAME_GAP: Duration = Duration::from_millis(40);
+    while *paused.borrow() {
+        let start = Instant::now();
+        for frame in clip.iter() {
+            if !*paused.borrow() {
+                // Resumed, the live frames take over from here
+                return Ok(());
+            }
+            sleep_until(start + frame.ts).await;
+            data_tx.send(frame.clone())?;
+        }
+        // Ensure each repeat takes some time even if the clip lacks timestamps
+        let end = clip.last().map(|frame| frame.ts).unwrap_or_default() + FRAME_GAP;
+        sleep_until(start + end).await;
+    }
+    Ok(())
+}
+
+fn last_keyframe(history: &WatchReceiver<VecDeque<StampedData>>) -> Option<StampedData> {
+    history
+        .borrow()
+        .iter()
+        .rev()
+        .find(|frame| frame.keyframe)
+        .cloned()
+}
+
+/// Resends the last keyframe of the live stream for as long as the stream is paused
+///
+/// Without this the player is left on whatever frame came last which may be
+/// a partially decoded one
+async fn freeze_frame(
+    history: &WatchReceiver<VecDeque<StampedData>>,
+    paused: &mut WatchReceiver<bool>,
+    data_tx: &BroadcastSender<StampedData>,
+) -> AnyResult<()> {
+    const FRAME_GAP: Duration = Duration::from_millis(500);
+    loop {
+        paused.wait_for(|paused| *paused).await?;
+        let mut keyframe = match last_keyframe(history) {
+            Some(keyframe) => keyframe,
+            None => {
+                // Nothing to freeze on yet
+                paused.wait_for(|paused| !*paused).await?;
+                continue;
+            }
+        };
+        loop {
+            tokio::select! {
+                v = paused.wait_for(|paused| !*paused) => {
+                    v?;
+                    // Resumed, the live frames take over from here
+                    break;
+                },
+                _ = sleep(FRAME_GAP) => {
+                    keyframe.ts += FRAME_GAP;
+                    data_tx.send(keyframe.clone())?;
+                },
+            }
+        }
+    }
+}
+
 fn check_live(app: &AppSrc) -> Result<()> {
     // log::debug!("Checking Live: {:?}", app.bus());
     app.bus().ok_or(anyhow!("App source is closed"))?;
@@ -542,6 +1194,75 @@ fn wait_for_keyframe<E, T: Stream<Item = Result<StampedData, E>> + Unpin>(
     })
 }
 
+/// Puts the latest parameter sets of the camera in front of each keyframe
+/// that lacks them, with `param_sets` set
+fn insert_parameter_sets<T: Stream<Item = AnyResult<StampedData>> + Unpin>(
+    mut stream: T,
+    param_sets: Option<WatchReceiver<ParameterSets>>,
+) -> impl Stream<Item = AnyResult<StampedData>> + Unpin {
+    Box::pin(async_stream::stream! {
+        while let Some(frame) = stream.next().await {
+            match (frame, &param_sets) {
+                (Ok(mut frame), Some(param_sets)) if frame.keyframe => {
+                    let data = param_sets.borrow().prepend_to(&frame.data);
+                    if let Some(data) = data {
+                        frame.data = Arc::new(data);
+                    }
+                    yield Ok(frame);
+                }
+                (frame, _) => yield frame,
+            }
+        }
+    })
+}
+
+/// Drops the frames that a client is behind on, when asked to through
+/// `jumps` or once it drifts more than `max_drift` behind
+///
+/// The drift is how far the timestamp of the frame being sent is behind the
+/// newest frame in the `history` of the camera stream. On a jump every frame
+/// is dropped until the latest keyframe of the history comes through
+fn jump_to_live<T: Stream<Item = AnyResult<StampedData>> + Unpin>(
+    mut stream: T,
+    mut jumps: WatchReceiver<u64>,
+    history: WatchReceiver<VecDeque<StampedData>>,
+    max_drift: Option<Duration>,
+) -> impl Stream<Item = AnyResult<StampedData>> + Unpin {
+    Box::pin(async_stream::stream! {
+        // Only the jumps asked for from now on
+        jumps.borrow_and_update();
+        let mut jumping = false;
+        while let Some(frame) = stream.next().await {
+            let Ok(frame) = frame else {
+                continue;
+            };
+            let (drift, latest_keyframe) = {
+                let history = history.borrow();
+                (
+                    history.back().map(|newest| newest.ts.saturating_sub(frame.ts)).unwrap_or_default(),
+                    history.iter().rev().find(|data| data.keyframe).map(|data| data.ts),
+                )
+            };
+            if jumps.has_changed().unwrap_or(false) {
+                jumps.borrow_and_update();
+                log::info!("Jumping a client to live, it is {}ms behind", drift.as_millis());
+                jumping = true;
+            } else if !jumping && max_drift.is_some_and(|max_drift| drift > max_drift) {
+                log::info!("Jumping a client to live, it drifted {}ms behind", drift.as_millis());
+                jumping = true;
+            }
+            if jumping {
+                // Older keyframes are still behind, any will do once the history has none
+                if !frame.keyframe || latest_keyframe.is_some_and(|latest| frame.ts < latest) {
+                    continue;
+                }
+                jumping = false;
+            }
+            yield Ok(frame);
+        }
+    })
+}
+
 // Take a stream of stamped data and release them
 // in waves when a new key frame is found
 // this ensure that the last frame sent is always an IFrame
@@ -695,3 +1416,156 @@ async fn send_to_appsrc<E, T: Stream<Item = Result<StampedData, E>> + Unpin>(
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(ms: u64, keyframe: bool) -> StampedData {
+        StampedData {
+            keyframe,
+            data: Arc::new(vec![]),
+            ts: Duration::from_millis(ms),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_insert_parameter_sets() {
+        // VPS, SPS and PPS then an IDR slice
+        let first = vec![
+            0, 0, 0, 1, 0x40, 1, 0xa, 0, 0, 0, 1, 0x42, 1, 0xb, 0, 0, 0, 1, 0x44, 1, 0xc, 0, 0, 0,
+            1, 0x26, 1, 0xd,
+        ];
+        let idr = first[first.len() - 7..].to_vec();
+        let mut sets = ParameterSets::default();
+        sets.update(&first);
+        let (_sets_tx, sets) = watch(sets);
+
+        let data = |keyframe: bool, data: &[u8]| {
+            AnyResult::Ok(StampedData {
+                keyframe,
+                data: Arc::new(data.to_vec()),
+                ts: Duration::ZERO,
+            })
+        };
+        let frames = vec![data(true, &idr), data(false, &[0, 0, 1, 2, 1])];
+        let sent = insert_parameter_sets(tokio_stream::iter(frames.clone()), Some(sets))
+            .map(|frame| frame.unwrap().data.to_vec())
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(sent, vec![first, vec![0, 0, 1, 2, 1]]);
+
+        // Without the option the frames are untouched
+        let sent = insert_parameter_sets(tokio_stream::iter(frames), None)
+            .map(|frame| frame.unwrap().data.to_vec())
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(sent, vec![idr, vec![0, 0, 1, 2, 1]]);
+    }
+
+    #[tokio::test]
+    async fn test_jump_to_live() {
+        // The camera is at 10s with keyframes every 2s
+        let history = (0..=100)
+            .map(|n| frame(n * 100, n % 20 == 0))
+            .collect::<VecDeque<_>>();
+        let (_history_tx, history) = watch(history);
+        let (jumps_tx, jumps) = watch(0);
+
+        // A client that is 6s behind drifts too far
+        let behind = (40..=100).map(|n| AnyResult::Ok(frame(n * 100, n % 20 == 0)));
+        let sent = jump_to_live(
+            tokio_stream::iter(behind.clone()),
+            jumps.clone(),
+            history.clone(),
+            Some(Duration::from_secs(5)),
+        )
+        .map(|frame| frame.unwrap().ts.as_millis())
+        .collect::<Vec<_>>()
+        .await;
+        assert_eq!(sent, vec![10000]);
+
+        // Under the max drift nothing is dropped
+        let sent = jump_to_live(
+            tokio_stream::iter(behind.clone()),
+            jumps.clone(),
+            history.clone(),
+            Some(Duration::from_secs(7)),
+        )
+        .collect::<Vec<_>>()
+        .await;
+        assert_eq!(sent.len(), 61);
+
+        // Asked to jump while sending
+        let mut stream = jump_to_live(tokio_stream::iter(behind), jumps, history, None);
+        assert_eq!(stream.next().await.unwrap().unwrap().ts.as_millis(), 4000);
+        jumps_tx.send_modify(|jumps| *jumps += 1);
+        assert_eq!(stream.next().await.unwrap().unwrap().ts.as_millis(), 10000);
+        assert!(stream.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_buffer() {
+        let not_ready = StreamConfig {
+            resolution: [0, 0],
+            vid_format: VidFormat::None,
+            aud_format: AudFormat::None,
+            bitrate: 0,
+            fps: 0,
+        };
+        let (config_tx, mut config) = watch(not_ready);
+        let start = Instant::now();
+        let signal = async {
+            sleep(Duration::from_millis(20)).await;
+            config_tx.send_modify(|config| {
+                config.resolution = [640, 360];
+                config.vid_format = VidFormat::H264;
+                config.bitrate = 1024;
+            });
+            sleep(Duration::from_millis(20)).await;
+            config_tx.send_modify(|config| config.aud_format = AudFormat::Aac);
+            // Keep the sender
+            futures::future::pending::<()>().await
+        };
+        tokio::select! {
+            v = wait_for_buffer("test", &mut config, Duration::from_secs(5)) => v.unwrap(),
+            _ = signal => unreachable!(),
+        }
+        // Done once the audio is known rather than at the end of the audio wait
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_still_shared_by_clients() {
+        let (paused_tx, paused) = watch(false);
+        let (still_tx, still) = watch(None);
+        let encodes = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let thread_encodes = encodes.clone();
+        let encode = move || {
+            thread_encodes.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            async { AnyResult::Ok(vec![frame(0, true)]) }
+        };
+
+        let mut set = JoinSet::new();
+        set.spawn(async move { encode_stills("test", paused, still_tx, encode).await });
+        let mut clients = vec![];
+        for _ in 0..8 {
+            let (data_tx, data_rx) = broadcast(100);
+            let (mut still, mut paused) = (still.clone(), paused_tx.subscribe());
+            set.spawn(async move { replay_still(&mut still, &mut paused, &data_tx).await });
+            clients.push(data_rx);
+        }
+
+        for pause in 1..=2 {
+            paused_tx.send_replace(true);
+            sleep(Duration::from_secs(1)).await;
+            paused_tx.send_replace(false);
+            sleep(Duration::from_secs(1)).await;
+            // Once per pause whatever the number of clients
+            assert_eq!(encodes.load(std::sync::atomic::Ordering::SeqCst), pause);
+        }
+        for client in clients.iter_mut() {
+            assert!(client.try_recv().is_ok());
+        }
+    }
+}
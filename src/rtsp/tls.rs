@@ -0,0 +1,111 @@
+//! Keeps the TLS certificates of the rtsp servers up to date
+//!
+//! The certificates are reloaded when the config changes or when one of the
+//! certificate files itself changes, e.g. when it is renewed
+use notify::{Event, RecommendedWatcher, RecursiveMode, Watcher};
+use std::{collections::HashMap, path::Path, sync::Arc};
+use tokio::{
+    sync::{
+        mpsc::{channel as mpsc, Sender as MpscSender},
+        watch::Receiver as WatchReceiver,
+    },
+    time::{sleep, Duration},
+};
+
+use super::{gst::NeoRtspServer, AnyResult};
+use crate::config::Config;
+
+/// How many times to try parsing a changed certificate
+///
+/// The file may still be being written when the change is first seen
+const RELOAD_ATTEMPTS: usize = 3;
+
+pub(super) async fn keep_tls_updated(
+    servers: Arc<HashMap<(String, u16), Arc<NeoRtspServer>>>,
+    mut config: WatchReceiver<Config>,
+) -> AnyResult<()> {
+    loop {
+        let current = config.borrow_and_update().clone();
+        // Dropping the watchers stops them so they are kept alive for this loop
+        let (tx, mut changes) = mpsc(10);
+        let _watchers = current
+            .certificate
+            .iter()
+            .chain(current.sni_certificates.values())
+            .filter_map(|cert_path| match watch_file(cert_path, tx.clone()) {
+                Ok(watcher) => Some(watcher),
+                Err(e) => {
+                    log::warn!("Could not watch {cert_path} for changes, it will not be reloaded when renewed: {e:?}");
+                    None
+                }
+            })
+            .collect::<Vec<_>>();
+        drop(tx);
+
+        tokio::select! {
+            v = config.changed() => {
+                v?;
+                for server in servers.values() {
+                    if let Err(e) = server.set_up_tls(&config.borrow().clone()) {
+                        log::error!("Could not seup TLS: {e}");
+                    }
+                }
+            },
+            Some(()) = changes.recv() => {
+                // Let the rest of the write land before reading it
+                sleep(Duration::from_millis(500)).await;
+                while changes.try_recv().is_ok() {}
+                reload(&servers, &current).await;
+            }
+        }
+    }
+}
+
+async fn reload(servers: &HashMap<(String, u16), Arc<NeoRtspServer>>, config: &Config) {
+    for attempt in 1..=RELOAD_ATTEMPTS {
+        match servers
+            .values()
+            .try_for_each(|server| server.set_up_tls(config))
+        {
+            Ok(()) => {
+                log::info!("Reloaded the TLS certificates");
+                return;
+            }
+            Err(e) if attempt < RELOAD_ATTEMPTS => {
+                log::debug!("Changed TLS certificate is not ready yet: {e:?}");
+                sleep(Duration::from_secs(1)).await;
+            }
+            Err(e) => {
+                log::error!(
+                    "Could not reload the changed TLS certificate, keeping the old one: {e:?}"
+                );
+            }
+        }
+    }
+}
+
+/// Watches the directory of the file so that replacing the file,
+/// as certbot does with its symlinks, is also noticed
+fn watch_file(path: &str, tx: MpscSender<()>) -> AnyResult<RecommendedWatcher> {
+    // Not canonicalized since the symlink itself is what gets replaced
+    let path = Path::new(path);
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let file_name = path.file_name().map(|name| name.to_os_string());
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<Event>| {
+        if let Ok(event) = event {
+            if event
+                .paths
+                .iter()
+                .any(|changed| changed.file_name().map(|name| name.to_os_string()) == file_name)
+            {
+                // Full means a reload is already pending
+                let _ = tx.try_send(());
+            }
+        }
+    })?;
+    watcher.watch(dir, RecursiveMode::NonRecursive)?;
+    Ok(watcher)
+}
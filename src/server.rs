@@ -0,0 +1,158 @@
+//! The rtsp server as a library
+//!
+//! [`NeolinkServer`] runs what `neolink rtsp` does inside another program. The
+//! config is given to the builder rather than read from a file and the server
+//! is stopped with [`NeolinkServer::stop`] rather than a signal. It runs on
+//! the tokio runtime of the program so the `worker_threads` and
+//! `max_blocking_threads` of the config are not used
+use anyhow::{anyhow, Context, Result};
+use std::sync::Arc;
+use tokio::{sync::Mutex, task::JoinHandle};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    common::NeoReactor,
+    config::{CameraConfig, Config},
+    exit::ExitError,
+    rtsp::{self, metrics::Metrics},
+    CameraStatus,
+};
+
+/// Sets up a [`NeolinkServer`]
+#[derive(Default)]
+pub struct NeolinkServerBuilder {
+    config: Option<Config>,
+    cameras: Vec<CameraConfig>,
+    opt: rtsp::Opt,
+}
+
+impl NeolinkServerBuilder {
+    /// The global settings and cameras, as in the config file
+    pub fn config(mut self, config: Config) -> Self {
+        self.config = Some(config);
+        self
+    }
+
+    /// Adds a camera to those of the config
+    pub fn camera(mut self, camera: CameraConfig) -> Self {
+        self.cameras.push(camera);
+        self
+    }
+
+    /// Serve `/healthz`, `/cameras` and the sdp of each stream on this port,
+    /// like `--status-port`
+    pub fn status_port(mut self, port: u16) -> Self {
+        self.opt.status_port = Some(port);
+        self
+    }
+
+    /// Serve prometheus metrics on this port, like `--metrics-port`
+    pub fn metrics_port(mut self, port: u16) -> Self {
+        self.opt.metrics_port = Some(port);
+        self
+    }
+
+    /// Checks the config the same way as when it is loaded from a file
+    pub fn build(self) -> Result<NeolinkServer> {
+        let mut config = match self.config {
+            Some(config) => config,
+            None => toml::from_str("").context("Failed to make the default config")?,
+        };
+        config.cameras.extend(self.cameras);
+        let config = config
+            .prepare()
+            .context("Failed to validate the config")
+            .context(ExitError::Config)?;
+        Ok(NeolinkServer {
+            config,
+            opt: self.opt,
+            metrics: Default::default(),
+            running: Default::default(),
+        })
+    }
+}
+
+/// The rtsp server of neolink
+///
+/// Build it with [`NeolinkServer::builder`]. It can be started and stopped as
+/// often as needed
+pub struct NeolinkServer {
+    config: Config,
+    opt: rtsp::Opt,
+    metrics: Arc<Metrics>,
+    running: Mutex<Option<Running>>,
+}
+
+/// The task of a started server
+struct Running {
+    cancel: CancellationToken,
+    task: JoinHandle<Result<()>>,
+}
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        self.cancel.cancel();
+    }
+}
+
+impl NeolinkServer {
+    /// Starts setting up a server
+    pub fn builder() -> NeolinkServerBuilder {
+        Default::default()
+    }
+
+    /// Connects to the cameras and starts serving them
+    ///
+    /// This returns once the server is running in the background. Errors that
+    /// stop it are returned by [`NeolinkServer::stop`]
+    pub async fn start(&self) -> Result<()> {
+        let mut running = self.running.lock().await;
+        if running
+            .as_ref()
+            .is_some_and(|running| !running.task.is_finished())
+        {
+            return Err(anyhow!("The server is already running"));
+        }
+        // Gstreamer is initialised here, before the elements are looked up
+        let config = rtsp::deps::prepare(self.config.clone())?;
+        let reactor = NeoReactor::new(config).await;
+        let cancel = CancellationToken::new();
+        let shutdown = cancel.clone();
+        let task = tokio::spawn(rtsp::serve(
+            self.opt.clone(),
+            reactor,
+            self.metrics.clone(),
+            async move {
+                shutdown.cancelled().await;
+                Ok(())
+            },
+        ));
+        *running = Some(Running { cancel, task });
+        Ok(())
+    }
+
+    /// Stops serving once the clients have disconnected or the `shutdown_grace`
+    /// of the config has passed
+    ///
+    /// Returns the error that stopped the server early, if any
+    pub async fn stop(&self) -> Result<()> {
+        let Some(mut running) = self.running.lock().await.take() else {
+            return Ok(());
+        };
+        running.cancel.cancel();
+        // The reactor ends with the task and disconnects the cameras
+        (&mut running.task).await?
+    }
+
+    /// The status of each camera that is being served, sorted by name
+    pub fn cameras(&self) -> Vec<CameraStatus> {
+        self.metrics.camera_status()
+    }
+
+    /// The status of one camera, `None` if it is not being served
+    pub fn camera_status(&self, name: &str) -> Option<CameraStatus> {
+        self.cameras()
+            .into_iter()
+            .find(|camera| camera.name == name)
+    }
+}
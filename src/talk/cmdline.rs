@@ -12,7 +12,7 @@ use std::str::FromStr;
 pub struct Opt {
     /// The name of the camera to talk through. Must be a name in the config
     pub camera: String,
-    /// The path to the audio file.
+    /// The path to the audio file, or `-` to read it from stdin.
     #[arg(short, long, value_parser = PathBuf::from_str, conflicts_with = "microphone")]
     pub file_path: Option<PathBuf>,
     /// Use the microphone as the source. Defaults to autoaudiosrc - Which microphone depends
@@ -8,9 +8,11 @@
 /// # Usage
 ///
 /// ```bash
-/// neolink talk --config=config.toml --adpcm-file=data.adpcm --sample-rate=16000 --block-size=512 CameraName
+/// neolink talk --config=config.toml --file-path=data.wav CameraName
 /// ```
 ///
+/// Use `--file-path=-` to read the audio from stdin
+///
 use anyhow::{anyhow, Context, Result};
 use neolink_core::bc::xml::TalkConfig;
 
@@ -71,6 +73,10 @@ pub(crate) async fn main(opt: Opt, reactor: NeoReactor) -> Result<()> {
     }
 
     let (mut set, rx) = match (&opt.file_path, &opt.microphone) {
+        (Some(path), false) if path.as_os_str() == "-" => {
+            gst::from_input("fdsrc fd=0", opt.volume, block_size, sample_rate)
+                .context("Failed to setup gst with stdin")?
+        }
         (Some(path), false) => gst::from_input(
             &format!(
                 "filesrc location={}",
@@ -96,7 +102,13 @@ pub(crate) async fn main(opt: Opt, reactor: NeoReactor) -> Result<()> {
             })
         })
         .await
-        .context("Talk stream ended early")?;
+        .map_err(|e| match e.downcast_ref::<neolink_core::Error>() {
+            Some(neolink_core::Error::TalkBusy) => anyhow!(
+                "Camera {} is busy talking with another client, try again once it has finished",
+                name
+            ),
+            _ => e.context("Talk stream ended early"),
+        })?;
 
     drop(rx);
     while set.join_next().await.is_some() {}
@@ -2,36 +2,40 @@
 //!
 use log::*;
 
-use super::config::CameraConfig;
+use super::config::{CameraConfig, Config};
 use anyhow::{anyhow, Context, Error, Result};
+use futures::stream::{FuturesUnordered, StreamExt};
 use neolink_core::bc_protocol::{
     BcCamera, BcCameraOpt, ConnectionProtocol, Credentials, DiscoveryMethods, MaxEncryption,
 };
 use std::{
     fmt::{Display, Error as FmtError, Formatter},
+    future::Future,
     net::{IpAddr, ToSocketAddrs},
     str::FromStr,
+    time::Duration,
 };
 
-pub(crate) fn timeout<F>(future: F) -> tokio::time::Timeout<F>
+/// Awaits the future but gives up with an error if it takes longer than `limit`
+///
+/// The error is not a login failure so the camera will be retried
+async fn within<F, T>(limit: Duration, action: &str, future: F) -> Result<T>
 where
-    F: std::future::Future,
+    F: std::future::Future<Output = Result<T>>,
 {
-    tokio::time::timeout(tokio::time::Duration::from_secs(15), future)
+    tokio::time::timeout(limit, future)
+        .await
+        .map_err(|_| anyhow!("Timed out after {:?} while {}", limit, action))?
 }
 
 pub(crate) enum AddressOrUid {
     Address(String),
     Uid(String, DiscoveryMethods),
-    AddressWithUid(String, String, DiscoveryMethods),
 }
 
 impl Display for AddressOrUid {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), FmtError> {
         match self {
-            AddressOrUid::AddressWithUid(addr, uid, _) => {
-                write!(f, "Address: {}, UID: {}", addr, uid)
-            }
             AddressOrUid::Address(host) => write!(f, "Address: {}", host),
             AddressOrUid::Uid(host, _) => write!(f, "UID: {}", host),
         }
@@ -47,11 +51,7 @@ impl AddressOrUid {
     ) -> Result<Self, Error> {
         match (address, uid) {
             (None, None) => Err(anyhow!("Neither address or uid given")),
-            (Some(host), Some(uid)) => Ok(AddressOrUid::AddressWithUid(
-                host.clone(),
-                uid.clone(),
-                *method,
-            )),
+            (Some(_), Some(_)) => Err(anyhow!("Only one of address or uid can be given")),
             (Some(host), None) => Ok(AddressOrUid::Address(host.clone())),
             (None, Some(host)) => Ok(AddressOrUid::Uid(host.clone(), *method)),
         }
@@ -99,6 +99,7 @@ impl AddressOrUid {
             },
             debug: camera_config.debug,
             max_discovery_retries: camera_config.max_discovery_retries,
+            source_addr: camera_config.source_addr,
         };
 
         trace!("Camera Info: {:?}", options);
@@ -113,21 +114,24 @@ pub(crate) async fn connect_and_login(camera_config: &CameraConfig) -> Result<Bc
         &camera_config.camera_uid,
         &camera_config.discovery,
     )
-    .unwrap();
+    .with_context(|| format!("Cannot connect to camera {}", camera_config.name))?;
     info!(
         "{}: Connecting to camera at {}",
         camera_config.name, camera_addr
     );
 
-    let camera = camera_addr
-        .connect_camera(camera_config)
-        .await
-        .with_context(|| {
-            format!(
-                "Failed to connect to camera {} at {} on channel {}",
-                camera_config.name, camera_addr, camera_config.channel_id
-            )
-        })?;
+    let camera = within(
+        Duration::from_secs(camera_config.connect_timeout_secs),
+        "connecting",
+        camera_addr.connect_camera(camera_config),
+    )
+    .await
+    .with_context(|| {
+        format!(
+            "Failed to connect to camera {} at {} on channel {}",
+            camera_config.name, camera_addr, camera_config.channel_id
+        )
+    })?;
 
     let max_encryption = match camera_config.max_encryption.to_lowercase().as_str() {
         "none" => MaxEncryption::None,
@@ -136,11 +140,245 @@ pub(crate) async fn connect_and_login(camera_config: &CameraConfig) -> Result<Bc
         _ => MaxEncryption::Aes,
     };
     info!("{}: Logging in", camera_config.name);
-    timeout(camera.login_with_maxenc(max_encryption))
-        .await
-        .with_context(|| format!("Failed to login to {}", camera_config.name))??;
+    // The core error is kept in the chain so rejected credentials can still be told apart
+    within(
+        Duration::from_secs(camera_config.login_timeout_secs),
+        "logging in",
+        async { Ok(camera.login_with_maxenc(max_encryption).await) },
+    )
+    .await
+    .and_then(|login| login.map_err(Error::from))
+    .with_context(|| {
+        format!(
+            "Failed to login to camera {} at {}",
+            camera_config.name, camera_addr
+        )
+    })?;
 
     info!("{}: Connected and logged in", camera_config.name);
 
     Ok(camera)
 }
+
+/// The cameras that a subcommand like `reboot` runs on
+pub(crate) enum CameraSelection {
+    /// A camera given by its name
+    One(String),
+    /// The enabled cameras that matched a glob or `--all`
+    Many(Vec<String>),
+}
+
+impl CameraSelection {
+    /// Selects by `camera`, which may be a glob with `*` and `?`, or every
+    /// enabled camera if `all`
+    pub(crate) fn new(config: &Config, camera: Option<&str>, all: bool) -> Result<Self> {
+        let pattern = match camera {
+            Some(name) if !all && !is_glob(name) => return Ok(Self::One(name.to_string())),
+            Some(pattern) if !all => pattern,
+            _ => "*",
+        };
+        let names = config
+            .cameras
+            .iter()
+            .filter(|camera| camera.enabled && glob_match(pattern, &camera.name))
+            .map(|camera| camera.name.clone())
+            .collect::<Vec<_>>();
+        if names.is_empty() {
+            return Err(anyhow!(
+                "No enabled camera in the config matches `{pattern}`"
+            ));
+        }
+        Ok(Self::Many(names))
+    }
+
+    /// Runs `action` on each camera at the same time
+    ///
+    /// A single camera gives its own result. For many a summary of each one
+    /// is printed to stderr and it only fails if all of them failed
+    pub(crate) async fn run<F, Fut>(self, action: F) -> Result<()>
+    where
+        F: Fn(String) -> Fut,
+        Fut: Future<Output = Result<()>>,
+    {
+        let names = match self {
+            Self::One(name) => return action(name).await,
+            Self::Many(names) => names,
+        };
+        let mut results = names
+            .into_iter()
+            .map(|name| {
+                let result = action(name.clone());
+                async move { (name, result.await) }
+            })
+            .collect::<FuturesUnordered<_>>()
+            .collect::<Vec<_>>()
+            .await;
+        results.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+        let width = results
+            .iter()
+            .map(|(name, _)| name.len())
+            .max()
+            .unwrap_or_default();
+        for (name, result) in results.iter() {
+            match result {
+                Ok(()) => eprintln!("{name:<width$}  ok"),
+                Err(e) => eprintln!("{name:<width$}  failed  {e:#}"),
+            }
+        }
+        let failed = results.iter().filter(|(_, result)| result.is_err()).count();
+        if failed == results.len() {
+            Err(anyhow!("All {failed} cameras failed"))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Whether `name` is a glob that can match more than one camera
+fn is_glob(name: &str) -> bool {
+    name.contains(['*', '?'])
+}
+
+/// Matches `name` against a glob where `*` is any run of characters and `?`
+/// any one character
+fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let name = name.chars().collect::<Vec<_>>();
+    let (mut p, mut n) = (0, 0);
+    // Where the last `*` was and how much of the name it has taken so far
+    let mut star = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, n));
+                p += 1;
+            }
+            Some(c) if *c == '?' || *c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                Some((star_p, star_n)) => {
+                    p = star_p + 1;
+                    n = star_n + 1;
+                    star = Some((star_p, star_n + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("*", "Garage"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("Front*", "Front Door"));
+        assert!(glob_match("*Door", "Front Door"));
+        assert!(glob_match("*o*o*", "Front Door"));
+        assert!(glob_match("Cam0?", "Cam01"));
+        assert!(!glob_match("Cam0?", "Cam1"));
+        assert!(!glob_match("Front*", "Garage"));
+        assert!(!glob_match("Garage", "Garage2"));
+        assert!(!is_glob("Garage"));
+        assert!(is_glob("Cam*"));
+    }
+
+    #[test]
+    fn test_camera_selection() {
+        let config: Config = toml::from_str(
+            r#"
+[[cameras]]
+name = "Front Door"
+username = "admin"
+address = "192.168.1.10"
+
+[[cameras]]
+name = "Front Yard"
+username = "admin"
+address = "192.168.1.11"
+
+[[cameras]]
+name = "Garage"
+username = "admin"
+address = "192.168.1.12"
+
+[[cameras]]
+name = "Front Gate"
+username = "admin"
+address = "192.168.1.13"
+enabled = false
+"#,
+        )
+        .unwrap();
+        let names = |selection| match selection {
+            CameraSelection::One(name) => vec![name],
+            CameraSelection::Many(names) => names,
+        };
+        assert!(matches!(
+            CameraSelection::new(&config, Some("Garage"), false).unwrap(),
+            CameraSelection::One(name) if name == "Garage"
+        ));
+        assert_eq!(
+            names(CameraSelection::new(&config, Some("Front*"), false).unwrap()),
+            vec!["Front Door", "Front Yard"]
+        );
+        assert_eq!(
+            names(CameraSelection::new(&config, None, true).unwrap()),
+            vec!["Front Door", "Front Yard", "Garage"]
+        );
+        assert!(CameraSelection::new(&config, Some("Back*"), false).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_camera_selection_run() {
+        let many = || CameraSelection::Many(vec!["a".to_string(), "b".to_string()]);
+        // Only some failing is not an error
+        assert!(many()
+            .run(|name| async move {
+                if name == "a" {
+                    Err(anyhow!("Offline"))
+                } else {
+                    Ok(())
+                }
+            })
+            .await
+            .is_ok());
+        assert!(many()
+            .run(|_| async { Err(anyhow!("Offline")) })
+            .await
+            .is_err());
+        // A single camera gives its own error
+        assert!(CameraSelection::One("a".to_string())
+            .run(|_| async { Err(anyhow!("Offline")) })
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_slow_connect_times_out() {
+        let slow_connect = async {
+            tokio::time::sleep(Duration::from_secs(60)).await;
+            Ok(())
+        };
+        let e = within(Duration::from_millis(10), "connecting", slow_connect)
+            .await
+            .unwrap_err();
+        // Must not be mistaken for bad credentials which stops the retries
+        assert!(e.downcast_ref::<neolink_core::Error>().is_none());
+
+        let fast_connect = async { Ok(1) };
+        assert_eq!(
+            within(Duration::from_millis(10), "connecting", fast_connect)
+                .await
+                .unwrap(),
+            1
+        );
+    }
+}
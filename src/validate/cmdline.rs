@@ -0,0 +1,5 @@
+use clap::Parser;
+
+/// The validate command checks the config for mistakes without connecting to any camera
+#[derive(Parser, Debug)]
+pub struct Opt {}
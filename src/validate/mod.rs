@@ -0,0 +1,376 @@
+///
+/// # Neolink Validate
+///
+/// This module handles the validate subcommand
+///
+/// The subcommand loads the config and checks it for mistakes without
+/// connecting to any camera. Besides the checks that are run on every start it
+/// looks for duplicate camera names, colliding rtsp paths, tls client auth or
+/// sni certificates without a certificate and servers that would bind the same
+/// port. Every
+/// problem is printed with the line of the config it is on, where it can be
+/// found, and the exit code is non zero if there are any
+///
+/// # Usage
+///
+/// ```bash
+/// neolink validate --config=config.toml
+/// ```
+///
+use anyhow::{anyhow, Context, Result};
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+use validator::{Validate, ValidationErrors, ValidationErrorsKind};
+
+mod cmdline;
+
+use crate::{
+    config::{Config, DuplicateNames, TlsClientAuth},
+    exit::ExitError,
+    rtsp::UNIX_PREFIX,
+};
+pub(crate) use cmdline::Opt;
+
+/// A mistake in the config
+#[derive(Debug, Eq, PartialEq)]
+struct Problem {
+    /// Where in the config, e.g. `cameras[0].pause.mode`
+    path: String,
+    message: String,
+}
+
+impl Problem {
+    fn new<P: Into<String>, M: Into<String>>(path: P, message: M) -> Self {
+        Self {
+            path: path.into(),
+            message: message.into(),
+        }
+    }
+}
+
+/// Entry point for the validate subcommand
+///
+/// Unlike the other subcommands the config is loaded here so that all of its
+/// problems can be listed rather than just the first
+pub(crate) fn main(_opt: &Opt, config_path: Option<PathBuf>) -> Result<()> {
+    let path = config_path.context("Must supply --config file")?;
+    // Syntax errors stop here, the toml error already shows the line
+    let config = Config::load(&path).context(ExitError::Config)?;
+    let sources = sources(&path).unwrap_or_default();
+
+    let mut problems = vec![];
+    if let Err(errors) = config.validate() {
+        flatten(&errors, "", &mut problems);
+    }
+    problems.sort_by(|a, b| a.path.cmp(&b.path));
+    if let Err(e) = config.clone().inherit_globals() {
+        problems.push(Problem::new("", e.to_string()));
+    }
+    let mut config = config;
+    if let Err(e) = config.resolve_bind_interface() {
+        problems.push(Problem::new("bind_interface", e.to_string()));
+    }
+    for (index, camera) in config.cameras.iter().enumerate() {
+        if let Some(addr) = camera.source_addr {
+            if let Err(e) = std::net::UdpSocket::bind((addr, 0)) {
+                problems.push(Problem::new(
+                    format!("cameras[{index}].source_addr"),
+                    format!("{addr} cannot be used as a local address of this host: {e}"),
+                ));
+            }
+        }
+    }
+    problems.extend(semantic_problems(&config));
+
+    if problems.is_empty() {
+        println!("{:?} is valid with {} cameras", path, config.cameras.len());
+        return Ok(());
+    }
+    for problem in problems.iter() {
+        if problem.path.is_empty() {
+            println!("error: {}", problem.message);
+        } else {
+            println!("error: {}: {}", problem.path, problem.message);
+        }
+        for (file, line, text) in sources
+            .iter()
+            .filter_map(|(file, source)| locate(source, &problem.path).map(|at| (file, at)))
+            .map(|(file, (line, text))| (file, line, text))
+            .take(1)
+        {
+            println!("  --> {}:{}", file.display(), line);
+            println!("   |  {}", text.trim());
+        }
+    }
+    Err(anyhow!("Found {} problems in {:?}", problems.len(), path).context(ExitError::Config))
+}
+
+/// The text of the config file or of each `*.toml` file of a config directory
+fn sources(path: &Path) -> Result<Vec<(PathBuf, String)>> {
+    let files = if path.is_dir() {
+        let mut files = fs::read_dir(path)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<Result<Vec<_>, _>>()?;
+        files.retain(|file| file.extension().is_some_and(|ext| ext == "toml"));
+        files.sort();
+        files
+    } else {
+        vec![path.to_path_buf()]
+    };
+    files
+        .into_iter()
+        .map(|file| Ok((file.clone(), fs::read_to_string(&file)?)))
+        .collect()
+}
+
+/// Turns the nested errors of the validator into one problem per field
+fn flatten(errors: &ValidationErrors, prefix: &str, problems: &mut Vec<Problem>) {
+    for (field, kind) in errors.errors() {
+        let path = match (*field, prefix) {
+            // Errors of the whole struct
+            ("__all__", prefix) => prefix.to_string(),
+            (field, "") => field.to_string(),
+            (field, prefix) => format!("{prefix}.{field}"),
+        };
+        match kind {
+            ValidationErrorsKind::Struct(errors) => flatten(errors, &path, problems),
+            ValidationErrorsKind::List(list) => {
+                for (index, errors) in list.iter() {
+                    flatten(errors, &format!("{path}[{index}]"), problems);
+                }
+            }
+            ValidationErrorsKind::Field(errors) => {
+                for error in errors.iter() {
+                    let message = error
+                        .message
+                        .as_ref()
+                        .map(|message| message.to_string())
+                        .unwrap_or_else(|| error.code.to_string());
+                    problems.push(Problem::new(path.clone(), message));
+                }
+            }
+        }
+    }
+}
+
+/// The checks that need the whole config rather than one field
+fn semantic_problems(config: &Config) -> Vec<Problem> {
+    let mut problems = vec![];
+    let enabled = config
+        .cameras
+        .iter()
+        .enumerate()
+        .filter(|(_, camera)| camera.enabled)
+        .collect::<Vec<_>>();
+
+    // Duplicate names
+    let mut names: HashMap<&str, usize> = Default::default();
+    let mut duplicates = false;
+    for (index, camera) in enabled.iter() {
+        if let Some(first) = names.insert(camera.name.as_str(), *index) {
+            if config.duplicate_names == DuplicateNames::Error {
+                duplicates = true;
+                problems.push(Problem::new(
+                    format!("cameras[{index}].name"),
+                    format!(
+                        "`{}` is also the name of cameras[{first}], rename one or set duplicate_names = \"suffix\"",
+                        camera.name
+                    ),
+                ));
+            }
+        }
+    }
+    // Other paths that collide, e.g. through rtsp_path
+    if !duplicates {
+        let mut config = config.clone();
+        config.expand_channels();
+        if let Err(e) = config.resolve_duplicate_names() {
+            problems.push(Problem::new("", format!("{:#}", e)));
+        }
+    }
+
+    if config.tls_client_auth != TlsClientAuth::None && config.certificate.is_none() {
+        problems.push(Problem::new(
+            "tls_client_auth",
+            "Client certificates are checked but there is no certificate to serve tls with",
+        ));
+    }
+
+    if !config.sni_certificates.is_empty() && config.certificate.is_none() {
+        problems.push(Problem::new(
+            "sni_certificates",
+            "There is no certificate for the clients that ask for another hostname or none",
+        ));
+    }
+
+    if config.http.tls && config.http_certificate().is_none() {
+        problems.push(Problem::new(
+            "http.tls",
+            "Https is turned on but there is no certificate to serve it with",
+        ));
+    }
+    if config.http.require_auth && config.users.is_empty() {
+        problems.push(Problem::new(
+            "http.require_auth",
+            "The http servers require auth but there are no [[users]] to log in with",
+        ));
+    }
+
+    // Servers that would bind the same port
+    let mut binds = vec![(
+        "bind_port".to_string(),
+        config.bind_addr.clone(),
+        config.bind_port,
+    )];
+    for (index, camera) in enabled.iter() {
+        let bind = camera.rtsp_bind(&config.bind_addr, config.bind_port);
+        if !binds
+            .iter()
+            .any(|(_, addr, port)| (addr, port) == (&bind.0, &bind.1))
+        {
+            binds.push((format!("cameras[{index}].bind_port"), bind.0, bind.1));
+        }
+    }
+    if let Some(onvif) = config.onvif.as_ref() {
+        // The onvif service binds the global address, or localhost for a unix socket
+        let addr = if config.bind_addr.starts_with(UNIX_PREFIX) {
+            "127.0.0.1".to_string()
+        } else {
+            config.bind_addr.clone()
+        };
+        binds.push(("onvif.port".to_string(), addr, onvif.port));
+    }
+    let binds = binds
+        .into_iter()
+        .filter(|(_, addr, _)| !addr.starts_with(UNIX_PREFIX))
+        .collect::<Vec<_>>();
+    for (n, (path, addr, port)) in binds.iter().enumerate() {
+        let conflict = binds[..n].iter().find(|(_, other_addr, other_port)| {
+            other_port == port
+                && (other_addr == addr || is_wildcard(other_addr) || is_wildcard(addr))
+        });
+        if let Some((other_path, other_addr, _)) = conflict {
+            problems.push(Problem::new(
+                path.clone(),
+                format!("{addr}:{port} conflicts with {other_path} at {other_addr}:{port}"),
+            ));
+        }
+    }
+
+    problems
+}
+
+/// Binds every address and so conflicts with all of them
+fn is_wildcard(addr: &str) -> bool {
+    matches!(addr, "0.0.0.0" | "::" | "[::]" | "")
+}
+
+/// The line number and text of the field at `path` e.g. `cameras[1].pause.mode`
+///
+/// This is a best guess that looks for the key within the table of the path
+fn locate(source: &str, path: &str) -> Option<(usize, String)> {
+    if path.is_empty() {
+        return None;
+    }
+    let lines = source.lines().collect::<Vec<_>>();
+    let (mut start, mut end) = (0, lines.len());
+    let first = path.split('.').next()?;
+    if let Some((table, index)) = indexed(first) {
+        let header = format!("[[{table}]]");
+        let headers = lines
+            .iter()
+            .enumerate()
+            .filter(|(_, line)| line.trim() == header)
+            .map(|(n, _)| n)
+            .collect::<Vec<_>>();
+        start = *headers.get(index)?;
+        end = headers.get(index + 1).copied().unwrap_or(lines.len());
+        if path == first {
+            return Some((start + 1, lines[start].to_string()));
+        }
+    }
+    let key = path.rsplit('.').next()?;
+    let key = indexed(key).map(|(key, _)| key).unwrap_or(key);
+    (start..end)
+        .find(|&n| {
+            lines[n]
+                .trim_start()
+                .strip_prefix(key)
+                .is_some_and(|rest| rest.trim_start().starts_with('='))
+        })
+        .map(|n| (n + 1, lines[n].to_string()))
+}
+
+/// Splits `cameras[1]` into `("cameras", 1)`
+fn indexed(segment: &str) -> Option<(&str, usize)> {
+    let (name, index) = segment.strip_suffix(']')?.split_once('[')?;
+    Some((name, index.parse().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"
+bind_port = 8554
+tls_client_auth = "require"
+
+[[cameras]]
+name = "Garage"
+username = "admin"
+address = "192.168.1.10"
+  [cameras.pause]
+  mode = "none"
+
+[[cameras]]
+name = "Garage"
+username = "admin"
+address = "192.168.1.11"
+bind = "192.168.1.2"
+  [cameras.pause]
+  mode = "blak"
+"#;
+
+    #[test]
+    fn test_problems() {
+        let config: Config = toml::from_str(CONFIG).unwrap();
+        let mut problems = vec![];
+        flatten(&config.validate().unwrap_err(), "", &mut problems);
+        assert_eq!(problems.len(), 1);
+        assert_eq!(problems[0].path, "cameras[1].pause.mode");
+
+        let paths = semantic_problems(&config)
+            .into_iter()
+            .map(|problem| problem.path)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            paths,
+            vec![
+                "cameras[1].name",
+                "tls_client_auth",
+                // 0.0.0.0:8554 already has every address
+                "cameras[1].bind_port"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_locate() {
+        assert_eq!(
+            locate(CONFIG, "cameras[1].pause.mode"),
+            Some((18, r#"  mode = "blak""#.to_string()))
+        );
+        assert_eq!(
+            locate(CONFIG, "cameras[1]"),
+            Some((12, "[[cameras]]".to_string()))
+        );
+        assert_eq!(
+            locate(CONFIG, "tls_client_auth"),
+            Some((3, r#"tls_client_auth = "require""#.to_string()))
+        );
+        assert_eq!(locate(CONFIG, "cameras[2].name"), None);
+    }
+}